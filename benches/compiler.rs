@@ -0,0 +1,131 @@
+//! Throughput of each compiler pass over synthetic programs with thousands of functions.
+//!
+//! Run with `cargo bench`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gwe::{
+    generators::{
+        gwe::{self as gwe_generator, FormatOptions},
+        web_assembly,
+    },
+    parser::{parse, Program},
+    tokenizer::tokenize,
+};
+
+const SIZES: &[usize] = &[100, 1000, 5000];
+
+/// A program with `functions` functions, each using locals, strings, ifs, loops and calls
+fn synthetic_program(functions: usize) -> String {
+    let mut body = String::from(
+        "import fn log(number: i32) console.log
+import fn say(offset: i32, length: i32) console.log
+import memory 1 js.mem
+
+",
+    );
+
+    for index in 0..functions {
+        body.push_str(&format!(
+            "fn function_{index}(n: i32): void {{
+    local x: i32 = n;
+    local message: string = \"Hello {index}\";
+    say();
+    global total: f32 = 1 + 2.5;
+    if (n) {{
+        log(x);
+    }} else {{
+        log(0);
+    }};
+    for (local y: i32 = 0, 10, 1) {{
+        log(y);
+    }};
+}}
+
+export function{index} function_{index}
+
+"
+        ));
+    }
+
+    body
+}
+
+fn parsed_program(source: &str) -> Program {
+    match parse(source.to_string()) {
+        Ok(program) => program,
+        Err(errors) => panic!("Synthetic program failed to parse: {:?}", errors),
+    }
+}
+
+fn bench_tokenize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tokenize");
+
+    for size in SIZES {
+        let source = synthetic_program(*size);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &source, |b, source| {
+            b.iter(|| tokenize(black_box(source.clone())))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.sample_size(10);
+
+    for size in SIZES {
+        let source = synthetic_program(*size);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &source, |b, source| {
+            b.iter(|| parse(black_box(source.clone())))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_generate_wat(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generate_wat");
+    group.sample_size(10);
+
+    for size in SIZES {
+        let program = parsed_program(&synthetic_program(*size));
+        let wat =
+            web_assembly::generate(program.clone()).expect("Synthetic program failed to generate");
+        assert_eq!(web_assembly::validate(&wat), Ok(()));
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &program, |b, program| {
+            b.iter(|| web_assembly::generate(black_box(program.clone())))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_generate_gwe(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generate_gwe");
+    group.sample_size(10);
+    let options = FormatOptions::default();
+
+    for size in SIZES {
+        let program = parsed_program(&synthetic_program(*size));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &program, |b, program| {
+            b.iter(|| gwe_generator::generate(black_box(program.clone()), &options))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_tokenize,
+    bench_parse,
+    bench_generate_wat,
+    bench_generate_gwe
+);
+criterion_main!(benches);
@@ -1,126 +1,905 @@
-use std::{slice::Iter, vec};
+use std::{
+    fmt::{Display, Formatter},
+    slice::Iter,
+    vec,
+};
 
 use crate::{
-    expressions::{parse_expression, Expression},
+    errors::CompileError,
+    expressions::{parse_statements, Expression, Tree},
     tokenizer::{
-        error_with_info, split_by_semicolon_within_brackets, tokenize, FullyQualifiedToken, Token,
+        check_name, error_with_info, error_without_info, normalize_line_endings, split_top_level,
+        take_argument_list, take_until_closing, tokenize_checked, FullyQualifiedToken, Token,
+        TokenInfo,
     },
 };
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Param {
     pub name: String,
     pub type_name: String,
+    /// Where the name is written, which params of signatures built by the
+    /// compiler don't have
+    pub info: Option<TokenInfo>,
+}
+
+/// `@name` or `@name("arg")` written before a function
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Attribute {
+    pub name: String,
+    pub args: Vec<String>,
 }
 
+/// Attributes that are understood, with the most arguments each can take
+const ATTRIBUTES: [(&str, usize); 4] = [("inline", 0), ("export", 1), ("test", 1), ("memory", 1)];
+
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Function {
     pub name: String,
     pub expressions: Vec<Expression>,
     pub params: Vec<Param>,
     pub return_type: String,
+    /// Text of the `///` comments before the function
+    pub doc: Option<String>,
+    pub attributes: Vec<Attribute>,
+    /// `T` in `fn identity<T>(x: T): T`, which each call fills in with a type
+    pub type_params: Vec<String>,
+}
+
+impl Function {
+    /// The name given by an `@export` attribute, which defaults to the function's name
+    pub fn export_name(&self) -> Option<String> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.name == "export")
+            .map(|attribute| match attribute.args.first() {
+                Some(name) => name.to_string(),
+                None => self.name.to_string(),
+            })
+    }
+
+    /// The type of the function when passed around, like `fn(i32): i32`
+    pub fn function_type(&self) -> String {
+        function_type(
+            &self
+                .params
+                .iter()
+                .map(|param| param.type_name.to_string())
+                .collect::<Vec<String>>(),
+            &self.return_type,
+        )
+    }
+
+    /// The memory named by a `@memory` attribute, which strings and memory
+    /// builtins in the function use instead of the first memory
+    pub fn memory_name(&self) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.name == "memory")
+            .and_then(|attribute| attribute.args.first())
+            .map(String::as_str)
+    }
+
+    /// The types a call fills the type params with, taken from the types of
+    /// its arguments where they're known. Type params are bound as aliases, so
+    /// that `resolve_type` substitutes them
+    pub fn bind_type_params(
+        &self,
+        arg_types: &[Option<String>],
+    ) -> Result<Vec<TypeAlias>, CompileError> {
+        let mut bound: Vec<TypeAlias> = vec![];
+
+        for (param, arg_type) in self.params.iter().zip(arg_types) {
+            let Some(arg_type) = arg_type
+                .as_ref()
+                .filter(|_| self.type_params.contains(&param.type_name))
+            else {
+                continue;
+            };
+
+            match bound.iter().find(|alias| alias.name == param.type_name) {
+                Some(alias) if alias.type_name != *arg_type => {
+                    return error_without_info(format!(
+                        "{} expects {} to be one type but got {} and {}",
+                        self.name, param.type_name, alias.type_name, arg_type
+                    ))
+                }
+                Some(_) => (),
+                None => bound.push(TypeAlias {
+                    name: param.type_name.to_string(),
+                    type_name: arg_type.to_string(),
+                }),
+            }
+        }
+
+        Ok(bound)
+    }
+
+    /// The function and its body as an indented tree
+    pub fn tree(&self) -> Tree {
+        let attributes: String = self
+            .attributes
+            .iter()
+            .map(|attribute| {
+                if attribute.args.is_empty() {
+                    format!("@{} ", attribute.name)
+                } else {
+                    format!("@{}({}) ", attribute.name, attribute.args.join(", "))
+                }
+            })
+            .collect();
+        let type_params = if self.type_params.is_empty() {
+            String::new()
+        } else {
+            format!("<{}>", self.type_params.join(", "))
+        };
+
+        Tree::body(
+            &format!(
+                "{}Function {}{}({}): {}",
+                attributes,
+                self.name,
+                type_params,
+                params_text(&self.params),
+                self.return_type
+            ),
+            &self.expressions,
+        )
+    }
+
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.attributes
+            .iter()
+            .any(|attribute| attribute.name == name)
+    }
 }
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Export {
     pub external_name: String,
     pub function_name: String,
+    /// Where the function name is written, which exports the compiler adds
+    /// don't have
+    pub info: Option<TokenInfo>,
 }
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ImportFunction {
     pub name: String,
     pub params: Vec<Param>,
     pub external_name: Vec<String>,
+    /// Text of the `///` comments before the import
+    pub doc: Option<String>,
+    /// Written inside an `import module { }` group, which the formatter keeps
+    pub grouped: bool,
 }
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ImportMemory {
+    /// Only needed when the module has more than one memory
+    pub name: Option<String>,
+    pub size: MemorySize,
+    pub external_name: Vec<String>,
+}
+
+/// `import table 10 funcref js.table`, a table of references from the host
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImportTable {
     pub size: i32,
+    pub element_type: String,
     pub external_name: Vec<String>,
 }
 
+/// Types of reference that tables can hold
+const TABLE_ELEMENT_TYPES: [&str; 2] = ["funcref", "externref"];
+
+/// `memory scratch 1`, a memory defined by the module rather than imported
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Memory {
+    pub name: Option<String>,
+    pub size: MemorySize,
+}
+
+/// The number of pages in a memory, written out or given by a constant
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemorySize {
+    Pages(i32),
+    Constant(String),
+}
+
+impl MemorySize {
+    /// The number of pages, once constants have been evaluated
+    pub fn pages(&self, constants: &[(String, i64)]) -> Result<i64, CompileError> {
+        match self {
+            MemorySize::Pages(pages) => Ok(i64::from(*pages)),
+            MemorySize::Constant(name) => find_constant(constants, name).ok_or_else(|| {
+                CompileError::new(format!("Unknown constant {} used as a memory size", name))
+            }),
+        }
+    }
+}
+
+impl Display for MemorySize {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            MemorySize::Pages(pages) => write!(f, "{}", pages),
+            MemorySize::Constant(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// `data "assets/logo.bin" as logo`, a file embedded in memory whose place
+/// there is `logo.offset` and `logo.length`
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Data {
+    pub name: String,
+    pub path: String,
+    /// Empty until `load_data` reads the file
+    pub bytes: Vec<u8>,
+}
+
+/// `type Seconds = f32;`, a name that can be used in place of another type
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TypeAlias {
+    pub name: String,
+    pub type_name: String,
+}
+
+/// `use std.math;`, a module from the standard library to compile in
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Use {
+    pub path: Vec<String>,
+}
+
+/// `interface Logger { fn log(n: i32); }`, functions a program is written
+/// against, which can either be imported or written in gwe
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Interface {
+    pub name: String,
+    pub functions: Vec<InterfaceFunction>,
+}
+
+/// One `fn log(n: i32);` of an interface, which returns void without a
+/// return type
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InterfaceFunction {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub return_type: String,
+}
+
+impl InterfaceFunction {
+    pub fn function_type(&self) -> String {
+        function_type(
+            &self
+                .params
+                .iter()
+                .map(|param| param.type_name.to_string())
+                .collect::<Vec<String>>(),
+            &self.return_type,
+        )
+    }
+}
+
+/// `const SIZE: i32 = 4 * 1024;`, a value worked out by the compiler and
+/// inlined wherever it's used
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Constant {
+    pub name: String,
+    pub type_name: String,
+    pub value: ConstantValue,
+}
+
+/// Types that constants can have
+const CONSTANT_TYPES: [&str; 2] = ["i32", "i64"];
+
+/// A constant's initializer, made of whole numbers, other constants, `+` and `*`
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConstantValue {
+    Number(i64),
+    Constant(String),
+    Addition(Box<ConstantValue>, Box<ConstantValue>),
+    Multiplication(Box<ConstantValue>, Box<ConstantValue>),
+}
+
+impl Display for ConstantValue {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ConstantValue::Number(value) => write!(f, "{}", value),
+            ConstantValue::Constant(name) => write!(f, "{}", name),
+            ConstantValue::Addition(left, right) => write!(f, "{} + {}", left, right),
+            ConstantValue::Multiplication(left, right) => {
+                let factor = |value: &ConstantValue| match value {
+                    ConstantValue::Addition(..) => format!("({})", value),
+                    _ => value.to_string(),
+                };
+                write!(f, "{} * {}", factor(left), factor(right))
+            }
+        }
+    }
+}
+
+pub fn find_constant(constants: &[(String, i64)], name: &str) -> Option<i64> {
+    constants
+        .iter()
+        .find(|(found, _)| found == name)
+        .map(|(_, value)| *value)
+}
+
+/// Works out the value of every constant, which can use constants declared
+/// anywhere in the file
+pub fn evaluate_constants(blocks: &[Block]) -> Result<Vec<(String, i64)>, CompileError> {
+    let aliases: Vec<TypeAlias> = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::TypeAlias(alias) => Some(alias.clone()),
+            _ => None,
+        })
+        .collect();
+    let constants: Vec<&Constant> = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Constant(constant) => Some(constant),
+            _ => None,
+        })
+        .collect();
+
+    fn evaluate(
+        constants: &[&Constant],
+        value: &ConstantValue,
+        evaluating: &mut Vec<String>,
+    ) -> Result<i64, CompileError> {
+        match value {
+            ConstantValue::Number(value) => Ok(*value),
+            ConstantValue::Constant(name) => {
+                if evaluating.contains(name) {
+                    return error_without_info(format!("Constant {} depends on itself", name));
+                }
+                let Some(constant) = constants.iter().find(|constant| constant.name == *name)
+                else {
+                    return error_without_info(format!("Unknown constant {}", name));
+                };
+
+                evaluating.push(name.to_string());
+                let value = evaluate(constants, &constant.value, evaluating)?;
+                evaluating.pop();
+                Ok(value)
+            }
+            ConstantValue::Addition(left, right) => evaluate(constants, left, evaluating)?
+                .checked_add(evaluate(constants, right, evaluating)?)
+                .ok_or_else(|| CompileError::new(format!("{} overflows", value))),
+            ConstantValue::Multiplication(left, right) => evaluate(constants, left, evaluating)?
+                .checked_mul(evaluate(constants, right, evaluating)?)
+                .ok_or_else(|| CompileError::new(format!("{} overflows", value))),
+        }
+    }
+
+    constants
+        .iter()
+        .map(|constant| {
+            let type_name = resolve_type(&aliases, &constant.type_name);
+            if !CONSTANT_TYPES.contains(&type_name.as_str()) {
+                return error_without_info(format!(
+                    "Constants are i32 or i64, but {} is {}",
+                    constant.name, constant.type_name
+                ));
+            }
+
+            let value = evaluate(
+                &constants,
+                &constant.value,
+                &mut vec![constant.name.clone()],
+            )?;
+            if type_name == "i32" && i32::try_from(value).is_err() {
+                return error_without_info(format!(
+                    "Constant {} is {}, which doesn't fit in an i32",
+                    constant.name, value
+                ));
+            }
+
+            Ok((constant.name.to_string(), value))
+        })
+        .collect()
+}
+
+/// `test "name" { ... }`, which is only compiled and run by `gwe test`
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Test {
+    pub name: String,
+    pub expressions: Vec<Expression>,
+}
+
+impl Test {
+    /// The test body as a function that takes nothing and returns nothing
+    pub fn as_function(&self, function_name: &str) -> Function {
+        Function {
+            name: function_name.to_string(),
+            expressions: self.expressions.clone(),
+            params: vec![],
+            return_type: String::from("void"),
+            doc: None,
+            attributes: vec![],
+            type_params: vec![],
+        }
+    }
+}
+
+/// Comments and blank lines, which the formatter writes back out where they
+/// were and everything else ignores
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Trivia {
+    /// `// text` on a line of its own
+    Comment(String),
+    /// `// text` after code on the same line
+    TrailingComment(String),
+    /// One or more empty lines
+    BlankLine,
+}
+
+impl Trivia {
+    fn from_token(token: &Token) -> Option<Trivia> {
+        match token {
+            Token::Comment {
+                body,
+                trailing: true,
+            } => Some(Trivia::TrailingComment(body.to_string())),
+            Token::Comment { body, .. } => Some(Trivia::Comment(body.to_string())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Block {
     Function(Function),
     Export(Export),
     ImportFunction(ImportFunction),
     ImportMemory(ImportMemory),
+    ImportTable(ImportTable),
+    Memory(Memory),
+    Data(Data),
+    Constant(Constant),
+    TypeAlias(TypeAlias),
+    Use(Use),
+    Interface(Interface),
+    Test(Test),
+    /// Only kept by `parse_with_trivia`
+    Trivia(Trivia),
 }
 
-pub fn into_blocks(body: String) -> Vec<String> {
+fn params_text(params: &[Param]) -> String {
+    params
+        .iter()
+        .map(|param| format!("{}: {}", param.name, param.type_name))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl Block {
+    /// The block and everything inside it as an indented tree
+    pub fn tree(&self) -> Tree {
+        match self {
+            Block::Function(function) => function.tree(),
+            Block::Export(export) => Tree::leaf(format!(
+                "Export {} as {}",
+                export.function_name, export.external_name
+            )),
+            Block::ImportFunction(import) => Tree::leaf(format!(
+                "ImportFunction {}({}) from {}",
+                import.name,
+                params_text(&import.params),
+                import.external_name.join(".")
+            )),
+            Block::ImportMemory(import) => Tree::leaf(format!(
+                "ImportMemory {}{} from {}",
+                import
+                    .name
+                    .as_ref()
+                    .map(|name| format!("{} ", name))
+                    .unwrap_or_default(),
+                import.size,
+                import.external_name.join(".")
+            )),
+            Block::ImportTable(import) => Tree::leaf(format!(
+                "ImportTable {} {} from {}",
+                import.size,
+                import.element_type,
+                import.external_name.join(".")
+            )),
+            Block::Memory(memory) => Tree::leaf(format!(
+                "Memory {}{}",
+                memory
+                    .name
+                    .as_ref()
+                    .map(|name| format!("{} ", name))
+                    .unwrap_or_default(),
+                memory.size
+            )),
+            Block::Data(data) => Tree::leaf(format!("Data {} from {:?}", data.name, data.path)),
+            Block::Constant(constant) => Tree::leaf(format!(
+                "Constant {}: {} = {}",
+                constant.name, constant.type_name, constant.value
+            )),
+            Block::TypeAlias(alias) => {
+                Tree::leaf(format!("TypeAlias {} = {}", alias.name, alias.type_name))
+            }
+            Block::Use(module) => Tree::leaf(format!("Use {}", module.path.join("."))),
+            Block::Interface(interface) => Tree::new(
+                format!("Interface {}", interface.name),
+                interface
+                    .functions
+                    .iter()
+                    .map(|function| {
+                        Tree::leaf(format!(
+                            "{}({}): {}",
+                            function.name,
+                            params_text(&function.params),
+                            function.return_type
+                        ))
+                    })
+                    .collect(),
+            ),
+            Block::Test(test) => Tree::body(&format!("Test {:?}", test.name), &test.expressions),
+            Block::Trivia(trivia) => Tree::leaf(format!("Trivia {:?}", trivia)),
+        }
+    }
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.tree())
+    }
+}
+
+/// The name of the type of functions taking `params` and returning `return_type`
+pub fn function_type(params: &[String], return_type: &str) -> String {
+    format!("fn({}): {}", params.join(", "), return_type)
+}
+
+/// The params and return type of a function type like `fn(i32, f32): i32`
+pub fn split_function_type(type_name: &str) -> Option<(Vec<String>, String)> {
+    let inner = type_name.strip_prefix("fn(")?;
+    let mut params: Vec<String> = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (index, character) in inner.char_indices() {
+        match character {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ',' if depth == 0 => {
+                params.push(inner[start..index].trim().to_string());
+                start = index + 1;
+            }
+            ')' => {
+                let last = inner[start..index].trim();
+                if !last.is_empty() {
+                    params.push(last.to_string());
+                }
+
+                let return_type = inner[index + 1..].strip_prefix(": ")?;
+                return Some((params, return_type.to_string()));
+            }
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// Follows aliases until a type that isn't an alias, stopping if they loop
+pub fn resolve_type(aliases: &[TypeAlias], type_name: &str) -> String {
+    if let Some((params, return_type)) = split_function_type(type_name) {
+        return function_type(
+            &params
+                .iter()
+                .map(|param| resolve_type(aliases, param))
+                .collect::<Vec<String>>(),
+            &resolve_type(aliases, &return_type),
+        );
+    }
+
+    let mut resolved = type_name;
+
+    for _ in 0..aliases.len() {
+        match aliases.iter().find(|alias| alias.name == resolved) {
+            Some(alias) => resolved = &alias.type_name,
+            None => break,
+        }
+    }
+
+    resolved.to_string()
+}
+
+/// Splits a file into blocks, along with the line each block starts on
+pub fn into_blocks_with_lines(body: String) -> Vec<(i32, String)> {
     let mut current_block: Vec<String> = Vec::new();
-    let mut blocks: Vec<Vec<String>> = vec![];
+    let mut current_start = 0;
+    let mut blocks: Vec<(i32, String)> = vec![];
 
-    for line in body.split('\n') {
-        if !line.trim().is_empty() {
-            current_block.push(line.to_string());
-            if line.starts_with("export") || line.starts_with("import") || line == "}" {
-                blocks.push(current_block.clone());
-                current_block.clear();
+    for (line_number, line) in normalize_line_endings(&body).split('\n').enumerate() {
+        if line.trim().is_empty() {
+            // blank lines are kept inside a block so that line numbers stay accurate
+            if !current_block.is_empty() {
+                current_block.push(String::new());
             }
+            continue;
+        }
+
+        if current_block.is_empty() {
+            current_start = line_number as i32;
+        }
+
+        current_block.push(line.to_string());
+        if line.starts_with("export")
+            // an import group runs until its closing }
+            || (line.starts_with("import") && !line.trim_end().ends_with('{'))
+            || line.starts_with("type ")
+            || line.starts_with("memory ")
+            || line.starts_with("data ")
+            || line.starts_with("const ")
+            || line.starts_with("use ")
+            // an interface can be written on one line
+            || (line.starts_with("interface ") && line.trim_end().ends_with('}'))
+            || line.split("//").next().map(str::trim_end) == Some("}")
+        {
+            blocks.push((current_start, current_block.join("\n")));
+            current_block.clear();
         }
     }
 
     if !current_block.is_empty() {
-        blocks.push(current_block.clone());
+        blocks.push((
+            current_start,
+            current_block.join("\n").trim_end().to_string(),
+        ));
     }
 
-    blocks.iter().map(|b| b.join("\n")).collect::<Vec<String>>()
+    blocks
+}
+
+pub fn into_blocks(body: String) -> Vec<String> {
+    into_blocks_with_lines(body)
+        .into_iter()
+        .map(|(_, block)| block)
+        .collect()
 }
 
 fn parse_params(
     tokens: &mut Iter<'_, FullyQualifiedToken>,
     entry_fqt: FullyQualifiedToken,
-) -> Result<Vec<Param>, String> {
-    let param_name: &mut Option<String> = &mut None;
-
-    let mut params: Vec<Param> = vec![];
+) -> Result<Vec<Param>, CompileError> {
+    take_argument_list(tokens)?
+        .iter()
+        .map(|param_tokens| {
+            if let Some(fqt) = param_tokens.first() {
+                check_name(fqt, "param")?;
+            }
 
-    while let token = tokens.next().map(|fqt| &fqt.token) {
-        match token {
-            Some(Token::RightParen) => break,
-            Some(Token::Identifier { body }) => match param_name {
-                Some(n) => {
-                    params.push(Param {
-                        name: n.to_string(),
-                        type_name: body.to_string(),
-                    });
-
-                    param_name.take();
+            match param_tokens
+                .iter()
+                .map(|fqt| &fqt.token)
+                .collect::<Vec<&Token>>()
+                .as_slice()
+            {
+                [Token::Identifier { body: name }, Token::Colon, Token::Identifier { body: type_name }] => {
+                    Ok(Param {
+                        name: name.to_string(),
+                        type_name: type_name.to_string(),
+                        info: Some(param_tokens[0].start()),
+                    })
                 }
-                None => {
-                    param_name.replace(body.to_string());
+                [Token::Identifier { body: name }, Token::Colon, Token::Fn, ..] => {
+                    Ok(Param {
+                        name: name.to_string(),
+                        type_name: parse_function_type(&param_tokens[2..])?,
+                        info: Some(param_tokens[0].start()),
+                    })
                 }
-            },
-            Some(Token::Comma) => {
-                param_name.take();
-            }
-            Some(Token::Colon) => (),
-            Some(value) => {
-                return Err(format!(
-                    "Failed parsing params, got unexpected token {}",
-                    value
-                ))
+                [Token::Identifier { body: name }] | [Token::Identifier { body: name }, Token::Colon] => {
+                    error_with_info(
+                        format!("Failed to find type for param {}", name),
+                        &entry_fqt,
+                    )
+                }
+                tokens => error_without_info(format!(
+                    "Failed parsing params, got unexpected tokens {}",
+                    tokens
+                        .iter()
+                        .map(|token| token.to_string())
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                )),
             }
-            None => return Err(String::from("Failed parsing params")),
+        })
+        .collect()
+}
+
+/// A type written in a function type, which can itself be a function type
+fn parse_type(tokens: &[FullyQualifiedToken]) -> Result<String, CompileError> {
+    match tokens {
+        [fqt] => match &fqt.token {
+            Token::Identifier { body } => Ok(body.to_string()),
+            token => error_with_info(format!("Expected a type but got {}", token), fqt),
+        },
+        [fqt, ..] if fqt.token == Token::Fn => parse_function_type(tokens),
+        [fqt, ..] => error_with_info(format!("Expected a type but got {}", fqt.token), fqt),
+        [] => error_without_info(String::from("Expected a type but got nothing")),
+    }
+}
+
+/// `fn(i32, f32): i32`, the type of params that take a function
+fn parse_function_type(tokens: &[FullyQualifiedToken]) -> Result<String, CompileError> {
+    let mut tokens = tokens.iter();
+
+    // fn
+    tokens.next();
+
+    match tokens.next() {
+        Some(fqt) if fqt.token == Token::LeftParen => (),
+        Some(fqt) => {
+            return error_with_info(
+                format!(
+                    "Expected ( after fn in a function type but got {}",
+                    fqt.token
+                ),
+                fqt,
+            )
+        }
+        None => {
+            return error_without_info(String::from(
+                "Expected ( after fn in a function type but got nothing",
+            ))
         }
     }
-    if let Some(name) = param_name {
-        return error_with_info(
-            format!("Failed to find type for param {}", name),
-            &entry_fqt,
-        );
+
+    let params = take_argument_list(&mut tokens)?
+        .iter()
+        .map(|param_tokens| parse_type(param_tokens))
+        .collect::<Result<Vec<String>, CompileError>>()?;
+
+    match tokens.next() {
+        Some(fqt) if fqt.token == Token::Colon => (),
+        _ => {
+            return error_without_info(String::from(
+                "Expected : and a return type after the params of a function type",
+            ))
+        }
+    }
+
+    let return_type = parse_type(tokens.as_slice())?;
+
+    Ok(function_type(&params, &return_type))
+}
+
+/// Parses `{ ... }` up to the end of the block, for a function or test named `name`
+fn parse_block_body(
+    tokens: &mut Iter<'_, FullyQualifiedToken>,
+    function_name: &str,
+    params: &[Param],
+) -> Result<Vec<Expression>, CompileError> {
+    // {
+    match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::LeftBracket => (),
+            token => return error_with_info(format!("Expected {{ but got {}", token), fqt),
+        },
+        None => return error_without_info(String::from("Expected { but got nothing")),
+    }
+
+    let mut original_tokens: Vec<FullyQualifiedToken> = vec![];
+
+    for token in tokens.clone() {
+        original_tokens.push(token.clone());
+    }
+
+    // cut off }
+    match original_tokens.pop() {
+        Some(FullyQualifiedToken {
+            token: Token::RightBracket,
+            info: _,
+        }) => (),
+        Some(fqt) => {
+            return error_with_info(
+                format!(
+                    "Expected }} at the end of {} but got {}",
+                    function_name, fqt.token
+                ),
+                &fqt,
+            )
+        }
+        None => {
+            return error_without_info(format!(
+                "Expected }} at the end of {} but got nothing",
+                function_name
+            ))
+        }
+    }
+
+    parse_statements(original_tokens, vec![], params.to_vec())
+}
+
+fn parse_function(tokens: Vec<FullyQualifiedToken>) -> Result<Function, CompileError> {
+    parse_function_in_scope(tokens, &[])
+}
+
+/// A `fn` inside another function, which sees the params and locals around it
+pub fn parse_closure(
+    tokens: Vec<FullyQualifiedToken>,
+    previous_expressions: &[Expression],
+    local_params: &[Param],
+) -> Result<Function, CompileError> {
+    let scope: Vec<Param> = local_params
+        .iter()
+        .cloned()
+        .chain(
+            previous_expressions
+                .iter()
+                .filter_map(|expression| match expression {
+                    Expression::LocalAssign {
+                        name,
+                        type_name,
+                        info,
+                        ..
+                    } => Some(Param {
+                        name: name.to_string(),
+                        type_name: type_name.to_string(),
+                        info: info.clone(),
+                    }),
+                    Expression::Closure { function } => Some(Param {
+                        name: function.name.to_string(),
+                        type_name: function.function_type(),
+                        info: None,
+                    }),
+                    _ => None,
+                }),
+        )
+        .collect();
+
+    let function = parse_function_in_scope(tokens, &scope)?;
+
+    if !function.type_params.is_empty() {
+        return error_without_info(format!("Closure {} can't have type params", function.name));
     }
 
-    Ok(params)
+    Ok(function)
 }
 
-fn parse_function(tokens: Vec<FullyQualifiedToken>) -> Result<Function, String> {
+/// A function whose body can also use the names in `scope`
+fn parse_function_in_scope(
+    tokens: Vec<FullyQualifiedToken>,
+    scope: &[Param],
+) -> Result<Function, CompileError> {
     let mut tokens = tokens.iter();
 
     // fn
-    let fn_token = tokens.next().unwrap();
+    let fn_token = match tokens.next() {
+        Some(fqt) => fqt,
+        None => return error_without_info(String::from("Expected fn but got nothing")),
+    };
+
+    if let Some(fqt) = tokens.clone().next() {
+        check_name(fqt, "function")?;
+    }
 
     let function_name = match tokens.next().map(|fqt| &fqt.token) {
         Some(Token::Identifier { body }) => body,
@@ -138,20 +917,22 @@ fn parse_function(tokens: Vec<FullyQualifiedToken>) -> Result<Function, String>
         }
     };
 
-    let open_parens = tokens.next();
+    let type_params = if tokens.clone().next().map(|fqt| &fqt.token) == Some(&Token::LessThan) {
+        tokens.next();
+        parse_type_params(&mut tokens)?
+    } else {
+        vec![]
+    };
 
-    match open_parens.map(|fqt| &fqt.token) {
-        Some(Token::LeftParen) => (),
-        Some(token) => {
-            return error_with_info(
-                format!("Expected parens but got {}", token),
-                open_parens.unwrap(),
-            )
-        }
-        None => return Err("Expected parens but got nothing".to_string()),
-    }
+    let open_parens = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::LeftParen => fqt,
+            token => return error_with_info(format!("Expected parens but got {}", token), fqt),
+        },
+        None => return error_without_info("Expected parens but got nothing".to_string()),
+    };
 
-    let params = match parse_params(&mut tokens, open_parens.unwrap().clone()) {
+    let params = match parse_params(&mut tokens, open_parens.clone()) {
         Err(error) => return Err(error),
         Ok(params) => params,
     };
@@ -169,7 +950,7 @@ fn parse_function(tokens: Vec<FullyQualifiedToken>) -> Result<Function, String>
                 )
             }
         },
-        None => return Err(String::from("Expected colon but got nothing")),
+        None => return error_without_info(String::from("Expected colon but got nothing")),
     }
 
     let return_type = match tokens.next() {
@@ -182,54 +963,61 @@ fn parse_function(tokens: Vec<FullyQualifiedToken>) -> Result<Function, String>
                 )
             }
         },
-        None => return Err(String::from("Expected return type name, but got nothing")),
+        None => {
+            return error_without_info(String::from("Expected return type name, but got nothing"))
+        }
     };
 
-    // {
-    match tokens.next() {
-        Some(fqt) => match &fqt.token {
-            Token::LeftBracket => (),
-            token => return error_with_info(format!("Expected {{ but got {}", token), fqt),
-        },
-        None => return Err(String::from("Expected { but got nothing")),
-    }
+    let visible: Vec<Param> = params.iter().chain(scope).cloned().collect();
+    let expressions = parse_block_body(&mut tokens, function_name, &visible)?;
 
-    let mut expressions: Vec<Expression> = vec![];
-    let mut original_tokens: Vec<FullyQualifiedToken> = vec![];
+    Ok(Function {
+        name: function_name.to_string(),
+        expressions,
+        params,
+        return_type,
+        doc: None,
+        attributes: vec![],
+        type_params,
+    })
+}
 
-    for token in tokens.clone() {
-        original_tokens.push(token.clone());
-    }
-
-    // cut off }
-    original_tokens.truncate(original_tokens.len() - 1);
-
-    let tokens_split_by_semicolon: Vec<Vec<FullyQualifiedToken>> =
-        split_by_semicolon_within_brackets(original_tokens);
+/// The names between `<` and `>` after a function's name
+fn parse_type_params(
+    tokens: &mut Iter<'_, FullyQualifiedToken>,
+) -> Result<Vec<String>, CompileError> {
+    let mut type_params: Vec<String> = vec![];
 
-    for expression_tokens in tokens_split_by_semicolon.iter() {
-        if expression_tokens.is_empty() {
-            continue;
+    loop {
+        match tokens.next() {
+            Some(fqt) => match &fqt.token {
+                Token::Identifier { body } => type_params.push(body.to_string()),
+                token => {
+                    return error_with_info(
+                        format!("Expected a type parameter but got {}", token),
+                        fqt,
+                    )
+                }
+            },
+            None => {
+                return error_without_info(String::from(
+                    "Expected a type parameter but got nothing",
+                ))
+            }
         }
-        match parse_expression(
-            &mut expression_tokens.iter(),
-            expressions.clone(),
-            params.clone(),
-        ) {
-            Ok(exp) => expressions.push(exp),
-            Err(error) => return Err(error),
+
+        match tokens.next() {
+            Some(fqt) => match &fqt.token {
+                Token::Comma => (),
+                Token::GreaterThan => return Ok(type_params),
+                token => return error_with_info(format!("Expected , or > but got {}", token), fqt),
+            },
+            None => return error_without_info(String::from("Expected > but got nothing")),
         }
     }
-
-    Ok(Function {
-        name: function_name.to_string(),
-        expressions,
-        params,
-        return_type,
-    })
 }
 
-fn parse_export(tokens: Vec<FullyQualifiedToken>) -> Result<Export, String> {
+fn parse_export(tokens: Vec<FullyQualifiedToken>) -> Result<Export, CompileError> {
     let mut tokens = tokens.iter();
     tokens.next();
 
@@ -243,13 +1031,13 @@ fn parse_export(tokens: Vec<FullyQualifiedToken>) -> Result<Export, String> {
                 )
             }
         },
-        None => return Err(String::from("Expected external name in export")),
+        None => return error_without_info(String::from("Expected external name in export")),
     };
 
-    let function_name = match tokens.next() {
-        None => return Err(String::from("Expected function name in export")),
+    let (function_name, info) = match tokens.next() {
+        None => return error_without_info(String::from("Expected function name in export")),
         Some(fqt) => match &fqt.token {
-            Token::Identifier { body } => body,
+            Token::Identifier { body } => (body, fqt.start()),
             token => {
                 return error_with_info(
                     format!("Expected function name in export, got {}", token),
@@ -262,10 +1050,11 @@ fn parse_export(tokens: Vec<FullyQualifiedToken>) -> Result<Export, String> {
     Ok(Export {
         external_name: external_name.to_string(),
         function_name: function_name.to_string(),
+        info: Some(info),
     })
 }
 
-fn parse_import_function(tokens: Vec<FullyQualifiedToken>) -> Result<ImportFunction, String> {
+fn parse_import_function(tokens: Vec<FullyQualifiedToken>) -> Result<ImportFunction, CompileError> {
     let mut tokens = tokens.iter();
 
     // import
@@ -273,6 +1062,10 @@ fn parse_import_function(tokens: Vec<FullyQualifiedToken>) -> Result<ImportFunct
     // fn
     tokens.next();
 
+    if let Some(fqt) = tokens.clone().next() {
+        check_name(fqt, "function")?;
+    }
+
     let name = match tokens.next() {
         Some(fqt) => match &fqt.token {
             Token::Identifier { body } => body,
@@ -283,53 +1076,385 @@ fn parse_import_function(tokens: Vec<FullyQualifiedToken>) -> Result<ImportFunct
                 )
             }
         },
-        None => return Err(String::from("Expected function name in export")),
+        None => return error_without_info(String::from("Expected function name in export")),
     };
 
-    let open_parens = tokens.next();
+    let open_parens = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::LeftParen => fqt,
+            token => return error_with_info(format!("Expected parens but got {}", token), fqt),
+        },
+        None => return error_without_info("Expected parens but got nothing".to_string()),
+    };
 
-    match open_parens.map(|fqt| &fqt.token) {
-        Some(Token::LeftParen) => (),
-        Some(token) => {
+    let params = match parse_params(&mut tokens, open_parens.clone()) {
+        Err(error) => return Err(error),
+        Ok(params) => params,
+    };
+
+    let external_name = parse_external_name(tokens)?;
+
+    Ok(ImportFunction {
+        name: name.to_string(),
+        params,
+        external_name,
+        doc: None,
+        grouped: false,
+    })
+}
+
+/// The dot separated path an import comes from, like `console.log`. Quoted
+/// segments can hold anything else, like `"./env.js".log`
+fn parse_external_name(tokens: Iter<'_, FullyQualifiedToken>) -> Result<Vec<String>, CompileError> {
+    let mut external_name: Vec<String> = vec![];
+
+    for fqt in tokens {
+        match &fqt.token {
+            Token::Identifier { body } | Token::Text { body } => {
+                external_name.push(body.to_string())
+            }
+            Token::Dot => (),
+            other => {
+                return error_with_info(
+                    format!("Expected dot, identifier or quoted name, got {}", other),
+                    fqt,
+                )
+            }
+        }
+    }
+
+    Ok(external_name)
+}
+
+fn is_import_group(tokens: &[FullyQualifiedToken]) -> bool {
+    matches!(
+        tokens
+            .iter()
+            .take(3)
+            .map(|fqt| &fqt.token)
+            .collect::<Vec<&Token>>()
+            .as_slice(),
+        [
+            Token::Import,
+            Token::Identifier { .. } | Token::Text { .. },
+            Token::LeftBracket
+        ]
+    )
+}
+
+/// `import console { fn log(n: i32); fn warn(n: i32); }`, several functions
+/// imported from one host module, each by its own name
+fn parse_import_group(
+    tokens: Vec<FullyQualifiedToken>,
+) -> Result<Vec<ImportFunction>, CompileError> {
+    let mut tokens = tokens.iter();
+
+    // import
+    tokens.next();
+
+    let module = match tokens.next().map(|fqt| &fqt.token) {
+        Some(Token::Identifier { body }) | Some(Token::Text { body }) => body,
+        _ => return error_without_info(String::from("Expected a module name in import")),
+    };
+
+    // {
+    tokens.next();
+
+    let declarations = take_until_closing(&mut tokens, Token::LeftBracket, Token::RightBracket)?;
+
+    if let Some(fqt) = tokens.next() {
+        return error_with_info(
+            format!(
+                "Unexpected token {} after the end of an import group",
+                fqt.token
+            ),
+            fqt,
+        );
+    }
+
+    split_top_level(declarations, Token::Semicolon)
+        .into_iter()
+        .map(|declaration| {
+            declaration
+                .into_iter()
+                .filter(|fqt| !matches!(fqt.token, Token::Comment { .. }))
+                .collect::<Vec<FullyQualifiedToken>>()
+        })
+        .filter(|declaration| !declaration.is_empty())
+        .map(|declaration| parse_grouped_import(module, declaration))
+        .collect()
+}
+
+/// One `fn name(params)` of an import group, imported as `module.name`
+fn parse_grouped_import(
+    module: &str,
+    declaration: Vec<FullyQualifiedToken>,
+) -> Result<ImportFunction, CompileError> {
+    let doc_lines: Vec<String> = declaration
+        .iter()
+        .map_while(|fqt| match &fqt.token {
+            Token::DocComment { body } => Some(body.to_string()),
+            _ => None,
+        })
+        .collect();
+    let mut tokens = declaration[doc_lines.len()..].iter();
+
+    match tokens.next() {
+        Some(fqt) if fqt.token == Token::Fn => (),
+        Some(fqt) => {
             return error_with_info(
-                format!("Expected parens but got {}", token),
-                open_parens.unwrap(),
+                format!(
+                    "Expected fn in the import group {}, got {}",
+                    module, fqt.token
+                ),
+                fqt,
             )
         }
-        None => return Err("Expected parens but got nothing".to_string()),
+        None => {
+            return error_without_info(format!(
+                "Expected fn in the import group {} after a doc comment",
+                module
+            ))
+        }
     }
 
-    let params = match parse_params(&mut tokens, open_parens.unwrap().clone()) {
-        Err(error) => return Err(error),
-        Ok(params) => params,
+    if let Some(fqt) = tokens.clone().next() {
+        check_name(fqt, "function")?;
+    }
+
+    let name = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Identifier { body } => body,
+            token => {
+                return error_with_info(
+                    format!("Expected function name in import, got {}", token),
+                    fqt,
+                )
+            }
+        },
+        None => return error_without_info(String::from("Expected function name in import")),
     };
 
-    let mut external_name: Vec<String> = vec![];
+    let open_parens = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::LeftParen => fqt,
+            token => return error_with_info(format!("Expected parens but got {}", token), fqt),
+        },
+        None => return error_without_info("Expected parens but got nothing".to_string()),
+    };
 
-    while let fqt = tokens.next() {
-        match fqt {
-            Some(token) => match &token.token {
-                Token::Identifier { body } => external_name.push(body.to_string()),
-                Token::Dot => (),
-                other => {
-                    return error_with_info(
-                        format!("Expected dot or identifier, got {}", other),
-                        token,
-                    )
-                }
-            },
-            None => break,
-        }
+    let params = parse_params(&mut tokens, open_parens.clone())?;
+
+    if let Some(fqt) = tokens.next() {
+        return error_with_info(
+            format!(
+                "Expected ; after the import of {} but got {}",
+                name, fqt.token
+            ),
+            fqt,
+        );
     }
 
     Ok(ImportFunction {
         name: name.to_string(),
         params,
-        external_name,
+        external_name: vec![module.to_string(), name.to_string()],
+        doc: (!doc_lines.is_empty()).then(|| doc_lines.join("\n")),
+        grouped: true,
+    })
+}
+
+fn parse_interface(tokens: Vec<FullyQualifiedToken>) -> Result<Interface, CompileError> {
+    let mut tokens = tokens.iter();
+
+    // interface
+    let interface_token = match tokens.next() {
+        Some(fqt) => fqt,
+        None => return error_without_info(String::from("Expected interface but got nothing")),
+    };
+
+    let name = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Identifier { body } => body.to_string(),
+            token => {
+                return error_with_info(
+                    format!("Expected an interface name but got {}", token),
+                    fqt,
+                )
+            }
+        },
+        None => {
+            return error_with_info(
+                String::from("Expected an interface name but got nothing"),
+                interface_token,
+            )
+        }
+    };
+
+    if let Some(error) = tokens
+        .next()
+        .filter(|fqt| fqt.token != Token::LeftBracket)
+        .map(|fqt| {
+            CompileError::with_info(
+                format!("Expected {{ after interface {} but got {}", name, fqt.token),
+                fqt.info.clone(),
+            )
+        })
+    {
+        return Err(error);
+    }
+
+    let declarations = take_until_closing(&mut tokens, Token::LeftBracket, Token::RightBracket)?;
+
+    if let Some(fqt) = tokens.next() {
+        return error_with_info(
+            format!(
+                "Unexpected token {} after the end of interface {}",
+                fqt.token, name
+            ),
+            fqt,
+        );
+    }
+
+    let functions = split_top_level(declarations, Token::Semicolon)
+        .into_iter()
+        .map(|declaration| {
+            declaration
+                .into_iter()
+                .filter(|fqt| !matches!(fqt.token, Token::Comment { .. }))
+                .collect::<Vec<FullyQualifiedToken>>()
+        })
+        .filter(|declaration| !declaration.is_empty())
+        .map(|declaration| parse_interface_function(&name, declaration))
+        .collect::<Result<Vec<InterfaceFunction>, CompileError>>()?;
+
+    Ok(Interface { name, functions })
+}
+
+/// One `fn name(params): type` of an interface
+fn parse_interface_function(
+    interface: &str,
+    declaration: Vec<FullyQualifiedToken>,
+) -> Result<InterfaceFunction, CompileError> {
+    let mut tokens = declaration.iter();
+
+    match tokens.next() {
+        Some(fqt) if fqt.token == Token::Fn => (),
+        Some(fqt) => {
+            return error_with_info(
+                format!("Expected fn in interface {}, got {}", interface, fqt.token),
+                fqt,
+            )
+        }
+        None => return error_without_info(format!("Expected fn in interface {}", interface)),
+    }
+
+    if let Some(fqt) = tokens.clone().next() {
+        check_name(fqt, "function")?;
+    }
+
+    let name = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Identifier { body } => body.to_string(),
+            token => {
+                return error_with_info(
+                    format!("Expected function name in interface, got {}", token),
+                    fqt,
+                )
+            }
+        },
+        None => return error_without_info(String::from("Expected function name in interface")),
+    };
+
+    let open_parens = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::LeftParen => fqt,
+            token => return error_with_info(format!("Expected parens but got {}", token), fqt),
+        },
+        None => return error_without_info("Expected parens but got nothing".to_string()),
+    };
+
+    let params = parse_params(&mut tokens, open_parens.clone())?;
+
+    let return_type = match (tokens.next(), tokens.next()) {
+        (None, _) => String::from("void"),
+        (Some(colon), Some(fqt)) if colon.token == Token::Colon => match &fqt.token {
+            Token::Identifier { body } => body.to_string(),
+            token => {
+                return error_with_info(
+                    format!("Expected return type name, but got {}", token),
+                    fqt,
+                )
+            }
+        },
+        (Some(fqt), _) => {
+            return error_with_info(
+                format!(
+                    "Expected ; after {} in interface {} but got {}",
+                    name, interface, fqt.token
+                ),
+                fqt,
+            )
+        }
+    };
+
+    if let Some(fqt) = tokens.next() {
+        return error_with_info(
+            format!(
+                "Expected ; after {} in interface {} but got {}",
+                name, interface, fqt.token
+            ),
+            fqt,
+        );
+    }
+
+    Ok(InterfaceFunction {
+        name,
+        params,
+        return_type,
     })
 }
 
-fn parse_import_memory(tokens: Vec<FullyQualifiedToken>) -> Result<ImportMemory, String> {
+/// The optional name and the size in pages after `memory`. A size can be a
+/// constant, so an identifier is only the name when a size follows it
+fn parse_memory_size(
+    tokens: &mut Iter<'_, FullyQualifiedToken>,
+) -> Result<(Option<String>, MemorySize), CompileError> {
+    let mut ahead = tokens.clone();
+    let name = match (
+        ahead.next().map(|fqt| &fqt.token),
+        ahead.next().map(|fqt| &fqt.token),
+        ahead.next().map(|fqt| &fqt.token),
+    ) {
+        (Some(Token::Identifier { body }), Some(Token::Number { .. }), _)
+        | (Some(Token::Identifier { body }), Some(Token::Identifier { .. }), None)
+        | (
+            Some(Token::Identifier { body }),
+            Some(Token::Identifier { .. }),
+            Some(Token::Identifier { .. }),
+        ) => {
+            tokens.next();
+            Some(body.to_string())
+        }
+        _ => None,
+    };
+
+    let size = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Number { body } => match body.parse::<i32>() {
+                Ok(v) => MemorySize::Pages(v),
+                Err(err) => return error_with_info(err.to_string(), fqt),
+            },
+            Token::Identifier { body } => MemorySize::Constant(body.to_string()),
+            token => return error_with_info(format!("Unexpected token {} in memory", token), fqt),
+        },
+        None => return error_without_info(String::from("Expected memory size but got nothing")),
+    };
+
+    Ok((name, size))
+}
+
+fn parse_import_memory(tokens: Vec<FullyQualifiedToken>) -> Result<ImportMemory, CompileError> {
     let mut tokens = tokens.iter();
 
     // import
@@ -337,74 +1462,1137 @@ fn parse_import_memory(tokens: Vec<FullyQualifiedToken>) -> Result<ImportMemory,
     // memory
     tokens.next();
 
+    let (name, size) = parse_memory_size(&mut tokens)?;
+
+    let external_name = parse_external_name(tokens)?;
+
+    Ok(ImportMemory {
+        name,
+        size,
+        external_name,
+    })
+}
+
+fn parse_import_table(tokens: Vec<FullyQualifiedToken>) -> Result<ImportTable, CompileError> {
+    let mut tokens = tokens.iter();
+
+    // import
+    tokens.next();
+    // table
+    tokens.next();
+
     let size = match tokens.next() {
         Some(fqt) => match &fqt.token {
             Token::Number { body } => match body.parse::<i32>() {
                 Ok(v) => v,
-                Err(err) => return Err(err.to_string()),
+                Err(err) => return error_with_info(err.to_string(), fqt),
             },
-            token => return error_with_info(format!("Unexpected token {} in import", token), fqt),
+            token => return error_with_info(format!("Unexpected token {} in table", token), fqt),
         },
-        None => return Err(String::from("Expected memory size but got nothing")),
+        None => return error_without_info(String::from("Expected table size but got nothing")),
     };
 
-    let mut external_name: Vec<String> = vec![];
-
-    while let fqt = tokens.next() {
-        match fqt {
-            Some(token) => match &token.token {
-                Token::Identifier { body } => external_name.push(body.to_string()),
-                Token::Dot => (),
-                other => {
-                    return error_with_info(
-                        format!("Expected dot or identifier, got {}", other),
-                        token,
-                    )
-                }
-            },
-            None => break,
+    let element_type = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Identifier { body } if TABLE_ELEMENT_TYPES.contains(&body.as_str()) => {
+                body.to_string()
+            }
+            token => {
+                return error_with_info(
+                    format!("Tables hold funcref or externref, not {}", token),
+                    fqt,
+                )
+            }
+        },
+        None => {
+            return error_without_info(String::from(
+                "Expected funcref or externref but got nothing",
+            ))
         }
-    }
+    };
 
-    Ok(ImportMemory {
+    let external_name = parse_external_name(tokens)?;
+
+    Ok(ImportTable {
         size,
+        element_type,
         external_name,
     })
 }
 
-pub fn parse_block(body: String) -> Result<Block, String> {
-    let tokens = tokenize(body);
+fn parse_memory(tokens: Vec<FullyQualifiedToken>) -> Result<Memory, CompileError> {
+    let mut tokens = tokens.iter();
 
-    match tokens.first().map(|fqt| &fqt.token) {
-        Some(Token::Fn) => parse_function(tokens).map(Block::Function),
-        Some(Token::Export) => parse_export(tokens).map(Block::Export),
-        Some(Token::Import) => match tokens.get(1).map(|fqt| &fqt.token) {
-            Some(Token::Fn) => parse_import_function(tokens).map(Block::ImportFunction),
-            Some(Token::Memory) => parse_import_memory(tokens).map(Block::ImportMemory),
-            _ => Err(String::from("Unexpected token in import statement")),
-        },
-        _ => Err(String::from("Unrecoginzed block")),
+    // memory
+    tokens.next();
+
+    let (name, size) = parse_memory_size(&mut tokens)?;
+
+    match tokens.next() {
+        Some(fqt) => error_with_info(
+            format!("Unexpected token {} after the memory size", fqt.token),
+            fqt,
+        ),
+        None => Ok(Memory { name, size }),
     }
 }
 
-#[cfg(test)]
-mod tests {
-
-    use super::*;
+fn parse_data(tokens: Vec<FullyQualifiedToken>) -> Result<Data, CompileError> {
+    let mut tokens = tokens.iter();
 
-    #[test]
-    fn export_block() {
-        assert_eq!(
-            parse_block(String::from("export sayHello say_hello")),
-            Ok(Block::Export(Export {
-                external_name: String::from("sayHello"),
-                function_name: String::from("say_hello")
-            }))
-        )
-    }
+    // data
+    tokens.next();
 
-    #[test]
-    fn multiple_blocks() {
+    let path = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Text { body } => body.to_string(),
+            token => {
+                return error_with_info(
+                    format!("Expected a file path in quotes but got {}", token),
+                    fqt,
+                )
+            }
+        },
+        None => {
+            return error_without_info(String::from(
+                "Expected a file path in quotes but got nothing",
+            ))
+        }
+    };
+
+    match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Identifier { body } if body == "as" => (),
+            token => {
+                return error_with_info(
+                    format!("Expected as after data \"{}\" but got {}", path, token),
+                    fqt,
+                )
+            }
+        },
+        None => {
+            return error_without_info(format!(
+                "Expected as after data \"{}\" but got nothing",
+                path
+            ))
+        }
+    }
+
+    let name = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Identifier { body } => body.to_string(),
+            token => {
+                return error_with_info(format!("Expected a data name but got {}", token), fqt)
+            }
+        },
+        None => return error_without_info(String::from("Expected a data name but got nothing")),
+    };
+
+    match tokens.next() {
+        Some(fqt) => error_with_info(
+            format!("Unexpected token {} after data {}", fqt.token, name),
+            fqt,
+        ),
+        None => Ok(Data {
+            name,
+            path,
+            bytes: vec![],
+        }),
+    }
+}
+
+fn parse_use(tokens: Vec<FullyQualifiedToken>) -> Result<Use, CompileError> {
+    let mut path: Vec<String> = vec![];
+    let mut tokens = tokens.iter().skip(1);
+
+    loop {
+        match tokens.next() {
+            Some(fqt) => match &fqt.token {
+                Token::Identifier { body } => path.push(body.to_string()),
+                // `memory` is a keyword, but also a module
+                Token::Memory => path.push(String::from("memory")),
+                token => {
+                    return error_with_info(
+                        format!("Expected a module name after use but got {}", token),
+                        fqt,
+                    )
+                }
+            },
+            None => {
+                return error_without_info(String::from(
+                    "Expected a module name after use but got nothing",
+                ))
+            }
+        }
+
+        match tokens.next() {
+            Some(fqt) if fqt.token == Token::Dot => (),
+            None => break,
+            Some(fqt) if fqt.token == Token::Semicolon => {
+                if let Some(fqt) = tokens.next() {
+                    return error_with_info(
+                        format!(
+                            "Unexpected token {} after use {}",
+                            fqt.token,
+                            path.join(".")
+                        ),
+                        fqt,
+                    );
+                }
+                break;
+            }
+            Some(fqt) => {
+                return error_with_info(
+                    format!(
+                        "Expected . or ; after use {} but got {}",
+                        path.join("."),
+                        fqt.token
+                    ),
+                    fqt,
+                )
+            }
+        }
+    }
+
+    Ok(Use { path })
+}
+
+fn parse_type_alias(tokens: Vec<FullyQualifiedToken>) -> Result<TypeAlias, CompileError> {
+    let mut tokens = tokens.iter();
+
+    // type
+    tokens.next();
+
+    let name = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Identifier { body } => body,
+            token => {
+                return error_with_info(format!("Expected a type name but got {}", token), fqt)
+            }
+        },
+        None => return error_without_info(String::from("Expected a type name but got nothing")),
+    };
+
+    match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Assign => (),
+            token => {
+                return error_with_info(
+                    format!("Expected = after type {} but got {}", name, token),
+                    fqt,
+                )
+            }
+        },
+        None => {
+            return error_without_info(format!("Expected = after type {} but got nothing", name))
+        }
+    }
+
+    let type_name = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Identifier { body } => body,
+            token => {
+                return error_with_info(
+                    format!(
+                        "Expected the type that {} stands for but got {}",
+                        name, token
+                    ),
+                    fqt,
+                )
+            }
+        },
+        None => {
+            return error_without_info(format!(
+                "Expected the type that {} stands for but got nothing",
+                name
+            ))
+        }
+    };
+
+    match tokens.next().map(|fqt| (&fqt.token, fqt)) {
+        None | Some((Token::Semicolon, _)) => (),
+        Some((token, fqt)) => {
+            return error_with_info(
+                format!("Expected ; after type {} but got {}", name, token),
+                fqt,
+            )
+        }
+    }
+
+    if let Some(fqt) = tokens.next() {
+        return error_with_info(
+            format!("Unexpected token {} after type {}", fqt.token, name),
+            fqt,
+        );
+    }
+
+    Ok(TypeAlias {
+        name: name.to_string(),
+        type_name: type_name.to_string(),
+    })
+}
+
+/// Sums of products, where each factor is a number, a constant or a sum in parens
+fn parse_constant_value(
+    tokens: &mut std::iter::Peekable<Iter<'_, FullyQualifiedToken>>,
+) -> Result<ConstantValue, CompileError> {
+    fn parse_factor(
+        tokens: &mut std::iter::Peekable<Iter<'_, FullyQualifiedToken>>,
+    ) -> Result<ConstantValue, CompileError> {
+        match tokens.next() {
+            Some(fqt) => match &fqt.token {
+                Token::Number { body } => match body.parse::<i64>() {
+                    Ok(value) => Ok(ConstantValue::Number(value)),
+                    Err(_) => {
+                        error_with_info(format!("Constants are whole numbers, not {}", body), fqt)
+                    }
+                },
+                Token::Identifier { body } => Ok(ConstantValue::Constant(body.to_string())),
+                Token::LeftParen => {
+                    let value = parse_constant_value(tokens)?;
+                    match tokens.next() {
+                        Some(FullyQualifiedToken {
+                            token: Token::RightParen,
+                            ..
+                        }) => Ok(value),
+                        Some(fqt) => {
+                            error_with_info(format!("Expected ) but got {}", fqt.token), fqt)
+                        }
+                        None => error_without_info(String::from("Expected ) but got nothing")),
+                    }
+                }
+                token => error_with_info(format!("Unexpected token {} in a constant", token), fqt),
+            },
+            None => error_without_info(String::from("Expected a value but got nothing")),
+        }
+    }
+
+    let mut product = parse_factor(tokens)?;
+    while tokens.next_if(|fqt| fqt.token == Token::Star).is_some() {
+        product = ConstantValue::Multiplication(Box::new(product), Box::new(parse_factor(tokens)?));
+    }
+
+    if tokens.next_if(|fqt| fqt.token == Token::Plus).is_some() {
+        return Ok(ConstantValue::Addition(
+            Box::new(product),
+            Box::new(parse_constant_value(tokens)?),
+        ));
+    }
+
+    Ok(product)
+}
+
+fn parse_constant(tokens: Vec<FullyQualifiedToken>) -> Result<Constant, CompileError> {
+    let mut tokens = tokens.iter().peekable();
+
+    // const
+    tokens.next();
+
+    if let Some(fqt) = tokens.peek() {
+        check_name(fqt, "constant")?;
+    }
+
+    let name = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Identifier { body } => body,
+            token => {
+                return error_with_info(format!("Expected a constant name but got {}", token), fqt)
+            }
+        },
+        None => {
+            return error_without_info(String::from("Expected a constant name but got nothing"))
+        }
+    };
+
+    if let Some(fqt) = tokens.next().filter(|fqt| fqt.token != Token::Colon) {
+        return error_with_info(
+            format!("Expected : after const {} but got {}", name, fqt.token),
+            fqt,
+        );
+    }
+
+    let type_name = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Identifier { body } => body,
+            token => {
+                return error_with_info(
+                    format!("Expected the type of {} but got {}", name, token),
+                    fqt,
+                )
+            }
+        },
+        None => {
+            return error_without_info(format!("Expected the type of {} but got nothing", name))
+        }
+    };
+
+    match tokens.next() {
+        Some(fqt) if fqt.token == Token::Assign => (),
+        Some(fqt) => {
+            return error_with_info(
+                format!("Expected = after const {} but got {}", name, fqt.token),
+                fqt,
+            )
+        }
+        None => {
+            return error_without_info(format!("Expected = after const {} but got nothing", name))
+        }
+    }
+
+    let value = parse_constant_value(&mut tokens)?;
+
+    match tokens.next().map(|fqt| (&fqt.token, fqt)) {
+        None | Some((Token::Semicolon, _)) => (),
+        Some((token, fqt)) => {
+            return error_with_info(
+                format!("Expected ; after const {} but got {}", name, token),
+                fqt,
+            )
+        }
+    }
+
+    if let Some(fqt) = tokens.next() {
+        return error_with_info(
+            format!("Unexpected token {} after const {}", fqt.token, name),
+            fqt,
+        );
+    }
+
+    Ok(Constant {
+        name: name.to_string(),
+        type_name: type_name.to_string(),
+        value,
+    })
+}
+
+fn parse_attribute(
+    tokens: &mut Iter<'_, FullyQualifiedToken>,
+    at: &FullyQualifiedToken,
+) -> Result<Attribute, CompileError> {
+    let name = match tokens.next() {
+        // export and memory are also keywords, for their blocks
+        Some(fqt)
+            if matches!(
+                fqt.token,
+                Token::Identifier { .. } | Token::Export | Token::Memory
+            ) =>
+        {
+            fqt.token.to_string()
+        }
+        Some(fqt) => {
+            return error_with_info(
+                format!("Expected an attribute name after @ but got {}", fqt.token),
+                fqt,
+            )
+        }
+        None => {
+            return error_with_info(
+                String::from("Expected an attribute name after @ but got nothing"),
+                at,
+            )
+        }
+    };
+
+    let mut args: Vec<String> = vec![];
+
+    if tokens.clone().next().map(|fqt| &fqt.token) == Some(&Token::LeftParen) {
+        tokens.next();
+
+        for arg_tokens in take_argument_list(tokens)? {
+            match arg_tokens.as_slice() {
+                [FullyQualifiedToken {
+                    token: Token::Text { body },
+                    ..
+                }] => args.push(body.to_string()),
+                [fqt, ..] => {
+                    return error_with_info(
+                        format!("Attribute @{} only takes strings, got {}", name, fqt.token),
+                        fqt,
+                    )
+                }
+                [] => (),
+            }
+        }
+    }
+
+    match ATTRIBUTES.iter().find(|(known, _)| *known == name) {
+        None => error_with_info(format!("Unknown attribute @{}", name), at),
+        Some((_, max_args)) if args.len() > *max_args => error_with_info(
+            format!(
+                "Attribute @{} takes at most {} arguments but got {}",
+                name,
+                max_args,
+                args.len()
+            ),
+            at,
+        ),
+        Some(_) => Ok(Attribute {
+            name: name.to_string(),
+            args,
+        }),
+    }
+}
+
+fn parse_test(tokens: Vec<FullyQualifiedToken>) -> Result<Test, CompileError> {
+    let mut tokens = tokens.iter();
+
+    // test
+    let test_token = match tokens.next() {
+        Some(fqt) => fqt,
+        None => return error_without_info(String::from("Expected test but got nothing")),
+    };
+
+    let name = match tokens.next() {
+        Some(fqt) => match &fqt.token {
+            Token::Text { body } => body.to_string(),
+            token => {
+                return error_with_info(
+                    format!("Expected a test name in quotes but got {}", token),
+                    fqt,
+                )
+            }
+        },
+        None => {
+            return error_with_info(
+                String::from("Expected a test name in quotes but got nothing"),
+                test_token,
+            )
+        }
+    };
+
+    let expressions = parse_block_body(&mut tokens, &format!("test {}", name), &[])?;
+
+    Ok(Test { name, expressions })
+}
+
+pub fn parse_block(body: String) -> Result<Block, CompileError> {
+    parse_block_with_trivia(body)?
+        .into_iter()
+        .find(|block| !matches!(block, Block::Trivia(_)))
+        .map_or_else(
+            || error_without_info(String::from("Unrecoginzed block")),
+            Ok,
+        )
+}
+
+/// A block along with the comments and blank lines around it. Comments
+/// between the statements of a body stay in the body, while any others
+/// outside of one are moved before the block
+pub fn parse_block_with_trivia(body: String) -> Result<Vec<Block>, CompileError> {
+    let tokens = tokenize_checked(body)?;
+    let is_comment = |fqt: &FullyQualifiedToken| matches!(fqt.token, Token::Comment { .. });
+
+    let body_start = tokens
+        .iter()
+        .position(|fqt| fqt.token == Token::LeftBracket);
+    let body_end = tokens
+        .iter()
+        .rposition(|fqt| fqt.token == Token::RightBracket);
+    let in_body = |index: usize| match (body_start, body_end) {
+        (Some(start), Some(end)) => start < index && index < end,
+        _ => false,
+    };
+    let last_code = tokens.iter().rposition(|fqt| !is_comment(fqt));
+
+    let mut before: Vec<Trivia> = vec![];
+    let mut moved: Vec<Trivia> = vec![];
+    let mut after: Vec<Trivia> = vec![];
+    let mut code: Vec<FullyQualifiedToken> = vec![];
+    let mut leading_line: Option<i32> = None;
+
+    for (index, fqt) in tokens.iter().enumerate() {
+        let leading = code.is_empty();
+
+        // blank lines are only kept between the comments leading up to the block
+        if let Some(line) = leading_line.filter(|_| leading) {
+            if fqt.info.line > line + 1 {
+                before.push(Trivia::BlankLine);
+            }
+        }
+
+        match Trivia::from_token(&fqt.token) {
+            Some(trivia) if leading => {
+                leading_line = Some(fqt.info.line);
+                before.push(trivia);
+            }
+            Some(_) if in_body(index) => code.push(fqt.clone()),
+            Some(Trivia::TrailingComment(body)) if last_code.is_some_and(|last| index > last) => {
+                after.push(Trivia::TrailingComment(body))
+            }
+            Some(Trivia::TrailingComment(body)) | Some(Trivia::Comment(body)) => {
+                moved.push(Trivia::Comment(body))
+            }
+            Some(Trivia::BlankLine) => (),
+            None => code.push(fqt.clone()),
+        }
+    }
+
+    let code_blocks = if code.is_empty() {
+        vec![]
+    } else if is_import_group(&code) {
+        parse_import_group(code)?
+            .into_iter()
+            .map(Block::ImportFunction)
+            .collect()
+    } else {
+        vec![parse_block_tokens(code)?]
+    };
+
+    Ok(before
+        .into_iter()
+        .chain(moved)
+        .map(Block::Trivia)
+        .chain(code_blocks)
+        .chain(after.into_iter().map(Block::Trivia))
+        .collect())
+}
+
+fn parse_block_tokens(mut tokens: Vec<FullyQualifiedToken>) -> Result<Block, CompileError> {
+    let doc_lines: Vec<String> = tokens
+        .iter()
+        .map_while(|fqt| match &fqt.token {
+            Token::DocComment { body } => Some(body.to_string()),
+            _ => None,
+        })
+        .collect();
+    let doc = if doc_lines.is_empty() {
+        None
+    } else {
+        Some(doc_lines.join("\n"))
+    };
+    let first_doc = (!doc_lines.is_empty()).then(|| tokens[0].clone());
+    tokens.drain(..doc_lines.len());
+
+    let mut attributes: Vec<Attribute> = vec![];
+    let mut remaining = tokens.iter();
+    while let Some(at) = remaining
+        .clone()
+        .next()
+        .filter(|fqt| fqt.token == Token::At)
+    {
+        remaining.next();
+        attributes.push(parse_attribute(&mut remaining, at)?);
+    }
+    let first_attribute = tokens.first().filter(|_| !attributes.is_empty()).cloned();
+    let tokens = remaining.cloned().collect::<Vec<FullyQualifiedToken>>();
+
+    let is_function = match (tokens.first(), tokens.get(1)) {
+        (Some(first), _) if first.token == Token::Fn => true,
+        (Some(first), Some(second)) => first.token == Token::Import && second.token == Token::Fn,
+        _ => false,
+    };
+
+    if let (Some(fqt), false) = (&first_doc, is_function) {
+        return error_with_info(
+            String::from("Doc comments can only be written before a fn or import fn"),
+            fqt,
+        );
+    }
+
+    if let Some(fqt) =
+        first_attribute.filter(|_| tokens.first().map(|fqt| &fqt.token) != Some(&Token::Fn))
+    {
+        return error_with_info(
+            String::from("Attributes can only be written before a fn"),
+            &fqt,
+        );
+    }
+
+    match tokens.first().map(|fqt| &fqt.token) {
+        Some(Token::Fn) => parse_function(tokens).map(|function| {
+            Block::Function(Function {
+                doc,
+                attributes,
+                ..function
+            })
+        }),
+        Some(Token::Import) if is_function => parse_import_function(tokens)
+            .map(|import| Block::ImportFunction(ImportFunction { doc, ..import })),
+        Some(Token::Export) => parse_export(tokens).map(Block::Export),
+        Some(Token::Type) => parse_type_alias(tokens).map(Block::TypeAlias),
+        Some(Token::Memory) => parse_memory(tokens).map(Block::Memory),
+        Some(Token::Identifier { body }) if body == "test" => parse_test(tokens).map(Block::Test),
+        Some(Token::Identifier { body }) if body == "data" => parse_data(tokens).map(Block::Data),
+        Some(Token::Identifier { body }) if body == "const" => {
+            parse_constant(tokens).map(Block::Constant)
+        }
+        Some(Token::Identifier { body }) if body == "use" => parse_use(tokens).map(Block::Use),
+        Some(Token::Identifier { body }) if body == "interface" => {
+            parse_interface(tokens).map(Block::Interface)
+        }
+        Some(Token::Import) => match tokens.get(1).map(|fqt| &fqt.token) {
+            Some(Token::Memory) => parse_import_memory(tokens).map(Block::ImportMemory),
+            Some(Token::Identifier { body }) if body == "table" => {
+                parse_import_table(tokens).map(Block::ImportTable)
+            }
+            _ => error_without_info(String::from("Unexpected token in import statement")),
+        },
+        _ => error_without_info(String::from("Unrecoginzed block")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn named_memory_blocks() {
+        assert_eq!(
+            parse_block(String::from("import memory heap 2 js.heap")),
+            Ok(Block::ImportMemory(ImportMemory {
+                name: Some(String::from("heap")),
+                size: MemorySize::Pages(2),
+                external_name: vec![String::from("js"), String::from("heap")]
+            }))
+        );
+        assert_eq!(
+            parse_block(String::from("memory scratch 1")),
+            Ok(Block::Memory(Memory {
+                name: Some(String::from("scratch")),
+                size: MemorySize::Pages(1)
+            }))
+        );
+        assert_eq!(
+            parse_block(String::from("memory 1")),
+            Ok(Block::Memory(Memory {
+                name: None,
+                size: MemorySize::Pages(1)
+            }))
+        );
+    }
+
+    #[test]
+    fn import_table_block() {
+        assert_eq!(
+            parse_block(String::from("import table 10 funcref js.table")),
+            Ok(Block::ImportTable(ImportTable {
+                size: 10,
+                element_type: String::from("funcref"),
+                external_name: vec![String::from("js"), String::from("table")]
+            }))
+        );
+        assert_eq!(
+            parse_block(String::from("import table 10 i32 js.table"))
+                .map_err(|error| error.message),
+            Err(String::from("Tables hold funcref or externref, not i32"))
+        );
+    }
+
+    #[test]
+    fn import_group_blocks() {
+        let log = |name: &str, line: i32| ImportFunction {
+            name: String::from(name),
+            params: vec![Param {
+                name: String::from("n"),
+                type_name: String::from("i32"),
+                info: Some(TokenInfo {
+                    line,
+                    index: 8 + name.len() as i32,
+                }),
+            }],
+            external_name: vec![String::from("console"), String::from(name)],
+            doc: None,
+            grouped: true,
+        };
+
+        assert_eq!(
+            parse_block_with_trivia(String::from(
+                "import console {\n    fn log(n: i32);\n    fn warn(n: i32)\n}"
+            )),
+            Ok(vec![
+                Block::ImportFunction(log("log", 1)),
+                Block::ImportFunction(log("warn", 2))
+            ])
+        );
+        assert_eq!(
+            into_blocks(String::from(
+                "import console {\n    fn log(n: i32);\n}\nimport fn warn(n: i32) console.warn"
+            ))
+            .len(),
+            2
+        );
+        assert_eq!(
+            parse_block_with_trivia(String::from("import console { log(n: i32); }"))
+                .map_err(|error| error.message),
+            Err(String::from(
+                "Expected fn in the import group console, got log"
+            ))
+        );
+    }
+
+    #[test]
+    fn data_block() {
+        assert_eq!(
+            parse_block(String::from("data \"assets/logo.bin\" as logo")),
+            Ok(Block::Data(Data {
+                name: String::from("logo"),
+                path: String::from("assets/logo.bin"),
+                bytes: vec![]
+            }))
+        );
+        assert_eq!(
+            parse_block(String::from("data \"assets/logo.bin\" logo"))
+                .map_err(|error| error.message),
+            Err(String::from(
+                "Expected as after data \"assets/logo.bin\" but got logo"
+            ))
+        );
+    }
+
+    #[test]
+    fn generic_function_block() {
+        let block = parse_block(String::from(
+            "fn pick<T, U>(kept: T, dropped: U): T {
+    return kept;
+}",
+        ));
+
+        match block {
+            Ok(Block::Function(function)) => {
+                assert_eq!(function.type_params, vec!["T", "U"]);
+                assert_eq!(function.return_type, "T");
+            }
+            other => panic!("Expected a function but got {:?}", other),
+        }
+        assert_eq!(
+            parse_block(String::from("fn pick<T U>(x: T): T {}")).map_err(|error| error.message),
+            Err(String::from("Expected , or > but got U"))
+        );
+    }
+
+    #[test]
+    fn blocks_display_as_trees() {
+        let block = parse_block(String::from(
+            "@inline
+fn pick(n: i32): i32 {
+    match n {
+        1 => {
+            return 10;
+        },
+        _ => {
+            for (local i: i32 = 0, 3, 1) {
+                log(\"hi\");
+            };
+        }
+    };
+    return n ? 1 : 2;
+}",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            block.to_string(),
+            "@inline Function pick(n: i32): i32
+  MatchStatement: i32
+    Variable n: i32
+    1 =>
+      Return
+        Number 10: f32
+    _ =>
+      ForStatement Up
+        LocalAssign i: i32
+          Number 0: f32
+        Number 3: f32
+        Number 1: f32
+        body
+          FunctionCall log
+            String \"hi\"
+  Return
+    Conditional
+      Variable n: i32
+      Number 1: f32
+      Number 2: f32
+"
+        );
+    }
+
+    #[test]
+    fn function_type_params() {
+        let block = parse_block(String::from(
+            "fn apply(f: fn(i32, fn(f32): void): i32, x: i32): i32 {
+    return f(x);
+}",
+        ));
+
+        match block {
+            Ok(Block::Function(function)) => {
+                assert_eq!(function.params[0].type_name, "fn(i32, fn(f32): void): i32");
+                assert_eq!(
+                    split_function_type(&function.params[0].type_name),
+                    Some((
+                        vec![String::from("i32"), String::from("fn(f32): void")],
+                        String::from("i32")
+                    ))
+                );
+                assert_eq!(
+                    function.function_type(),
+                    "fn(fn(i32, fn(f32): void): i32, i32): i32"
+                );
+            }
+            other => panic!("Expected a function but got {:?}", other),
+        }
+        assert_eq!(
+            parse_block(String::from("fn apply(f: fn(i32), x: i32): i32 {}"))
+                .map_err(|error| error.message),
+            Err(String::from(
+                "Expected : and a return type after the params of a function type"
+            ))
+        );
+    }
+
+    #[test]
+    fn constant_block() {
+        assert_eq!(
+            parse_block(String::from("const SIZE: i32 = 4 * (PAGES + 1) * 1024;")),
+            Ok(Block::Constant(Constant {
+                name: String::from("SIZE"),
+                type_name: String::from("i32"),
+                value: ConstantValue::Multiplication(
+                    Box::new(ConstantValue::Multiplication(
+                        Box::new(ConstantValue::Number(4)),
+                        Box::new(ConstantValue::Addition(
+                            Box::new(ConstantValue::Constant(String::from("PAGES"))),
+                            Box::new(ConstantValue::Number(1))
+                        ))
+                    )),
+                    Box::new(ConstantValue::Number(1024))
+                )
+            }))
+        );
+        assert_eq!(
+            parse_block(String::from("const HALF: f32 = 0.5;")).map_err(|error| error.message),
+            Err(String::from("Constants are whole numbers, not 0.5"))
+        );
+    }
+
+    #[test]
+    fn constants_are_evaluated() {
+        let blocks = |body: &str| -> Vec<Block> {
+            body.lines()
+                .map(|line| parse_block(line.to_string()).unwrap())
+                .collect()
+        };
+
+        assert_eq!(
+            evaluate_constants(&blocks(
+                "const SIZE: i32 = PAGES * 2 + 1;
+const PAGES: i32 = 4 * (1 + 1);"
+            )),
+            Ok(vec![(String::from("SIZE"), 17), (String::from("PAGES"), 8)])
+        );
+        assert_eq!(
+            evaluate_constants(&blocks(
+                "const A: i32 = B + 1;
+const B: i32 = A;"
+            )),
+            Err(CompileError::new(String::from(
+                "Constant A depends on itself"
+            )))
+        );
+        assert_eq!(
+            evaluate_constants(&blocks("const BIG: i32 = 65536 * 65536;")),
+            Err(CompileError::new(String::from(
+                "Constant BIG is 4294967296, which doesn't fit in an i32"
+            )))
+        );
+        assert_eq!(
+            evaluate_constants(&blocks("const BIG: i64 = 65536 * 65536;")),
+            Ok(vec![(String::from("BIG"), 4294967296)])
+        );
+    }
+
+    #[test]
+    fn memory_sizes_can_be_constants() {
+        assert_eq!(
+            parse_block(String::from("memory PAGES")),
+            Ok(Block::Memory(Memory {
+                name: None,
+                size: MemorySize::Constant(String::from("PAGES"))
+            }))
+        );
+        assert_eq!(
+            parse_block(String::from("memory scratch PAGES")),
+            Ok(Block::Memory(Memory {
+                name: Some(String::from("scratch")),
+                size: MemorySize::Constant(String::from("PAGES"))
+            }))
+        );
+        assert_eq!(
+            parse_block(String::from("import memory PAGES js.mem")),
+            Ok(Block::ImportMemory(ImportMemory {
+                name: None,
+                size: MemorySize::Constant(String::from("PAGES")),
+                external_name: vec![String::from("js"), String::from("mem")]
+            }))
+        );
+        assert_eq!(
+            parse_block(String::from("import memory heap PAGES js.heap")),
+            Ok(Block::ImportMemory(ImportMemory {
+                name: Some(String::from("heap")),
+                size: MemorySize::Constant(String::from("PAGES")),
+                external_name: vec![String::from("js"), String::from("heap")]
+            }))
+        );
+    }
+
+    #[test]
+    fn type_alias_block() {
+        assert_eq!(
+            parse_block(String::from("type Seconds = f32;")),
+            Ok(Block::TypeAlias(TypeAlias {
+                name: String::from("Seconds"),
+                type_name: String::from("f32")
+            }))
+        )
+    }
+
+    #[test]
+    fn use_block() {
+        assert_eq!(
+            parse_block(String::from("use std.math;")),
+            Ok(Block::Use(Use {
+                path: vec![String::from("std"), String::from("math")]
+            }))
+        );
+        assert_eq!(
+            parse_block(String::from("use std math;")),
+            Err(CompileError::with_info(
+                String::from("Expected . or ; after use std but got math"),
+                crate::tokenizer::TokenInfo { line: 0, index: 12 }
+            ))
+        );
+    }
+
+    #[test]
+    fn interface_block() {
+        assert_eq!(
+            parse_block(String::from(
+                "interface Clock {
+    fn tick(n: i32);
+    fn now(): f64;
+}"
+            )),
+            Ok(Block::Interface(Interface {
+                name: String::from("Clock"),
+                functions: vec![
+                    InterfaceFunction {
+                        name: String::from("tick"),
+                        params: vec![Param {
+                            name: String::from("n"),
+                            type_name: String::from("i32"),
+                            info: Some(TokenInfo { line: 1, index: 12 }),
+                        }],
+                        return_type: String::from("void"),
+                    },
+                    InterfaceFunction {
+                        name: String::from("now"),
+                        params: vec![],
+                        return_type: String::from("f64"),
+                    },
+                ]
+            }))
+        );
+        assert_eq!(
+            into_blocks(String::from(
+                "interface Logger { fn log(n: i32); }\nfn main(): void {\n}"
+            ))
+            .len(),
+            2
+        );
+        assert_eq!(
+            parse_block(String::from("interface Logger { fn log(n: i32) i32; }"))
+                .map_err(|error| error.message),
+            Err(String::from(
+                "Expected ; after log in interface Logger but got i32"
+            ))
+        );
+    }
+
+    #[test]
+    fn doc_comments_attach_to_functions() {
+        assert!(matches!(
+            parse_block(String::from("/// Says hi\n///\n/// Twice\nfn say_hi(): void {\n}")),
+            Ok(Block::Function(Function { doc: Some(doc), .. })) if doc == "Says hi\n\nTwice"
+        ));
+        assert!(matches!(
+            parse_block(String::from("/// Logs\nimport fn log(n: i32) console.log")),
+            Ok(Block::ImportFunction(ImportFunction { doc: Some(doc), .. })) if doc == "Logs"
+        ));
+        assert_eq!(
+            parse_block(String::from("/// Runs\nexport main main")).map(|_| ()),
+            Err(CompileError::with_info(
+                String::from("Doc comments can only be written before a fn or import fn"),
+                crate::tokenizer::TokenInfo { line: 0, index: 0 }
+            ))
+        );
+    }
+
+    #[test]
+    fn attributes_attach_to_functions() {
+        assert!(matches!(
+            parse_block(String::from("/// Adds\n@inline\n@export(\"add\")\nfn add_numbers(): void {\n}")),
+            Ok(Block::Function(Function { doc: Some(_), attributes, .. })) if attributes == vec![
+                Attribute { name: String::from("inline"), args: vec![] },
+                Attribute { name: String::from("export"), args: vec![String::from("add")] },
+            ]
+        ));
+        assert_eq!(
+            parse_block(String::from("@fast\nfn main(): void {\n}")).map(|_| ()),
+            Err(CompileError::with_info(
+                String::from("Unknown attribute @fast"),
+                crate::tokenizer::TokenInfo { line: 0, index: 0 }
+            ))
+        );
+        assert_eq!(
+            parse_block(String::from("@inline(\"now\")\nfn main(): void {\n}")).map(|_| ()),
+            Err(CompileError::with_info(
+                String::from("Attribute @inline takes at most 0 arguments but got 1"),
+                crate::tokenizer::TokenInfo { line: 0, index: 0 }
+            ))
+        );
+        assert_eq!(
+            parse_block(String::from("@test\nexport main main")).map(|_| ()),
+            Err(CompileError::with_info(
+                String::from("Attributes can only be written before a fn"),
+                crate::tokenizer::TokenInfo { line: 0, index: 0 }
+            ))
+        );
+    }
+
+    #[test]
+    fn export_block() {
+        assert_eq!(
+            parse_block(String::from("export sayHello say_hello")),
+            Ok(Block::Export(Export {
+                external_name: String::from("sayHello"),
+                function_name: String::from("say_hello"),
+                info: Some(TokenInfo { line: 0, index: 16 }),
+            }))
+        )
+    }
+
+    #[test]
+    fn test_block() {
+        assert_eq!(
+            parse_block(String::from("test \"nothing\" {\n}")),
+            Ok(Block::Test(Test {
+                name: String::from("nothing"),
+                expressions: vec![]
+            }))
+        );
+
+        assert_eq!(
+            parse_block(String::from("test nothing {\n}")),
+            Err(CompileError::with_info(
+                String::from("Expected a test name in quotes but got nothing"),
+                crate::tokenizer::TokenInfo { line: 0, index: 12 }
+            ))
+        );
+    }
+
+    #[test]
+    fn multiple_blocks() {
         let blocks = into_blocks(String::from(
             "import fn log(number: i32) console.log
 import memory 1 js.mem
@@ -426,6 +2614,34 @@ fn main(): void {
         )
     }
 
+    #[test]
+    fn block_start_lines() {
+        let blocks = into_blocks_with_lines(String::from(
+            "import fn log(number: i32) console.log
+
+fn main(): void {
+
+    log(3.14);
+}",
+        ));
+
+        assert_eq!(
+            blocks,
+            vec![
+                (0, String::from("import fn log(number: i32) console.log")),
+                (
+                    2,
+                    String::from(
+                        "fn main(): void {
+
+    log(3.14);
+}"
+                    )
+                )
+            ]
+        )
+    }
+
     #[test]
     fn single_block() {
         let blocks = into_blocks(String::from(
@@ -0,0 +1,68 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::PathBuf,
+};
+
+use crate::output::write_atomically;
+
+/// Generated code kept on disk between builds, so that files which haven't
+/// changed since they were last built aren't compiled again
+#[derive(PartialEq, Debug, Clone)]
+pub struct BuildCache {
+    pub directory: PathBuf,
+}
+
+impl BuildCache {
+    pub fn new(directory: PathBuf) -> Self {
+        BuildCache { directory }
+    }
+
+    /// Names what `source` compiles to with `settings`, by this version of
+    /// the compiler
+    pub fn key(source: &str, settings: &str) -> String {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        source.hash(&mut hasher);
+        settings.hash(&mut hasher);
+
+        format!("{:016x}", hasher.finish())
+    }
+
+    fn path(&self, key: &str, extension: &str) -> PathBuf {
+        self.directory.join(format!("{}.{}", key, extension))
+    }
+
+    pub fn get(&self, key: &str, extension: &str) -> Option<Vec<u8>> {
+        fs::read(self.path(key, extension)).ok()
+    }
+
+    pub fn put(&self, key: &str, extension: &str, code: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        write_atomically(&self.path(key, extension), code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_is_found_by_key() {
+        let cache = BuildCache::new(std::env::temp_dir().join("gwe_code_is_found_by_key"));
+        let _ = fs::remove_dir_all(&cache.directory);
+
+        let key = BuildCache::key("fn main(): void {\n}", "wat");
+
+        assert_eq!(cache.get(&key, "wat"), None);
+        cache.put(&key, "wat", b"(module)").unwrap();
+        assert_eq!(cache.get(&key, "wat"), Some(b"(module)".to_vec()));
+        assert_eq!(cache.get(&key, "wasm"), None);
+
+        assert_eq!(key, BuildCache::key("fn main(): void {\n}", "wat"));
+        assert_ne!(key, BuildCache::key("fn main(): void {\n}", "wasm"));
+        assert_ne!(key, BuildCache::key("fn main(): i32 {\n}", "wat"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use toml::{Table, Value};
+
+use crate::generators::gwe::FormatOptions;
+
+pub const CONFIG_FILE_NAME: &str = "gwe.toml";
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Config {
+    pub format: FormatOptions,
+}
+
+fn invalid(message: String) -> String {
+    format!("Invalid {}: {}", CONFIG_FILE_NAME, message)
+}
+
+pub fn parse_config(body: &str) -> Result<Config, String> {
+    let table: Table = match body.parse() {
+        Ok(table) => table,
+        Err(error) => return Err(invalid(error.to_string())),
+    };
+
+    let config = match table.get("format") {
+        Some(Value::Table(format)) => Config {
+            format: parse_format(format)?,
+        },
+        Some(_) => return Err(invalid(String::from("format should be a table"))),
+        None => Config::default(),
+    };
+
+    config.format.validate()?;
+
+    Ok(config)
+}
+
+/// The `[format]` table, where anything left out keeps its default and
+/// unknown keys are ignored
+fn parse_format(table: &Table) -> Result<FormatOptions, String> {
+    let mut format = FormatOptions::default();
+
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            ("indent_width", Value::Integer(width)) => {
+                format.indent_width = parse_size(key, *width)?
+            }
+            ("max_line_length", Value::Integer(length)) => {
+                format.max_line_length = parse_size(key, *length)?
+            }
+            ("brace_style", Value::String(style)) => {
+                format.brace_style = style.parse().map_err(invalid)?
+            }
+            ("indent_width" | "max_line_length", _) => {
+                return Err(invalid(format!("{} should be a number", key)))
+            }
+            ("brace_style", _) => {
+                return Err(invalid(String::from(
+                    "brace_style should be same-line or next-line",
+                )))
+            }
+            _ => (),
+        }
+    }
+
+    Ok(format)
+}
+
+fn parse_size(key: &str, value: i64) -> Result<usize, String> {
+    usize::try_from(value).map_err(|_| invalid(format!("{} can't be negative", key)))
+}
+
+/// The `gwe.toml` in the given directory, otherwise the nearest one in a
+/// directory above it
+pub fn find_config(directory: &Path) -> Option<PathBuf> {
+    // a file given without a directory is in the working directory
+    let directory = if directory.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        directory
+    };
+    let directory = fs::canonicalize(directory).unwrap_or_else(|_| directory.to_path_buf());
+
+    directory
+        .ancestors()
+        .map(|ancestor| ancestor.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Reads the `gwe.toml` nearest to the given directory, falling back to the
+/// defaults when there isn't one
+pub fn read_config(directory: &Path) -> Result<Config, String> {
+    let Some(path) = find_config(directory) else {
+        return Ok(Config::default());
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(body) => parse_config(&body),
+        Err(error) => Err(format!(
+            "Unable to read {} due to {}",
+            path.to_string_lossy(),
+            error
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generators::gwe::BraceStyle;
+
+    #[test]
+    fn empty_config_uses_defaults() {
+        assert_eq!(parse_config(""), Ok(Config::default()))
+    }
+
+    #[test]
+    fn format_table() {
+        assert_eq!(
+            parse_config(
+                "[format]
+indent_width = 2
+max_line_length = 60
+brace_style = \"next-line\""
+            ),
+            Ok(Config {
+                format: FormatOptions {
+                    indent_width: 2,
+                    max_line_length: 60,
+                    brace_style: BraceStyle::NextLine,
+                }
+            })
+        )
+    }
+
+    #[test]
+    fn partial_format_table() {
+        assert_eq!(
+            parse_config("[format]\nindent_width = 2"),
+            Ok(Config {
+                format: FormatOptions {
+                    indent_width: 2,
+                    ..FormatOptions::default()
+                }
+            })
+        )
+    }
+
+    #[test]
+    fn config_is_found_above_the_source() {
+        let project = std::env::temp_dir().join("gwe_config_is_found_above_the_source");
+        let source = project.join("src");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(project.join(CONFIG_FILE_NAME), "[format]\nindent_width = 2").unwrap();
+
+        assert_eq!(
+            find_config(&source),
+            Some(fs::canonicalize(&project).unwrap().join(CONFIG_FILE_NAME))
+        );
+        assert_eq!(read_config(&source).unwrap().format.indent_width, 2);
+
+        fs::remove_dir_all(&project).unwrap();
+    }
+
+    #[test]
+    fn wrongly_typed_values_are_rejected() {
+        assert_eq!(
+            parse_config("[format]\nindent_width = \"2\""),
+            Err(String::from(
+                "Invalid gwe.toml: indent_width should be a number"
+            ))
+        );
+        assert_eq!(
+            parse_config("[format]\nbrace_style = \"sideways\""),
+            Err(String::from(
+                "Invalid gwe.toml: Unknown brace style sideways, expected same-line or next-line"
+            ))
+        );
+    }
+
+    #[test]
+    fn zero_indent_is_rejected() {
+        assert_eq!(
+            parse_config("[format]\nindent_width = 0"),
+            Err(String::from("indent_width must be at least 1"))
+        )
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    blocks::into_blocks_with_lines,
+    errors::CompileError,
+    tokenizer::{normalize_line_endings, tokenize, Token},
+};
+
+/// The call put before each statement, which the wasm generator turns into
+/// an increment of the statement's counter when generating with coverage
+pub const HIT: &str = "__coverage_hit";
+
+/// Counters are i32s in a single page of memory
+const MAX_STATEMENTS: usize = 65536 / 4;
+
+/// A program with a hit call before each statement that starts a line
+#[derive(PartialEq, Debug, Clone)]
+pub struct Instrumented {
+    pub source: String,
+    /// The line, counting from 1, of each counted statement
+    pub lines: Vec<i32>,
+}
+
+/// What a pair of braces holds
+struct Level<'a> {
+    statements: bool,
+    /// The first token of the statement being read
+    first: Option<&'a Token>,
+    /// Whether the statement being read has opened braces of its own
+    opened: bool,
+}
+
+/// The lines, relative to the block, where a statement is the first thing on
+/// the line
+fn statement_lines(block: &str) -> Vec<i32> {
+    let tokens = tokenize(block.to_string());
+    let mut levels = vec![Level {
+        statements: false,
+        first: None,
+        opened: false,
+    }];
+    let mut lines: Vec<i32> = vec![];
+    let mut last_line = -1;
+
+    for fqt in &tokens {
+        let starts_line = fqt.info.line != last_line;
+        last_line = fqt.info.line;
+
+        let Some(level) = levels.last_mut() else {
+            break;
+        };
+
+        match &fqt.token {
+            Token::DocComment { .. } => (),
+            Token::LeftBracket => {
+                // the braces after a match hold its arms, not statements
+                let arms = !level.opened
+                    && matches!(level.first, Some(Token::Identifier { body }) if body == "match");
+                level.opened = true;
+                levels.push(Level {
+                    statements: !arms,
+                    first: None,
+                    opened: false,
+                });
+            }
+            Token::RightBracket => {
+                levels.pop();
+            }
+            Token::Semicolon => {
+                level.first = None;
+                level.opened = false;
+            }
+            token => {
+                if level.statements && level.first.is_none() && starts_line {
+                    lines.push(fqt.info.line);
+                }
+                if level.first.is_none() {
+                    level.first = Some(token);
+                }
+            }
+        }
+    }
+
+    lines
+}
+
+/// Counts every statement which starts a line, leaving the lines where they
+/// are so that errors and counts point at the source as it was written
+pub fn instrument(source: &str) -> Result<Instrumented, CompileError> {
+    let statement_lines: Vec<usize> = into_blocks_with_lines(source.to_string())
+        .into_iter()
+        .flat_map(|(start_line, block)| {
+            statement_lines(&block)
+                .into_iter()
+                .map(move |line| (start_line + line) as usize)
+        })
+        .collect();
+
+    if statement_lines.len() > MAX_STATEMENTS {
+        return Err(CompileError::new(format!(
+            "Only {} statements can be counted, but there are {}",
+            MAX_STATEMENTS,
+            statement_lines.len()
+        )));
+    }
+
+    let mut lines: Vec<i32> = vec![];
+    let source = normalize_line_endings(source)
+        .split('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            if statement_lines.contains(&index) {
+                let code = line.trim_start();
+                let indent = &line[..line.len() - code.len()];
+                let hit = format!("{}{}({}); {}", indent, HIT, lines.len(), code);
+                lines.push(index as i32 + 1);
+                hit
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    Ok(Instrumented { source, lines })
+}
+
+/// An lcov report of how many times each line ran, from the hits of each
+/// counted statement
+pub fn lcov(file: &str, lines: &[i32], hits: &[i32]) -> String {
+    let mut counts: BTreeMap<i32, i32> = BTreeMap::new();
+    for (line, hits) in lines.iter().zip(hits) {
+        *counts.entry(*line).or_default() += hits;
+    }
+
+    let records: String = counts
+        .iter()
+        .map(|(line, hits)| format!("DA:{},{}\n", line, hits))
+        .collect();
+    let hit = counts.values().filter(|hits| **hits > 0).count();
+
+    format!(
+        "TN:\nSF:{}\n{}LF:{}\nLH:{}\nend_of_record\n",
+        file,
+        records,
+        counts.len(),
+        hit
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statements_starting_lines_are_counted() {
+        let instrumented = instrument(
+            "fn main(n: i32): i32 {
+    local x: i32 = 1;
+    if (n) { log(x); } else {
+        log(2);
+    };
+    match n {
+        1 => {
+            log(3);
+        },
+        _ => {
+        }
+    };
+    return x;
+}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            instrumented,
+            Instrumented {
+                source: String::from(
+                    "fn main(n: i32): i32 {
+    __coverage_hit(0); local x: i32 = 1;
+    __coverage_hit(1); if (n) { log(x); } else {
+        __coverage_hit(2); log(2);
+    };
+    __coverage_hit(3); match n {
+        1 => {
+            __coverage_hit(4); log(3);
+        },
+        _ => {
+        }
+    };
+    __coverage_hit(5); return x;
+}"
+                ),
+                lines: vec![2, 3, 4, 6, 8, 13],
+            }
+        );
+    }
+
+    #[test]
+    fn lines_are_reported_with_their_hits() {
+        assert_eq!(
+            lcov("main.gwe", &[2, 3, 5], &[1, 0, 4]),
+            "TN:\nSF:main.gwe\nDA:2,1\nDA:3,0\nDA:5,4\nLF:3\nLH:2\nend_of_record\n"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{errors::CompileError, tokenizer::TokenInfo};
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16_LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16_BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// The text of a source file. A byte order mark is left out rather than
+/// becoming part of the first token, and files marked as UTF-16 are
+/// transcoded. Anything else has to be UTF-8
+pub fn decode(bytes: &[u8]) -> Result<String, CompileError> {
+    if let Some(rest) = bytes.strip_prefix(UTF16_LE_BOM) {
+        decode_utf16(rest, u16::from_le_bytes)
+    } else if let Some(rest) = bytes.strip_prefix(UTF16_BE_BOM) {
+        decode_utf16(rest, u16::from_be_bytes)
+    } else {
+        let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+
+        match std::str::from_utf8(bytes) {
+            Ok(body) => Ok(body.to_string()),
+            Err(error) => {
+                // everything before the bad byte is valid, so it can be
+                // counted to say where the problem is
+                let valid = std::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap_or_default();
+                let line = valid.matches('\n').count();
+                let index = valid
+                    .rsplit('\n')
+                    .next()
+                    .unwrap_or_default()
+                    .chars()
+                    .count();
+
+                Err(CompileError::with_info(
+                    String::from("File is not valid UTF-8, save it as UTF-8 to compile it"),
+                    TokenInfo {
+                        line: line as i32,
+                        index: index as i32,
+                    },
+                ))
+            }
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> Result<String, CompileError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(CompileError::new(String::from(
+            "File is marked as UTF-16 but ends partway through a character",
+        )));
+    }
+
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| to_unit([pair[0], pair[1]]));
+
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|_| {
+            CompileError::new(String::from(
+                "File is marked as UTF-16 but has an unpaired surrogate",
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_order_marks_are_left_out() {
+        assert_eq!(decode(b"\xEF\xBB\xBFfn"), Ok(String::from("fn")));
+        assert_eq!(decode(b"fn"), Ok(String::from("fn")));
+        assert_eq!(decode(b"\xFF\xFEf\0n\0"), Ok(String::from("fn")));
+        assert_eq!(decode(b"\xFE\xFF\0f\0n"), Ok(String::from("fn")));
+    }
+
+    #[test]
+    fn encoding_problems_are_reported() {
+        assert_eq!(
+            decode(b"fn main(): void {\n    l\xE9og();\n}").map_err(|error| error.to_string()),
+            Err(String::from(
+                "File is not valid UTF-8, save it as UTF-8 to compile it at line 2, index 5"
+            ))
+        );
+        assert_eq!(
+            decode(b"\xFF\xFEf\0n").map_err(|error| error.message),
+            Err(String::from(
+                "File is marked as UTF-16 but ends partway through a character"
+            ))
+        );
+        assert_eq!(
+            decode(b"\xFF\xFE\0\xD8").map_err(|error| error.message),
+            Err(String::from(
+                "File is marked as UTF-16 but has an unpaired surrogate"
+            ))
+        );
+    }
+}
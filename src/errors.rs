@@ -0,0 +1,285 @@
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use crate::tokenizer::TokenInfo;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct CompileError {
+    pub message: String,
+    pub info: Option<TokenInfo>,
+}
+
+impl CompileError {
+    pub fn new(message: String) -> Self {
+        CompileError {
+            message,
+            info: None,
+        }
+    }
+
+    pub fn with_info(message: String, info: TokenInfo) -> Self {
+        CompileError {
+            message,
+            info: Some(info),
+        }
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match &self.info {
+            Some(info) => write!(
+                f,
+                "{} at line {}, index {}",
+                self.message,
+                info.line + 1,
+                info.index
+            ),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+pub fn join_errors(errors: &[CompileError]) -> String {
+    errors
+        .iter()
+        .map(|error| error.to_string())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// Which stage of the compiler an error came from
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ErrorCode {
+    ReadError,
+    /// The file isn't text the compiler can read
+    EncodingError,
+    ParseError,
+    TypeError,
+    TransformError,
+    GenerateError,
+}
+
+impl ErrorCode {
+    fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::ReadError => "read-error",
+            ErrorCode::EncodingError => "encoding-error",
+            ErrorCode::ParseError => "parse-error",
+            ErrorCode::TypeError => "type-error",
+            ErrorCode::TransformError => "transform-error",
+            ErrorCode::GenerateError => "generate-error",
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Position {
+    pub line: i32,
+    pub column: i32,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// A CompileError tied to a file, for tools that read the compiler's output.
+/// Lines and columns start at 1, and are 0 when the error has no position
+#[derive(PartialEq, Debug, Clone)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: i32,
+    pub column: i32,
+    pub span: Span,
+    pub severity: Severity,
+    pub message: String,
+    pub code: ErrorCode,
+}
+
+impl Diagnostic {
+    pub fn from_error(file: &str, error: &CompileError, code: ErrorCode) -> Self {
+        let (line, column) = match &error.info {
+            Some(info) => (info.line + 1, info.index + 1),
+            None => (0, 0),
+        };
+
+        Diagnostic {
+            file: file.to_string(),
+            line,
+            column,
+            span: Span {
+                start: Position { line, column },
+                end: Position {
+                    line,
+                    column: if column == 0 { 0 } else { column + 1 },
+                },
+            },
+            severity: Severity::Error,
+            message: error.message.to_string(),
+            code,
+        }
+    }
+
+    /// Renders the diagnostic for a terminal, with the source line and a caret
+    /// under the reported position when the source is available
+    pub fn render(&self, source: Option<&str>, color: bool) -> String {
+        let paint = |code: &str, text: &str| {
+            if color {
+                format!("\x1b[{}m{}\x1b[0m", code, text)
+            } else {
+                text.to_string()
+            }
+        };
+
+        let label = match self.severity {
+            Severity::Error => paint("1;31", "error"),
+            Severity::Warning => paint("1;33", "warning"),
+        };
+
+        let mut output = format!("{}: {}\n", label, paint("1", &self.message));
+
+        if self.line == 0 {
+            output.push_str(&format!("{} {}", paint("1;34", " -->"), self.file));
+            return output;
+        }
+
+        output.push_str(&format!(
+            "{} {}:{}:{}",
+            paint("1;34", " -->"),
+            self.file,
+            self.line,
+            self.column
+        ));
+
+        let source_line = source.and_then(|source| source.lines().nth((self.line - 1) as usize));
+
+        if let Some(source_line) = source_line {
+            let number = self.line.to_string();
+            let gutter = " ".repeat(number.len());
+            let caret_color = match self.severity {
+                Severity::Error => "1;31",
+                Severity::Warning => "1;33",
+            };
+
+            output.push_str(&format!(
+                "\n{}\n{}{}\n{}{}",
+                paint("1;34", &format!("{} |", gutter)),
+                paint("1;34", &format!("{} | ", number)),
+                source_line,
+                paint("1;34", &format!("{} | ", gutter)),
+                paint(
+                    caret_color,
+                    &format!("{}^", " ".repeat((self.column - 1) as usize))
+                ),
+            ));
+        }
+
+        output
+    }
+
+    /// The diagnostic as one line of json, with the keys in the order of the fields
+    pub fn to_json(&self) -> String {
+        let string = |text: &str| serde_json::Value::from(text).to_string();
+        let position = |position: &Position| {
+            format!(
+                "{{\"line\":{},\"column\":{}}}",
+                position.line, position.column
+            )
+        };
+
+        format!(
+            "{{\"file\":{},\"line\":{},\"column\":{},\"span\":{{\"start\":{},\"end\":{}}},\"severity\":{},\"message\":{},\"code\":{}}}",
+            string(&self.file),
+            self.line,
+            self.column,
+            position(&self.span.start),
+            position(&self.span.end),
+            string(self.severity.name()),
+            string(&self.message),
+            string(self.code.name())
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_without_info() {
+        assert_eq!(
+            CompileError::new(String::from("Unrecoginzed block")).to_string(),
+            "Unrecoginzed block"
+        )
+    }
+
+    #[test]
+    fn display_with_info() {
+        assert_eq!(
+            CompileError::with_info(
+                String::from("Expected { but got }"),
+                TokenInfo { line: 0, index: 36 }
+            )
+            .to_string(),
+            "Expected { but got } at line 1, index 36"
+        )
+    }
+
+    #[test]
+    fn diagnostic_as_json() {
+        let error = CompileError::with_info(
+            String::from("Expected { but got }"),
+            TokenInfo { line: 0, index: 36 },
+        );
+
+        assert_eq!(
+            Diagnostic::from_error("examples/main.gwe", &error, ErrorCode::ParseError).to_json(),
+            "{\"file\":\"examples/main.gwe\",\"line\":1,\"column\":37,\"span\":{\"start\":{\"line\":1,\"column\":37},\"end\":{\"line\":1,\"column\":38}},\"severity\":\"error\",\"message\":\"Expected { but got }\",\"code\":\"parse-error\"}"
+        )
+    }
+
+    #[test]
+    fn render_with_source() {
+        let error = CompileError::with_info(
+            String::from("Expected parens but got {"),
+            TokenInfo { line: 1, index: 7 },
+        );
+
+        assert_eq!(
+            Diagnostic::from_error("main.gwe", &error, ErrorCode::ParseError)
+                .render(Some("fn main(): void {}\nfn bad {}"), false),
+            "error: Expected parens but got {
+ --> main.gwe:2:8
+  |
+2 | fn bad {}
+  |        ^"
+        )
+    }
+
+    #[test]
+    fn render_without_position() {
+        let error = CompileError::new(String::from("Unable to read file"));
+
+        assert_eq!(
+            Diagnostic::from_error("main.gwe", &error, ErrorCode::ReadError).render(None, true),
+            "\x1b[1;31merror\x1b[0m: \x1b[1mUnable to read file\x1b[0m\n\x1b[1;34m -->\x1b[0m main.gwe"
+        )
+    }
+}
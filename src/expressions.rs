@@ -1,10 +1,79 @@
 use crate::{
-    blocks::Param,
-    tokenizer::{error_with_info, split_by_semicolon_within_brackets, FullyQualifiedToken, Token},
+    blocks::{parse_closure, Function, Param, Trivia},
+    errors::CompileError,
+    tokenizer::{
+        character_literal, character_value, check_name, error_with_info, error_without_info,
+        split_by_semicolon_within_brackets, split_top_level, take_argument_list,
+        take_until_closing, FullyQualifiedToken, Token, TokenInfo,
+    },
+};
+use std::{
+    fmt::{Display, Formatter},
+    slice::Iter,
 };
-use std::slice::Iter;
 
+/// A function the compiler provides, which is called like any other but
+/// lowers to a single wasm instruction. Every param is an i32
+pub struct Builtin {
+    pub name: &'static str,
+    pub params: &'static [&'static str],
+    pub return_type: &'static str,
+    pub instruction: &'static str,
+}
+
+pub const BUILTINS: [Builtin; 3] = [
+    Builtin {
+        name: "memory_copy",
+        params: &["dst", "src", "len"],
+        return_type: "void",
+        instruction: "memory.copy",
+    },
+    Builtin {
+        name: "memory_fill",
+        params: &["dst", "val", "len"],
+        return_type: "void",
+        instruction: "memory.fill",
+    },
+    Builtin {
+        name: "memory_grow",
+        params: &["pages"],
+        return_type: "i32",
+        instruction: "memory.grow",
+    },
+];
+
+/// Functions from the runtime that modules include when they call them,
+/// unless the program defines its own
+pub const RUNTIME_FUNCTIONS: [Builtin; 2] = [
+    Builtin {
+        name: "alloc",
+        params: &["size"],
+        return_type: "i32",
+        instruction: "call $alloc",
+    },
+    Builtin {
+        name: "reset",
+        params: &[],
+        return_type: "void",
+        instruction: "call $reset",
+    },
+];
+
+pub fn find_builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// Which way a for loop moves its variable: up adds the step and loops
+/// while below the limit, down takes it away and loops while above it
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expression {
     Number {
         value: String,
@@ -16,11 +85,15 @@ pub enum Expression {
     },
     Return {
         expression: Box<Expression>,
+        /// Where `return` is written, which returns the compiler adds don't have
+        info: Option<TokenInfo>,
     },
     LocalAssign {
         name: String,
         type_name: String,
         expression: Box<Expression>,
+        /// Where the name is written, which locals the compiler adds don't have
+        info: Option<TokenInfo>,
     },
     GlobalAssign {
         name: String,
@@ -30,6 +103,10 @@ pub enum Expression {
     Addition {
         left: Box<Expression>,
         right: Box<Expression>,
+        /// Empty when both sides are literals nothing has given a type yet
+        type_name: String,
+        /// Where the + is written, which additions the compiler adds don't have
+        info: Option<TokenInfo>,
     },
     String {
         body: String,
@@ -37,184 +114,800 @@ pub enum Expression {
     FunctionCall {
         name: String,
         args: Vec<Expression>,
+        /// Where the name is written, which calls the compiler adds don't have
+        info: Option<TokenInfo>,
+    },
+    /// `tailcall f(args)`, returning what the call returns without keeping
+    /// the caller's frame around
+    TailCall {
+        name: String,
+        args: Vec<Expression>,
     },
     MemoryReference {
         offset: i32,
         length: i32,
     },
+    /// `logo.offset` or `logo.length` of a data block
+    DataField {
+        name: String,
+        field: String,
+    },
+    /// A name that isn't a param or local, which has to be a constant. The
+    /// type is the constant's, filled in once all blocks are parsed
+    Constant {
+        name: String,
+        type_name: String,
+    },
+    /// `fn name(params): type { ... }` inside a function, which can use the
+    /// params and locals of the functions around it
+    Closure {
+        function: Box<Function>,
+    },
+    /// A call to a closure, through the table of closures. Only made by
+    /// `lift_closures`
+    ClosureCall {
+        name: String,
+        type_index: usize,
+        args: Vec<Expression>,
+    },
+    /// Stores a value in a closure's environment. Only made by `lift_closures`
+    EnvironmentStore {
+        name: String,
+        offset: i32,
+        type_name: String,
+        value: Box<Expression>,
+    },
+    /// Loads a captured value from the environment a closure was given. Only
+    /// made by `lift_closures`
+    EnvironmentLoad {
+        offset: i32,
+        type_name: String,
+    },
     IfStatement {
         predicate: Box<Expression>,
         success: Vec<Expression>,
         fail: Vec<Expression>,
     },
+    /// `predicate ? success : fail`, picking between two values
+    Conditional {
+        predicate: Box<Expression>,
+        success: Box<Expression>,
+        fail: Box<Expression>,
+        /// The type of both values, left empty while both are number literals
+        /// that haven't been given a type
+        type_name: String,
+    },
+    /// `match subject { 0 => { ... }, _ => { ... } }`, running the arm whose
+    /// number is the subject's value, otherwise the `_` arm
+    MatchStatement {
+        subject: Box<Expression>,
+        /// The type of the subject, which the patterns are compared as
+        type_name: String,
+        arms: Vec<(i64, Vec<Expression>)>,
+        default: Option<Vec<Expression>>,
+        /// Where `match` is written, which matches the compiler adds don't have
+        info: Option<TokenInfo>,
+    },
     Boolean {
         value: bool,
     },
+    /// `'a'`, the character's code point as an i32
+    Character {
+        value: char,
+    },
+    /// `[1, 2, 3]`, whose values are laid out in a data segment when the
+    /// module is built. The type is the type of each element
+    Array {
+        type_name: String,
+        values: Vec<Expression>,
+    },
+    /// `table[i]`, loading the element at `index` of an array local. The line
+    /// is where it was written, which debug builds report when it's out of bounds
+    Index {
+        name: String,
+        /// The type of the element
+        type_name: String,
+        index: Box<Expression>,
+        /// How many elements the array has, known once it's laid out as data
+        length: usize,
+        line: i32,
+    },
     ForStatement {
         initial_value: Box<Expression>,
         incrementor: Box<Expression>,
         break_condition: Box<Expression>,
+        direction: Direction,
+        body: Vec<Expression>,
+    },
+    /// `for (x in table) { ... }`, running the body with `x` as each element
+    /// of an array in turn. It becomes an index loop once the array's length
+    /// is known, and the line is where the loop was written
+    ForEach {
+        name: String,
+        /// The type of the element
+        type_name: String,
+        array: String,
+        body: Vec<Expression>,
+        line: i32,
+    },
+    /// `assert(left)`, or `assert_eq(left, right)` comparing two values of
+    /// `type_name`. The line is where the assert was written
+    Assert {
+        left: Box<Expression>,
+        right: Option<Box<Expression>>,
+        type_name: String,
+        line: i32,
+    },
+    /// `panic("message")`, which always traps
+    Panic {
+        message: Box<Expression>,
+    },
+    /// `try { ... } catch { ... }`, where the catch block runs when an
+    /// import called in the try block throws
+    TryStatement {
         body: Vec<Expression>,
+        catch: Vec<Expression>,
     },
+    /// A comment or blank line between statements, only kept by
+    /// `parse_with_trivia`
+    Trivia {
+        trivia: Trivia,
+    },
+}
+
+/// A node of the AST as an indented tree, for reading rather than compiling.
+/// Used by `--emit ast-text` and in test failures
+#[derive(PartialEq, Debug, Clone)]
+pub struct Tree {
+    pub label: String,
+    pub children: Vec<Tree>,
+}
+
+impl Tree {
+    pub fn new(label: String, children: Vec<Tree>) -> Self {
+        Tree { label, children }
+    }
+
+    pub fn leaf(label: String) -> Self {
+        Tree::new(label, vec![])
+    }
+
+    /// A named list of statements, like the body of an if
+    pub fn body(label: &str, expressions: &[Expression]) -> Self {
+        Tree::new(
+            label.to_string(),
+            expressions.iter().map(Expression::tree).collect(),
+        )
+    }
+
+    fn write(&self, f: &mut Formatter, depth: usize) -> std::fmt::Result {
+        writeln!(f, "{}{}", "  ".repeat(depth), self.label)?;
+        for child in &self.children {
+            child.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Tree {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// `label: type`, leaving out types that haven't been inferred yet
+fn typed(label: String, type_name: &str) -> String {
+    if type_name.is_empty() {
+        label
+    } else {
+        format!("{}: {}", label, type_name)
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.tree())
+    }
 }
 
 impl Expression {
     pub fn map<F: FnOnce(Expression) -> Expression>(self, f: F) -> Expression {
         f(self)
     }
+
+    /// The expression and everything inside it as an indented tree
+    pub fn tree(&self) -> Tree {
+        match self {
+            Expression::Number { value, type_name } => {
+                Tree::leaf(typed(format!("Number {}", value), type_name))
+            }
+            Expression::Variable { body, type_name } => {
+                Tree::leaf(typed(format!("Variable {}", body), type_name))
+            }
+            Expression::Return { expression, .. } => {
+                Tree::new(String::from("Return"), vec![expression.tree()])
+            }
+            Expression::LocalAssign {
+                name,
+                type_name,
+                expression,
+                ..
+            } => Tree::new(
+                typed(format!("LocalAssign {}", name), type_name),
+                vec![expression.tree()],
+            ),
+            Expression::GlobalAssign {
+                name,
+                type_name,
+                expression,
+            } => Tree::new(
+                typed(format!("GlobalAssign {}", name), type_name),
+                vec![expression.tree()],
+            ),
+            Expression::Addition { left, right, .. } => {
+                Tree::new(String::from("Addition"), vec![left.tree(), right.tree()])
+            }
+            Expression::String { body } => Tree::leaf(format!("String {:?}", body)),
+            Expression::FunctionCall { name, args, .. } => {
+                Tree::body(&format!("FunctionCall {}", name), args)
+            }
+            Expression::TailCall { name, args } => Tree::body(&format!("TailCall {}", name), args),
+            Expression::MemoryReference { offset, length } => Tree::leaf(format!(
+                "MemoryReference offset {} length {}",
+                offset, length
+            )),
+            Expression::DataField { name, field } => {
+                Tree::leaf(format!("DataField {}.{}", name, field))
+            }
+            Expression::Constant { name, type_name } => {
+                Tree::leaf(typed(format!("Constant {}", name), type_name))
+            }
+            Expression::Closure { function } => {
+                Tree::new(String::from("Closure"), vec![function.tree()])
+            }
+            Expression::ClosureCall {
+                name,
+                type_index,
+                args,
+            } => Tree::body(&format!("ClosureCall {} type {}", name, type_index), args),
+            Expression::EnvironmentStore {
+                name,
+                offset,
+                type_name,
+                value,
+            } => Tree::new(
+                typed(
+                    format!("EnvironmentStore {} at {}", name, offset),
+                    type_name,
+                ),
+                vec![value.tree()],
+            ),
+            Expression::EnvironmentLoad { offset, type_name } => {
+                Tree::leaf(typed(format!("EnvironmentLoad at {}", offset), type_name))
+            }
+            Expression::IfStatement {
+                predicate,
+                success,
+                fail,
+            } => Tree::new(
+                String::from("IfStatement"),
+                vec![
+                    predicate.tree(),
+                    Tree::body("then", success),
+                    Tree::body("else", fail),
+                ],
+            ),
+            Expression::Conditional {
+                predicate,
+                success,
+                fail,
+                type_name,
+            } => Tree::new(
+                typed(String::from("Conditional"), type_name),
+                vec![predicate.tree(), success.tree(), fail.tree()],
+            ),
+            Expression::MatchStatement {
+                subject,
+                type_name,
+                arms,
+                default,
+                ..
+            } => Tree::new(
+                typed(String::from("MatchStatement"), type_name),
+                std::iter::once(subject.tree())
+                    .chain(
+                        arms.iter()
+                            .map(|(pattern, body)| Tree::body(&format!("{} =>", pattern), body)),
+                    )
+                    .chain(default.iter().map(|body| Tree::body("_ =>", body)))
+                    .collect(),
+            ),
+            Expression::Boolean { value } => Tree::leaf(format!("Boolean {}", value)),
+            Expression::Character { value } => {
+                Tree::leaf(format!("Character {}", character_literal(*value)))
+            }
+            Expression::Array { type_name, values } => {
+                Tree::body(&typed(String::from("Array"), type_name), values)
+            }
+            Expression::Index {
+                name,
+                type_name,
+                index,
+                ..
+            } => Tree::new(
+                typed(format!("Index {}", name), type_name),
+                vec![index.tree()],
+            ),
+            Expression::ForStatement {
+                initial_value,
+                incrementor,
+                break_condition,
+                direction,
+                body,
+            } => Tree::new(
+                format!("ForStatement {:?}", direction),
+                vec![
+                    initial_value.tree(),
+                    break_condition.tree(),
+                    incrementor.tree(),
+                    Tree::body("body", body),
+                ],
+            ),
+            Expression::ForEach {
+                name,
+                type_name,
+                array,
+                body,
+                ..
+            } => Tree::body(
+                &typed(format!("ForEach {} in {}", name, array), type_name),
+                body,
+            ),
+            Expression::Assert {
+                left,
+                right,
+                type_name,
+                line,
+            } => Tree::new(
+                typed(format!("Assert on line {}", line + 1), type_name),
+                std::iter::once(left.tree())
+                    .chain(right.iter().map(|right| right.tree()))
+                    .collect(),
+            ),
+            Expression::Panic { message } => Tree::new(String::from("Panic"), vec![message.tree()]),
+            Expression::TryStatement { body, catch } => Tree::new(
+                String::from("TryStatement"),
+                vec![Tree::body("body", body), Tree::body("catch", catch)],
+            ),
+            Expression::Trivia { trivia } => Tree::leaf(format!("Trivia {:?}", trivia)),
+        }
+    }
+
+    /// The type of value this expression produces, when it can be known from
+    /// the expression alone
+    pub fn type_name(&self) -> Option<String> {
+        match self {
+            Expression::Number { type_name, .. }
+            | Expression::Variable { type_name, .. }
+            | Expression::Constant { type_name, .. }
+            | Expression::Index { type_name, .. } => Some(type_name.to_string()),
+            Expression::Array { type_name, .. } if !type_name.is_empty() => {
+                Some(format!("[{}]", type_name))
+            }
+            Expression::Boolean { .. }
+            | Expression::Character { .. }
+            | Expression::DataField { .. } => Some(String::from("i32")),
+            Expression::Conditional { type_name, .. } | Expression::Addition { type_name, .. }
+                if !type_name.is_empty() =>
+            {
+                Some(type_name.to_string())
+            }
+            Expression::String { .. } => Some(String::from("string")),
+            _ => None,
+        }
+    }
+}
+
+/// The type shared by two sides of an expression, taken from whichever isn't
+/// a literal. Empty when both are, until inference gives them a type
+fn operand_type(left: &Expression, right: &Expression) -> String {
+    match (left, right) {
+        (Expression::Number { .. }, Expression::Number { .. }) => String::new(),
+        (Expression::Number { .. }, known) | (known, _) => known.type_name().unwrap_or_default(),
+    }
 }
 
-fn try_to_match(tokens: &mut Iter<'_, FullyQualifiedToken>, token: Token) -> Option<String> {
+type MatchArms = (Vec<(i64, Vec<Expression>)>, Option<Vec<Expression>>);
+
+fn try_to_match(tokens: &mut Iter<'_, FullyQualifiedToken>, token: Token) -> Option<CompileError> {
     match tokens.next() {
         Some(fqt) => {
             if token != fqt.token {
-                Some(
-                    error_with_info::<()>(format!("Expected : but got {}", &fqt.token), fqt)
-                        .unwrap_err(),
-                )
+                Some(CompileError::with_info(
+                    format!("Expected {} but got {}", token, &fqt.token),
+                    fqt.info.clone(),
+                ))
             } else {
                 None
             }
         }
-        None => Some(format!("Expected {} but got nothing", token)),
+        None => Some(CompileError::new(format!(
+            "Expected {} but got nothing",
+            token
+        ))),
     }
 }
 
-fn between_next(
-    tokens: Vec<FullyQualifiedToken>,
-    start: Token,
-    end: Token,
-) -> Option<Vec<FullyQualifiedToken>> {
-    let mut new_tokens: Vec<FullyQualifiedToken> = vec![];
-    let mut seen_start = false;
-
-    for fqt in tokens {
-        if seen_start {
-            if fqt.token == end {
-                return Some(new_tokens);
-            } else {
-                new_tokens.push(fqt);
-            }
-        } else if fqt.token == start {
-            seen_start = true;
+fn find_top_level(tokens: &[FullyQualifiedToken], token: Token) -> Option<usize> {
+    let mut depth = 0;
+
+    for (index, fqt) in tokens.iter().enumerate() {
+        match fqt.token {
+            Token::LeftParen | Token::LeftBracket | Token::LeftSquare => depth += 1,
+            Token::RightParen | Token::RightBracket | Token::RightSquare => depth -= 1,
+            ref current if depth == 0 && *current == token => return Some(index),
+            _ => (),
         }
     }
 
     None
 }
 
-fn between_next_next(
+/// Parses a `{ ... }` block, where locals declared inside are only visible to
+/// the statements after them in the same block
+fn parse_body(
+    tokens: &mut Iter<'_, FullyQualifiedToken>,
+    previous_expressions: Vec<Expression>,
+    local_params: Vec<Param>,
+) -> Result<Vec<Expression>, CompileError> {
+    if let Some(error) = try_to_match(tokens, Token::LeftBracket) {
+        return Err(error);
+    }
+
+    let body_tokens = take_until_closing(tokens, Token::LeftBracket, Token::RightBracket)?;
+
+    parse_statements(body_tokens, previous_expressions, local_params)
+}
+
+/// The statements inside a `{ ... }`, with the comments between them and a
+/// blank line wherever they were separated by one or more
+pub fn parse_statements(
     tokens: Vec<FullyQualifiedToken>,
-    start: Token,
-    end: Token,
-) -> Option<Vec<FullyQualifiedToken>> {
-    let mut new_tokens: Vec<FullyQualifiedToken> = vec![];
-    let mut seen_start = 0;
-
-    for fqt in tokens {
-        if fqt.token == start {
-            seen_start += 1;
-        } else if seen_start > 1 && fqt.token == end {
-            return Some(new_tokens);
-        } else if seen_start > 1 {
-            new_tokens.push(fqt);
+    previous_expressions: Vec<Expression>,
+    local_params: Vec<Param>,
+) -> Result<Vec<Expression>, CompileError> {
+    let mut scope = previous_expressions;
+    let mut body: Vec<Expression> = vec![];
+    let mut last_line: Option<i32> = None;
+
+    for expression_tokens in split_by_semicolon_within_brackets(tokens).iter() {
+        let (Some(first), Some(last)) = (expression_tokens.first(), expression_tokens.last())
+        else {
+            continue;
+        };
+
+        if last_line.is_some_and(|line| first.info.line > line + 1) {
+            body.push(Expression::Trivia {
+                trivia: Trivia::BlankLine,
+            });
         }
+        last_line = Some(last.info.line);
+
+        let exp = match (expression_tokens.as_slice(), &first.token) {
+            // a comment trailing the opening brace has no statement to follow
+            ([_], Token::Comment { body: comment, .. }) if body.is_empty() => Expression::Trivia {
+                trivia: Trivia::Comment(comment.to_string()),
+            },
+            (
+                [_],
+                Token::Comment {
+                    body: comment,
+                    trailing,
+                },
+            ) => Expression::Trivia {
+                trivia: if *trailing {
+                    Trivia::TrailingComment(comment.to_string())
+                } else {
+                    Trivia::Comment(comment.to_string())
+                },
+            },
+            _ => parse_expression(
+                &mut expression_tokens.iter(),
+                scope.clone(),
+                local_params.clone(),
+            )?,
+        };
+        scope.push(exp.clone());
+        body.push(exp);
     }
 
-    None
+    Ok(body)
 }
 
 fn parse_params(
     tokens: &mut Iter<'_, FullyQualifiedToken>,
     previous_expressions: Vec<Expression>,
     local_params: Vec<Param>,
-) -> Result<Vec<Expression>, String> {
-    let mut tokens_for_current_expression: Vec<FullyQualifiedToken> = vec![];
-    let mut arguments: Vec<Expression> = vec![];
+) -> Result<Vec<Expression>, CompileError> {
+    take_argument_list(tokens)?
+        .iter()
+        .map(|argument_tokens| {
+            parse_expression(
+                &mut argument_tokens.iter(),
+                previous_expressions.clone(),
+                local_params.clone(),
+            )
+        })
+        .collect()
+}
 
-    while let maybe_fqt = tokens.next() {
-        match maybe_fqt {
+/// The last part of a for header, `up` or `down`
+fn parse_direction(tokens: &[FullyQualifiedToken]) -> Result<Direction, CompileError> {
+    match tokens {
+        [fqt] => match &fqt.token {
+            Token::Identifier { body } if body == "up" => Ok(Direction::Up),
+            Token::Identifier { body } if body == "down" => Ok(Direction::Down),
+            token => error_with_info(
+                format!(
+                    "Expected up or down as the direction of a for, not {}",
+                    token
+                ),
+                fqt,
+            ),
+        },
+        _ => error_without_info(String::from(
+            "Expected up or down as the direction of a for",
+        )),
+    }
+}
+
+/// `predicate ? success : fail`, where `question` is where the `?` is
+fn parse_conditional(
+    tokens: &[FullyQualifiedToken],
+    question: usize,
+    previous_expressions: Vec<Expression>,
+    local_params: Vec<Param>,
+) -> Result<Expression, CompileError> {
+    let (predicate_tokens, rest) = tokens.split_at(question);
+    let rest = &rest[1..];
+
+    // the : belonging to this ?, skipping those of conditionals inside it
+    let mut depth = 0;
+    let mut pending = 0;
+    let mut colon: Option<usize> = None;
+    for (index, fqt) in rest.iter().enumerate() {
+        match fqt.token {
+            Token::LeftParen | Token::LeftBracket => depth += 1,
+            Token::RightParen | Token::RightBracket => depth -= 1,
+            Token::Question if depth == 0 => pending += 1,
+            Token::Colon if depth == 0 && pending > 0 => pending -= 1,
+            Token::Colon if depth == 0 => {
+                colon = Some(index);
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    let Some(colon) = colon else {
+        return error_with_info(
+            String::from("Expected : after the first value of a conditional"),
+            &tokens[question],
+        );
+    };
+    let (success_tokens, fail_tokens) = rest.split_at(colon);
+
+    let predicate = parse_expression(
+        &mut predicate_tokens.iter(),
+        previous_expressions.clone(),
+        local_params.clone(),
+    )?;
+    let success = parse_expression(
+        &mut success_tokens.iter(),
+        previous_expressions.clone(),
+        local_params.clone(),
+    )?;
+    let fail = parse_expression(
+        &mut fail_tokens[1..].iter(),
+        previous_expressions,
+        local_params,
+    )?;
+
+    let type_name = operand_type(&success, &fail);
+
+    Ok(Expression::Conditional {
+        predicate: Box::new(predicate),
+        success: Box::new(success),
+        fail: Box::new(fail),
+        type_name,
+    })
+}
+
+/// The arms of a match, after the subject and `{` have been taken
+fn parse_match_arms(
+    arm_tokens: Vec<FullyQualifiedToken>,
+    previous_expressions: &[Expression],
+    local_params: &[Param],
+) -> Result<MatchArms, CompileError> {
+    let mut arms: Vec<(i64, Vec<Expression>)> = vec![];
+    let mut default: Option<Vec<Expression>> = None;
+    let mut pending: Vec<Expression> = vec![];
+
+    for arm in split_top_level(arm_tokens, Token::Comma) {
+        // a trailing comma after the last arm
+        if arm.is_empty() {
+            continue;
+        }
+
+        // comments before or after an arm go at the start or end of its body
+        let as_trivia = |fqt: &FullyQualifiedToken| match &fqt.token {
+            Token::Comment { body, .. } => Some(Expression::Trivia {
+                trivia: Trivia::Comment(body.to_string()),
+            }),
+            _ => None,
+        };
+        let leading: Vec<Expression> = arm.iter().map_while(as_trivia).collect();
+        let mut trailing: Vec<Expression> = arm.iter().rev().map_while(as_trivia).collect();
+        trailing.reverse();
+
+        if leading.len() == arm.len() {
+            pending.extend(leading);
+            continue;
+        }
+
+        let mut tokens = arm[leading.len()..arm.len() - trailing.len()].iter();
+        let pattern = tokens.next();
+
+        match (tokens.next(), tokens.next()) {
+            (Some(assign), Some(arrow))
+                if assign.token == Token::Assign && arrow.token == Token::GreaterThan => {}
+            (Some(fqt), _) => {
+                return error_with_info(
+                    format!("Expected => after a match pattern but got {}", fqt.token),
+                    fqt,
+                )
+            }
+            (None, _) => {
+                return error_without_info(String::from(
+                    "Expected => after a match pattern but got nothing",
+                ))
+            }
+        }
+
+        let body = if tokens.clone().next().map(|fqt| &fqt.token) == Some(&Token::LeftBracket) {
+            let body = parse_body(
+                &mut tokens,
+                previous_expressions.to_vec(),
+                local_params.to_vec(),
+            )?;
+
+            if let Some(fqt) = tokens.next() {
+                return error_with_info(
+                    format!(
+                        "Unexpected token {} after the end of a match arm",
+                        fqt.token
+                    ),
+                    fqt,
+                );
+            }
+
+            body
+        } else {
+            vec![parse_expression(
+                &mut tokens,
+                previous_expressions.to_vec(),
+                local_params.to_vec(),
+            )?]
+        };
+        let body = [std::mem::take(&mut pending), leading, body, trailing].concat();
+
+        match pattern {
             Some(fqt) => match &fqt.token {
-                Token::RightParen => break,
-                Token::Comma => {
-                    match parse_expression(
-                        &mut tokens_for_current_expression.iter(),
-                        previous_expressions.clone(),
-                        local_params.clone(),
-                    ) {
-                        Ok(exp) => arguments.push(exp),
-                        Err(error) => return Err(error),
-                    };
-
-                    tokens_for_current_expression.clear();
-                }
-                _ => {
-                    tokens_for_current_expression.push(fqt.clone());
+                Token::Identifier { body: name } if name == "_" => match default {
+                    Some(_) => {
+                        return error_with_info(
+                            String::from("A match can only have one _ arm"),
+                            fqt,
+                        )
+                    }
+                    None => default = Some(body),
+                },
+                Token::Number { body: number } => match number.parse::<i64>() {
+                    Ok(value) => arms.push((value, body)),
+                    Err(_) => {
+                        return error_with_info(
+                            format!("Match patterns are whole numbers or _, not {}", number),
+                            fqt,
+                        )
+                    }
+                },
+                token => {
+                    return error_with_info(
+                        format!("Match patterns are whole numbers or _, not {}", token),
+                        fqt,
+                    )
                 }
             },
-            None => return Err(String::from("Failed parsing params")),
+            None => return error_without_info(String::from("Expected a match pattern")),
         }
     }
 
-    if !tokens_for_current_expression.is_empty() {
-        match parse_expression(
-            &mut tokens_for_current_expression.iter(),
-            previous_expressions,
-            local_params,
-        ) {
-            Ok(exp) => arguments.push(exp),
-            Err(error) => return Err(error),
-        };
+    // comments after the last arm, which is the default when there is one
+    if let Some(body) = default
+        .as_mut()
+        .or_else(|| arms.last_mut().map(|(_, body)| body))
+    {
+        body.append(&mut pending);
     }
 
-    Ok(arguments)
+    Ok((arms, default))
 }
 
 fn find_type(
     variable_name: String,
     previous_expressions: Vec<Expression>,
     local_params: Vec<Param>,
-) -> Result<String, String> {
+) -> Option<String> {
     for param in local_params {
         if param.name == variable_name {
-            return Ok(param.type_name);
+            return Some(param.type_name);
         }
     }
 
     for expression in previous_expressions {
-        if let Expression::LocalAssign {
-            name,
-            type_name,
-            expression: _,
-        } = expression
-        {
-            if name == variable_name {
-                return Ok(type_name);
+        match expression {
+            Expression::LocalAssign {
+                name, type_name, ..
+            } if name == variable_name => return Some(type_name),
+            // closures can be passed to params of function type
+            Expression::Closure { function } if function.name == variable_name => {
+                return Some(function.function_type())
             }
+            _ => (),
         }
     }
 
-    Err(format!("Couldn't find type for variable {}", variable_name))
+    None
 }
 
 pub fn parse_expression(
     tokens: &mut Iter<'_, FullyQualifiedToken>,
     previous_expressions: Vec<Expression>,
     local_params: Vec<Param>,
-) -> Result<Expression, String> {
-    let has_addition = tokens.clone().any(|fqt| fqt.token == Token::Plus);
-    let has_assign = tokens.clone().any(|fqt| fqt.token == Token::Assign);
+) -> Result<Expression, CompileError> {
+    if tokens.as_slice().first().map(|fqt| &fqt.token) == Some(&Token::Fn) {
+        return parse_closure(
+            tokens.as_slice().to_vec(),
+            &previous_expressions,
+            &local_params,
+        )
+        .map(|function| Expression::Closure {
+            function: Box::new(function),
+        });
+    }
+
+    // only look outside of parens and brackets, so that calls and blocks keep their additions
+    let addition = find_top_level(tokens.as_slice(), Token::Plus);
+    let has_assign = find_top_level(tokens.as_slice(), Token::Assign).is_some();
 
-    if has_addition && !has_assign {
-        let sides: Vec<Vec<FullyQualifiedToken>> = tokens
-            .clone()
-            .as_slice()
-            .splitn(2, |fqt| fqt.token == Token::Plus)
-            .map(|v| v.to_vec())
-            .collect();
+    // conditionals are looser than additions, and statements like return hold them
+    let starts_statement = matches!(
+        tokens.as_slice().first().map(|fqt| &fqt.token),
+        Some(Token::Return) | Some(Token::TailCall) | Some(Token::Local) | Some(Token::Global)
+    );
+    let question = find_top_level(tokens.as_slice(), Token::Question);
+    if let (Some(question), false, false) = (question, has_assign, starts_statement) {
+        return parse_conditional(
+            tokens.as_slice(),
+            question,
+            previous_expressions,
+            local_params,
+        );
+    }
 
-        let left_tokens = &mut sides[0].iter();
-        let right_tokens = &mut sides[1].iter();
+    if let (Some(plus), false, None, false) = (addition, has_assign, question, starts_statement) {
+        let (left, right) = tokens.as_slice().split_at(plus);
+        let (left_tokens, right_tokens) = (&mut left.iter(), &mut right[1..].iter());
 
         return match parse_expression(
             left_tokens,
@@ -226,16 +919,41 @@ pub fn parse_expression(
                 previous_expressions.clone(),
                 local_params.clone(),
             ) {
-                Ok(right) => Ok(Expression::Addition {
-                    left: Box::new(left),
-                    right: Box::new(right),
-                }),
+                Ok(right) => {
+                    let type_name = operand_type(&left, &right);
+
+                    Ok(Expression::Addition {
+                        left: Box::new(left),
+                        right: Box::new(right),
+                        type_name,
+                        info: Some(tokens.as_slice()[plus].info.clone()),
+                    })
+                }
                 Err(err) => Err(err),
             },
             Err(err) => Err(err),
         };
     }
 
+    // a whole expression wrapped in parens, like `(1 + 2)`
+    if let [first, .., last] = tokens.as_slice() {
+        if first.token == Token::LeftParen && last.token == Token::RightParen {
+            let mut inner = tokens.clone();
+            inner.next();
+
+            if take_until_closing(&mut inner, Token::LeftParen, Token::RightParen)
+                .is_ok_and(|_| inner.as_slice().is_empty())
+            {
+                let inner_tokens = &tokens.as_slice()[1..tokens.as_slice().len() - 1];
+                return parse_expression(
+                    &mut inner_tokens.iter(),
+                    previous_expressions,
+                    local_params,
+                );
+            }
+        }
+    }
+
     while let maybe_fqt = tokens.next() {
         match maybe_fqt {
             Some(fqt) => {
@@ -243,10 +961,38 @@ pub fn parse_expression(
                     Token::Return => {
                         return parse_expression(tokens, previous_expressions, local_params).map(|exp| Expression::Return {
                             expression: Box::new(exp),
+                            info: Some(fqt.start()),
                         })
                     }
-                    Token::Local => match tokens.next().map(|fqt|  &fqt.token) {
-                        Some(Token::Identifier { body: name }) => {
+                    Token::TailCall => {
+                        return match parse_expression(tokens, previous_expressions, local_params)? {
+                            Expression::FunctionCall { name, .. } if find_builtin(&name).is_some() => {
+                                error_with_info(format!("{} is a builtin and can't be tail called", name), fqt)
+                            }
+                            Expression::FunctionCall { name, args, .. } => Ok(Expression::TailCall { name, args }),
+                            _ => error_with_info(String::from("tailcall needs a function call, like tailcall f(x)"), fqt),
+                        }
+                    }
+                    Token::Local => match tokens.next().map(|fqt| check_name(fqt, "local").map(|_| (&fqt.token, fqt.start()))).transpose()? {
+                        Some((Token::Identifier { body: name }, info)) if tokens.clone().next().map(|fqt| &fqt.token) == Some(&Token::Assign) => {
+                            tokens.next();
+
+                            let exp = parse_expression(tokens, previous_expressions, local_params)?;
+
+                            return match exp.type_name() {
+                                Some(type_name) => Ok(Expression::LocalAssign {
+                                    name: name.to_string(),
+                                    type_name,
+                                    expression: Box::new(exp),
+                                    info: Some(info),
+                                }),
+                                None => error_without_info(format!(
+                                    "Couldn't infer the type of local {}, add a type annotation",
+                                    name
+                                )),
+                            };
+                        }
+                        Some((Token::Identifier { body: name }, info)) => {
                             // skip ":"
                             if let Some(error) = try_to_match(tokens, Token::Colon) {
                                 return Err(error);
@@ -263,10 +1009,31 @@ pub fn parse_expression(
                                         return parse_expression(tokens, previous_expressions, local_params).map(|exp| Expression::LocalAssign {
                                             name: name.to_string(),
                                             type_name: type_name.to_string(),
-                                            expression: Box::new(exp.map(|expression| match expression {
-                                                Expression::Number { value, type_name: _ } => Expression::Number { value, type_name: type_name.to_string() },
-                                                _ => expression
-                                            })),
+                                            expression: Box::new(exp),
+                                            info: Some(info),
+                                        });
+                                    }
+
+                                    Token::LeftSquare => {
+                                        let element = match (tokens.next().map(|fqt| &fqt.token), tokens.next().map(|fqt| &fqt.token)) {
+                                            (Some(Token::Identifier { body }), Some(Token::RightSquare)) => body.to_string(),
+                                            _ => return error_with_info(format!("The array type of {} should be written like [i32]", name), fqt),
+                                        };
+
+                                        if let Some(error) = try_to_match(tokens, Token::Assign) {
+                                            return Err(error);
+                                        }
+
+                                        let exp = match parse_expression(tokens, previous_expressions, local_params)? {
+                                            Expression::Array { values, .. } => Expression::Array { type_name: element.clone(), values },
+                                            exp => exp,
+                                        };
+
+                                        return Ok(Expression::LocalAssign {
+                                            name: name.to_string(),
+                                            type_name: format!("[{}]", element),
+                                            expression: Box::new(exp),
+                                            info: Some(info),
                                         });
                                     }
 
@@ -278,26 +1045,26 @@ pub fn parse_expression(
                                     }
                                 }
                                 None => {
-                                    return Err(String::from(
+                                    return error_without_info(String::from(
                                         "Failed parsing expression, was expecting an identifier token for the type name",
                                     ))
                                 }
                             }
                         }
-                        Some(token) => {
-                            return Err(format!(
+                        Some((token, _)) => {
+                            return error_without_info(format!(
                                 "Failed parsing expression, got unexpected token {}",
                                 token
                             ))
                         }
                         None => {
-                            return Err(String::from(
+                            return error_without_info(String::from(
                                 "Failed parsing expression, was expecting an identifier token for the variable name",
                             ))
                         }
                     },
                     Token::Global => match tokens.next() {
-                        Some(fqt) => match &fqt.token {
+                        Some(fqt) => match check_name(fqt, "global").map(|_| &fqt.token)? {
                             Token::Identifier { body: name } => {
                                 // skip ":"
                                 if let Some(error) = try_to_match(tokens, Token::Colon) {
@@ -319,13 +1086,13 @@ pub fn parse_expression(
                                     }
 
                                     Some(token) => {
-                                        return Err(format!(
+                                        return error_without_info(format!(
                                             "Failed parsing expression, got unexpected token {}",
                                             token
                                         ))
                                     }
                                     None => {
-                                        return Err(String::from(
+                                        return error_without_info(String::from(
                                             "Failed parsing expression, was expecting an identifier token for the type name",
                                         ))
                                     }
@@ -340,25 +1107,119 @@ pub fn parse_expression(
 
                         }
                         None => {
-                            return Err(String::from(
+                            return error_without_info(String::from(
                                 "Failed parsing expression, was expecting an identifier token for the variable name",
                             ))
                         }
                     },
+                    Token::Identifier { body } if (body == "assert" || body == "assert_eq") && tokens.clone().next().map(|fqt| &fqt.token) == Some(&Token::LeftParen) => {
+                        tokens.next();
+
+                        let mut args = parse_params(tokens, previous_expressions, local_params)?.into_iter().map(Box::new);
+
+                        return match (body.as_str(), args.next(), args.next(), args.next()) {
+                            ("assert", Some(left), None, _) => Ok(Expression::Assert {
+                                left,
+                                right: None,
+                                type_name: String::from("i32"),
+                                line: fqt.info.line,
+                            }),
+                            ("assert_eq", Some(left), Some(right), None) => Ok(Expression::Assert {
+                                type_name: left.type_name().or(right.type_name()).unwrap_or(String::from("f32")),
+                                left,
+                                right: Some(right),
+                                line: fqt.info.line,
+                            }),
+                            ("assert", ..) => error_with_info(String::from("assert takes 1 argument"), fqt),
+                            _ => error_with_info(String::from("assert_eq takes 2 arguments"), fqt),
+                        };
+                    }
+                    Token::Identifier { body } if body == "panic" && tokens.clone().next().map(|fqt| &fqt.token) == Some(&Token::LeftParen) => {
+                        tokens.next();
+
+                        return match parse_params(tokens, previous_expressions, local_params)?.as_slice() {
+                            [message @ Expression::String { .. }] => Ok(Expression::Panic { message: Box::new(message.clone()) }),
+                            _ => error_with_info(String::from("panic takes a message in quotes"), fqt),
+                        };
+                    }
+                    Token::Identifier { body } if body == "match" && !matches!(tokens.clone().next().map(|fqt| &fqt.token), None | Some(Token::LeftParen) | Some(Token::Dot)) => {
+                        let info = fqt.start();
+                        // the subject goes up to the { starting the arms
+                        let mut subject_tokens: Vec<FullyQualifiedToken> = vec![];
+                        let mut depth = 0;
+                        loop {
+                            match tokens.next() {
+                                Some(fqt) if fqt.token == Token::LeftBracket && depth == 0 => break,
+                                Some(fqt) => {
+                                    match fqt.token {
+                                        Token::LeftParen => depth += 1,
+                                        Token::RightParen => depth -= 1,
+                                        _ => (),
+                                    }
+                                    subject_tokens.push(fqt.clone());
+                                }
+                                None => return error_with_info(String::from("Expected { after the subject of a match"), fqt),
+                            }
+                        }
+                        let subject = parse_expression(&mut subject_tokens.iter(), previous_expressions.clone(), local_params.clone())?;
+
+                        let arm_tokens = take_until_closing(tokens, Token::LeftBracket, Token::RightBracket)?;
+                        let (arms, default) = parse_match_arms(arm_tokens, &previous_expressions, &local_params)?;
+
+                        if let Some(fqt) = tokens.next() {
+                            return error_with_info(format!("Unexpected token {} after the end of a match", fqt.token), fqt);
+                        }
+
+                        return Ok(Expression::MatchStatement {
+                            type_name: subject.type_name().unwrap_or_default(),
+                            subject: Box::new(subject),
+                            arms,
+                            default,
+                            info: Some(info),
+                        })
+                    }
                     Token::Identifier { body } => {
+                        let info = fqt.start();
                         match tokens.next() {
                             Some(fqt) => match &fqt.token {
                                 Token::LeftParen => match parse_params(tokens, previous_expressions, local_params) {
-                                    Ok(expressions) => return Ok(Expression::FunctionCall { name: body.to_string(), args: expressions.to_vec() }),
+                                    Ok(expressions) => return Ok(Expression::FunctionCall { name: body.to_string(), args: expressions.to_vec(), info: Some(info) }),
                                     Err(error) => return Err(error)
                                 },
+                                Token::LeftSquare => {
+                                    let index_tokens = take_until_closing(tokens, Token::LeftSquare, Token::RightSquare)?;
+                                    if let Some(fqt) = tokens.next() {
+                                        return error_with_info(format!("Unexpected token {}", fqt.token), fqt);
+                                    }
+
+                                    let type_name = match find_type(body.to_string(), previous_expressions.clone(), local_params.clone()) {
+                                        Some(type_name) if type_name.starts_with('[') && type_name.ends_with(']') => type_name[1..type_name.len() - 1].to_string(),
+                                        _ => return error_with_info(format!("{} isn't an array, so can't be indexed", body), fqt),
+                                    };
+
+                                    return Ok(Expression::Index {
+                                        name: body.to_string(),
+                                        type_name,
+                                        index: Box::new(parse_expression(&mut index_tokens.iter(), previous_expressions, local_params)?),
+                                        length: 0,
+                                        line: fqt.info.line,
+                                    });
+                                }
+                                Token::Dot => match tokens.next().map(|fqt| &fqt.token) {
+                                    Some(Token::Identifier { body: field }) if (field == "offset" || field == "length") && tokens.clone().next().is_none() => {
+                                        return Ok(Expression::DataField { name: body.to_string(), field: field.to_string() })
+                                    }
+                                    _ if find_type(body.to_string(), previous_expressions, local_params).is_some_and(|type_name| type_name.starts_with('[')) => {
+                                        return error_with_info(format!("Array {} only has an offset and a length", body), fqt)
+                                    }
+                                    _ => return error_with_info(format!("Data {} only has an offset and a length", body), fqt)
+                                },
                                 token => return error_with_info(format!("Unexpected token {}", token), fqt)
                             }
                             None => {
-                                return find_type(body.to_string(), previous_expressions, local_params).map(|type_name|
-                                    Expression::Variable {
-                                    body: body.to_string(),
-                                    type_name
+                                return Ok(match find_type(body.to_string(), previous_expressions, local_params) {
+                                    Some(type_name) => Expression::Variable { body: body.to_string(), type_name },
+                                    None => Expression::Constant { name: body.to_string(), type_name: String::new() },
                                 })
                             }
                         }
@@ -367,75 +1228,126 @@ pub fn parse_expression(
                     Token::Text { body } => return Ok(Expression::String { body: body.to_string() }),
                     Token::Number { body } => return Ok(Expression::Number { value: body.to_string(), type_name: String::from("f32") }),
                     Token::If => {
-                        let tokens_clone = tokens.cloned().collect::<Vec<FullyQualifiedToken>>();
-                        let predicate_tokens = match between_next(tokens_clone.clone(), Token::LeftParen, Token::RightParen) {
-                            Some(fqts) => fqts,
-                            None => return Err(String::from("Couldn't find predicate tokens"))
-                        };
-
+                        if let Some(error) = try_to_match(tokens, Token::LeftParen) {
+                            return Err(error);
+                        }
+                        let predicate_tokens = take_until_closing(tokens, Token::LeftParen, Token::RightParen)?;
                         let predicate = match parse_expression(&mut predicate_tokens.iter(), previous_expressions.clone(), local_params.clone()) {
                             Err(error) => return Err(error),
                             Ok(v) => v,
                         };
 
-                        let success_tokens = match between_next(tokens_clone.clone(), Token::LeftBracket, Token::RightBracket) {
-                            Some(fqts) => fqts,
-                            None => return Err(String::from("Couldn't find success tokens"))
-                        };
-
-                        let mut success: Vec<Expression> = vec![];
-                        let success_tokens_split_by_semicolon: Vec<Vec<FullyQualifiedToken>> =
-                            split_by_semicolon_within_brackets(success_tokens);
+                        let success = parse_body(tokens, previous_expressions.clone(), local_params.clone())?;
 
-                        for expression_tokens in success_tokens_split_by_semicolon.iter() {
-                            if expression_tokens.is_empty() {
-                                continue;
-                            }
-                            let exp = parse_expression(
-                                &mut expression_tokens.iter(),
-                                previous_expressions.clone(),
-                                local_params.clone(),
-                            )?;
-                            success.push(exp);
-                        }
-
-                        let fail_tokens = match between_next_next(tokens_clone.clone(), Token::LeftBracket, Token::RightBracket) {
-                            Some(fqts) => fqts,
-                            None => return Err(String::from("Couldn't find fail tokens"))
+                        // the else branch is optional, but needs its keyword when it's there
+                        let fail = match tokens.next() {
+                            None => vec![],
+                            Some(fqt) if fqt.token == Token::Else => match tokens.clone().next() {
+                                Some(next) if next.token == Token::LeftBracket => {
+                                    parse_body(tokens, previous_expressions.clone(), local_params.clone())?
+                                }
+                                Some(next) => return error_with_info(format!("Expected {{ after else but got {}", next.token), next),
+                                None => return error_with_info(String::from("Expected { after else but got nothing"), fqt),
+                            },
+                            Some(fqt) => return error_with_info(format!("Expected else or ; after the first branch of an if but got {}", fqt.token), fqt),
                         };
 
-                        let mut fail: Vec<Expression> = vec![];
-                        let fail_tokens_split_by_semicolon: Vec<Vec<FullyQualifiedToken>> =
-                            split_by_semicolon_within_brackets(fail_tokens);
-
-                        for expression_tokens in fail_tokens_split_by_semicolon.iter() {
-                            if expression_tokens.is_empty() {
-                                continue;
-                            }
-                            let exp = parse_expression(
-                                &mut expression_tokens.iter(),
-                                previous_expressions.clone(),
-                                local_params.clone(),
-                            )?;
-                            fail.push(exp);
+                        if let Some(fqt) = tokens.next() {
+                            return error_with_info(format!("Unexpected token {} after the end of an if", fqt.token), fqt);
                         }
 
                         return Ok(Expression::IfStatement {
                             predicate: Box::new(predicate),
                             success,
                             fail
-
                         })
                     }
+                    Token::Try => {
+                        let body = parse_body(tokens, previous_expressions.clone(), local_params.clone())?;
+
+                        if let Some(error) = try_to_match(tokens, Token::Catch) {
+                            return Err(error);
+                        }
+
+                        let catch = parse_body(tokens, previous_expressions.clone(), local_params.clone())?;
+
+                        if let Some(fqt) = tokens.next() {
+                            return error_with_info(format!("Unexpected token {} after the end of a try", fqt.token), fqt);
+                        }
+
+                        return Ok(Expression::TryStatement { body, catch })
+                    }
+                    Token::LeftSquare => {
+                        let list_tokens = take_until_closing(tokens, Token::LeftSquare, Token::RightSquare)?;
+                        if let Some(fqt) = tokens.next() {
+                            return error_with_info(format!("Unexpected token {}", fqt.token), fqt);
+                        }
+
+                        let values = split_top_level(list_tokens, Token::Comma)
+                            .into_iter()
+                            .filter(|value_tokens| !value_tokens.is_empty())
+                            .map(|value_tokens| parse_expression(&mut value_tokens.iter(), previous_expressions.clone(), local_params.clone()))
+                            .collect::<Result<Vec<Expression>, CompileError>>()?;
+
+                        return Ok(Expression::Array {
+                            type_name: values.first().and_then(|value| value.type_name()).unwrap_or_default(),
+                            values,
+                        });
+                    }
+                    Token::Character { body } => match character_value(body) {
+                        Ok(value) => return Ok(Expression::Character { value }),
+                        Err(error) => return error_with_info(error, fqt),
+                    },
                     Token::True => return Ok(Expression::Boolean { value: true }),
                     Token::False => return Ok(Expression::Boolean { value: false }),
                     Token::For => {
-                        let tokens_clone = tokens.cloned().collect::<Vec<FullyQualifiedToken>>();
+                        if let Some(error) = try_to_match(tokens, Token::LeftParen) {
+                            return Err(error);
+                        }
+                        let header_tokens = take_until_closing(tokens, Token::LeftParen, Token::RightParen)?;
+
+                        if let [name_fqt, in_fqt, array_fqt] = header_tokens.as_slice() {
+                            if let (Token::Identifier { body: name }, Token::Identifier { body: keyword }, Token::Identifier { body: array }) = (&name_fqt.token, &in_fqt.token, &array_fqt.token) {
+                                if keyword == "in" {
+                                    check_name(name_fqt, "local")?;
+
+                                    let type_name = match find_type(array.to_string(), previous_expressions.clone(), local_params.clone()) {
+                                        Some(type_name) if type_name.starts_with('[') && type_name.ends_with(']') => type_name[1..type_name.len() - 1].to_string(),
+                                        _ => return error_with_info(format!("{} isn't an array, so can't be looped over", array), array_fqt),
+                                    };
+
+                                    // the body sees the element as a local
+                                    let mut previous_expressions_with_element = previous_expressions.clone();
+                                    previous_expressions_with_element.push(Expression::LocalAssign {
+                                        name: name.to_string(),
+                                        type_name: type_name.to_string(),
+                                        expression: Box::new(Expression::Number { value: String::from("0"), type_name: type_name.to_string() }),
+                                        info: Some(name_fqt.start()),
+                                    });
+
+                                    let body = parse_body(tokens, previous_expressions_with_element, local_params.clone())?;
 
-                        let initializer_tokens = match between_next(tokens_clone.clone(), Token::LeftParen, Token::Comma) {
-                            Some(fqts) => fqts,
-                            None => return Err(String::from("Couldn't find initializer tokens"))
+                                    if let Some(fqt) = tokens.next() {
+                                        return error_with_info(format!("Unexpected token {} after the end of a for", fqt.token), fqt);
+                                    }
+
+                                    return Ok(Expression::ForEach {
+                                        name: name.to_string(),
+                                        type_name,
+                                        array: array.to_string(),
+                                        body,
+                                        line: fqt.info.line,
+                                    });
+                                }
+                            }
+                        }
+
+                        let (initializer_tokens, conditional_tokens, incrementor_tokens, direction) = match split_top_level(header_tokens, Token::Comma).as_slice() {
+                            [initializer, conditional, incrementor] => (initializer.clone(), conditional.clone(), incrementor.clone(), Direction::Up),
+                            [initializer, conditional, incrementor, direction] => (initializer.clone(), conditional.clone(), incrementor.clone(), parse_direction(direction)?),
+                            _ => return error_without_info(String::from("Expected an initializer, a break condition, an incrementor and maybe a direction in for"))
                         };
+
                         let initializer = match parse_expression(&mut initializer_tokens.iter(), previous_expressions.clone(), local_params.clone()) {
                             Err(error) => return Err(error),
                             Ok(v) => v,
@@ -444,56 +1356,27 @@ pub fn parse_expression(
                         let mut previous_expression_with_initializer = previous_expressions.clone();
                         previous_expression_with_initializer.push(initializer.clone());
 
-                        let conditional_tokens = match between_next(tokens_clone.clone(), Token::Comma, Token::Comma) {
-                            Some(fqts) => fqts,
-                            None => return Err(String::from("Couldn't find conditional tokens"))
-                        };
                         let conditional = match parse_expression(&mut conditional_tokens.iter(), previous_expression_with_initializer.clone(), local_params.clone()) {
                             Err(error) => return Err(error),
                             Ok(v) => v,
-                        }.map(|expression| match expression {
-                            Expression::Number { value, type_name: _ } => Expression::Number { value, type_name: String::from("i32") },
-                            _ => expression
-                        });
-
-                        let incrementor_tokens = match between_next_next(tokens_clone.clone(), Token::Comma, Token::RightParen) {
-                            Some(fqts) => fqts,
-                            None => return Err(String::from("Couldn't find incrementor tokens"))
                         };
+
                         let incrementor = match parse_expression(&mut incrementor_tokens.iter(), previous_expression_with_initializer.clone(), local_params.clone()) {
                             Err(error) => return Err(error),
                             Ok(v) => v,
-                        }.map(|expression| match expression {
-                            Expression::Number { value, type_name: _ } => Expression::Number { value, type_name: String::from("i32") },
-                            _ => expression
-                        });
-
-                        let body_tokens = match between_next(tokens_clone.clone(), Token::LeftBracket, Token::RightBracket) {
-                            Some(fqts) => fqts,
-                            None => return Err(String::from("Couldn't find body tokens"))
                         };
-                        let mut body: Vec<Expression> = vec![];
-                        let tokens_split_by_semicolon: Vec<Vec<FullyQualifiedToken>> =
-                            split_by_semicolon_within_brackets(body_tokens);
 
-                        for expression_tokens in tokens_split_by_semicolon.iter() {
-                            if expression_tokens.is_empty() {
-                                continue;
-                            }
-                            match parse_expression(
-                                &mut expression_tokens.iter(),
-                                previous_expression_with_initializer.clone(),
-                                local_params.clone(),
-                            ) {
-                                Ok(exp) => body.push(exp),
-                                Err(error) => return Err(error),
-                            }
+                        let body = parse_body(tokens, previous_expression_with_initializer, local_params.clone())?;
+
+                        if let Some(fqt) = tokens.next() {
+                            return error_with_info(format!("Unexpected token {} after the end of a for", fqt.token), fqt);
                         }
 
                         return Ok(Expression::ForStatement{
                             initial_value: Box::new(initializer),
                             incrementor: Box::new(incrementor),
                             break_condition: Box::new(conditional),
+                            direction,
                             body
                         })
                     }
@@ -505,9 +1388,13 @@ pub fn parse_expression(
                     }
                 }
             }
-            None => return Err(String::from("Failed parsing expression, ran out of tokens")),
+            None => {
+                return error_without_info(String::from(
+                    "Failed parsing expression, ran out of tokens",
+                ))
+            }
         }
     }
 
-    Err(String::from(""))
+    error_without_info(String::from(""))
 }
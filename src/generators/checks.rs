@@ -0,0 +1,309 @@
+use crate::{
+    expressions::{Direction, Expression},
+    visit::{walk_expression, Visitor},
+};
+
+/// A runtime check that debug builds put around an instruction. Each check is
+/// a helper function which does what the instruction would, after calling the
+/// panic handler with a message saying where it failed when it doesn't hold
+#[derive(PartialEq, Debug, Clone)]
+pub enum Check {
+    /// `memory_copy` or `memory_fill` stays inside the memory
+    Bounds {
+        builtin: String,
+        memory: Option<String>,
+    },
+    /// A for loop's step doesn't wrap its integer variable around
+    Overflow {
+        type_name: String,
+        direction: Direction,
+    },
+    /// An index into an array is less than its length. Every index shares a
+    /// helper, but each has its own message saying which line it's on
+    Index { array: String, line: i32 },
+}
+
+fn step(direction: &Direction) -> &'static str {
+    match direction {
+        Direction::Up => "add",
+        Direction::Down => "sub",
+    }
+}
+
+impl Check {
+    pub fn helper_name(&self) -> String {
+        match self {
+            Check::Bounds {
+                builtin,
+                memory: Some(memory),
+            } => format!("__check_{}_{}", builtin, memory),
+            Check::Bounds { builtin, .. } => format!("__check_{}", builtin),
+            Check::Overflow {
+                type_name,
+                direction,
+            } => format!("__check_{}_{}", type_name, step(direction)),
+            Check::Index { .. } => String::from("__check_index"),
+        }
+    }
+
+    /// What the panic handler is given when the check fails in `function`
+    pub fn message(&self, function: &str) -> String {
+        match self {
+            Check::Bounds { builtin, .. } => {
+                format!("{} out of bounds in {}", builtin, function)
+            }
+            Check::Overflow { type_name, .. } => {
+                format!("{} for loop overflowed in {}", type_name, function)
+            }
+            Check::Index { array, line } => {
+                format!(
+                    "{} index out of bounds on line {} in {}",
+                    array,
+                    line + 1,
+                    function
+                )
+            }
+        }
+    }
+
+    /// The helper, taking the instruction's operands followed by the offset
+    /// and length of the message
+    pub fn generate_helper(&self, handler: Option<&str>) -> String {
+        let fail = match handler {
+            Some(handler) => format!(
+                "(call ${} (local.get $message) (local.get $message_length))\n      (unreachable)",
+                handler
+            ),
+            None => String::from("(unreachable)"),
+        };
+
+        match self {
+            Check::Bounds { builtin, memory } => {
+                let memory_index = match memory {
+                    Some(memory) => format!(" ${}", memory),
+                    None => String::new(),
+                };
+                let second = if builtin == "memory_copy" {
+                    "src"
+                } else {
+                    "val"
+                };
+                let past_end = |start: &str| {
+                    format!(
+                        "(i64.gt_u
+      (i64.add (i64.extend_i32_u (local.get ${})) (i64.extend_i32_u (local.get $len)))
+      (i64.mul (i64.extend_i32_u (memory.size{})) (i64.const 65536)))",
+                        start, memory_index
+                    )
+                };
+                let out_of_bounds = if builtin == "memory_copy" {
+                    format!("(i32.or\n    {}\n    {})", past_end("dst"), past_end("src"))
+                } else {
+                    past_end("dst")
+                };
+                let memories = if builtin == "memory_copy" {
+                    memory_index.repeat(2)
+                } else {
+                    memory_index.to_string()
+                };
+
+                format!(
+                    "(func ${} (param $dst i32) (param ${second} i32) (param $len i32) (param $message i32) (param $message_length i32)
+  (if {}
+    (then
+      {}
+    )
+  )
+  ({}{} (local.get $dst) (local.get ${second}) (local.get $len))
+)",
+                    self.helper_name(),
+                    out_of_bounds,
+                    fail,
+                    builtin.replace('_', "."),
+                    memories,
+                    second = second
+                )
+            }
+            Check::Overflow {
+                type_name,
+                direction,
+            } => {
+                let step = step(direction);
+                // i32s are stepped as i64s, where they can't overflow, and
+                // i64s overflow when the sign comes out wrong
+                let (result, overflowed) = if type_name == "i32" {
+                    (
+                        format!(
+                            "(i64.{} (i64.extend_i32_s (local.get $value)) (i64.extend_i32_s (local.get $step)))",
+                            step
+                        ),
+                        String::from(
+                            "(i64.ne (local.get $result) (i64.extend_i32_s (i32.wrap_i64 (local.get $result))))",
+                        ),
+                    )
+                } else {
+                    let same_sign = match direction {
+                        Direction::Up => "(i64.xor (local.get $step) (local.get $result))",
+                        Direction::Down => "(i64.xor (local.get $value) (local.get $step))",
+                    };
+
+                    (
+                        format!("(i64.{} (local.get $value) (local.get $step))", step),
+                        format!(
+                            "(i64.lt_s (i64.and (i64.xor (local.get $value) (local.get $result)) {}) (i64.const 0))",
+                            same_sign
+                        ),
+                    )
+                };
+                let wrapped = if type_name == "i32" {
+                    "(i32.wrap_i64 (local.get $result))"
+                } else {
+                    "(local.get $result)"
+                };
+
+                format!(
+                    "(func ${} (param $value {type_name}) (param $step {type_name}) (param $message i32) (param $message_length i32) (result {type_name})
+  (local $result i64)
+  (local.set $result {})
+  (if {}
+    (then
+      {}
+    )
+  )
+  {}
+)",
+                    self.helper_name(),
+                    result,
+                    overflowed,
+                    fail,
+                    wrapped,
+                    type_name = type_name
+                )
+            }
+            Check::Index { .. } => format!(
+                "(func ${} (param $index i32) (param $length i32) (param $message i32) (param $message_length i32) (result i32)
+  (if (i32.ge_u (local.get $index) (local.get $length))
+    (then
+      {}
+    )
+  )
+  (local.get $index)
+)",
+                self.helper_name(),
+                fail
+            ),
+        }
+    }
+}
+
+struct FindChecks<'a> {
+    memory: Option<&'a str>,
+    found: &'a mut Vec<Check>,
+}
+
+impl FindChecks<'_> {
+    fn add(&mut self, check: Check) {
+        if !self.found.contains(&check) {
+            self.found.push(check);
+        }
+    }
+}
+
+impl Visitor for FindChecks<'_> {
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::FunctionCall { name, .. }
+                if name == "memory_copy" || name == "memory_fill" =>
+            {
+                self.add(Check::Bounds {
+                    builtin: name.to_string(),
+                    memory: self.memory.map(str::to_string),
+                })
+            }
+            Expression::ForStatement {
+                initial_value,
+                direction,
+                ..
+            } => {
+                if let Expression::LocalAssign { type_name, .. } = initial_value.as_ref() {
+                    if type_name == "i32" || type_name == "i64" {
+                        self.add(Check::Overflow {
+                            type_name: type_name.to_string(),
+                            direction: direction.clone(),
+                        });
+                    }
+                }
+            }
+            Expression::Index { name, line, .. } => self.add(Check::Index {
+                array: name.to_string(),
+                line: *line,
+            }),
+            _ => (),
+        }
+
+        walk_expression(self, expression)
+    }
+}
+
+/// The checks needed by the expressions of a function using `memory`
+pub fn find_checks(expressions: &[Expression], memory: Option<&str>, found: &mut Vec<Check>) {
+    let mut finder = FindChecks { memory, found };
+
+    for expression in expressions {
+        finder.visit_expression(expression);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{blocks::Block, parser::parse};
+
+    #[test]
+    fn checks_are_found_once_in_nested_bodies() {
+        let program = parse(String::from(
+            "fn main(n: i32): void {
+    local table: [i32] = [1, 2];
+    memory_fill(0, 0, table[n]);
+    for (local i: i32 = 0, 10, 1) {
+        memory_fill(0, 0, i);
+    };
+    for (local j: i64 = 10, 0, 1, down) {
+    };
+    for (local x: f32 = 0.0, 1.0, 0.5) {
+    };
+}",
+        ))
+        .unwrap();
+        let mut found: Vec<Check> = vec![];
+
+        if let Some(Block::Function(function)) = program.blocks.first() {
+            find_checks(&function.expressions, Some("scratch"), &mut found);
+        }
+
+        assert_eq!(
+            found
+                .iter()
+                .map(|check| (check.helper_name(), check.message("main")))
+                .collect::<Vec<(String, String)>>(),
+            vec![
+                (
+                    String::from("__check_memory_fill_scratch"),
+                    String::from("memory_fill out of bounds in main")
+                ),
+                (
+                    String::from("__check_index"),
+                    String::from("table index out of bounds on line 3 in main")
+                ),
+                (
+                    String::from("__check_i32_add"),
+                    String::from("i32 for loop overflowed in main")
+                ),
+                (
+                    String::from("__check_i64_sub"),
+                    String::from("i64 for loop overflowed in main")
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::str::FromStr;
+
+use crate::{
+    blocks::{Block, Function, Param},
+    parser::Program,
+};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+impl FromStr for DocFormat {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "markdown" => Ok(DocFormat::Markdown),
+            "html" => Ok(DocFormat::Html),
+            _ => Err(format!(
+                "Unknown doc format {}, expected markdown or html",
+                str
+            )),
+        }
+    }
+}
+
+/// A documented signature, named the way callers of the module see it
+struct Entry {
+    name: String,
+    signature: String,
+    doc: Option<String>,
+}
+
+fn signature(keyword: &str, name: &str, params: &[Param], return_type: Option<&str>) -> String {
+    let params = params
+        .iter()
+        .map(|param| format!("{}: {}", param.name, param.type_name))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    match return_type {
+        Some(return_type) => format!("{} {}({}): {}", keyword, name, params, return_type),
+        None => format!("{} {}({})", keyword, name, params),
+    }
+}
+
+fn function_entry(name: &str, function: &Function) -> Entry {
+    Entry {
+        name: name.to_string(),
+        signature: signature(
+            "fn",
+            &function.name,
+            &function.params,
+            Some(&function.return_type),
+        ),
+        doc: function.doc.clone(),
+    }
+}
+
+fn exports(program: &Program) -> Vec<Entry> {
+    let functions: Vec<&Function> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function) => Some(function),
+            _ => None,
+        })
+        .collect();
+
+    program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Export(export) => functions
+                .iter()
+                .find(|function| function.name == export.function_name)
+                .map(|function| function_entry(&export.external_name, function)),
+            Block::Function(function) => function
+                .export_name()
+                .map(|external_name| function_entry(&external_name, function)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn imports(program: &Program) -> Vec<Entry> {
+    program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::ImportFunction(import) => Some(Entry {
+                name: import.external_name.join("."),
+                signature: signature("import fn", &import.name, &import.params, None),
+                doc: import.doc.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn markdown_section(heading: &str, entries: &[Entry]) -> String {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            let doc = match &entry.doc {
+                Some(doc) => format!("\n\n{}", doc),
+                None => String::new(),
+            };
+
+            format!(
+                "### {}\n\n```gwe\n{}\n```{}",
+                entry.name, entry.signature, doc
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n\n");
+
+    format!("## {}\n\n{}", heading, entries)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html_section(heading: &str, entries: &[Entry]) -> String {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            // blank lines in a doc comment separate paragraphs
+            let doc = match &entry.doc {
+                Some(doc) => doc
+                    .split("\n\n")
+                    .map(|paragraph| format!("\n<p>{}</p>", escape_html(paragraph)))
+                    .collect(),
+                None => String::new(),
+            };
+
+            format!(
+                "<h3 id=\"{}\">{}</h3>\n<pre><code>{}</code></pre>{}",
+                escape_html(&entry.name),
+                escape_html(&entry.name),
+                escape_html(&entry.signature),
+                doc
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!("<h2>{}</h2>\n{}", heading, entries)
+}
+
+/// Documents the functions a module exports, and the functions it needs to be given
+pub fn generate(program: &Program, title: &str, format: DocFormat) -> String {
+    let sections: Vec<(&str, Vec<Entry>)> =
+        vec![("Exports", exports(program)), ("Imports", imports(program))]
+            .into_iter()
+            .filter(|(_, entries)| !entries.is_empty())
+            .collect();
+
+    match format {
+        DocFormat::Markdown => {
+            let sections: String = sections
+                .iter()
+                .map(|(heading, entries)| format!("\n{}\n", markdown_section(heading, entries)))
+                .collect();
+
+            format!("# {}\n{}", title, sections)
+        }
+        DocFormat::Html => {
+            let sections = sections
+                .iter()
+                .map(|(heading, entries)| html_section(heading, entries))
+                .collect::<Vec<String>>()
+                .join("\n");
+
+            format!(
+                "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{title}</title>
+</head>
+<body>
+<h1>{title}</h1>
+{sections}
+</body>
+</html>
+",
+                title = escape_html(title),
+                sections = sections
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::join_errors, parser::parse};
+
+    const SOURCE: &str = "/// Writes a number to the console
+import fn log(number: i32) console.log
+
+/// Counts to three
+///
+/// Each number is logged
+fn main(): void {
+    for (local x: i32 = 0, 3, 1) {
+        log(x);
+    };
+}
+
+fn helper(): void {
+}
+
+export run main";
+
+    fn program() -> Program {
+        match parse(String::from(SOURCE)) {
+            Ok(program) => program,
+            Err(errors) => panic!("{}", join_errors(&errors)),
+        }
+    }
+
+    #[test]
+    fn markdown() {
+        assert_eq!(
+            generate(&program(), "counter", DocFormat::Markdown),
+            "# counter
+
+## Exports
+
+### run
+
+```gwe
+fn main(): void
+```
+
+Counts to three
+
+Each number is logged
+
+## Imports
+
+### console.log
+
+```gwe
+import fn log(number: i32)
+```
+
+Writes a number to the console
+"
+        )
+    }
+
+    #[test]
+    fn html() {
+        assert_eq!(
+            generate(&program(), "<counter>", DocFormat::Html),
+            "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>&lt;counter&gt;</title>
+</head>
+<body>
+<h1>&lt;counter&gt;</h1>
+<h2>Exports</h2>
+<h3 id=\"run\">run</h3>
+<pre><code>fn main(): void</code></pre>
+<p>Counts to three</p>
+<p>Each number is logged</p>
+<h2>Imports</h2>
+<h3 id=\"console.log\">console.log</h3>
+<pre><code>import fn log(number: i32)</code></pre>
+<p>Writes a number to the console</p>
+</body>
+</html>
+"
+        )
+    }
+}
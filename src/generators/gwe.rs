@@ -1,38 +1,338 @@
+use std::str::FromStr;
+
 use crate::{
-    blocks::{Block, Export, Function, ImportFunction, ImportMemory, Param},
-    expressions::Expression,
+    blocks::{
+        Attribute, Block, Constant, Data, Export, Function, ImportFunction, ImportMemory,
+        ImportTable, Interface, Memory, MemorySize, Param, Test, Trivia, TypeAlias, Use,
+    },
+    errors::CompileError,
+    expressions::{Direction, Expression},
+    generators::{find_function, Backend},
+    parser::{parse_sections, Program, Section},
+    tokenizer::{character_literal, tokenize, FullyQualifiedToken, Token},
 };
 
-pub fn indent(body: String) -> String {
-    body.split('\n')
+/// Where the opening `{` of a function, if or for body is placed
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BraceStyle {
+    /// `fn main(): void {`
+    SameLine,
+    /// `fn main(): void` followed by `{` on its own line
+    NextLine,
+}
+
+impl FromStr for BraceStyle {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "same-line" => Ok(BraceStyle::SameLine),
+            "next-line" => Ok(BraceStyle::NextLine),
+            _ => Err(format!(
+                "Unknown brace style {}, expected same-line or next-line",
+                str
+            )),
+        }
+    }
+}
+
+/// Settings for the gwe formatter, read from the `[format]` table of `gwe.toml`
+#[derive(PartialEq, Debug, Clone)]
+pub struct FormatOptions {
+    /// Number of spaces per indentation level, must be at least 1
+    pub indent_width: usize,
+    /// Function signatures and calls longer than this are split with one argument per line
+    pub max_line_length: usize,
+    pub brace_style: BraceStyle,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent_width: 4,
+            max_line_length: 80,
+            brace_style: BraceStyle::SameLine,
+        }
+    }
+}
+
+impl FormatOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        // blocks are split on unindented lines, so a zero indent would
+        // produce files that no longer parse
+        if self.indent_width == 0 {
+            return Err(String::from("indent_width must be at least 1"));
+        }
+
+        Ok(())
+    }
+
+    fn open_brace(&self) -> &'static str {
+        match self.brace_style {
+            BraceStyle::SameLine => " {",
+            BraceStyle::NextLine => "\n{",
+        }
+    }
+
+    /// Closes a block that's continued by another, like else or catch
+    fn continue_with(&self, keyword: &str) -> String {
+        match self.brace_style {
+            BraceStyle::SameLine => format!("}} {}", keyword),
+            BraceStyle::NextLine => format!("}}\n{}", keyword),
+        }
+    }
+}
+
+/// Pads every line, leaving blank lines between them empty
+pub fn indent(body: String, width: usize) -> String {
+    let padding = " ".repeat(width);
+
+    body.lines()
         .map(|line| {
             if line.is_empty() {
-                String::from("")
+                String::from("\n")
             } else {
-                format!("    {}\n", line)
+                format!("{}{}\n", padding, line)
             }
         })
         .collect()
 }
 
-pub fn generate(program: crate::parser::Program) -> String {
-    let blocks: Vec<String> = program.blocks.into_iter().map(generate_block).collect();
+/// Blocks are separated by a blank line, apart from comments which sit
+/// directly above what follows them unless a blank line was written between
+pub fn generate(program: Program, options: &FormatOptions) -> String {
+    generate_blocks(program.blocks, options)
+}
+
+fn generate_blocks(blocks: Vec<Block>, options: &FormatOptions) -> String {
+    let mut output = String::new();
+    let mut after_comment = false;
+    let mut blocks = blocks.into_iter().peekable();
+
+    while let Some(block) = blocks.next() {
+        match block {
+            Block::Trivia(Trivia::BlankLine) => after_comment = false,
+            Block::Trivia(Trivia::TrailingComment(body)) if !output.is_empty() => {
+                output.push(' ');
+                output.push_str(&generate_comment(&body));
+            }
+            block => {
+                if !output.is_empty() {
+                    output.push_str(if after_comment { "\n" } else { "\n\n" });
+                }
+                after_comment = matches!(block, Block::Trivia(_));
+
+                let code = match block {
+                    Block::ImportFunction(import) if import.grouped => {
+                        let module = import.external_name.first().cloned();
+                        let mut group = vec![import];
 
-    blocks.join("\n\n")
+                        while let Some(Block::ImportFunction(next)) = blocks.next_if(|next| {
+                            matches!(next, Block::ImportFunction(next) if next.grouped && next.external_name.first() == module.as_ref())
+                        }) {
+                            group.push(next);
+                        }
+
+                        generate_import_group(group, options)
+                    }
+                    block => generate_block(block, options),
+                };
+                output.push_str(&code);
+            }
+        }
+    }
+
+    output
+}
+
+/// Formats only the blocks touching the zero-based, inclusive line range,
+/// leaving every other line of the file byte-identical. The whole file is
+/// still parsed, so that types are inferred the same as a full format
+pub fn format_range(
+    source: &str,
+    start_line: i32,
+    end_line: i32,
+    options: &FormatOptions,
+) -> Result<String, Vec<CompileError>> {
+    let sections: Vec<Section> = parse_sections(source.to_string())?
+        .into_iter()
+        .filter(|section| {
+            section.module.is_none()
+                && section.start_line <= end_line
+                && section.end_line() >= start_line
+        })
+        .collect();
+
+    let (first, last) = match (sections.first(), sections.last()) {
+        (Some(first), Some(last)) => (first.start_line as usize, last.end_line() as usize),
+        _ => return Ok(source.to_string()),
+    };
+
+    let formatted = generate_blocks(
+        sections
+            .into_iter()
+            .flat_map(|section| section.blocks)
+            .collect(),
+        options,
+    );
+
+    // formatted lines end the same way as the ones they replace
+    let mut lines: Vec<&str> = source.split('\n').collect();
+    let ending = if source.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let last_ending = if lines[last].ends_with('\r') {
+        "\r"
+    } else {
+        ""
+    };
+    let replacement = format!("{}{}", formatted.replace('\n', ending), last_ending);
+    lines.splice(first..=last, replacement.split('\n'));
+
+    Ok(lines.join("\n"))
+}
+
+/// Formats only the block on the given zero-based line
+pub fn format_block_at(
+    source: &str,
+    line: i32,
+    options: &FormatOptions,
+) -> Result<String, Vec<CompileError>> {
+    format_range(source, line, line, options)
+}
+
+/// Formatting as a target, writing the program back out as gwe
+pub struct Gwe {
+    pub options: FormatOptions,
+}
+
+impl Backend for Gwe {
+    fn extension(&self) -> &str {
+        "gwe"
+    }
+
+    fn generate_module(&self, program: Program) -> Result<Vec<u8>, CompileError> {
+        Ok(generate(program, &self.options).into_bytes())
+    }
+
+    fn generate_function(&self, program: Program, name: &str) -> Result<String, CompileError> {
+        Ok(generate_function(
+            find_function(program, name)?,
+            &self.options,
+        ))
+    }
 }
 
 fn generate_param(param: Param) -> String {
     format!("{}: {}", param.name, param.type_name)
 }
 
-fn generate_expression(expression: Expression) -> String {
+/// Joins `items` inside parens, splitting them one per line when `prefix(items)suffix`
+/// would be longer than the max line length at the given depth
+fn wrap_list(
+    prefix: &str,
+    items: Vec<String>,
+    suffix: &str,
+    options: &FormatOptions,
+    depth: usize,
+) -> String {
+    let single_line = format!("{}({}){}", prefix, items.join(", "), suffix);
+    let longest_line = single_line.split('\n').map(str::len).max().unwrap_or(0);
+
+    if items.is_empty() || depth * options.indent_width + longest_line <= options.max_line_length {
+        single_line
+    } else {
+        format!(
+            "{}(\n{}){}",
+            prefix,
+            indent(items.join(",\n"), options.indent_width),
+            suffix
+        )
+    }
+}
+
+fn generate_comment(body: &str) -> String {
+    if body.is_empty() {
+        String::from("//")
+    } else {
+        format!("// {}", body)
+    }
+}
+
+fn generate_trivia(trivia: Trivia) -> String {
+    match trivia {
+        Trivia::Comment(body) | Trivia::TrailingComment(body) => generate_comment(&body),
+        Trivia::BlankLine => String::new(),
+    }
+}
+
+/// One statement per line, keeping comments and single blank lines between them
+fn generate_body(expressions: Vec<Expression>, options: &FormatOptions, depth: usize) -> String {
+    let mut lines: Vec<String> = vec![];
+
+    for expression in expressions {
+        match expression {
+            Expression::Trivia {
+                trivia: Trivia::BlankLine,
+            } => {
+                if lines.last().is_some_and(|line| !line.is_empty()) {
+                    lines.push(String::new());
+                }
+            }
+            Expression::Trivia {
+                trivia: Trivia::TrailingComment(body),
+            } if lines.last().is_some_and(|line| !line.is_empty()) => {
+                if let Some(line) = lines.last_mut() {
+                    line.push(' ');
+                    line.push_str(&generate_comment(&body));
+                }
+            }
+            Expression::Trivia { trivia } => lines.push(generate_trivia(trivia)),
+            expression => lines.push(format!(
+                "{};",
+                generate_expression(expression, options, depth)
+            )),
+        }
+    }
+
+    if lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+
+    lines.join("\n")
+}
+
+/// Conditionals are looser than anything around them, so keep their parens
+fn generate_operand(expression: Expression, options: &FormatOptions, depth: usize) -> String {
     match expression {
-        Expression::Addition { left, right } => {
-            let generated_left = generate_expression(*left);
-            let generated_right = generate_expression(*right);
+        Expression::Conditional { .. } => {
+            format!("({})", generate_expression(expression, options, depth))
+        }
+        expression => generate_expression(expression, options, depth),
+    }
+}
+
+fn generate_expression(expression: Expression, options: &FormatOptions, depth: usize) -> String {
+    match expression {
+        Expression::Addition { left, right, .. } => {
+            let generated_left = generate_operand(*left, options, depth);
+            let generated_right = generate_operand(*right, options, depth);
 
             format!("{} + {}", generated_left, generated_right)
         }
+        Expression::Conditional {
+            predicate,
+            success,
+            fail,
+            ..
+        } => format!(
+            "{} ? {} : {}",
+            generate_operand(*predicate, options, depth),
+            generate_expression(*success, options, depth),
+            generate_expression(*fail, options, depth)
+        ),
         Expression::GlobalAssign {
             name,
             type_name,
@@ -42,303 +342,1285 @@ fn generate_expression(expression: Expression) -> String {
                 "global {}: {} = {}",
                 name,
                 type_name,
-                generate_expression(*expression)
+                generate_expression(*expression, options, depth)
             )
         }
         Expression::LocalAssign {
             name,
             type_name,
             expression,
+            ..
         } => {
             format!(
                 "local {}: {} = {}",
                 name,
                 type_name,
-                generate_expression(*expression)
+                generate_expression(*expression, options, depth)
             )
         }
         Expression::Number {
             value,
             type_name: _,
         } => value,
-        Expression::Return { expression } => {
-            format!("return {}", generate_expression(*expression))
+        Expression::Return { expression, .. } => {
+            format!(
+                "return {}",
+                generate_expression(*expression, options, depth)
+            )
         }
         Expression::Variable { body, type_name: _ } => body,
+        Expression::DataField { name, field } => format!("{}.{}", name, field),
+        Expression::Constant { name, .. } => name,
         Expression::String { body } => format!("\"{}\"", body),
-        Expression::FunctionCall { name, args } => {
+        Expression::FunctionCall { name, args, .. } => {
             let params = args
-                .iter()
-                .map(|e| generate_expression(e.clone()))
-                .collect::<Vec<String>>()
-                .join(", ");
+                .into_iter()
+                .map(|e| generate_expression(e, options, depth + 1))
+                .collect::<Vec<String>>();
+
+            wrap_list(&name, params, "", options, depth)
+        }
+        Expression::TailCall { name, args } => {
+            let params = args
+                .into_iter()
+                .map(|e| generate_expression(e, options, depth + 1))
+                .collect::<Vec<String>>();
 
-            format!("{}({})", name, params)
+            wrap_list(&format!("tailcall {}", name), params, "", options, depth)
+        }
+        Expression::Closure { function } => {
+            let params: Vec<String> = function.params.into_iter().map(generate_param).collect();
+            let header = wrap_list(
+                &format!("fn {}", function.name),
+                params,
+                &format!(": {}{}", function.return_type, options.open_brace()),
+                options,
+                depth,
+            );
+
+            format!(
+                "{}\n{}}}",
+                header,
+                indent(
+                    generate_body(function.expressions, options, depth + 1),
+                    options.indent_width
+                )
+            )
+        }
+        // only made when generating wat
+        Expression::MemoryReference { .. }
+        | Expression::ClosureCall { .. }
+        | Expression::EnvironmentStore { .. }
+        | Expression::EnvironmentLoad { .. } => String::from(""),
+        Expression::Trivia { trivia } => generate_trivia(trivia),
+        Expression::Assert { left, right, .. } => {
+            let name = if right.is_some() {
+                "assert_eq"
+            } else {
+                "assert"
+            };
+            let params = std::iter::once(left)
+                .chain(right)
+                .map(|e| generate_expression(*e, options, depth + 1))
+                .collect::<Vec<String>>();
+
+            wrap_list(name, params, "", options, depth)
+        }
+        Expression::Panic { message } => {
+            format!("panic({})", generate_expression(*message, options, depth))
         }
-        Expression::MemoryReference {
-            offset: _,
-            length: _,
-        } => String::from(""),
         Expression::IfStatement {
             predicate,
             success,
             fail,
         } => {
-            let success_expressions = success
-                .iter()
-                .map(|expression| format!("{};", generate_expression(expression.clone())))
-                .collect::<Vec<String>>()
-                .join("\n");
+            let success_expressions = generate_body(success, options, depth + 1);
+
+            let fail_branch = if fail.is_empty() {
+                String::from("}")
+            } else {
+                format!(
+                    "{}{}\n{}}}",
+                    options.continue_with("else"),
+                    options.open_brace(),
+                    indent(
+                        generate_body(fail, options, depth + 1),
+                        options.indent_width
+                    )
+                )
+            };
 
-            let fail_expressions = fail
-                .iter()
-                .map(|expression| format!("{};", generate_expression(expression.clone())))
+            format!(
+                "if ({}){}
+{}{}",
+                generate_expression(*predicate, options, depth),
+                options.open_brace(),
+                indent(success_expressions, options.indent_width),
+                fail_branch
+            )
+        }
+        Expression::MatchStatement {
+            subject,
+            arms,
+            default,
+            ..
+        } => {
+            let arm = |pattern: String, body: Vec<Expression>| {
+                format!(
+                    "{} => {{\n{}}}",
+                    pattern,
+                    indent(
+                        generate_body(body, options, depth + 2),
+                        options.indent_width
+                    )
+                )
+            };
+            let arms = arms
+                .into_iter()
+                .map(|(pattern, body)| arm(pattern.to_string(), body))
+                .chain(default.map(|body| arm(String::from("_"), body)))
                 .collect::<Vec<String>>()
-                .join("\n");
+                .join(",\n");
 
             format!(
-                "if ({}) {{
-{}
-}} else {{
-{}
-}}",
-                generate_expression(*predicate),
-                indent(success_expressions),
-                indent(fail_expressions)
+                "match {}{}\n{}}}",
+                generate_expression(*subject, options, depth),
+                options.open_brace(),
+                indent(arms, options.indent_width)
+            )
+        }
+        Expression::TryStatement { body, catch } => {
+            let body_expressions = generate_body(body, options, depth + 1);
+            let catch_expressions = generate_body(catch, options, depth + 1);
+
+            format!(
+                "try{}
+{}{}{}
+{}}}",
+                options.open_brace(),
+                indent(body_expressions, options.indent_width),
+                options.continue_with("catch"),
+                options.open_brace(),
+                indent(catch_expressions, options.indent_width)
             )
         }
         Expression::Boolean { value } => format!("{}", value),
+        Expression::Character { value } => character_literal(value),
+        Expression::Array { values, .. } => format!(
+            "[{}]",
+            values
+                .into_iter()
+                .map(|value| generate_expression(value, options, depth))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        Expression::Index { name, index, .. } => {
+            format!("{}[{}]", name, generate_expression(*index, options, depth))
+        }
+        Expression::ForEach {
+            name, array, body, ..
+        } => {
+            let body_expressions = generate_body(body, options, depth + 1);
+
+            format!(
+                "for ({} in {}){}
+{}}}",
+                name,
+                array,
+                options.open_brace(),
+                indent(body_expressions, options.indent_width)
+            )
+        }
         Expression::ForStatement {
             initial_value,
             break_condition,
             incrementor,
+            direction,
             body,
         } => {
-            let body_expressions = body
-                .iter()
-                .map(|expression| format!("{};", generate_expression(expression.clone())))
-                .collect::<Vec<String>>()
-                .join("\n");
+            let body_expressions = generate_body(body, options, depth + 1);
+
+            format!(
+                "for ({}, {}, {}{}){}
+{}}}",
+                generate_expression(*initial_value, options, depth),
+                generate_expression(*break_condition, options, depth),
+                generate_expression(*incrementor, options, depth),
+                match direction {
+                    Direction::Up => "",
+                    Direction::Down => ", down",
+                },
+                options.open_brace(),
+                indent(body_expressions, options.indent_width)
+            )
+        }
+    }
+}
+
+fn generate_doc(doc: &Option<String>) -> String {
+    match doc {
+        Some(doc) => doc
+            .split('\n')
+            .map(|line| {
+                if line.is_empty() {
+                    String::from("///\n")
+                } else {
+                    format!("/// {}\n", line)
+                }
+            })
+            .collect(),
+        None => String::new(),
+    }
+}
+
+fn generate_attribute(attribute: &Attribute) -> String {
+    if attribute.args.is_empty() {
+        format!("@{}\n", attribute.name)
+    } else {
+        let args = attribute
+            .args
+            .iter()
+            .map(|arg| format!("\"{}\"", arg))
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!("@{}({})\n", attribute.name, args)
+    }
+}
+
+fn generate_function(function: Function, options: &FormatOptions) -> String {
+    let doc = generate_doc(&function.doc)
+        + &function
+            .attributes
+            .iter()
+            .map(generate_attribute)
+            .collect::<String>();
+    let params: Vec<String> = function.params.into_iter().map(generate_param).collect();
+    let type_params = if function.type_params.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>", function.type_params.join(", "))
+    };
+    let signature = wrap_list(
+        &format!("fn {}{}", function.name, type_params),
+        params,
+        &format!(": {}{}", function.return_type, options.open_brace()),
+        options,
+        0,
+    );
+
+    format!(
+        "{}{}",
+        doc,
+        generate_block_body(signature, function.expressions, options)
+    )
+}
+
+/// A `{`-ending header followed by its statements and the closing `}`
+fn generate_block_body(
+    header: String,
+    expressions: Vec<Expression>,
+    options: &FormatOptions,
+) -> String {
+    let body = indent(generate_body(expressions, options, 1), options.indent_width);
+
+    format!("{}\n{}}}", header, body)
+}
+
+fn generate_test(test: Test, options: &FormatOptions) -> String {
+    generate_block_body(
+        format!("test \"{}\"{}", test.name, options.open_brace()),
+        test.expressions,
+        options,
+    )
+}
+
+fn generate_export(export: Export) -> String {
+    format!("export {} {}", export.external_name, export.function_name)
+}
+
+/// Segments which wouldn't read back as a single identifier are quoted
+fn generate_external_name(external_name: &[String]) -> String {
+    external_name
+        .iter()
+        .map(|segment| match tokenize(segment.to_string()).as_slice() {
+            [FullyQualifiedToken {
+                token: Token::Identifier { body },
+                ..
+            }] if body == segment => segment.to_string(),
+            _ => format!("\"{}\"", segment),
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+fn generate_import_function(import: ImportFunction) -> String {
+    let params: Vec<String> = import.params.into_iter().map(generate_param).collect();
+    let external_name = generate_external_name(&import.external_name);
+    format!(
+        "{}import fn {}({}) {}",
+        generate_doc(&import.doc),
+        import.name,
+        params.join(", "),
+        external_name
+    )
+}
+
+/// Imports which were grouped by module, which are kept together. The brace
+/// always stays on the line of the import, where it marks the group's start
+fn generate_import_group(group: Vec<ImportFunction>, options: &FormatOptions) -> String {
+    let module = group
+        .first()
+        .and_then(|import| import.external_name.first())
+        .map(|module| generate_external_name(std::slice::from_ref(module)))
+        .unwrap_or_default();
+
+    let declarations: String = group
+        .into_iter()
+        .map(|import| {
+            let params: Vec<String> = import.params.into_iter().map(generate_param).collect();
+            format!(
+                "{}fn {}({});",
+                generate_doc(&import.doc),
+                import.name,
+                params.join(", ")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!(
+        "import {} {{\n{}}}",
+        module,
+        indent(declarations, options.indent_width)
+    )
+}
+
+fn generate_memory_size(name: Option<String>, size: MemorySize) -> String {
+    match name {
+        Some(name) => format!("{} {}", name, size),
+        None => size.to_string(),
+    }
+}
+
+fn generate_import_memory(import: ImportMemory) -> String {
+    let external_name = generate_external_name(&import.external_name);
+    format!(
+        "import memory {} {}",
+        generate_memory_size(import.name, import.size),
+        external_name
+    )
+}
+
+fn generate_import_table(import: ImportTable) -> String {
+    format!(
+        "import table {} {} {}",
+        import.size,
+        import.element_type,
+        generate_external_name(&import.external_name)
+    )
+}
+
+fn generate_memory(memory: Memory) -> String {
+    format!("memory {}", generate_memory_size(memory.name, memory.size))
+}
+
+fn generate_data(data: Data) -> String {
+    format!("data \"{}\" as {}", data.path, data.name)
+}
+
+fn generate_constant(constant: Constant) -> String {
+    format!(
+        "const {}: {} = {};",
+        constant.name, constant.type_name, constant.value
+    )
+}
+
+fn generate_type_alias(alias: TypeAlias) -> String {
+    format!("type {} = {};", alias.name, alias.type_name)
+}
+
+fn generate_use(module: Use) -> String {
+    format!("use {};", module.path.join("."))
+}
+
+fn generate_interface(interface: Interface, options: &FormatOptions) -> String {
+    let declarations: String = interface
+        .functions
+        .into_iter()
+        .map(|function| {
+            let return_type = if function.return_type == "void" {
+                String::new()
+            } else {
+                format!(": {}", function.return_type)
+            };
+            let params: Vec<String> = function.params.into_iter().map(generate_param).collect();
             format!(
-                "for ({}, {}, {}) {{
-{}
-}}",
-                generate_expression(*initial_value),
-                generate_expression(*break_condition),
-                generate_expression(*incrementor),
-                indent(body_expressions)
+                "fn {}({}){};",
+                function.name,
+                params.join(", "),
+                return_type
             )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!(
+        "interface {}{}\n{}}}",
+        interface.name,
+        options.open_brace(),
+        indent(declarations, options.indent_width)
+    )
+}
+
+fn generate_block(block: Block, options: &FormatOptions) -> String {
+    match block {
+        Block::Function(function) => generate_function(function, options),
+        Block::Export(export) => generate_export(export),
+        Block::ImportFunction(import) => generate_import_function(import),
+        Block::ImportMemory(import) => generate_import_memory(import),
+        Block::ImportTable(import) => generate_import_table(import),
+        Block::Memory(memory) => generate_memory(memory),
+        Block::Data(data) => generate_data(data),
+        Block::Constant(constant) => generate_constant(constant),
+        Block::TypeAlias(alias) => generate_type_alias(alias),
+        Block::Use(module) => generate_use(module),
+        Block::Interface(interface) => generate_interface(interface, options),
+        Block::Test(test) => generate_test(test, options),
+        Block::Trivia(trivia) => generate_trivia(trivia),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        errors::{join_errors, CompileError},
+        parser::{parse, parse_with_trivia, Program},
+        visit_mut::{walk_block_mut, walk_expression_mut, walk_function_mut, VisitorMut},
+    };
+
+    use super::*;
+
+    fn option_matrix() -> Vec<FormatOptions> {
+        vec![
+            FormatOptions::default(),
+            FormatOptions {
+                indent_width: 2,
+                ..FormatOptions::default()
+            },
+            FormatOptions {
+                brace_style: BraceStyle::NextLine,
+                ..FormatOptions::default()
+            },
+            FormatOptions {
+                max_line_length: 20,
+                ..FormatOptions::default()
+            },
+            FormatOptions {
+                indent_width: 1,
+                max_line_length: 1,
+                brace_style: BraceStyle::NextLine,
+            },
+        ]
+    }
+
+    /// Asserts remember the line they were written on, locals and params
+    /// where they were declared and exports where their function is named,
+    /// which formatting is free to move
+    struct ForgetLines;
+
+    fn forget_params(params: &mut [Param]) {
+        for param in params.iter_mut() {
+            param.info = None;
+        }
+    }
+
+    impl VisitorMut for ForgetLines {
+        fn visit_block_mut(&mut self, block: &mut Block) {
+            match block {
+                Block::ImportFunction(import) => forget_params(&mut import.params),
+                Block::Export(export) => export.info = None,
+                Block::Interface(interface) => {
+                    for function in interface.functions.iter_mut() {
+                        forget_params(&mut function.params);
+                    }
+                }
+                _ => (),
+            }
+            walk_block_mut(self, block)
+        }
+
+        fn visit_function_mut(&mut self, function: &mut Function) {
+            forget_params(&mut function.params);
+            walk_function_mut(self, function)
+        }
+
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            match expression {
+                Expression::Assert { line, .. }
+                | Expression::Index { line, .. }
+                | Expression::ForEach { line, .. } => *line = 0,
+                Expression::LocalAssign { info, .. }
+                | Expression::Addition { info, .. }
+                | Expression::Return { info, .. }
+                | Expression::FunctionCall { info, .. }
+                | Expression::MatchStatement { info, .. } => *info = None,
+                _ => (),
+            }
+            walk_expression_mut(self, expression)
+        }
+    }
+
+    fn parse_without_lines(input: String) -> Result<Program, Vec<CompileError>> {
+        parse_with_trivia(input).map(|mut program| {
+            ForgetLines.visit_program_mut(&mut program);
+            program
+        })
+    }
+
+    /// parse -> format -> parse -> format must reach a fixed point after one
+    /// pass, and formatting must never change the parsed program
+    fn assert_stable(input: &str) {
+        let original = match parse_without_lines(input.to_string()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => program,
+        };
+
+        for options in option_matrix() {
+            let once = generate(original.clone(), &options);
+
+            let reparsed = match parse_without_lines(once.clone()) {
+                Err(err) => panic!(
+                    "Formatted output failed to parse with {:?}: {}\n{}",
+                    options,
+                    join_errors(&err),
+                    once
+                ),
+                Ok(program) => program,
+            };
+
+            assert!(
+                reparsed == original,
+                "Formatting with {:?} changed the program:\n{}\nfrom\n{}\nto\n{}",
+                options,
+                once,
+                original,
+                reparsed
+            );
+
+            let twice = generate(reparsed, &options);
+
+            assert_eq!(
+                twice, once,
+                "Formatting with {:?} did not reach a fixed point",
+                options
+            );
+        }
+    }
+
+    #[test]
+    fn examples_are_stable() {
+        let files = fs::read_dir("examples/");
+
+        assert!(files.is_ok());
+
+        for file in files.unwrap() {
+            let path = file.unwrap().path();
+
+            if path.to_string_lossy().ends_with(".gwe") {
+                assert_stable(&fs::read_to_string(path).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_kept() {
+        let input = "// Logging
+
+import fn log(n: i32) console.log // from js
+import memory 1 js.mem
+
+/// Picks a number
+fn pick(n: i32): i32 {
+    // the first
+    local x: i32 = 1;
+
+    if (n) { // taken
+        log(x);
+    };
+
+
+    return x + 2; // plus two
+}
+
+// trailing
+";
+
+        let program = match parse_with_trivia(input.to_string()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => program,
+        };
+
+        assert_eq!(
+            generate(program, &FormatOptions::default()),
+            "// Logging
+
+import fn log(n: i32) console.log // from js
+
+import memory 1 js.mem
+
+/// Picks a number
+fn pick(n: i32): i32 {
+    // the first
+    local x: i32 = 1;
+
+    if (n) {
+        // taken
+        log(x);
+    };
+
+    return x + 2; // plus two
+}
+
+// trailing"
+        );
+        assert_stable(input);
+    }
+
+    #[test]
+    fn comments_inside_statements_move_before_them() {
+        let input = "fn pick(n: i32): i32 {
+    return add(1, // one
+        2);
+}";
+
+        let program = match parse_with_trivia(input.to_string()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => program,
+        };
+
+        assert_eq!(
+            generate(program, &FormatOptions::default()),
+            "fn pick(n: i32): i32 {
+    // one
+    return add(1, 2);
+}"
+        );
+    }
+
+    #[test]
+    fn range_formatting_keeps_crlf_line_endings() {
+        let input =
+            "fn one(): i32 {\r\n  return   1;\r\n}\r\n\r\nfn two(): i32 {\r\n  return   2;\r\n}";
+
+        assert_eq!(
+            format_range(input, 1, 1, &FormatOptions::default()),
+            Ok(String::from(
+                "fn one(): i32 {\r\n    return 1;\r\n}\r\n\r\nfn two(): i32 {\r\n  return   2;\r\n}"
+            ))
+        );
+        assert_eq!(
+            format_range(input, 5, 5, &FormatOptions::default()),
+            Ok(String::from(
+                "fn one(): i32 {\r\n  return   1;\r\n}\r\n\r\nfn two(): i32 {\r\n    return 2;\r\n}"
+            ))
+        );
+    }
+
+    #[test]
+    fn range_formatting_leaves_other_blocks_alone() {
+        let input = "fn one(): i32 {
+  return   1;
+}
+
+// two
+fn two(): i32 {
+      return 2;
+}
+fn three(): i32 {
+        return   3;
+}
+";
+
+        let options = FormatOptions::default();
+
+        assert_eq!(
+            format_range(input, 6, 6, &options),
+            Ok(String::from(
+                "fn one(): i32 {
+  return   1;
+}
+
+// two
+fn two(): i32 {
+    return 2;
+}
+fn three(): i32 {
+        return   3;
+}
+"
+            ))
+        );
+
+        assert_eq!(
+            format_block_at(input, 1, &options),
+            Ok(input.replacen("  return   1;", "    return 1;", 1))
+        );
+
+        assert_eq!(
+            format_range(input, 5, 9, &options),
+            Ok(String::from(
+                "fn one(): i32 {
+  return   1;
+}
+
+// two
+fn two(): i32 {
+    return 2;
+}
+
+fn three(): i32 {
+    return 3;
+}
+"
+            ))
+        );
+
+        assert_eq!(format_range(input, 3, 3, &options), Ok(input.to_string()));
+    }
+
+    #[test]
+    fn empty_function() {
+        let input = String::from(
+            "fn hello_world(): void {
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn empty_with_an_arg_function() {
+        let input = String::from(
+            "fn hello_world(name: string): void {
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn empty_with_several_args_function() {
+        let input = String::from(
+            "fn hello_world(name: string, age: i32): void {
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn return_function() {
+        let input = String::from(
+            "fn hello_world(name: string): string {
+    return name;
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn local_var_and_addition_function() {
+        let input = String::from(
+            "fn hello_world(name: string): string {
+    local message: string = \"Hello \" + name;
+    return message;
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn global_var_and_addition_function() {
+        let input = String::from(
+            "fn hello_world(): void {
+    global num: f32 = 123 + 3.14;
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn export_function() {
+        let input = String::from(
+            "fn hello_world(): f32 {
+    return 3.14;
+}
+
+export helloWorld hello_world",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn import_function() {
+        let input = String::from("import fn log(number: i32) console.log");
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn import_group() {
+        let input = String::from(
+            "import console {
+    /// Logs a number
+    fn log(number: i32);
+    fn warn(number: i32);
+}
+
+import fn error(number: i32) console.error
+
+fn main(): void {
+    log(1);
+    warn(2);
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        match parse(String::from(
+            "import console { fn log(number: i32); fn warn(number: i32); }",
+        )) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(
+                    generate(
+                        program,
+                        &FormatOptions {
+                            indent_width: 2,
+                            ..FormatOptions::default()
+                        }
+                    ),
+                    "import console {
+  fn log(number: i32);
+  fn warn(number: i32);
+}"
+                );
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn import_with_quoted_names() {
+        let input = String::from(
+            "import fn fd_write(fd: i32) \"wasi-snapshot/preview1\".fd_write
+
+import \"./env.js\" {
+    fn log(number: i32);
+}
+
+import memory 1 env.\"memory\"
+
+import table 2 funcref \"./env.js\".table",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn call_function() {
+        let input = String::from(
+            "import fn log(number: i32) console.log
+
+fn main(): void {
+    log(3);
+}
+
+export main main",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
         }
+
+        assert_stable(&input);
     }
-}
 
-fn generate_function(function: Function) -> String {
-    let params: Vec<String> = function.params.into_iter().map(generate_param).collect();
-    if function.expressions.is_empty() {
-        format!(
-            "fn {}({}): {} {{\n}}",
-            function.name,
-            params.join(", "),
-            function.return_type
-        )
-    } else {
-        let body = indent(
-            function
-                .expressions
-                .into_iter()
-                .map(generate_expression)
-                .map(|line| format!("{};\n", line))
-                .collect::<Vec<String>>()
-                .join(""),
+    #[test]
+    fn doc_comments() {
+        let input = String::from(
+            "/// Logs a number
+import fn log(number: i32) console.log
+
+/// Entry point
+///
+/// Logs once
+@inline
+@export(\"run\")
+fn main(): void {
+    log(1);
+}",
         );
 
-        format!(
-            "fn {}({}): {} {{\n{}}}",
-            function.name,
-            params.join(", "),
-            function.return_type,
-            body
-        )
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
     }
-}
 
-fn generate_export(export: Export) -> String {
-    format!("export {} {}", export.external_name, export.function_name)
-}
+    #[test]
+    fn test_block() {
+        let input = String::from(
+            "import fn log(number: i32) console.log
 
-fn generate_import_function(import: ImportFunction) -> String {
-    let params: Vec<String> = import.params.into_iter().map(generate_param).collect();
-    let external_name = import.external_name.join(".");
-    format!(
-        "import fn {}({}) {}",
-        import.name,
-        params.join(", "),
-        external_name
-    )
-}
+test \"logs a number\" {
+    log(1);
+    assert(true);
+    assert_eq(1, 1);
+    panic(\"unreachable\");
+}",
+        );
 
-fn generate_import_memory(import: ImportMemory) -> String {
-    let external_name = import.external_name.join(".");
-    format!("import memory {} {}", import.size, external_name)
-}
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
 
-fn generate_block(block: Block) -> String {
-    match block {
-        Block::Function(function) => generate_function(function),
-        Block::Export(export) => generate_export(export),
-        Block::ImportFunction(import) => generate_import_function(import),
-        Block::ImportMemory(import) => generate_import_memory(import),
+        assert_stable(&input);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::parser::parse;
+    #[test]
+    fn try_catch() {
+        let input = String::from(
+            "import fn log(number: i32) console.log
 
-    use super::*;
+fn main(): void {
+    try {
+        log(1);
+    } catch {
+        log(2);
+    };
+    log(3);
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
 
     #[test]
-    fn empty_function() {
+    fn named_memories() {
         let input = String::from(
-            "fn hello_world(): void {
+            "import memory heap 1 js.heap
+
+memory scratch 1
+
+@memory(\"scratch\")
+fn clear(): void {
+    memory_fill(0, 0, 4);
 }",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
-    fn empty_with_an_arg_function() {
+    fn import_table() {
+        let input = String::from("import table 10 funcref js.table");
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn data_block() {
         let input = String::from(
-            "fn hello_world(name: string): void {
+            "data \"assets/logo.bin\" as logo
+
+fn size(): i32 {
+    return logo.length;
 }",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
-    fn empty_with_several_args_function() {
+    fn match_statement() {
         let input = String::from(
-            "fn hello_world(name: string, age: i32): void {
+            "fn main(n: i32): void {
+    match n {
+        0 => {
+            log(1);
+        },
+        _ => {
+            log(n);
+        }
+    };
 }",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
-    fn return_function() {
+    fn conditionals() {
         let input = String::from(
-            "fn hello_world(name: string): string {
-    return name;
+            "fn main(c: i32, x: f32): f32 {
+    local picked: f32 = c ? x + 1.0 : x;
+    local total: f32 = (c ? 1.0 : 2.0) + picked;
+    return total;
 }",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
-    fn local_var_and_addition_function() {
+    fn closures() {
         let input = String::from(
-            "fn hello_world(name: string): string {
-    local message: string = \"Hello \" + name;
-    return message;
+            "fn main(n: f32): f32 {
+    fn add(x: f32): f32 {
+        return x + n;
+    };
+    return add(1.5);
 }",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
-    fn global_var_and_addition_function() {
+    fn function_type_params() {
         let input = String::from(
-            "fn hello_world(): void {
-    global num: f32 = 123 + 3.14;
+            "fn apply(f: fn(i32, fn(f32): void): i32, x: i32, g: fn(f32): void): i32 {
+    return f(x, g);
 }",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
-    fn export_function() {
+    fn generic_function() {
         let input = String::from(
-            "fn hello_world(): f32 {
-    return 3.14;
+            "fn pick<T, U>(kept: T, dropped: U): T {
+    return kept;
 }
 
-export helloWorld hello_world",
+fn main(n: i32): i32 {
+    return pick(n, 0.5);
+}",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
-    fn import_function() {
-        let input = String::from("import fn log(number: i32) console.log");
+    fn constants() {
+        let input = String::from(
+            "const PAGES: i32 = 2 * (1 + 1);
+
+memory PAGES
+
+const BYTES: i64 = PAGES * 65536;
+
+fn size(): i64 {
+    return BYTES;
+}",
+        );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
-    fn call_function() {
+    fn tail_call() {
         let input = String::from(
-            "import fn log(number: i32) console.log
+            "fn spin(times: f32): f32 {
+    tailcall spin(times + 1);
+}",
+        );
 
-fn main(): void {
-    log(3.14);
-}
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
 
-export main main",
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn type_alias() {
+        let input = String::from(
+            "type Seconds = f32;
+
+fn wait(delay: Seconds): Seconds {
+    return delay;
+}",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
@@ -354,11 +1636,13 @@ export main main",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
@@ -378,11 +1662,13 @@ export main main",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
@@ -402,11 +1688,13 @@ export main main",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
     }
 
     #[test]
@@ -424,10 +1712,283 @@ export main main",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program, &FormatOptions::default()), input);
+            }
+        }
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn for_loop_down() {
+        let input = String::from(
+            "import fn log(number: f32) console.log
+
+fn main(): void {
+    for (local x: f32 = 10.0, 0.0, 2.5, down) {
+        log(x);
+    };
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), input);
+                assert_eq!(generate(program, &FormatOptions::default()), input);
             }
         }
+
+        assert_stable(&input);
+    }
+
+    fn format_twice(input: String, options: &FormatOptions) -> (String, String) {
+        let once = match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => generate(program, options),
+        };
+
+        let twice = match parse(once.clone()) {
+            Err(err) => panic!(
+                "Formatted output failed to parse: {}\n{}",
+                join_errors(&err),
+                once
+            ),
+            Ok(program) => generate(program, options),
+        };
+
+        (once, twice)
+    }
+
+    #[test]
+    fn next_line_braces() {
+        let input = String::from(
+            "fn main(n: i32): void {
+    if (n) { log(1); } else { log(2); };
+    for (local x: i32 = 0, 10, 1) { log(x); };
+}",
+        );
+        let output = String::from(
+            "fn main(n: i32): void
+{
+    if (n)
+    {
+        log(1);
+    }
+    else
+    {
+        log(2);
+    };
+    for (local x: i32 = 0, 10, 1)
+    {
+        log(x);
+    };
+}",
+        );
+
+        let options = FormatOptions {
+            brace_style: BraceStyle::NextLine,
+            ..FormatOptions::default()
+        };
+        let (once, twice) = format_twice(input, &options);
+
+        assert_eq!(once, output);
+        assert_eq!(twice, output);
+    }
+
+    #[test]
+    fn characters_keep_their_escapes() {
+        let input = String::from(
+            "fn main(): void {
+    log('a');
+    log('\\n');
+    log('\\'');
+    log('\\u{7}');
+}",
+        );
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn arrays_keep_their_values() {
+        let input = String::from(
+            "fn main(i: i32): i32 {
+    local table: [i32] = [1, 2, 3, 5, 8];
+    log(table.length);
+    for (n in table) {
+        log(n);
+    };
+    return table[i];
+}",
+        );
+
+        assert_stable(&input);
+    }
+
+    #[test]
+    fn ifs_without_an_else() {
+        let input = String::from(
+            "fn main(n: i32): void {
+    if (n) { log(1); };
+    if (n) { log(1); } else { };
+}",
+        );
+        let output = String::from(
+            "fn main(n: i32): void {
+    if (n) {
+        log(1);
+    };
+    if (n) {
+        log(1);
+    };
+}",
+        );
+
+        let (once, twice) = format_twice(input, &FormatOptions::default());
+
+        assert_eq!(once, output);
+        assert_eq!(twice, output);
+    }
+
+    #[test]
+    fn interfaces() {
+        let input = String::from(
+            "interface Logger { fn log(n: i32); fn level(): i32; }
+
+import fn log(n: i32) console.log",
+        );
+
+        assert_stable(&input);
+        assert_eq!(
+            generate(parse(input).unwrap(), &FormatOptions::default()),
+            "interface Logger {
+    fn log(n: i32);
+    fn level(): i32;
+}
+
+import fn log(n: i32) console.log"
+        );
+    }
+
+    #[test]
+    fn uses_keep_out_the_module() {
+        let input = String::from(
+            "use   std.math ;
+fn main(): f32 {
+    local x: f32 = double(2.0);
+    return x;
+}",
+        );
+        let output = String::from(
+            "use std.math;
+
+fn main(): f32 {
+    local x: f32 = double(2.0);
+    return x;
+}",
+        );
+
+        let format = |source: String| {
+            generate(
+                parse_with_trivia(source).unwrap(),
+                &FormatOptions::default(),
+            )
+        };
+
+        assert_eq!(format(input), output);
+        assert_eq!(format(output.clone()), output);
+    }
+
+    #[test]
+    fn indent_width() {
+        let input = String::from(
+            "fn main(n: i32): void {
+    if (n) { log(1); } else { log(2); };
+}",
+        );
+        let output = String::from(
+            "fn main(n: i32): void {
+  if (n) {
+    log(1);
+  } else {
+    log(2);
+  };
+}",
+        );
+
+        let options = FormatOptions {
+            indent_width: 2,
+            ..FormatOptions::default()
+        };
+        let (once, twice) = format_twice(input, &options);
+
+        assert_eq!(once, output);
+        assert_eq!(twice, output);
+    }
+
+    #[test]
+    fn long_signature_is_wrapped() {
+        let input = String::from(
+            "fn add_three(first_number: i32, second_number: i32, third_number: i32): i32 {
+    return first_number;
+}",
+        );
+        let output = String::from(
+            "fn add_three(
+    first_number: i32,
+    second_number: i32,
+    third_number: i32
+): i32 {
+    return first_number;
+}",
+        );
+
+        let options = FormatOptions {
+            max_line_length: 40,
+            ..FormatOptions::default()
+        };
+        let (once, twice) = format_twice(input, &options);
+
+        assert_eq!(once, output);
+        assert_eq!(twice, output);
+    }
+
+    #[test]
+    fn long_call_is_wrapped() {
+        let input = String::from(
+            "fn main(first_number: i32, second_number: i32): void {
+    log(first_number, second_number, first_number);
+}",
+        );
+        let output = String::from(
+            "fn main(
+    first_number: i32,
+    second_number: i32
+): void {
+    log(
+        first_number,
+        second_number,
+        first_number
+    );
+}",
+        );
+
+        let options = FormatOptions {
+            max_line_length: 40,
+            ..FormatOptions::default()
+        };
+        let (once, twice) = format_twice(input, &options);
+
+        assert_eq!(once, output);
+        assert_eq!(twice, output);
+    }
+
+    #[test]
+    fn brace_style_from_str() {
+        assert_eq!("same-line".parse(), Ok(BraceStyle::SameLine));
+        assert_eq!("next-line".parse(), Ok(BraceStyle::NextLine));
+        assert!("egyptian".parse::<BraceStyle>().is_err());
     }
 }
@@ -0,0 +1,623 @@
+use crate::{
+    blocks::{Block, Function},
+    expressions::Expression,
+    parser::Program,
+};
+
+/// Functions with more expressions than this are left as calls
+const INLINE_MAX_SIZE: usize = 12;
+
+/// How many expressions make up the expressions, counting the ones inside
+fn size(expressions: &[Expression]) -> usize {
+    expressions
+        .iter()
+        .map(|expression| {
+            1 + match expression {
+                Expression::Return { expression, .. }
+                | Expression::LocalAssign { expression, .. } => {
+                    size(std::slice::from_ref(expression))
+                }
+                Expression::Addition { left, right, .. } => {
+                    size(std::slice::from_ref(left)) + size(std::slice::from_ref(right))
+                }
+                Expression::Conditional {
+                    predicate,
+                    success,
+                    fail,
+                    ..
+                } => [predicate, success, fail]
+                    .into_iter()
+                    .map(|part| size(std::slice::from_ref(part)))
+                    .sum(),
+                Expression::FunctionCall { args, .. } => size(args),
+                _ => 0,
+            }
+        })
+        .sum()
+}
+
+/// Whether an expression can be moved into another function as it is
+fn is_movable(expression: &Expression, function: &Function, imports: &[String]) -> bool {
+    let movable = |expression: &Expression| is_movable(expression, function, imports);
+
+    match expression {
+        Expression::Number { .. }
+        | Expression::Variable { .. }
+        | Expression::Constant { .. }
+        | Expression::Boolean { .. }
+        | Expression::Character { .. }
+        | Expression::DataField { .. } => true,
+        Expression::Addition { left, right, .. } => movable(left) && movable(right),
+        Expression::Conditional {
+            predicate,
+            success,
+            fail,
+            ..
+        } => movable(predicate) && movable(success) && movable(fail),
+        Expression::FunctionCall { name, args, .. } => {
+            name != &function.name && !imports.contains(name) && args.iter().all(movable)
+        }
+        _ => false,
+    }
+}
+
+/// Small functions made of locals and calls, ending in an optional return,
+/// which don't call themselves
+fn is_inlinable(function: &Function, imports: &[String]) -> bool {
+    let statements_movable = function
+        .expressions
+        .iter()
+        .enumerate()
+        .all(|(index, expression)| match expression {
+            Expression::LocalAssign { expression, .. } => is_movable(expression, function, imports),
+            Expression::FunctionCall { .. } => is_movable(expression, function, imports),
+            Expression::Return { expression, .. } => {
+                index == function.expressions.len() - 1 && is_movable(expression, function, imports)
+            }
+            _ => false,
+        });
+    // strings and closures are passed differently to plain numbers
+    let plain = |type_name: &str| type_name != "string" && !type_name.starts_with("fn(");
+
+    function.type_params.is_empty()
+        && function.params.iter().all(|param| plain(&param.type_name))
+        && plain(&function.return_type)
+        && size(&function.expressions) <= INLINE_MAX_SIZE
+        && statements_movable
+}
+
+/// Gives the params and locals of an inlined function names of their own in
+/// the function they're copied into
+fn rename(expression: Expression, names: &[String], prefix: &str) -> Expression {
+    let renamed = |name: String| {
+        if names.contains(&name) {
+            format!("{}{}", prefix, name)
+        } else {
+            name
+        }
+    };
+    let rename_boxed = |expression: Box<Expression>| Box::new(rename(*expression, names, prefix));
+
+    match expression {
+        Expression::Variable { body, type_name } => Expression::Variable {
+            body: renamed(body),
+            type_name,
+        },
+        Expression::LocalAssign {
+            name,
+            type_name,
+            expression,
+            ..
+        } => Expression::LocalAssign {
+            name: renamed(name),
+            type_name,
+            expression: rename_boxed(expression),
+            // the copy isn't written anywhere in the caller
+            info: None,
+        },
+        Expression::Return { expression, info } => Expression::Return {
+            expression: rename_boxed(expression),
+            info,
+        },
+        Expression::Addition {
+            left,
+            right,
+            type_name,
+            info,
+        } => Expression::Addition {
+            left: rename_boxed(left),
+            right: rename_boxed(right),
+            type_name,
+            info,
+        },
+        Expression::Conditional {
+            predicate,
+            success,
+            fail,
+            type_name,
+        } => Expression::Conditional {
+            predicate: rename_boxed(predicate),
+            success: rename_boxed(success),
+            fail: rename_boxed(fail),
+            type_name,
+        },
+        Expression::FunctionCall { name, args, info } => Expression::FunctionCall {
+            name,
+            args: args
+                .into_iter()
+                .map(|arg| rename(arg, names, prefix))
+                .collect(),
+            info,
+        },
+        expression => expression,
+    }
+}
+
+struct Inliner<'a> {
+    inlinable: &'a [Function],
+    /// How many calls have been inlined, so each copy's locals are unique
+    count: usize,
+}
+
+impl Inliner<'_> {
+    /// The statements a call becomes, with the value it returns given to `result`
+    fn expand(
+        &mut self,
+        caller: &Function,
+        name: &str,
+        args: &[Expression],
+        result: &dyn Fn(Expression) -> Expression,
+    ) -> Option<Vec<Expression>> {
+        let callee = self.inlinable.iter().find(|function| {
+            function.name == name
+                && function.name != caller.name
+                && function.memory_name() == caller.memory_name()
+        })?;
+
+        let prefix = format!("__inline_{}_", self.count);
+        self.count += 1;
+
+        let names: Vec<String> = callee
+            .params
+            .iter()
+            .map(|param| param.name.to_string())
+            .chain(
+                callee
+                    .expressions
+                    .iter()
+                    .filter_map(|expression| match expression {
+                        Expression::LocalAssign { name, .. } => Some(name.to_string()),
+                        _ => None,
+                    }),
+            )
+            .collect();
+
+        let params = callee
+            .params
+            .iter()
+            .zip(args)
+            .map(|(param, arg)| Expression::LocalAssign {
+                name: format!("{}{}", prefix, param.name),
+                type_name: param.type_name.to_string(),
+                expression: Box::new(arg.clone()),
+                info: None,
+            });
+        let body = callee.expressions.iter().map(|expression| {
+            match rename(expression.clone(), &names, &prefix) {
+                Expression::Return { expression, .. } => result(*expression),
+                expression => expression,
+            }
+        });
+
+        Some(params.chain(body).collect())
+    }
+
+    /// Expands the calls to void functions made as a statement, and the calls
+    /// which are the value of a local or what's returned. A call anywhere
+    /// else, like an argument or one side of an addition, stays a call, as
+    /// copying the body in would need its value in a local first, which would
+    /// move it ahead of what's evaluated before it
+    fn inline_all(&mut self, caller: &Function, expressions: Vec<Expression>) -> Vec<Expression> {
+        let mut inlined = vec![];
+
+        for expression in expressions {
+            let expanded = match &expression {
+                Expression::FunctionCall { name, args, .. }
+                    if self.inlinable.iter().any(|function| {
+                        &function.name == name && function.return_type == "void"
+                    }) =>
+                {
+                    self.expand(caller, name, args, &|expression| expression)
+                }
+                Expression::LocalAssign {
+                    name: local,
+                    type_name,
+                    expression: value,
+                    info,
+                } => match value.as_ref() {
+                    Expression::FunctionCall { name, args, .. } => {
+                        self.expand(caller, name, args, &|expression| Expression::LocalAssign {
+                            name: local.to_string(),
+                            type_name: type_name.to_string(),
+                            expression: Box::new(expression),
+                            info: info.clone(),
+                        })
+                    }
+                    _ => None,
+                },
+                Expression::Return {
+                    expression: value,
+                    info,
+                } => match value.as_ref() {
+                    Expression::FunctionCall { name, args, .. } => {
+                        self.expand(caller, name, args, &|expression| Expression::Return {
+                            expression: Box::new(expression),
+                            info: info.clone(),
+                        })
+                    }
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            match (expanded, expression) {
+                (Some(expanded), _) => inlined.extend(expanded),
+                (
+                    None,
+                    Expression::IfStatement {
+                        predicate,
+                        success,
+                        fail,
+                    },
+                ) => inlined.push(Expression::IfStatement {
+                    predicate,
+                    success: self.inline_all(caller, success),
+                    fail: self.inline_all(caller, fail),
+                }),
+                (
+                    None,
+                    Expression::ForStatement {
+                        initial_value,
+                        incrementor,
+                        break_condition,
+                        direction,
+                        body,
+                    },
+                ) => inlined.push(Expression::ForStatement {
+                    initial_value,
+                    incrementor,
+                    break_condition,
+                    direction,
+                    body: self.inline_all(caller, body),
+                }),
+                (
+                    None,
+                    Expression::ForEach {
+                        name,
+                        type_name,
+                        array,
+                        body,
+                        line,
+                    },
+                ) => inlined.push(Expression::ForEach {
+                    name,
+                    type_name,
+                    array,
+                    body: self.inline_all(caller, body),
+                    line,
+                }),
+                (None, Expression::TryStatement { body, catch }) => {
+                    inlined.push(Expression::TryStatement {
+                        body: self.inline_all(caller, body),
+                        catch: self.inline_all(caller, catch),
+                    })
+                }
+                (
+                    None,
+                    Expression::MatchStatement {
+                        subject,
+                        type_name,
+                        arms,
+                        default,
+                        info,
+                    },
+                ) => inlined.push(Expression::MatchStatement {
+                    subject,
+                    type_name,
+                    arms: arms
+                        .into_iter()
+                        .map(|(pattern, body)| (pattern, self.inline_all(caller, body)))
+                        .collect(),
+                    default: default.map(|default| self.inline_all(caller, default)),
+                    info,
+                }),
+                (None, expression) => inlined.push(expression),
+            }
+        }
+
+        inlined
+    }
+}
+
+/// Replaces calls to small functions with copies of their bodies, where the
+/// call is a statement of its own, a local's value or a return. Calls to imports
+/// stay where they're written when `keep_imports` is set, as whether their
+/// errors are caught can depend on the function they're in
+pub fn inline(program: Program, keep_imports: bool) -> Program {
+    let imports: Vec<String> = if keep_imports {
+        program
+            .blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::ImportFunction(import) => Some(import.name.to_string()),
+                _ => None,
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    let inlinable: Vec<Function> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function) if is_inlinable(function, &imports) => Some(function.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut inliner = Inliner {
+        inlinable: &inlinable,
+        count: 0,
+    };
+
+    Program {
+        blocks: program
+            .blocks
+            .into_iter()
+            .map(|block| match block {
+                Block::Function(function) => Block::Function(Function {
+                    expressions: inliner.inline_all(&function, function.expressions.clone()),
+                    ..function
+                }),
+                block => block,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::join_errors;
+    use crate::parser::parse;
+    use crate::tokenizer::TokenInfo;
+
+    fn inline_source(source: &str) -> Vec<Expression> {
+        match parse(String::from(source)) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => match inline(program, false).blocks.pop() {
+                Some(Block::Function(function)) => function.expressions,
+                _ => vec![],
+            },
+        }
+    }
+
+    #[test]
+    fn small_functions_are_copied_into_their_callers() {
+        let variable = |body: &str| Expression::Variable {
+            body: String::from(body),
+            type_name: String::from("f32"),
+        };
+
+        assert_eq!(
+            inline_source(
+                "fn double(x: f32): f32 {
+    local twice: f32 = x + x;
+    return twice;
+}
+
+fn main(n: f32): f32 {
+    local result: f32 = double(n);
+    return double(result);
+}"
+            ),
+            vec![
+                Expression::LocalAssign {
+                    name: String::from("__inline_0_x"),
+                    type_name: String::from("f32"),
+                    expression: Box::new(variable("n")),
+                    info: None,
+                },
+                Expression::LocalAssign {
+                    name: String::from("__inline_0_twice"),
+                    type_name: String::from("f32"),
+                    expression: Box::new(Expression::Addition {
+                        left: Box::new(variable("__inline_0_x")),
+                        right: Box::new(variable("__inline_0_x")),
+                        type_name: String::from("f32"),
+                        info: Some(TokenInfo { line: 1, index: 25 }),
+                    }),
+                    info: None,
+                },
+                Expression::LocalAssign {
+                    name: String::from("result"),
+                    type_name: String::from("f32"),
+                    expression: Box::new(variable("__inline_0_twice")),
+                    info: Some(TokenInfo { line: 6, index: 10 }),
+                },
+                Expression::LocalAssign {
+                    name: String::from("__inline_1_x"),
+                    type_name: String::from("f32"),
+                    expression: Box::new(variable("result")),
+                    info: None,
+                },
+                Expression::LocalAssign {
+                    name: String::from("__inline_1_twice"),
+                    type_name: String::from("f32"),
+                    expression: Box::new(Expression::Addition {
+                        left: Box::new(variable("__inline_1_x")),
+                        right: Box::new(variable("__inline_1_x")),
+                        type_name: String::from("f32"),
+                        info: Some(TokenInfo { line: 1, index: 25 }),
+                    }),
+                    info: None,
+                },
+                Expression::Return {
+                    expression: Box::new(variable("__inline_1_twice")),
+                    info: Some(TokenInfo { line: 7, index: 4 }),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn only_calls_in_statement_position_are_copied() {
+        let calls = |expressions: &[Expression]| {
+            let mut names = vec![];
+            find_calls(expressions, &mut names);
+            names
+        };
+
+        let expressions = inline_source(
+            "import fn log(n: i32) console.log
+
+fn double(x: i32): i32 {
+    return x + x;
+}
+
+fn shout(x: i32): void {
+    log(x);
+}
+
+fn main(n: i32): i32 {
+    shout(n);
+    local twice: i32 = double(n);
+    log(double(twice));
+    local more: i32 = double(n) + 1;
+    if (double(n)) {
+        local inside: i32 = double(n);
+    } else {
+    };
+    return double(more) + twice;
+}",
+        );
+
+        // the statement, the local and the local inside the if are copied in,
+        // leaving the calls that are an argument, part of an addition or a
+        // predicate
+        assert_eq!(
+            calls(&expressions),
+            vec!["log", "log", "double", "double", "double", "double"]
+        );
+        assert_eq!(
+            expressions
+                .iter()
+                .filter(|expression| matches!(
+                    expression,
+                    Expression::LocalAssign { name, .. } if name.starts_with("__inline_")
+                ))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn calls_in_loops_trys_and_matches_are_copied() {
+        let expressions = inline_source(
+            "import fn log(n: i32) console.log
+
+fn shout(x: i32): void {
+    log(x);
+}
+
+fn main(n: i32): void {
+    local table: [i32] = [1, 2, 3];
+    for (x in table) {
+        shout(x);
+    };
+    try {
+        shout(n);
+    } catch {
+        shout(0);
+    };
+    match n {
+        0 => shout(1),
+        _ => {
+            shout(2);
+        },
+    };
+}",
+        );
+
+        let mut names = vec![];
+        find_calls(&expressions, &mut names);
+        assert_eq!(names, vec!["log", "log", "log", "log", "log"]);
+    }
+
+    fn find_calls(expressions: &[Expression], names: &mut Vec<String>) {
+        for expression in expressions {
+            match expression {
+                Expression::FunctionCall { name, args, .. } => {
+                    names.push(name.to_string());
+                    find_calls(args, names);
+                }
+                Expression::LocalAssign { expression, .. }
+                | Expression::Return { expression, .. } => {
+                    find_calls(std::slice::from_ref(expression), names)
+                }
+                Expression::Addition { left, right, .. } => {
+                    find_calls(std::slice::from_ref(left), names);
+                    find_calls(std::slice::from_ref(right), names);
+                }
+                Expression::IfStatement {
+                    predicate,
+                    success,
+                    fail,
+                } => {
+                    find_calls(std::slice::from_ref(predicate), names);
+                    find_calls(success, names);
+                    find_calls(fail, names);
+                }
+                Expression::ForEach { body, .. } => find_calls(body, names),
+                Expression::TryStatement { body, catch } => {
+                    find_calls(body, names);
+                    find_calls(catch, names);
+                }
+                Expression::MatchStatement { arms, default, .. } => {
+                    for (_, body) in arms {
+                        find_calls(body, names);
+                    }
+                    find_calls(default.as_deref().unwrap_or_default(), names);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    #[test]
+    fn recursive_and_branching_functions_stay_calls() {
+        let expressions = inline_source(
+            "fn forever(n: i32): i32 {
+    return forever(n);
+}
+
+fn pick(n: i32): i32 {
+    if (n) {
+        return 1;
+    } else {
+        return 2;
+    };
+}
+
+fn main(n: i32): i32 {
+    local picked: i32 = pick(n);
+    return forever(picked);
+}",
+        );
+
+        assert!(matches!(
+            expressions.as_slice(),
+            [Expression::LocalAssign { .. }, Expression::Return { .. }]
+        ));
+    }
+}
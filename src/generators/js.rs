@@ -0,0 +1,416 @@
+use crate::{
+    blocks::{Block, Function, ImportFunction, Param},
+    errors::CompileError,
+    generators::{
+        find_function,
+        naming::{self, NamingPolicy},
+        Backend,
+    },
+    parser::Program,
+    typechecker::resolve_aliases,
+};
+
+const PRELUDE: &str = "const encoder = new TextEncoder();
+const decoder = new TextDecoder();
+
+// Instantiates the module from its bytes, converting strings to and from
+// UTF-8 for its imports and exports
+export async function instantiate(source, imports = {}) {
+  let exports;
+
+  const read = (offset, length) =>
+    decoder.decode(new Uint8Array(exports.__memory.buffer, offset, length));
+
+  // strings are written one after the other into the string buffer, giving
+  // the offset and length of each
+  const write = (strings) => {
+    const start = exports.__string_buffer.value;
+    const end = start + exports.__string_buffer_length.value;
+    let offset = start;
+
+    return strings.flatMap((string) => {
+      const bytes = encoder.encode(string);
+      if (offset + bytes.length > end) {
+        throw new RangeError(\"Strings are too long for the string buffer\");
+      }
+      new Uint8Array(exports.__memory.buffer, offset, bytes.length).set(bytes);
+      offset += bytes.length;
+      return [offset - bytes.length, bytes.length];
+    });
+  };
+";
+
+fn is_string(param: &Param) -> bool {
+    param.type_name == "string"
+}
+
+/// Imports taking strings are given them decoded
+fn generate_import(import: &ImportFunction) -> Option<String> {
+    if !import.params.iter().any(is_string) {
+        return None;
+    }
+
+    let (module, name) = match import.external_name.as_slice() {
+        [module, name] => (module, name),
+        _ => return None,
+    };
+
+    let params = import
+        .params
+        .iter()
+        .map(|param| {
+            if is_string(param) {
+                format!("{}, {}__length", param.name, param.name)
+            } else {
+                param.name.to_string()
+            }
+        })
+        .collect::<Vec<String>>();
+    let args = import
+        .params
+        .iter()
+        .map(|param| {
+            if is_string(param) {
+                format!("read({}, {}__length)", param.name, param.name)
+            } else {
+                param.name.to_string()
+            }
+        })
+        .collect::<Vec<String>>();
+
+    Some(format!(
+        "  wrapped[\"{module}\"] = {{
+    ...wrapped[\"{module}\"],
+    \"{name}\": ({params}) => imports[\"{module}\"][\"{name}\"]({args}),
+  }};",
+        module = module,
+        name = name,
+        params = params.join(", "),
+        args = args.join(", ")
+    ))
+}
+
+/// Exports take and return strings as JS strings
+fn generate_export(external_name: &str, function: &Function) -> String {
+    if function.return_type != "string" && !function.params.iter().any(is_string) {
+        return format!("    \"{name}\": exports[\"{name}\"],", name = external_name);
+    }
+
+    let params = function
+        .params
+        .iter()
+        .map(|param| param.name.to_string())
+        .collect::<Vec<String>>();
+    let strings = function
+        .params
+        .iter()
+        .filter(|param| is_string(param))
+        .map(|param| param.name.to_string())
+        .collect::<Vec<String>>();
+
+    let mut string_index = 0;
+    let args = function
+        .params
+        .iter()
+        .map(|param| {
+            if is_string(param) {
+                string_index += 2;
+                format!(
+                    "strings[{}], strings[{}]",
+                    string_index - 2,
+                    string_index - 1
+                )
+            } else {
+                param.name.to_string()
+            }
+        })
+        .collect::<Vec<String>>();
+
+    let call = format!("exports[\"{}\"]({})", external_name, args.join(", "));
+    let body = if function.return_type == "string" {
+        format!(
+            "const length = {};\n      return read(exports.__string_buffer.value, length);",
+            call
+        )
+    } else {
+        format!("return {};", call)
+    };
+
+    format!(
+        "    \"{name}\": ({params}) => {{
+      const strings = write([{strings}]);
+      {body}
+    }},",
+        name = external_name,
+        params = params.join(", "),
+        strings = strings.join(", "),
+        body = body
+    )
+}
+
+/// The functions the module exports, with the names they're exported as
+fn exports(program: &Program) -> Vec<(String, &Function)> {
+    let find_function = |name: &str| {
+        program.blocks.iter().find_map(|block| match block {
+            Block::Function(function) if function.name == name => Some(function),
+            _ => None,
+        })
+    };
+
+    program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Export(export) => find_function(&export.function_name)
+                .map(|function| (export.external_name.to_string(), function)),
+            Block::Function(function) => function
+                .export_name()
+                .map(|external_name| (external_name, function)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// An ES module wrapping the module's imports and exports so that they
+/// take and return JS strings
+pub fn generate(program: &Program) -> String {
+    let imports = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::ImportFunction(import) => generate_import(import),
+            _ => None,
+        })
+        .collect::<Vec<String>>();
+
+    let exports = exports(program)
+        .into_iter()
+        .map(|(external_name, function)| generate_export(&external_name, function))
+        .collect::<Vec<String>>();
+
+    let wrapped_imports = if imports.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n", imports.join("\n"))
+    };
+
+    format!(
+        "{}
+  const wrapped = {{ ...imports }};
+{}
+  const {{ instance }} = await WebAssembly.instantiate(source, wrapped);
+  exports = instance.exports;
+
+  return {{
+{}
+  }};
+}}
+",
+        PRELUDE,
+        wrapped_imports,
+        exports.join("\n")
+    )
+}
+
+/// JS glue loading the module and converting its strings
+#[derive(Default)]
+pub struct Js {
+    /// The same policy as the module was generated with
+    pub naming: NamingPolicy,
+}
+
+impl Backend for Js {
+    fn extension(&self) -> &str {
+        "js"
+    }
+
+    fn generate_module(&self, program: Program) -> Result<Vec<u8>, CompileError> {
+        Ok(generate(&naming::apply(program, &self.naming)).into_bytes())
+    }
+
+    /// The entry a function gets in the returned exports
+    fn generate_function(&self, program: Program, name: &str) -> Result<String, CompileError> {
+        let function = find_function(
+            naming::apply(program, &self.naming),
+            &self.naming.symbol(name),
+        )?;
+        let external_name = function
+            .export_name()
+            .unwrap_or_else(|| self.naming.export_name(name));
+
+        Ok(generate_export(&external_name, &function))
+    }
+}
+
+/// The TypeScript type a gwe type has once it's passed through the glue
+fn typescript_type(type_name: &str) -> &str {
+    match type_name {
+        "i32" | "f32" | "f64" => "number",
+        "i64" => "bigint",
+        "string" => "string",
+        "void" => "void",
+        _ => "unknown",
+    }
+}
+
+fn declare_export(external_name: &str, function: &Function) -> String {
+    let params = function
+        .params
+        .iter()
+        .map(|param| format!("{}: {}", param.name, typescript_type(&param.type_name)))
+        .collect::<Vec<String>>();
+
+    format!(
+        "  \"{}\": ({}) => {};",
+        external_name,
+        params.join(", "),
+        typescript_type(&function.return_type)
+    )
+}
+
+/// TypeScript declarations for the ES module `generate` gives
+pub fn generate_declarations(program: &Program) -> String {
+    let program = resolve_aliases(program.clone());
+    let exports = exports(&program)
+        .into_iter()
+        .map(|(external_name, function)| declare_export(&external_name, function))
+        .collect::<Vec<String>>();
+
+    format!(
+        "export interface Exports {{
+{}
+}}
+
+export function instantiate(
+  source: BufferSource,
+  imports?: Record<string, Record<string, unknown>>,
+): Promise<Exports>;
+",
+        exports.join("\n")
+    )
+}
+
+/// Types for the JS glue, so TypeScript can check calls into the module
+#[derive(Default)]
+pub struct Dts {
+    /// The same policy as the module was generated with
+    pub naming: NamingPolicy,
+}
+
+impl Backend for Dts {
+    fn extension(&self) -> &str {
+        "d.ts"
+    }
+
+    fn generate_module(&self, program: Program) -> Result<Vec<u8>, CompileError> {
+        Ok(generate_declarations(&naming::apply(program, &self.naming)).into_bytes())
+    }
+
+    /// The entry a function gets in the Exports interface
+    fn generate_function(&self, program: Program, name: &str) -> Result<String, CompileError> {
+        let function = find_function(
+            resolve_aliases(naming::apply(program, &self.naming)),
+            &self.naming.symbol(name),
+        )?;
+        let external_name = function
+            .export_name()
+            .unwrap_or_else(|| self.naming.export_name(name));
+
+        Ok(declare_export(&external_name, &function))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{errors::join_errors, parser::parse};
+
+    use super::*;
+
+    #[test]
+    fn strings_are_converted_at_the_boundary() {
+        let input = String::from(
+            "import fn log(message: string) console.log
+import fn count(n: i32) console.count
+import memory 1 js.mem
+
+fn greet(greeting: string, times: i32): string {
+    log(greeting);
+    return greeting;
+}
+
+@export
+fn main(): void {
+}
+
+export greet greet",
+        );
+
+        let program = match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => program,
+        };
+        let output = generate(&program);
+
+        assert!(output.starts_with(PRELUDE));
+        assert_eq!(
+            &output[PRELUDE.len()..],
+            "
+  const wrapped = { ...imports };
+  wrapped[\"console\"] = {
+    ...wrapped[\"console\"],
+    \"log\": (message, message__length) => imports[\"console\"][\"log\"](read(message, message__length)),
+  };
+
+  const { instance } = await WebAssembly.instantiate(source, wrapped);
+  exports = instance.exports;
+
+  return {
+    \"main\": exports[\"main\"],
+    \"greet\": (greeting, times) => {
+      const strings = write([greeting]);
+      const length = exports[\"greet\"](strings[0], strings[1], times);
+      return read(exports.__string_buffer.value, length);
+    },
+  };
+}
+"
+        );
+    }
+
+    #[test]
+    fn declarations_describe_the_glue() {
+        let input = String::from(
+            "type Count = i64;
+
+fn greet(greeting: string, times: i32): string {
+    return greeting;
+}
+
+@export
+fn total(n: Count): Count {
+    return n;
+}
+
+export greet greet",
+        );
+
+        let program = match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => program,
+        };
+
+        assert_eq!(
+            generate_declarations(&program),
+            "export interface Exports {
+  \"total\": (n: bigint) => bigint;
+  \"greet\": (greeting: string, times: number) => string;
+}
+
+export function instantiate(
+  source: BufferSource,
+  imports?: Record<string, Record<string, unknown>>,
+): Promise<Exports>;
+"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+/// How generated wat is laid out
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Layout {
+    /// However the generator wrote it
+    #[default]
+    Generated,
+    /// One line per instruction, indented two spaces for each paren it's inside
+    Pretty,
+    /// Everything on one line with no more whitespace than needed, for
+    /// embedding in strings
+    Minified,
+}
+
+/// The wat laid out as asked for
+pub fn apply(wat: String, layout: Layout) -> String {
+    match layout {
+        Layout::Generated => wat,
+        Layout::Pretty => pretty(&wat),
+        Layout::Minified => minify(&wat),
+    }
+}
+
+/// How far the parens outside of strings move the depth, along with how many
+/// parens close before anything else on the line
+fn depth_change(code: &str) -> (i32, i32) {
+    let mut change = 0;
+    let mut leading_closes = 0;
+    let mut leading = true;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for character in code.chars() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => change += 1,
+            ')' if !in_string => {
+                change -= 1;
+                if leading {
+                    leading_closes += 1;
+                }
+            }
+            _ => (),
+        }
+
+        if character != ')' && !character.is_whitespace() {
+            leading = false;
+        }
+    }
+
+    (change, leading_closes)
+}
+
+/// Reindents every line by how deep it is in parens, so the layout doesn't
+/// depend on how the generator happened to indent each piece
+pub fn pretty(wat: &str) -> String {
+    let mut depth = 0;
+
+    wat.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (change, leading_closes) = depth_change(line);
+            let indent = (depth - leading_closes).max(0) as usize;
+            depth += change;
+
+            format!("{}{}", "  ".repeat(indent), line)
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Collapses whitespace outside of strings, dropping it next to parens where
+/// it isn't needed to separate tokens
+pub fn minify(wat: &str) -> String {
+    let mut output = String::new();
+    let mut space = false;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for character in wat.chars() {
+        if in_string {
+            match character {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => (),
+            }
+            output.push(character);
+            continue;
+        }
+
+        if character.is_whitespace() {
+            space = true;
+            continue;
+        }
+
+        if space && character != ')' && !output.is_empty() && !output.ends_with('(') {
+            output.push(' ');
+        }
+        space = false;
+
+        if character == '"' {
+            in_string = true;
+        }
+        output.push(character);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAT: &str = "(module
+  (func $main (param $n i32) (result i32)
+      (if
+    (local.get $n)
+        (then
+          (call $log (i32.const 1))
+  )
+      )
+    (i32.const 0))
+  (data (i32.const 0) \"a  (b\\\" )\")
+)";
+
+    #[test]
+    fn pretty_indents_by_depth() {
+        assert_eq!(
+            pretty(WAT),
+            "(module
+  (func $main (param $n i32) (result i32)
+    (if
+      (local.get $n)
+      (then
+        (call $log (i32.const 1))
+      )
+    )
+    (i32.const 0))
+  (data (i32.const 0) \"a  (b\\\" )\")
+)"
+        );
+        assert_eq!(pretty(&pretty(WAT)), pretty(WAT));
+    }
+
+    #[test]
+    fn minify_keeps_strings() {
+        assert_eq!(
+            minify(WAT),
+            "(module (func $main (param $n i32) (result i32) (if (local.get $n) (then (call $log (i32.const 1)))) (i32.const 0)) (data (i32.const 0) \"a  (b\\\" )\"))"
+        );
+    }
+}
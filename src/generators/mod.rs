@@ -1,2 +1,46 @@
+use crate::{
+    blocks::{Block, Function},
+    errors::CompileError,
+    parser::Program,
+};
+
+pub mod checks;
+pub mod docs;
 pub mod gwe;
+pub mod inlining;
+pub mod js;
+pub mod layout;
+pub mod naming;
+pub mod peephole;
+pub mod stack;
 pub mod web_assembly;
+
+/// A target programs are generated for. The CLI picks one by name and only
+/// talks to it through this, so a new target is a new implementation
+pub trait Backend {
+    /// The extension given to files written for this target
+    fn extension(&self) -> &str;
+
+    /// Whether data blocks need the bytes of their files read in first
+    fn reads_data(&self) -> bool {
+        false
+    }
+
+    /// The whole program, as text or binary depending on the target
+    fn generate_module(&self, program: Program) -> Result<Vec<u8>, CompileError>;
+
+    /// One function of the program as text, for seeing what it becomes
+    fn generate_function(&self, program: Program, name: &str) -> Result<String, CompileError>;
+}
+
+/// The function called `name`, for backends generating just that function
+pub fn find_function(program: Program, name: &str) -> Result<Function, CompileError> {
+    program
+        .blocks
+        .into_iter()
+        .find_map(|block| match block {
+            Block::Function(function) if function.name == name => Some(function),
+            _ => None,
+        })
+        .ok_or_else(|| CompileError::new(format!("There's no function called {}", name)))
+}
@@ -0,0 +1,225 @@
+use std::str::FromStr;
+
+use crate::{
+    blocks::{Attribute, Block},
+    expressions::Expression,
+    parser::Program,
+    visit_mut::{walk_expression_mut, VisitorMut},
+};
+
+/// How export names are written
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Case {
+    /// As the source gives them
+    #[default]
+    Keep,
+    /// `say_hello` becomes `sayHello`, as JS code expects
+    Camel,
+}
+
+impl FromStr for Case {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "keep" => Ok(Case::Keep),
+            "camel" => Ok(Case::Camel),
+            _ => Err(format!("Unknown case {}, expected keep or camel", str)),
+        }
+    }
+}
+
+/// How the names in the source become the names in generated code. The wat
+/// generator and the JS glue both go through this, so that the glue always
+/// looks for the exports under the names the module gives them
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct NamingPolicy {
+    pub export_case: Case,
+    /// Put before every export name, after the case is changed
+    pub export_prefix: String,
+    /// Put before the wat symbol of every function and imported function, so
+    /// the wat of several modules can be put together without clashes
+    pub symbol_prefix: String,
+}
+
+impl NamingPolicy {
+    /// The name an export is given to the host
+    pub fn export_name(&self, name: &str) -> String {
+        let name = match self.export_case {
+            Case::Keep => name.to_string(),
+            Case::Camel => camel_case(name),
+        };
+
+        format!("{}{}", self.export_prefix, name)
+    }
+
+    /// The name a function has in the wat, without the `$`
+    pub fn symbol(&self, name: &str) -> String {
+        format!("{}{}", self.symbol_prefix, name)
+    }
+
+    fn is_default(&self) -> bool {
+        *self == NamingPolicy::default()
+    }
+}
+
+/// `say_hello` as `sayHello`. Leading underscores are kept, so that names
+/// like `__memory` stay as they are
+fn camel_case(name: &str) -> String {
+    let leading = name.len() - name.trim_start_matches('_').len();
+    let mut camel = name[..leading].to_string();
+    let mut upper = false;
+
+    for character in name[leading..].chars() {
+        match character {
+            '_' => upper = true,
+            character if upper => {
+                camel.extend(character.to_uppercase());
+                upper = false;
+            }
+            character => camel.push(character),
+        }
+    }
+
+    camel
+}
+
+/// Renames calls to the functions the program defines or imports
+struct RenameCalls<'a> {
+    policy: &'a NamingPolicy,
+    functions: Vec<String>,
+}
+
+impl VisitorMut for RenameCalls<'_> {
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        if let Expression::FunctionCall { name, .. } | Expression::TailCall { name, .. } =
+            expression
+        {
+            if self.functions.contains(name) {
+                *name = self.policy.symbol(name);
+            }
+        }
+
+        walk_expression_mut(self, expression);
+    }
+}
+
+/// The program with its exports and function names given by the policy
+pub fn apply(program: Program, policy: &NamingPolicy) -> Program {
+    if policy.is_default() {
+        return program;
+    }
+
+    let functions: Vec<String> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function) => Some(function.name.clone()),
+            Block::ImportFunction(import) => Some(import.name.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut program = Program {
+        blocks: program
+            .blocks
+            .into_iter()
+            .map(|block| match block {
+                Block::Function(mut function) => {
+                    // an @export without a name would otherwise take the new symbol
+                    if let Some(external_name) = function.export_name() {
+                        function
+                            .attributes
+                            .retain(|attribute| attribute.name != "export");
+                        function.attributes.push(Attribute {
+                            name: String::from("export"),
+                            args: vec![policy.export_name(&external_name)],
+                        });
+                    }
+                    function.name = policy.symbol(&function.name);
+                    Block::Function(function)
+                }
+                Block::ImportFunction(mut import) => {
+                    import.name = policy.symbol(&import.name);
+                    Block::ImportFunction(import)
+                }
+                Block::Export(mut export) => {
+                    export.function_name = policy.symbol(&export.function_name);
+                    export.external_name = policy.export_name(&export.external_name);
+                    Block::Export(export)
+                }
+                block => block,
+            })
+            .collect(),
+    };
+
+    RenameCalls { policy, functions }.visit_program_mut(&mut program);
+
+    program
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generators::{
+            js,
+            web_assembly::{generate_with, validate, GenerateOptions},
+        },
+        parser::parse,
+    };
+
+    #[test]
+    fn exports_and_symbols_follow_the_policy() {
+        let program = parse(String::from(
+            "import fn log(n: i32) console.log
+
+fn log_twice(n: i32): void {
+    log(n);
+    log(n);
+}
+
+@export
+fn say_hello(n: i32): void {
+    log_twice(n);
+}
+
+export __log_twice log_twice",
+        ))
+        .unwrap();
+        let policy = NamingPolicy {
+            export_case: Case::Camel,
+            export_prefix: String::from("app_"),
+            symbol_prefix: String::from("app."),
+        };
+
+        let wat = generate_with(
+            program.clone(),
+            &GenerateOptions {
+                naming: policy.clone(),
+                ..GenerateOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(validate(&wat), Ok(()));
+        assert!(wat.contains("(import \"console\" \"log\" (func $app.log"));
+        assert!(wat.contains("(call $app.log_twice)"));
+        assert!(wat.contains("(export \"app_sayHello\" (func $app.say_hello))"));
+        assert!(wat.contains("(export \"app___logTwice\" (func $app.log_twice))"));
+
+        let glue = js::generate(&apply(program, &policy));
+        assert!(glue.contains("\"app_sayHello\""));
+        assert!(!glue.contains("\"say_hello\""));
+    }
+
+    #[test]
+    fn cases() {
+        assert_eq!(camel_case("say_hello_world"), "sayHelloWorld");
+        assert_eq!(camel_case("__string_buffer"), "__stringBuffer");
+        assert_eq!(
+            "snake".parse::<Case>(),
+            Err(String::from("Unknown case snake, expected keep or camel"))
+        );
+    }
+}
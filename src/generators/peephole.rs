@@ -0,0 +1,244 @@
+/// Cleans up generated wat by looking at neighbouring lines, which hold one
+/// instruction each at the same indentation when they're in the same block
+pub fn optimize(wat: &str) -> String {
+    let lines = wat.lines().map(str::to_string).collect();
+
+    merge_data(drop_constants(tee_sets(lines))).join("\n")
+}
+
+fn split_indent(line: &str) -> (&str, &str) {
+    let code = line.trim_start();
+
+    (&line[..line.len() - code.len()], code)
+}
+
+/// Whether the parens outside of strings close on the same line they open
+fn is_balanced(code: &str) -> bool {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for character in code.chars() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => depth -= 1,
+            _ => (),
+        }
+    }
+
+    depth == 0 && !in_string
+}
+
+/// The local and what follows it in a whole `(local.set $name ...)`
+fn local_set(code: &str) -> Option<(&str, &str)> {
+    let rest = code.strip_prefix("(local.set $")?;
+    let end = rest.find([' ', ')'])?;
+
+    if is_balanced(code) {
+        Some(rest.split_at(end))
+    } else {
+        None
+    }
+}
+
+/// A set followed by a get of the same local is a tee, which keeps the value
+/// on the stack
+fn tee_sets(lines: Vec<String>) -> Vec<String> {
+    let mut output: Vec<String> = vec![];
+    let mut lines = lines.into_iter().peekable();
+
+    while let Some(line) = lines.next() {
+        let (indent, code) = split_indent(&line);
+        let tee = local_set(code).and_then(|(name, rest)| {
+            let get = format!("(local.get ${})", name);
+
+            match lines.peek().map(|next| split_indent(next)) {
+                Some((next_indent, next_code)) if next_indent == indent && next_code == get => {
+                    Some(format!("{}(local.tee ${}{}", indent, name, rest))
+                }
+                _ => None,
+            }
+        });
+
+        match tee {
+            Some(tee) => {
+                lines.next();
+                output.push(tee);
+            }
+            None => output.push(line),
+        }
+    }
+
+    output
+}
+
+fn is_constant(code: &str) -> bool {
+    match code
+        .strip_prefix('(')
+        .and_then(|code| code.strip_suffix(')'))
+        .map(|inner| inner.split_whitespace().collect::<Vec<&str>>())
+        .as_deref()
+    {
+        Some([instruction, value]) => {
+            matches!(
+                *instruction,
+                "i32.const" | "i64.const" | "f32.const" | "f64.const"
+            ) && !value.contains(['(', ')'])
+        }
+        _ => false,
+    }
+}
+
+/// Constants which are dropped straight away do nothing
+fn drop_constants(lines: Vec<String>) -> Vec<String> {
+    let mut output: Vec<String> = vec![];
+    let mut lines = lines.into_iter().peekable();
+
+    while let Some(line) = lines.next() {
+        let (indent, code) = split_indent(&line);
+
+        let folded = code
+            .strip_prefix("(drop ")
+            .and_then(|code| code.strip_suffix(')'))
+            .is_some_and(is_constant);
+        let flat = is_constant(code)
+            && lines
+                .peek()
+                .is_some_and(|next| split_indent(next) == (indent, "(drop)"));
+
+        if flat {
+            lines.next();
+        } else if !folded {
+            output.push(line);
+        }
+    }
+
+    output
+}
+
+/// The offset and escaped bytes of an active data segment in the first memory
+fn data_segment(code: &str) -> Option<(i64, &str)> {
+    let rest = code.strip_prefix("(data (i32.const ")?;
+    let (offset, rest) = rest.split_once(") \"")?;
+    let bytes = rest.strip_suffix("\")")?;
+
+    Some((offset.parse().ok()?, bytes))
+}
+
+/// Bytes are escaped as `\xx`, anything else is one byte per character
+fn escaped_length(bytes: &str) -> i64 {
+    let escapes = bytes.matches('\\').count();
+
+    (bytes.chars().count() - escapes * 2) as i64
+}
+
+/// Segments which carry on where the one before ends become one segment
+fn merge_data(lines: Vec<String>) -> Vec<String> {
+    let mut output: Vec<String> = vec![];
+
+    for line in lines {
+        let (indent, code) = split_indent(&line);
+
+        let merged = output.last().and_then(|previous| {
+            let (previous_indent, previous_code) = split_indent(previous);
+            let (offset, bytes) = data_segment(previous_code)?;
+            let (next_offset, next_bytes) = data_segment(code)?;
+
+            if previous_indent == indent && offset + escaped_length(bytes) == next_offset {
+                Some(format!(
+                    "{}(data (i32.const {}) \"{}{}\")",
+                    indent, offset, bytes, next_bytes
+                ))
+            } else {
+                None
+            }
+        });
+
+        match merged {
+            Some(merged) => {
+                output.pop();
+                output.push(merged);
+            }
+            None => output.push(line),
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_followed_by_gets_are_tees() {
+        assert_eq!(
+            optimize(
+                "(func $main (result i32)
+  (local $x i32)
+  (local.set $x (i32.add (i32.const 1) (i32.const 2)))
+  (local.get $x)
+  (local.set $x)
+  (local.get $x)
+  (local.set $x (i32.const 3))
+  (local.get $y)
+)"
+            ),
+            "(func $main (result i32)
+  (local $x i32)
+  (local.tee $x (i32.add (i32.const 1) (i32.const 2)))
+  (local.tee $x)
+  (local.set $x (i32.const 3))
+  (local.get $y)
+)"
+        );
+    }
+
+    #[test]
+    fn sets_split_over_lines_are_left_alone() {
+        let wat = "(local.set $x
+  (i32.const 1)
+)
+(local.get $x)";
+
+        assert_eq!(optimize(wat), wat);
+    }
+
+    #[test]
+    fn dropped_constants_are_removed() {
+        assert_eq!(
+            optimize(
+                "(func $main
+  (i32.const 1)
+  (drop)
+  (drop (f32.const 2.0))
+  (call $log)
+  (drop)
+)"
+            ),
+            "(func $main
+  (call $log)
+  (drop)
+)"
+        );
+    }
+
+    #[test]
+    fn adjacent_data_is_merged() {
+        assert_eq!(
+            optimize(
+                "(data (i32.const 0) \"ab\")
+(data (i32.const 2) \"\\00c\")
+(data (i32.const 4) \"d\")
+(data (i32.const 8) \"e\")
+(data (memory $other) (i32.const 9) \"f\")"
+            ),
+            "(data (i32.const 0) \"ab\\00cd\")
+(data (i32.const 8) \"e\")
+(data (memory $other) (i32.const 9) \"f\")"
+        );
+    }
+}
@@ -0,0 +1,837 @@
+use std::collections::HashMap;
+
+use crate::errors::CompileError;
+
+/// A word of wat like `i32.add`, `$x` or a string, or a list in parens
+#[derive(PartialEq, Debug, Clone)]
+enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+impl Sexp {
+    fn head(&self) -> Option<&str> {
+        match self {
+            Sexp::List(items) => match items.first() {
+                Some(Sexp::Atom(head)) => Some(head),
+                _ => None,
+            },
+            Sexp::Atom(_) => None,
+        }
+    }
+
+    fn items(&self) -> &[Sexp] {
+        match self {
+            Sexp::List(items) => items,
+            Sexp::Atom(_) => &[],
+        }
+    }
+}
+
+/// Writes a piece of wat back out, for types like `(ref $array)`
+fn render(sexp: &Sexp) -> String {
+    match sexp {
+        Sexp::Atom(atom) => atom.to_string(),
+        Sexp::List(items) => format!(
+            "({})",
+            items.iter().map(render).collect::<Vec<String>>().join(" ")
+        ),
+    }
+}
+
+/// The wat as lists, leaving out comments
+fn parse(wat: &str) -> Vec<Sexp> {
+    let mut lists: Vec<Vec<Sexp>> = vec![vec![]];
+    let mut atom = String::new();
+    let mut chars = wat.chars().peekable();
+
+    fn finish(atom: &mut String, lists: &mut [Vec<Sexp>]) {
+        if !atom.is_empty() {
+            if let Some(list) = lists.last_mut() {
+                list.push(Sexp::Atom(std::mem::take(atom)));
+            }
+        }
+    }
+
+    while let Some(character) = chars.next() {
+        match character {
+            '"' => {
+                atom.push(character);
+                while let Some(character) = chars.next() {
+                    atom.push(character);
+                    match character {
+                        '\\' => atom.extend(chars.next()),
+                        '"' => break,
+                        _ => (),
+                    }
+                }
+            }
+            ';' if chars.peek() == Some(&';') => {
+                finish(&mut atom, &mut lists);
+                for character in chars.by_ref() {
+                    if character == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' if chars.peek() == Some(&';') => {
+                finish(&mut atom, &mut lists);
+                let mut previous = ' ';
+                for character in chars.by_ref() {
+                    if previous == ';' && character == ')' {
+                        break;
+                    }
+                    previous = character;
+                }
+            }
+            '(' => {
+                finish(&mut atom, &mut lists);
+                lists.push(vec![]);
+            }
+            ')' => {
+                finish(&mut atom, &mut lists);
+                if lists.len() > 1 {
+                    let list = lists.pop().unwrap_or_default();
+                    if let Some(parent) = lists.last_mut() {
+                        parent.push(Sexp::List(list));
+                    }
+                }
+            }
+            _ if character.is_whitespace() => finish(&mut atom, &mut lists),
+            _ => atom.push(character),
+        }
+    }
+
+    finish(&mut atom, &mut lists);
+    lists.into_iter().flatten().collect()
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+struct Signature {
+    params: Vec<String>,
+    results: Vec<String>,
+}
+
+/// The types listed in `(param ...)`, `(result ...)` or `(local ...)`
+/// fields, along with their names when they have one
+fn typed(fields: &[Sexp], kind: &str) -> Vec<(Option<String>, String)> {
+    fields
+        .iter()
+        .filter(|field| field.head() == Some(kind))
+        .flat_map(|field| match &field.items()[1..] {
+            [Sexp::Atom(name), type_name] if name.starts_with('$') => {
+                vec![(Some(name.to_string()), render(type_name))]
+            }
+            types => types
+                .iter()
+                .map(|type_name| (None, render(type_name)))
+                .collect(),
+        })
+        .collect()
+}
+
+fn signature(fields: &[Sexp]) -> Signature {
+    Signature {
+        params: typed(fields, "param").into_iter().map(|(_, t)| t).collect(),
+        results: typed(fields, "result")
+            .into_iter()
+            .map(|(_, t)| t)
+            .collect(),
+    }
+}
+
+/// The name given right after the head of a list, like `$main` in `(func $main ...)`
+fn name(field: &Sexp) -> Option<String> {
+    match field.items().get(1) {
+        Some(Sexp::Atom(name)) if name.starts_with('$') => Some(name.to_string()),
+        _ => None,
+    }
+}
+
+/// The type of a global, which is mutable when it's wrapped in `(mut ...)`
+fn global_type(fields: &[Sexp]) -> Option<String> {
+    fields.iter().find_map(|field| match field {
+        Sexp::List(items) if field.head() == Some("mut") => items.get(1).map(render),
+        Sexp::Atom(atom) if !atom.starts_with('$') => Some(atom.to_string()),
+        _ => None,
+    })
+}
+
+/// What the functions and globals of a module take and give, so calls and
+/// globals can be checked wherever they're used
+#[derive(Default)]
+struct Module {
+    functions: HashMap<String, Signature>,
+    globals: HashMap<String, String>,
+}
+
+impl Module {
+    fn declare(&mut self, field: &Sexp) {
+        let fields = field.items().get(1..).unwrap_or_default();
+
+        match (field.head(), name(field)) {
+            (Some("func"), Some(name)) => {
+                self.functions.insert(name, signature(fields));
+            }
+            (Some("global"), Some(name)) => {
+                if let Some(type_name) = global_type(&fields[1..]) {
+                    self.globals.insert(name, type_name);
+                }
+            }
+            (Some("import"), _) => {
+                for imported in fields.iter().filter(|field| matches!(field, Sexp::List(_))) {
+                    self.declare(imported);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Why a function stopped being audited
+enum Stop {
+    /// It uses something the audit doesn't know the types of, so it's left
+    /// for the validator
+    Unsupported,
+    Invalid(String),
+}
+
+type Value = Option<String>;
+
+/// The stack inside a block. Once something like `return` or `br` is hit,
+/// anything left to pop is unknown, as the code can't be reached
+#[derive(Default)]
+struct Frame {
+    stack: Vec<Value>,
+    unreachable: bool,
+}
+
+struct Label {
+    name: Option<String>,
+    /// What a branch to the label takes off the stack
+    types: Vec<String>,
+}
+
+struct Audit<'a> {
+    module: &'a Module,
+    locals: Vec<(Option<String>, String)>,
+    results: Vec<String>,
+    frames: Vec<Frame>,
+    labels: Vec<Label>,
+}
+
+const NUMBERS: [&str; 4] = ["i32", "i64", "f32", "f64"];
+
+const BINARY: [&str; 19] = [
+    "add", "sub", "mul", "div", "div_s", "div_u", "rem_s", "rem_u", "and", "or", "xor", "shl",
+    "shr_s", "shr_u", "rotl", "rotr", "min", "max", "copysign",
+];
+
+const COMPARE: [&str; 14] = [
+    "eq", "ne", "lt", "lt_s", "lt_u", "gt", "gt_s", "gt_u", "le", "le_s", "le_u", "ge", "ge_s",
+    "ge_u",
+];
+
+const UNARY: [&str; 13] = [
+    "clz",
+    "ctz",
+    "popcnt",
+    "abs",
+    "neg",
+    "sqrt",
+    "ceil",
+    "floor",
+    "trunc",
+    "nearest",
+    "extend8_s",
+    "extend16_s",
+    "extend32_s",
+];
+
+/// The types a stack holds, for errors
+fn describe(types: &[Value]) -> String {
+    if types.is_empty() {
+        String::from("nothing")
+    } else {
+        types
+            .iter()
+            .map(|value| value.as_deref().unwrap_or("anything"))
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+}
+
+/// Whether an atom is an immediate of the instruction before it, rather than
+/// the next instruction
+fn is_immediate(atom: &str) -> bool {
+    atom.starts_with(['$', '-', '+', '"'])
+        || atom.starts_with(|character: char| character.is_ascii_digit())
+        || atom.contains('=')
+        || atom.starts_with("nan")
+        || atom.starts_with("inf")
+}
+
+/// The label a block starts with, which holds its results, along with the
+/// rest of the block
+fn block_type(items: &[Sexp]) -> Result<(Label, &[Sexp]), Stop> {
+    let (name, items) = match items.first() {
+        Some(Sexp::Atom(name)) if name.starts_with('$') => (Some(name.to_string()), &items[1..]),
+        _ => (None, items),
+    };
+
+    let typed_fields = items
+        .iter()
+        .take_while(|item| matches!(item.head(), Some("result") | Some("param") | Some("type")))
+        .count();
+    let (fields, rest) = items.split_at(typed_fields);
+
+    if fields.iter().any(|field| field.head() != Some("result")) {
+        return Err(Stop::Unsupported);
+    }
+
+    let label = Label {
+        name,
+        types: signature(fields).results,
+    };
+
+    Ok((label, rest))
+}
+
+impl Audit<'_> {
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("there's always a frame")
+    }
+
+    fn push(&mut self, type_name: &str) {
+        self.frame().stack.push(Some(type_name.to_string()));
+    }
+
+    fn pop(&mut self, instruction: &str, expected: Option<&str>) -> Result<Value, Stop> {
+        let frame = self.frame();
+
+        match (frame.stack.pop(), expected) {
+            (Some(Some(found)), Some(expected)) if found != expected => Err(Stop::Invalid(
+                format!("{} needs {} but got {}", instruction, expected, found),
+            )),
+            (Some(Some(found)), _) => Ok(Some(found)),
+            (Some(None), expected) => Ok(expected.map(str::to_string)),
+            (None, expected) if frame.unreachable => Ok(expected.map(str::to_string)),
+            (None, expected) => Err(Stop::Invalid(format!(
+                "{} needs {} but the stack is empty",
+                instruction,
+                expected.unwrap_or("a value")
+            ))),
+        }
+    }
+
+    fn pop_all(&mut self, instruction: &str, types: &[String]) -> Result<(), Stop> {
+        for type_name in types.iter().rev() {
+            self.pop(instruction, Some(type_name))?;
+        }
+
+        Ok(())
+    }
+
+    fn unreachable(&mut self) {
+        let frame = self.frame();
+        frame.stack.clear();
+        frame.unreachable = true;
+    }
+
+    fn local(&self, immediate: Option<&str>) -> Result<String, Stop> {
+        let index = match immediate {
+            Some(name) if name.starts_with('$') => self
+                .locals
+                .iter()
+                .position(|(local, _)| local.as_deref() == Some(name)),
+            Some(index) => index.parse::<usize>().ok(),
+            None => None,
+        };
+
+        match index.and_then(|index| self.locals.get(index)) {
+            Some((_, type_name)) => Ok(type_name.to_string()),
+            None => Err(Stop::Invalid(format!(
+                "{} isn't a local",
+                immediate.unwrap_or("nothing")
+            ))),
+        }
+    }
+
+    fn label(&self, immediate: Option<&str>) -> Result<Vec<String>, Stop> {
+        let label = match immediate {
+            Some(name) if name.starts_with('$') => self
+                .labels
+                .iter()
+                .rev()
+                .find(|label| label.name.as_deref() == Some(name)),
+            Some(depth) => depth
+                .parse::<usize>()
+                .ok()
+                .and_then(|depth| self.labels.iter().rev().nth(depth)),
+            None => None,
+        };
+
+        match label {
+            Some(label) => Ok(label.types.clone()),
+            None => Err(Stop::Invalid(format!(
+                "{} isn't a label",
+                immediate.unwrap_or("nothing")
+            ))),
+        }
+    }
+
+    /// Runs one instruction once its folded operands are on the stack
+    fn apply(&mut self, instruction: &str, immediates: &[&str]) -> Result<(), Stop> {
+        let first = immediates.first().copied();
+
+        match instruction {
+            "nop" => (),
+            "unreachable" => self.unreachable(),
+            "drop" => {
+                self.pop(instruction, None)?;
+            }
+            "select" => {
+                self.pop(instruction, Some("i32"))?;
+                let second = self.pop(instruction, None)?;
+                let first = self.pop(instruction, second.as_deref())?;
+                self.frame().stack.push(first.or(second));
+            }
+            "local.get" => {
+                let type_name = self.local(first)?;
+                self.push(&type_name);
+            }
+            "local.set" | "local.tee" => {
+                let type_name = self.local(first)?;
+                self.pop(instruction, Some(&type_name))?;
+                if instruction == "local.tee" {
+                    self.push(&type_name);
+                }
+            }
+            "global.get" | "global.set" => {
+                let type_name = match first.and_then(|name| self.module.globals.get(name)) {
+                    Some(type_name) => type_name.to_string(),
+                    None => return Err(Stop::Unsupported),
+                };
+                if instruction == "global.get" {
+                    self.push(&type_name);
+                } else {
+                    self.pop(instruction, Some(&type_name))?;
+                }
+            }
+            "call" | "return_call" => {
+                let signature = match first.and_then(|name| self.module.functions.get(name)) {
+                    Some(signature) => signature.clone(),
+                    None => return Err(Stop::Unsupported),
+                };
+                let call = format!("{} {}", instruction, first.unwrap_or_default());
+                self.pop_all(&call, &signature.params)?;
+                if instruction == "call" {
+                    for type_name in &signature.results {
+                        self.push(type_name);
+                    }
+                } else {
+                    self.unreachable();
+                }
+            }
+            "return" => {
+                let results = self.results.clone();
+                self.pop_all(instruction, &results)?;
+                self.unreachable();
+            }
+            "br" | "br_if" => {
+                if instruction == "br_if" {
+                    self.pop(instruction, Some("i32"))?;
+                }
+                let types = self.label(first)?;
+                self.pop_all(instruction, &types)?;
+                if instruction == "br" {
+                    self.unreachable();
+                } else {
+                    for type_name in &types {
+                        self.push(type_name);
+                    }
+                }
+            }
+            "memory.size" => self.push("i32"),
+            "memory.grow" => {
+                self.pop(instruction, Some("i32"))?;
+                self.push("i32");
+            }
+            "memory.fill" | "memory.copy" => {
+                for _ in 0..3 {
+                    self.pop(instruction, Some("i32"))?;
+                }
+            }
+            _ => return self.number(instruction),
+        }
+
+        Ok(())
+    }
+
+    /// Runs an instruction on one of the number types, like `i32.add` or
+    /// `f32.convert_i32_s`
+    fn number(&mut self, instruction: &str) -> Result<(), Stop> {
+        let Some((type_name, operation)) = instruction.split_once('.') else {
+            return Err(Stop::Unsupported);
+        };
+        if !NUMBERS.contains(&type_name) {
+            return Err(Stop::Unsupported);
+        }
+        // the type converted from, like the i32 in `f32.convert_i32_s`
+        let source = operation
+            .split('_')
+            .skip(1)
+            .find(|part| NUMBERS.contains(part));
+
+        if operation == "const" {
+            self.push(type_name);
+        } else if operation.starts_with("load") {
+            self.pop(instruction, Some("i32"))?;
+            self.push(type_name);
+        } else if operation.starts_with("store") {
+            self.pop(instruction, Some(type_name))?;
+            self.pop(instruction, Some("i32"))?;
+        } else if BINARY.contains(&operation) || COMPARE.contains(&operation) {
+            self.pop(instruction, Some(type_name))?;
+            self.pop(instruction, Some(type_name))?;
+            self.push(if COMPARE.contains(&operation) {
+                "i32"
+            } else {
+                type_name
+            });
+        } else if operation == "eqz" {
+            self.pop(instruction, Some(type_name))?;
+            self.push("i32");
+        } else if UNARY.contains(&operation) {
+            self.pop(instruction, Some(type_name))?;
+            self.push(type_name);
+        } else if let Some(source) = source {
+            self.pop(instruction, Some(source))?;
+            self.push(type_name);
+        } else {
+            return Err(Stop::Unsupported);
+        }
+
+        Ok(())
+    }
+
+    /// Runs instructions written one after another, where flat instructions
+    /// take the words after them as immediates
+    fn sequence(&mut self, items: &[Sexp]) -> Result<(), Stop> {
+        let mut index = 0;
+
+        while let Some(item) = items.get(index) {
+            index += 1;
+
+            match item {
+                Sexp::List(_) => self.folded(item)?,
+                Sexp::Atom(instruction) => {
+                    // flat blocks need their ends matched up, which generated wat doesn't use
+                    if ["block", "loop", "if", "else", "end", "try", "try_table"]
+                        .contains(&instruction.as_str())
+                    {
+                        return Err(Stop::Unsupported);
+                    }
+
+                    let mut immediates = vec![];
+                    while let Some(Sexp::Atom(atom)) = items.get(index) {
+                        if !is_immediate(atom) {
+                            break;
+                        }
+                        immediates.push(atom.as_str());
+                        index += 1;
+                    }
+
+                    self.apply(instruction, &immediates)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs an instruction in parens, whose operands come first
+    fn folded(&mut self, sexp: &Sexp) -> Result<(), Stop> {
+        let items = sexp.items();
+        let Some(Sexp::Atom(instruction)) = items.first() else {
+            return Err(Stop::Unsupported);
+        };
+
+        match instruction.as_str() {
+            "block" | "loop" => self.block(instruction, &items[1..]),
+            "if" => self.conditional(&items[1..]),
+            _ => {
+                let immediates = items[1..]
+                    .iter()
+                    .filter_map(|item| match item {
+                        Sexp::Atom(atom) => Some(atom.as_str()),
+                        Sexp::List(_) => None,
+                    })
+                    .collect::<Vec<&str>>();
+
+                for operand in items[1..]
+                    .iter()
+                    .filter(|item| matches!(item, Sexp::List(_)))
+                {
+                    self.folded(operand)?;
+                }
+
+                self.apply(instruction, &immediates)
+            }
+        }
+    }
+
+    /// Runs a block with its own stack, which has to end up holding just its
+    /// results, which are then pushed to the block outside it
+    fn scoped(
+        &mut self,
+        what: &str,
+        label: Label,
+        results: &[String],
+        body: &[Sexp],
+    ) -> Result<(), Stop> {
+        self.frames.push(Frame::default());
+        self.labels.push(label);
+
+        self.sequence(body)?;
+
+        let frame = self.frames.pop().unwrap_or_default();
+        self.labels.pop();
+
+        let expected = results.iter().cloned().map(Some).collect::<Vec<Value>>();
+        let matches = |stack: &[Value]| {
+            stack
+                .iter()
+                .zip(&expected)
+                .all(|(found, expected)| found.is_none() || found == expected)
+        };
+
+        let balanced = if frame.unreachable {
+            frame.stack.len() <= expected.len()
+                && frame
+                    .stack
+                    .iter()
+                    .rev()
+                    .zip(expected.iter().rev())
+                    .all(|(found, expected)| found.is_none() || found == expected)
+        } else {
+            frame.stack.len() == expected.len() && matches(&frame.stack)
+        };
+
+        if !balanced {
+            return Err(Stop::Invalid(format!(
+                "{} should leave {} on the stack but leaves {}",
+                what,
+                describe(&expected),
+                describe(&frame.stack)
+            )));
+        }
+
+        for type_name in results {
+            self.push(type_name);
+        }
+
+        Ok(())
+    }
+
+    fn block(&mut self, instruction: &str, items: &[Sexp]) -> Result<(), Stop> {
+        let (mut label, body) = block_type(items)?;
+        let results = label.types.clone();
+        // branching to a loop starts it again, rather than leaving it
+        if instruction == "loop" {
+            label.types = vec![];
+        }
+        let what = match &label.name {
+            Some(name) => format!("{} {}", instruction, name),
+            None => instruction.to_string(),
+        };
+
+        self.scoped(&what, label, &results, body)
+    }
+
+    fn conditional(&mut self, items: &[Sexp]) -> Result<(), Stop> {
+        let (
+            Label {
+                name,
+                types: results,
+            },
+            rest,
+        ) = block_type(items)?;
+        let branch = |head: &str| rest.iter().find(|item| item.head() == Some(head));
+
+        for condition in rest
+            .iter()
+            .filter(|item| !matches!(item.head(), Some("then") | Some("else")))
+        {
+            self.folded(condition)?;
+        }
+        self.pop("if", Some("i32"))?;
+
+        let mut branches = vec![("then", branch("then"))];
+        if branch("else").is_some() || !results.is_empty() {
+            branches.push(("else", branch("else")));
+        }
+
+        for (what, body) in branches {
+            let label = Label {
+                name: name.clone(),
+                types: results.clone(),
+            };
+            let body = body.map(|body| &body.items()[1..]).unwrap_or_default();
+
+            self.scoped(&format!("The {} of an if", what), label, &results, body)?;
+            // each branch starts from the stack before the if
+            for _ in &results {
+                self.frame().stack.pop();
+            }
+        }
+
+        for type_name in &results {
+            self.push(type_name);
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs through each function in the generated wat, checking every
+/// instruction finds the types it needs on the stack and every block leaves
+/// what it says it returns. This reports problems by the gwe function they're
+/// in, before wat2wasm or the runtime see the module. Functions using
+/// instructions it doesn't know, like the GC and exception ones, are left for
+/// the validator
+pub fn audit(wat: &str) -> Result<(), CompileError> {
+    let fields = parse(wat)
+        .into_iter()
+        .flat_map(|sexp| match sexp.head() {
+            Some("module") => sexp.items()[1..].to_vec(),
+            _ => vec![sexp],
+        })
+        .collect::<Vec<Sexp>>();
+
+    let mut module = Module::default();
+    for field in &fields {
+        module.declare(field);
+    }
+
+    for (index, field) in fields
+        .iter()
+        .filter(|field| field.head() == Some("func"))
+        .enumerate()
+    {
+        let items = &field.items()[1..];
+        let function = name(field).unwrap_or_else(|| format!("function {}", index));
+
+        let declarations = items
+            .iter()
+            .take_while(|item| match item {
+                Sexp::Atom(atom) => atom.starts_with('$'),
+                Sexp::List(_) => matches!(
+                    item.head(),
+                    Some("export") | Some("type") | Some("param") | Some("result") | Some("local")
+                ),
+            })
+            .count();
+        let (declared, body) = items.split_at(declarations);
+
+        let signature = signature(declared);
+        let mut audit = Audit {
+            module: &module,
+            locals: [typed(declared, "param"), typed(declared, "local")].concat(),
+            results: signature.results.clone(),
+            // the function's results are pushed here once its body is done
+            frames: vec![Frame::default()],
+            labels: vec![],
+        };
+        let label = Label {
+            name: None,
+            types: signature.results.clone(),
+        };
+
+        match audit.scoped("The function", label, &signature.results, body) {
+            Err(Stop::Invalid(problem)) => {
+                return Err(CompileError::new(format!(
+                    "Generated wat for {} doesn't balance the stack: {}",
+                    function.trim_start_matches('$'),
+                    problem
+                )))
+            }
+            Err(Stop::Unsupported) | Ok(()) => (),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balanced_functions_pass() {
+        assert!(audit(
+            "(module
+  (import \"console\" \"log\" (func $log (param f32)))
+  (global $count (mut i32) (i32.const 0))
+  (func $add (param $x f32) (param $y f32) (result f32)
+    (f32.add (local.get $x) (local.get $y))
+  )
+  (func $main (param $n i32) (result i32)
+    (local $i i32)
+    (if
+      (local.get $n)
+      (then
+        (i32.const 1)
+        (return)
+      )
+    )
+    (loop $loop
+      (call $log (call $add (f32.const 1) (f32.const 2)))
+      local.get $i
+      i32.const 1
+      i32.add
+      local.tee $i
+      (i32.lt_s (i32.const 10))
+      (br_if $loop)
+    )
+    (global.set $count (i32.const 1))
+    (global.get $count)
+  )
+)"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn unbalanced_functions_are_reported_by_name() {
+        assert_eq!(
+            audit("(module\n  (func $main (result i32)\n    (i32.const 1)\n    (i32.const 2)\n  )\n)"),
+            Err(CompileError::new(String::from(
+                "Generated wat for main doesn't balance the stack: The function should leave i32 on the stack but leaves i32 i32"
+            )))
+        );
+        assert_eq!(
+            audit("(module\n  (func $main (param $x f32) (result i32)\n    (i32.add (local.get $x) (i32.const 2))\n  )\n)"),
+            Err(CompileError::new(String::from(
+                "Generated wat for main doesn't balance the stack: i32.add needs i32 but got f32"
+            )))
+        );
+        assert_eq!(
+            audit("(module\n  (func $main\n    (if\n      (i32.const 1)\n      (then\n        (i32.const 1)\n      )\n    )\n  )\n)"),
+            Err(CompileError::new(String::from(
+                "Generated wat for main doesn't balance the stack: The then of an if should leave nothing on the stack but leaves i32"
+            )))
+        );
+        assert_eq!(
+            audit("(module\n  (func $main\n    (drop)\n  )\n)"),
+            Err(CompileError::new(String::from(
+                "Generated wat for main doesn't balance the stack: drop needs a value but the stack is empty"
+            )))
+        );
+    }
+
+    #[test]
+    fn unknown_instructions_are_left_for_the_validator() {
+        assert!(audit("(module\n  (type $bytes (array i8))\n  (func $main (param $a (ref $bytes)) (result i32)\n    (array.len (local.get $a))\n  )\n)").is_ok());
+    }
+}
@@ -1,8 +1,26 @@
-use std::vec;
+use std::{cell::RefCell, str::FromStr, vec};
+
+use wasmparser::{Validator, WasmFeatures};
 
 use crate::{
-    blocks::{Block, Export, Function, ImportFunction, ImportMemory, Param},
-    expressions::Expression,
+    blocks::{
+        evaluate_constants, find_constant, split_function_type, Block, Data, Export, Function,
+        ImportFunction, ImportMemory, ImportTable, Memory, Param,
+    },
+    coverage::HIT,
+    errors::CompileError,
+    expressions::{find_builtin, Direction, Expression, RUNTIME_FUNCTIONS},
+    generators::{
+        checks::{find_checks, Check},
+        find_function, inlining,
+        layout::{self, Layout},
+        naming::{self, NamingPolicy},
+        peephole, stack, Backend,
+    },
+    options::Passes,
+    parser::Program,
+    typechecker::{monomorphize, resolve_aliases},
+    visit_mut::{walk_expression_mut, VisitorMut},
 };
 
 pub fn indent(body: String) -> String {
@@ -17,628 +35,5399 @@ pub fn indent(body: String) -> String {
         .collect()
 }
 
-pub fn generate(program: crate::parser::Program) -> String {
-    let blocks: Vec<String> = program
-        .blocks
-        .clone()
-        .into_iter()
-        .map(generate_block)
-        .collect();
-    let globals = program
-        .blocks
-        .clone()
-        .iter()
-        .filter_map(|block| match block {
-            Block::Function(function) => match define_globals(function.expressions.clone()) {
-                str if str.is_empty() => None,
-                str if !str.is_empty() => Some(str),
-                _ => None,
-            },
-            _ => None,
-        })
-        .collect::<Vec<String>>();
+/// Failed asserts call this import with their line before trapping, so hosts
+/// can say which assert failed
+pub const ASSERT_FAILED_IMPORT: (&str, &str) = ("gwe", "assert_failed");
 
-    let globals_and_blocks = [globals, blocks].concat();
+/// Where coverage counts hits, one i32 for each statement
+const COVERAGE_RUNTIME: &str = "(memory $__coverage 1)
+(func $__coverage_dump (export \"__coverage_dump\") (param $statement i32) (result i32)
+  (i32.load $__coverage (i32.mul (local.get $statement) (i32.const 4)))
+)";
 
-    format!(
-        "(module
-{})",
-        indent(globals_and_blocks.join("\n\n"))
-    )
+/// Where instrumented functions pass their index when they're traced
+pub const TRACE_IMPORT: (&str, &str) = ("gwe", "trace");
+
+fn uses_asserts(expressions: &[Expression]) -> bool {
+    expressions.iter().any(|expression| match expression {
+        Expression::Assert { .. } => true,
+        Expression::IfStatement { success, fail, .. } => {
+            uses_asserts(success) || uses_asserts(fail)
+        }
+        Expression::ForStatement { body, .. } => uses_asserts(body),
+        Expression::TryStatement { body, catch } => uses_asserts(body) || uses_asserts(catch),
+        Expression::MatchStatement { arms, default, .. } => {
+            arms.iter().any(|(_, body)| uses_asserts(body))
+                || default.as_deref().is_some_and(uses_asserts)
+        }
+        _ => false,
+    })
 }
 
-fn define_globals(expressions: Vec<Expression>) -> String {
-    expressions
-        .into_iter()
-        .filter_map(|expression| match expression {
-            Expression::GlobalAssign {
-                name,
-                type_name,
-                expression: _,
-            } => Some((name, type_name)),
-            _ => None,
-        })
-        .map(|(name, type_name)| format!("(global ${} (mut {}))", name, type_name))
-        .collect::<Vec<String>>()
-        .join("\n")
+/// The first function tail called in the expressions that matches
+fn find_tail_call(expressions: &[Expression], matches: &dyn Fn(&str) -> bool) -> Option<String> {
+    expressions.iter().find_map(|expression| match expression {
+        Expression::TailCall { name, .. } if matches(name) => Some(name.to_string()),
+        Expression::IfStatement { success, fail, .. } => {
+            find_tail_call(success, matches).or_else(|| find_tail_call(fail, matches))
+        }
+        Expression::ForStatement { body, .. } => find_tail_call(body, matches),
+        Expression::TryStatement { body, catch } => {
+            find_tail_call(body, matches).or_else(|| find_tail_call(catch, matches))
+        }
+        Expression::MatchStatement { arms, default, .. } => arms
+            .iter()
+            .map(|(_, body)| body)
+            .chain(default)
+            .find_map(|body| find_tail_call(body, matches)),
+        _ => None,
+    })
 }
 
-fn define_locals(expressions: Vec<Expression>) -> String {
-    expressions
-        .into_iter()
-        .filter_map(|expression| match expression {
-            Expression::LocalAssign {
-                name,
-                type_name,
-                expression: _,
-            } => {
-                if type_name == *"string" {
-                    None
-                } else {
-                    Some((name, type_name))
-                }
+/// The first function called anywhere in the expressions that matches
+fn find_call(expressions: &[Expression], matches: &dyn Fn(&str) -> bool) -> Option<String> {
+    let find_in = |expression: &Expression| find_call(std::slice::from_ref(expression), matches);
+
+    expressions.iter().find_map(|expression| match expression {
+        Expression::FunctionCall { name, args, .. } if matches(name) => Some(name.to_string()),
+        Expression::FunctionCall { args, .. } => find_call(args, matches),
+        Expression::TailCall { name, .. } if matches(name) => Some(name.to_string()),
+        Expression::TailCall { args, .. } => find_call(args, matches),
+        Expression::ClosureCall { args, .. } => find_call(args, matches),
+        Expression::Return { expression, .. }
+        | Expression::LocalAssign { expression, .. }
+        | Expression::GlobalAssign { expression, .. } => find_in(expression),
+        Expression::Addition { left, right, .. } => find_in(left).or_else(|| find_in(right)),
+        Expression::Conditional {
+            predicate,
+            success,
+            fail,
+            ..
+        } => find_in(predicate)
+            .or_else(|| find_in(success))
+            .or_else(|| find_in(fail)),
+        Expression::IfStatement {
+            predicate,
+            success,
+            fail,
+        } => find_in(predicate)
+            .or_else(|| find_call(success, matches))
+            .or_else(|| find_call(fail, matches)),
+        Expression::ForStatement {
+            initial_value,
+            incrementor,
+            break_condition,
+            body,
+            ..
+        } => find_in(initial_value)
+            .or_else(|| find_in(incrementor))
+            .or_else(|| find_in(break_condition))
+            .or_else(|| find_call(body, matches)),
+        Expression::Assert { left, right, .. } => {
+            find_in(left).or_else(|| right.as_deref().and_then(find_in))
+        }
+        Expression::TryStatement { body, catch } => {
+            find_call(body, matches).or_else(|| find_call(catch, matches))
+        }
+        Expression::MatchStatement {
+            subject,
+            arms,
+            default,
+            ..
+        } => find_in(subject).or_else(|| {
+            arms.iter()
+                .map(|(_, body)| body)
+                .chain(default)
+                .find_map(|body| find_call(body, matches))
+        }),
+        _ => None,
+    })
+}
+
+fn find_call_in_program(program: &Program, matches: &dyn Fn(&str) -> bool) -> Option<String> {
+    program.blocks.iter().find_map(|block| match block {
+        Block::Function(function) => find_call(&function.expressions, matches),
+        _ => None,
+    })
+}
+
+/// Places each data block's bytes in memory one after the other from the
+/// start. With `dedupe`, blocks with the same bytes as one before share its
+/// offset
+fn layout_data(
+    program: &Program,
+    start: i32,
+    dedupe: bool,
+) -> Result<Vec<(String, i32, Vec<u8>)>, CompileError> {
+    let mut offset = start;
+    let mut laid_out: Vec<(String, i32, Vec<u8>)> = vec![];
+
+    for block in &program.blocks {
+        let Block::Data(data) = block else {
+            continue;
+        };
+
+        let same = laid_out
+            .iter()
+            .find(|(_, _, bytes)| dedupe && *bytes == data.bytes)
+            .map(|(_, offset, _)| *offset);
+        if let Some(same) = same {
+            laid_out.push((data.name.to_string(), same, data.bytes.clone()));
+            continue;
+        }
+
+        let end = i32::try_from(data.bytes.len())
+            .ok()
+            .and_then(|length| offset.checked_add(length));
+
+        match end {
+            Some(end) => {
+                laid_out.push((data.name.to_string(), offset, data.bytes.clone()));
+                offset = end;
             }
-            Expression::ForStatement {
-                initial_value,
-                incrementor: _,
-                break_condition: _,
-                body: _,
-            } => match *initial_value {
-                Expression::LocalAssign {
+            None => {
+                return Err(CompileError::new(format!(
+                    "Data {} is too big to fit in memory",
+                    data.name
+                )))
+            }
+        }
+    }
+
+    Ok(laid_out)
+}
+
+/// The bytes of one array value, little-endian as wasm loads them
+fn encode_element(
+    value: &Expression,
+    type_name: &str,
+    constants: &[(String, i64)],
+) -> Option<Vec<u8>> {
+    let text = match value {
+        Expression::Number { value, .. } => value.to_string(),
+        Expression::Character { value } => (*value as u32).to_string(),
+        Expression::Boolean { value } => (*value as i32).to_string(),
+        Expression::Constant { name, .. } => find_constant(constants, name)?.to_string(),
+        _ => return None,
+    };
+
+    match type_name {
+        "i32" => text.parse::<i32>().ok().map(|n| n.to_le_bytes().to_vec()),
+        "i64" => text.parse::<i64>().ok().map(|n| n.to_le_bytes().to_vec()),
+        "f32" => text.parse::<f32>().ok().map(|n| n.to_le_bytes().to_vec()),
+        "f64" => text.parse::<f64>().ok().map(|n| n.to_le_bytes().to_vec()),
+        _ => None,
+    }
+}
+
+/// Moves the values of array literals into data blocks, so that the array's
+/// local holds the offset its elements start at. Loops over an array become
+/// index loops, now that its length is known
+struct LowerArrays<'a> {
+    function: String,
+    constants: &'a [(String, i64)],
+    /// The data name and number of values of each array lowered so far
+    arrays: Vec<(String, String, usize)>,
+    data: Vec<Data>,
+    errors: Vec<CompileError>,
+}
+
+impl VisitorMut for LowerArrays<'_> {
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        let Expression::LocalAssign {
+            name,
+            type_name,
+            expression: value,
+            ..
+        } = expression
+        else {
+            match expression {
+                Expression::Array { .. } => {
+                    self.errors.push(CompileError::new(String::from(
+                        "Array literals can only be given to a local, like local table: [i32] = [1, 2]",
+                    )));
+                }
+                Expression::DataField { name, field } => {
+                    if let Some((_, data_name, count)) =
+                        self.arrays.iter().find(|(local, _, _)| local == name)
+                    {
+                        *expression = if field == "length" {
+                            Expression::Number {
+                                value: count.to_string(),
+                                type_name: String::from("i32"),
+                            }
+                        } else {
+                            Expression::DataField {
+                                name: data_name.to_string(),
+                                field: String::from("offset"),
+                            }
+                        };
+                    }
+                }
+                Expression::Index { name, length, .. } => {
+                    if let Some((_, _, count)) =
+                        self.arrays.iter().find(|(local, _, _)| local == name)
+                    {
+                        *length = *count;
+                    }
+                    walk_expression_mut(self, expression)
+                }
+                Expression::ForEach {
                     name,
                     type_name,
-                    expression: _,
+                    array,
+                    body,
+                    line,
                 } => {
-                    if type_name == *"string" {
-                        None
-                    } else {
-                        Some((name, type_name))
+                    let count = self
+                        .arrays
+                        .iter()
+                        .find(|(local, _, _)| local == array)
+                        .map(|(_, _, count)| *count);
+
+                    match count {
+                        // for loops check whether to go round again after their body
+                        Some(0) => self.errors.push(CompileError::new(format!(
+                            "{} has no values to loop over",
+                            array
+                        ))),
+                        Some(count) => {
+                            let index_name = format!("{}.index", name);
+                            let element = Expression::LocalAssign {
+                                name: name.to_string(),
+                                type_name: type_name.to_string(),
+                                expression: Box::new(Expression::Index {
+                                    name: array.to_string(),
+                                    type_name: type_name.to_string(),
+                                    index: Box::new(Expression::Variable {
+                                        body: index_name.clone(),
+                                        type_name: String::from("i32"),
+                                    }),
+                                    length: count,
+                                    line: *line,
+                                }),
+                                info: None,
+                            };
+                            let number = |value: usize| {
+                                Box::new(Expression::Number {
+                                    value: value.to_string(),
+                                    type_name: String::from("i32"),
+                                })
+                            };
+
+                            *expression = Expression::ForStatement {
+                                initial_value: Box::new(Expression::LocalAssign {
+                                    name: index_name,
+                                    type_name: String::from("i32"),
+                                    expression: number(0),
+                                    info: None,
+                                }),
+                                incrementor: number(1),
+                                break_condition: number(count),
+                                direction: Direction::Up,
+                                body: std::iter::once(element)
+                                    .chain(std::mem::take(body))
+                                    .collect(),
+                            };
+                            walk_expression_mut(self, expression)
+                        }
+                        None => self.errors.push(CompileError::new(format!(
+                            "{} can only be looped over in the function it's written in",
+                            array
+                        ))),
                     }
                 }
-                _ => None,
-            },
-            _ => None,
-        })
-        .map(|(name, type_name)| format!("(local ${} {})", name, type_name))
-        .collect::<Vec<String>>()
-        .join("\n")
-}
+                expression => walk_expression_mut(self, expression),
+            }
+            return;
+        };
+        let Expression::Array {
+            type_name: element,
+            values,
+        } = value.as_ref()
+        else {
+            return walk_expression_mut(self, expression);
+        };
+
+        if array_element_size(element).is_none() {
+            self.errors.push(CompileError::new(format!(
+                "Array {} holds {}, but arrays can only hold i32, i64, f32 or f64",
+                name, element
+            )));
+            return;
+        }
+
+        let mut bytes = vec![];
+        for value in values {
+            match encode_element(value, element, self.constants) {
+                Some(encoded) => bytes.extend(encoded),
+                None => {
+                    self.errors.push(CompileError::new(format!(
+                        "Array {} can only hold {} numbers known when compiling",
+                        name, element
+                    )));
+                    return;
+                }
+            }
+        }
 
-fn generate_param(param: Param) -> String {
-    format!("(param ${} {})", param.name, param.type_name)
+        let data_name = format!("{}.{}", self.function, name);
+        self.arrays
+            .push((name.to_string(), data_name.clone(), values.len()));
+        self.data.push(Data {
+            name: data_name.clone(),
+            path: String::new(),
+            bytes,
+        });
+        *type_name = String::from("i32");
+        **value = Expression::DataField {
+            name: data_name,
+            field: String::from("offset"),
+        };
+    }
 }
 
-fn extract_strings(expressions: Vec<Expression>) -> (Option<String>, Vec<Expression>) {
-    let mut strings: Vec<(i32, String)> = vec![];
-    let mut offset: i32 = 0;
+/// The size in bytes of each element of an array of `type_name`
+fn array_element_size(type_name: &str) -> Option<i32> {
+    match type_name {
+        "i32" | "f32" => Some(4),
+        "i64" | "f64" => Some(8),
+        _ => None,
+    }
+}
 
-    let new_expressions = expressions
-        .iter()
-        .map(|exp| match exp {
-            Expression::LocalAssign {
-                name: _,
-                type_name,
-                expression,
-            } => {
-                if type_name == &String::from("string") {
-                    let length: i32 = match *expression.clone() {
-                        Expression::String { body } => {
-                            strings.push((offset, body.clone()));
-                            body.len().try_into().unwrap()
-                        }
-                        _ => 0,
-                    };
+fn lower_arrays(program: Program) -> Result<Program, CompileError> {
+    let constants = evaluate_constants(&program.blocks)?;
+    let mut data: Vec<Data> = vec![];
+    let mut blocks: Vec<Block> = vec![];
 
-                    offset += length;
+    for block in program.blocks {
+        blocks.push(match block {
+            Block::Function(mut function) => {
+                let mut lower = LowerArrays {
+                    function: function.name.to_string(),
+                    constants: &constants,
+                    arrays: vec![],
+                    data: vec![],
+                    errors: vec![],
+                };
+                lower.visit_function_mut(&mut function);
 
-                    Expression::MemoryReference {
-                        offset: offset - length,
-                        length,
-                    }
-                } else {
-                    exp.clone()
+                if let Some(error) = lower.errors.into_iter().next() {
+                    return Err(error);
                 }
+                data.extend(lower.data);
+                Block::Function(function)
             }
-            _ => exp.clone(),
-        })
-        .collect::<Vec<Expression>>();
+            block => block,
+        });
+    }
 
-    let output = if strings.is_empty() {
-        None
-    } else {
-        let datas: String = strings
-            .iter()
-            .map(|(offset, string)| format!("(data (i32.const {}) \"{}\")", offset, string))
-            .collect::<Vec<String>>()
-            .join("\n");
-        Some(format!("{}\n", datas))
-    };
+    blocks.extend(data.into_iter().map(Block::Data));
 
-    (output, new_expressions)
+    Ok(Program { blocks })
 }
 
-fn generate_expression(expression: Expression) -> String {
-    match expression {
-        Expression::Addition { left, right } => {
-            let generated_left = generate_expression(*left);
-            let generated_right = generate_expression(*right);
+/// Drops the functions which nothing exported can reach, through calls or
+/// the closure table. Programs without exports are left as they are
+fn remove_dead_functions(program: Program, table: &[String]) -> Program {
+    let mut reachable: Vec<String> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Export(export) => Some(export.function_name.to_string()),
+            Block::Function(function) if function.export_name().is_some() => {
+                Some(function.name.to_string())
+            }
+            _ => None,
+        })
+        .collect();
 
-            format!("(f32.add {} {})", generated_left, generated_right)
-        }
-        Expression::GlobalAssign {
-            name,
-            type_name: _,
-            expression,
-        } => {
-            format!(
-                "(global.set ${} {})",
-                name,
-                generate_expression(*expression)
-            )
-        }
-        Expression::LocalAssign {
-            name,
-            type_name: _,
-            expression,
-        } => {
-            format!("(local.set ${} {})", name, generate_expression(*expression))
-        }
-        Expression::Number { value, type_name } => format!("({}.const {})", type_name, value),
-        Expression::Return { expression } => generate_expression(*expression),
-        Expression::Variable { body, type_name: _ } => format!("(local.get ${})", body),
-        Expression::String { body } => format!("\"{}\"", body),
-        Expression::FunctionCall { name, args } => {
-            let params = args
-                .iter()
-                .map(|e| generate_expression(e.clone()))
-                .collect::<Vec<String>>()
-                .join("\n");
-            format!("{}\n(call ${})", params, name)
-        }
-        Expression::MemoryReference { offset, length } => {
-            format!("(i32.const {})\n(i32.const {})", offset, length)
-        }
-        Expression::IfStatement {
-            predicate,
-            success,
-            fail,
-        } => {
-            let success_expressions = success
-                .iter()
-                .map(|expression| generate_expression(expression.clone()))
-                .collect::<Vec<String>>()
-                .join("\n");
+    if reachable.is_empty() {
+        return program;
+    }
+    reachable.extend(table.iter().cloned());
 
-            let fail_expressions = fail
-                .iter()
-                .map(|expression| generate_expression(expression.clone()))
-                .collect::<Vec<String>>()
-                .join("\n");
-            format!(
-                "(if
-  {}
-  (then
-{}
-  )
-  (else
-{}
-  )
-)",
-                generate_expression(*predicate),
-                indent(indent(success_expressions)),
-                indent(indent(fail_expressions))
-            )
-        }
-        Expression::Boolean { value } => {
-            if value {
-                "(i32.const 0)".to_string()
-            } else {
-                "(i32.const 1)".to_string()
+    let mut index = 0;
+    while let Some(name) = reachable.get(index).cloned() {
+        index += 1;
+
+        let Some(Block::Function(function)) = program
+            .blocks
+            .iter()
+            .find(|block| matches!(block, Block::Function(function) if function.name == name))
+        else {
+            continue;
+        };
+
+        // find_call stops at the first match, so every call is collected by
+        // never matching
+        let called = RefCell::new(vec![]);
+        find_call(&function.expressions, &|name| {
+            called.borrow_mut().push(name.to_string());
+            false
+        });
+
+        for name in called.into_inner() {
+            if !reachable.contains(&name) {
+                reachable.push(name);
             }
         }
-        Expression::ForStatement {
-            initial_value,
-            break_condition,
-            incrementor,
-            body,
-        } => {
-            let body_expressions = body
-                .iter()
-                .map(|expression| generate_expression(expression.clone()))
-                .collect::<Vec<String>>()
-                .join("\n");
+    }
 
-            let variable_name = match *initial_value.clone() {
-                Expression::LocalAssign {
-                    name,
-                    type_name: _,
-                    expression: _,
-                } => name,
-                _ => return String::from(""),
-            };
+    Program {
+        blocks: program
+            .blocks
+            .into_iter()
+            .filter(|block| match block {
+                Block::Function(function) => reachable.contains(&function.name),
+                _ => true,
+            })
+            .collect(),
+    }
+}
 
-            let type_name = match *initial_value.clone() {
-                Expression::LocalAssign {
-                    name: _,
-                    type_name,
-                    expression: _,
-                } => type_name,
-                _ => return String::from("i32"),
-            };
+/// Leaves the name section out of binary wasm, copying every other section
+pub fn strip_names(wasm: &[u8]) -> Vec<u8> {
+    // the magic number and version
+    let mut stripped = wasm[..8.min(wasm.len())].to_vec();
+    let mut position = 8;
 
-            format!(
-                "{}
-(loop $loop
-{}
-  (local.get ${variable_name})
-  {incrementor}
-  ({type_name}.add)
-  (local.set ${variable_name})
+    while position < wasm.len() {
+        let start = position;
+        let id = wasm[position];
+        position += 1;
+        let size = read_leb(wasm, &mut position);
+        let contents = position;
+        let end = (contents + size).min(wasm.len());
 
-  (local.get ${variable_name})
-  {break_condition}
-  ({type_name}.lt_s)
-  (br_if $loop)
-)",
-                generate_expression(*initial_value),
-                indent(body_expressions),
-                incrementor = generate_expression(*incrementor),
-                variable_name = variable_name,
-                break_condition = generate_expression(*break_condition),
-                type_name = type_name
-            )
+        let is_names = id == 0 && {
+            let length = read_leb(wasm, &mut position);
+            wasm.get(position..position + length) == Some(b"name".as_slice())
+        };
+
+        if !is_names {
+            stripped.extend_from_slice(&wasm[start..end]);
         }
+        position = end;
     }
+
+    stripped
 }
 
-fn generate_function(function: Function) -> String {
-    let params: String = if function.params.is_empty() {
-        String::from("")
-    } else {
-        String::from(" ")
-            + &function
-                .params
-                .clone()
-                .into_iter()
-                .map(generate_param)
-                .collect::<Vec<String>>()
-                .join(" ")
-    };
+/// Binary wasm with a custom section added after every other section.
+/// Engines skip custom sections, so this doesn't change what the module does
+pub fn add_custom_section(wasm: &[u8], name: &str, contents: &[u8]) -> Vec<u8> {
+    let mut section = write_leb(name.len());
+    section.extend_from_slice(name.as_bytes());
+    section.extend_from_slice(contents);
+
+    let mut added = wasm.to_vec();
+    added.push(0);
+    added.extend(write_leb(section.len()));
+    added.extend(section);
+    added
+}
+
+/// An unsigned LEB128 number as bytes
+fn write_leb(mut value: usize) -> Vec<u8> {
+    let mut bytes = vec![];
+
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// An unsigned LEB128 number, moving past it
+fn read_leb(bytes: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    while let Some(byte) = bytes.get(*position) {
+        *position += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    value
+}
+
+/// Bytes as a wat string, with anything that isn't printable ASCII escaped as
+/// `\hh`. Strings are UTF-8, so non-ASCII characters become several escapes,
+/// one per byte, matching the lengths which are counted in bytes
+fn escape_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| match byte {
+            0x20..=0x7e if !matches!(byte, b'"' | b'\\') => (*byte as char).to_string(),
+            _ => format!("\\{:02x}", byte),
+        })
+        .collect()
+}
+
+fn has_memory(blocks: &[Block]) -> bool {
+    blocks
+        .iter()
+        .any(|block| matches!(block, Block::ImportMemory(_) | Block::Memory(_)))
+}
+
+/// Programs can import a function from here to be given the offset and length
+/// in memory of a panic's message, before it traps
+pub const PANIC_IMPORT: (&str, &str) = ("gwe", "panic");
+
+/// The memory taken by the strings a function assigns to its locals or returns
+fn function_strings_length(function: &Function) -> i32 {
+    function
+        .expressions
+        .iter()
+        .map(|expression| match expression {
+            Expression::LocalAssign { expression, .. } | Expression::Return { expression, .. } => {
+                match expression.as_ref() {
+                    Expression::String { body } => i32::try_from(body.len()).unwrap_or(i32::MAX),
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        })
+        .fold(0, i32::saturating_add)
+}
+
+/// Where each function's strings start in memory, as laid out by
+/// `extract_strings`. Functions each get their own space, as the data segments
+/// holding their strings are all written when the module is instantiated
+fn layout_local_strings(program: &Program) -> Vec<(String, i32)> {
+    let mut offset: i32 = 0;
+
+    program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function) => {
+                let start = offset;
+                offset = offset.saturating_add(function_strings_length(function));
+                Some((function.name.to_string(), start))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The memory taken by every function's strings, from the start of memory
+fn local_strings_length(program: &Program) -> i32 {
+    program
+        .blocks
+        .iter()
+        .map(|block| match block {
+            Block::Function(function) => function_strings_length(function),
+            _ => 0,
+        })
+        .fold(0, i32::saturating_add)
+}
+
+fn call_panic_handler(
+    expressions: Vec<Expression>,
+    handler: &str,
+    messages: &mut Vec<(i32, String)>,
+    offset: &mut i32,
+) -> Result<Vec<Expression>, CompileError> {
+    let mut called: Vec<Expression> = vec![];
+
+    for expression in expressions {
+        match expression {
+            Expression::Panic { message } => {
+                if let Expression::String { body } = message.as_ref() {
+                    let length = match i32::try_from(body.len()) {
+                        Ok(length) => length,
+                        Err(_) => {
+                            return Err(CompileError::new(String::from(
+                                "Panic message is too long to fit in memory",
+                            )))
+                        }
+                    };
+
+                    messages.push((*offset, body.to_string()));
+                    called.push(Expression::FunctionCall {
+                        name: handler.to_string(),
+                        args: vec![Expression::MemoryReference {
+                            offset: *offset,
+                            length,
+                        }],
+                        info: None,
+                    });
+
+                    *offset = match offset.checked_add(length) {
+                        Some(offset) => offset,
+                        None => {
+                            return Err(CompileError::new(String::from(
+                                "Panic messages are too long to fit in memory",
+                            )))
+                        }
+                    };
+                }
+
+                called.push(Expression::Panic { message });
+            }
+            Expression::IfStatement {
+                predicate,
+                success,
+                fail,
+            } => called.push(Expression::IfStatement {
+                predicate,
+                success: call_panic_handler(success, handler, messages, offset)?,
+                fail: call_panic_handler(fail, handler, messages, offset)?,
+            }),
+            Expression::ForStatement {
+                initial_value,
+                incrementor,
+                break_condition,
+                direction,
+                body,
+            } => called.push(Expression::ForStatement {
+                initial_value,
+                incrementor,
+                break_condition,
+                direction,
+                body: call_panic_handler(body, handler, messages, offset)?,
+            }),
+            Expression::TryStatement { body, catch } => called.push(Expression::TryStatement {
+                body: call_panic_handler(body, handler, messages, offset)?,
+                catch: call_panic_handler(catch, handler, messages, offset)?,
+            }),
+            Expression::MatchStatement {
+                subject,
+                type_name,
+                arms,
+                default,
+                info,
+            } => called.push(Expression::MatchStatement {
+                subject,
+                type_name,
+                arms: arms
+                    .into_iter()
+                    .map(|(pattern, body)| {
+                        call_panic_handler(body, handler, messages, offset)
+                            .map(|body| (pattern, body))
+                    })
+                    .collect::<Result<Vec<(i64, Vec<Expression>)>, CompileError>>()?,
+                default: match default {
+                    Some(default) => Some(call_panic_handler(default, handler, messages, offset)?),
+                    None => None,
+                },
+                info,
+            }),
+            expression => called.push(expression),
+        }
+    }
+
+    Ok(called)
+}
+
+/// Messages stored in memory for the panic handler, after their offsets
+type Messages = Vec<(i32, String)>;
+
+fn find_panic_handler(program: &Program) -> Option<ImportFunction> {
+    let (module, name) = PANIC_IMPORT;
+
+    program.blocks.iter().find_map(|block| match block {
+        Block::ImportFunction(import) if import.external_name == [module, name] => {
+            Some(import.clone())
+        }
+        _ => None,
+    })
+}
+
+/// The checks debug builds make in each function, along with the messages
+/// passed to the panic handler when they fail, which go after the panic
+/// messages when there's a memory to put them in
+fn layout_checks(program: &Program, start: i32) -> Result<(Vec<Check>, Messages), CompileError> {
+    let mut checks: Vec<Check> = vec![];
+    let mut messages: Vec<(i32, String)> = vec![];
+    let mut offset = start;
+    let has_handler = find_panic_handler(program).is_some() && has_memory(&program.blocks);
+
+    for block in &program.blocks {
+        if let Block::Function(function) = block {
+            let mut found: Vec<Check> = vec![];
+            find_checks(&function.expressions, function.memory_name(), &mut found);
+
+            for check in found {
+                if has_handler {
+                    let message = check.message(&function.name);
+                    let length = message.len() as i32;
+                    messages.push((offset, message));
+                    offset = match offset.checked_add(length) {
+                        Some(offset) => offset,
+                        None => {
+                            return Err(CompileError::new(String::from(
+                                "Check messages are too long to fit in memory",
+                            )))
+                        }
+                    };
+                }
+                if !checks.contains(&check) {
+                    checks.push(check);
+                }
+            }
+        }
+    }
+
+    Ok((checks, messages))
+}
+
+/// When the program imports a panic handler, every panic first calls it with
+/// its message, which is stored in memory after the strings of locals
+fn layout_panic_messages(program: Program) -> Result<(Program, Vec<(i32, String)>), CompileError> {
+    let Some(handler) = find_panic_handler(&program) else {
+        return Ok((program, vec![]));
+    };
+
+    if handler.params.len() != 2 || handler.params.iter().any(|param| param.type_name != "i32") {
+        return Err(CompileError::new(format!(
+            "The panic handler {} has to take an offset and a length, both i32",
+            handler.name
+        )));
+    }
+
+    let mut messages: Vec<(i32, String)> = vec![];
+    let mut offset = local_strings_length(&program);
+    let mut blocks: Vec<Block> = vec![];
+
+    for block in program.blocks {
+        blocks.push(match block {
+            Block::Function(mut function) => {
+                function.expressions = call_panic_handler(
+                    function.expressions,
+                    &handler.name,
+                    &mut messages,
+                    &mut offset,
+                )?;
+                Block::Function(function)
+            }
+            block => block,
+        });
+    }
+
+    let has_memory = has_memory(&blocks);
+
+    if !messages.is_empty() && !has_memory {
+        return Err(CompileError::new(format!(
+            "Panic messages are passed to {} through memory, add an import memory",
+            handler.name
+        )));
+    }
+
+    Ok((Program { blocks }, messages))
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct GenerateOptions {
+    /// Include the allocator when the program calls it. Programs managing
+    /// their own memory can turn this off
+    pub runtime: bool,
+    /// Experimental: strings become WasmGC byte arrays rather than living in
+    /// linear memory, for runtimes supporting the GC proposal
+    pub gc: bool,
+    pub exceptions: Exceptions,
+    /// Lower `tailcall` to `return_call`, for runtimes supporting the tail
+    /// call proposal
+    pub tail_calls: bool,
+    /// The passes to run, from the profile or optimization level
+    pub passes: Passes,
+    /// Check the generated module is valid wasm before handing it over, so
+    /// generator bugs are reported by the compiler rather than the runtime
+    pub validate: bool,
+    /// Check every function of the generated wat balances the stack, so
+    /// generator bugs are reported by the function they're in
+    pub audit_stack: bool,
+    /// Record calls to every function, for profiling
+    pub instrument: Option<Instrument>,
+    /// Count the hits of a program instrumented by `coverage::instrument`,
+    /// read back through the exported `__coverage_dump`
+    pub coverage: bool,
+    pub layout: Layout,
+    /// How functions and exports are named in the wat
+    pub naming: NamingPolicy,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions {
+            runtime: true,
+            gc: false,
+            exceptions: Exceptions::Proposal,
+            tail_calls: false,
+            passes: Passes::default(),
+            validate: false,
+            audit_stack: false,
+            instrument: None,
+            coverage: false,
+            layout: Layout::Generated,
+            naming: NamingPolicy::default(),
+        }
+    }
+}
+
+/// What instrumented functions record when they're called. Calls are counted
+/// as i32s in the exported `__calls` memory, one for each function in the
+/// order they're defined, followed by the names of the functions one per line
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Instrument {
+    Calls,
+    /// Counts calls and also passes the function's index to the imported
+    /// `gwe.trace`
+    Trace,
+}
+
+impl FromStr for Instrument {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "calls" => Ok(Instrument::Calls),
+            "trace" => Ok(Instrument::Trace),
+            _ => Err(format!(
+                "Unknown instrumentation {}, expected calls or trace",
+                str
+            )),
+        }
+    }
+}
+
+/// How `try` blocks catch what imports throw
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Exceptions {
+    /// The exception handling proposal's `try` and `catch_all`
+    Proposal,
+    /// Every import returns an i32, where anything but 0 is an error. Errors
+    /// outside of a try are ignored
+    ErrorCodes,
+}
+
+impl FromStr for Exceptions {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "proposal" => Ok(Exceptions::Proposal),
+            "error-codes" => Ok(Exceptions::ErrorCodes),
+            _ => Err(format!(
+                "Unknown exception handling {}, expected proposal or error-codes",
+                str
+            )),
+        }
+    }
+}
+
+/// What code generation needs to know about the whole module, and where in it
+/// code is being generated
+#[derive(Clone)]
+struct Context {
+    gc: bool,
+    /// Every string literal, each stored once in a passive data segment when
+    /// targeting WasmGC
+    strings: Vec<String>,
+    exceptions: Exceptions,
+    imports: Vec<String>,
+    /// How many try blocks are around the code, so each gets its own labels
+    try_depth: usize,
+    /// The label of the innermost try block being generated, when inside one
+    current_try: Option<usize>,
+    /// How many matches are around the code, so each gets its own labels
+    match_depth: usize,
+    /// The memory picked by the function being generated, otherwise the first
+    memory: Option<String>,
+    /// The name, offset and length of each data block
+    data: Vec<(String, i32, i32)>,
+    /// Exported functions returning strings, which are exported through a
+    /// wrapper copying the string into the string buffer
+    string_exports: Vec<String>,
+    /// Where each function's strings start in memory
+    string_offsets: Vec<(String, i32)>,
+    /// The name and value of each constant
+    constants: Vec<(String, i64)>,
+    instrument: Option<Instrument>,
+    /// Every function in the order they're defined, which is the order their
+    /// calls are counted in
+    function_names: Vec<String>,
+    /// What each function returns, so that calls made as statements can
+    /// drop the result
+    return_types: Vec<(String, String)>,
+    coverage: bool,
+    /// The function being generated
+    function: String,
+    /// The panic handler, and the messages failed checks pass it, when
+    /// generating with checks
+    checks: Option<(Option<String>, Messages)>,
+    /// Whether to mark which statement each piece of wat comes from, for
+    /// tracing wat2wasm's errors back to the gwe
+    mark_statements: bool,
+}
+
+impl Context {
+    /// How many values a call to the function leaves on the stack
+    fn result_count(&self, name: &str) -> usize {
+        let builtin = find_builtin(name).or_else(|| {
+            RUNTIME_FUNCTIONS
+                .iter()
+                .find(|function| function.name == name)
+        });
+
+        let return_type = match builtin {
+            Some(builtin) => builtin.return_type,
+            None => self
+                .return_types
+                .iter()
+                .find(|(function, _)| function == name)
+                .map_or("void", |(_, return_type)| return_type.as_str()),
+        };
+
+        match return_type {
+            "void" => 0,
+            // a pointer and a length, unless it's a WasmGC array
+            "string" if !self.gc => 2,
+            _ => 1,
+        }
+    }
+
+    /// The instructions calling the helper for a check, in place of the
+    /// instruction it checks
+    fn call_check(&self, check: &Check) -> Option<String> {
+        let (_, messages) = self.checks.as_ref()?;
+        let message = check.message(&self.function);
+        let (offset, length) = messages
+            .iter()
+            .find(|(_, found)| *found == message)
+            .map(|(offset, found)| (*offset, found.len()))
+            .unwrap_or_default();
+
+        Some(format!(
+            "(i32.const {})\n(i32.const {})\n(call ${})",
+            offset,
+            length,
+            check.helper_name()
+        ))
+    }
+}
+
+impl Context {
+    /// The memory index instructions take, left out for the first memory
+    fn memory_index(&self) -> String {
+        match &self.memory {
+            Some(name) => format!(" ${}", name),
+            None => String::new(),
+        }
+    }
+
+    fn value_type(&self, type_name: &str) -> String {
+        match type_name {
+            "string" if self.gc => String::from("(ref null $string)"),
+            // an offset and a length into memory
+            "string" => String::from("i32 i32"),
+            // functions are passed around as their environment
+            _ if split_function_type(type_name).is_some() => String::from("i32"),
+            _ => type_name.to_string(),
+        }
+    }
+}
+
+fn collect_strings(expressions: &[Expression], found: &mut Vec<String>) {
+    for expression in expressions {
+        match expression {
+            Expression::String { body } if !found.contains(body) => found.push(body.to_string()),
+            Expression::Return { expression, .. }
+            | Expression::LocalAssign { expression, .. }
+            | Expression::GlobalAssign { expression, .. } => {
+                collect_strings(std::slice::from_ref(expression.as_ref()), found)
+            }
+            Expression::FunctionCall { args, .. }
+            | Expression::TailCall { args, .. }
+            | Expression::ClosureCall { args, .. } => collect_strings(args, found),
+            Expression::IfStatement { success, fail, .. } => {
+                collect_strings(success, found);
+                collect_strings(fail, found);
+            }
+            Expression::ForStatement { body, .. } => collect_strings(body, found),
+            Expression::TryStatement { body, catch } => {
+                collect_strings(body, found);
+                collect_strings(catch, found);
+            }
+            Expression::MatchStatement { arms, default, .. } => {
+                for body in arms.iter().map(|(_, body)| body).chain(default) {
+                    collect_strings(body, found);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Whether anything in the program has the string type, which needs the
+/// array type declaring when targeting WasmGC
+fn uses_string_type(program: &Program) -> bool {
+    let is_string = |param: &Param| param.type_name == "string";
+
+    program.blocks.iter().any(|block| match block {
+        Block::Function(function) => {
+            let mut locals: Vec<(String, String)> = vec![];
+            find_local_assigns(&function.expressions, &mut locals);
+
+            function.return_type == "string"
+                || function.params.iter().any(is_string)
+                || locals.iter().any(|(_, type_name)| type_name == "string")
+        }
+        Block::ImportFunction(import) => import.params.iter().any(is_string),
+        _ => false,
+    })
+}
+
+/// How many bytes of strings can be passed to or returned from exported
+/// functions at once
+pub const STRING_BUFFER_LENGTH: i32 = 1024;
+
+/// Functions exported by an export block or an `@export` attribute
+pub fn exported_functions(program: &Program) -> Vec<&Function> {
+    let exported: Vec<&str> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Export(export) => Some(export.function_name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function)
+                if function.export_name().is_some()
+                    || exported.contains(&function.name.as_str()) =>
+            {
+                Some(function)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether strings are passed between the module and the host, through
+/// exported functions or imports taking strings
+fn crosses_string_boundary(context: &Context, program: &Program) -> bool {
+    let is_string = |param: &Param| param.type_name == "string";
+
+    !context.gc
+        && (exported_functions(program).iter().any(|function| {
+            function.return_type == "string" || function.params.iter().any(is_string)
+        }) || program.blocks.iter().any(|block| match block {
+            Block::ImportFunction(import) => import.params.iter().any(is_string),
+            _ => false,
+        }))
+}
+
+/// The buffer hosts write strings into before calling an export, and
+/// exports returning strings copy them into, along with the memory exported
+/// for hosts to read strings from
+fn generate_string_boundary(context: &Context, program: &Program, buffer: i32) -> String {
+    let wrappers = exported_functions(program)
+        .into_iter()
+        .filter(|function| context.string_exports.contains(&function.name))
+        .map(|function| {
+            let params = function
+                .params
+                .iter()
+                .map(|param| generate_param(context, param.clone()))
+                .collect::<Vec<String>>();
+            let args = function
+                .params
+                .iter()
+                .map(|param| {
+                    generate_expression(
+                        context,
+                        Expression::Variable {
+                            body: param.name.to_string(),
+                            type_name: param.type_name.to_string(),
+                        },
+                    )
+                })
+                .collect::<Vec<String>>();
+            let source_memory = match function.memory_name() {
+                Some(name) => format!(" 0 ${}", name),
+                None => String::new(),
+            };
+
+            format!(
+                "(func $__export_{name}{params} (result i32)
+  (local $__offset i32)
+  (local $__length i32)
+{args}  (call ${name})
+  (local.set $__length)
+  (local.set $__offset)
+  (if
+    (i32.gt_u (local.get $__length) (global.get $__string_buffer_length))
+    (then
+      unreachable
+    )
+  )
+  (memory.copy{source_memory} (global.get $__string_buffer) (local.get $__offset) (local.get $__length))
+  (local.get $__length)
+)",
+                name = function.name,
+                params = params
+                    .iter()
+                    .map(|param| format!(" {}", param))
+                    .collect::<String>(),
+                args = indent(args.join("\n")),
+                source_memory = source_memory,
+            )
+        })
+        .collect::<Vec<String>>();
+
+    [
+        vec![format!(
+            "(global $__string_buffer i32 (i32.const {}))
+(global $__string_buffer_length i32 (i32.const {}))
+(export \"__string_buffer\" (global $__string_buffer))
+(export \"__string_buffer_length\" (global $__string_buffer_length))
+(export \"__memory\" (memory 0))",
+            buffer, STRING_BUFFER_LENGTH
+        )],
+        wrappers,
+    ]
+    .concat()
+    .join("\n\n")
+}
+
+/// A bump allocator over the memory after the module's data, which `reset`
+/// frees all at once
+fn generate_runtime(heap_base: i32) -> String {
+    format!(
+        "(global $__heap (mut i32) (i32.const {heap_base}))
+
+(func $alloc (param $size i32) (result i32)
+  (local $pointer i32)
+  (local.set $pointer (global.get $__heap))
+  (global.set $__heap
+    (i32.and
+      (i32.add (i32.add (global.get $__heap) (local.get $size)) (i32.const 7))
+      (i32.const -8)
+    )
+  )
+  (if
+    (i32.gt_u (global.get $__heap) (i32.mul (memory.size) (i32.const 65536)))
+    (then
+      (if
+        (i32.eq
+          (memory.grow
+            (i32.div_u
+              (i32.sub
+                (i32.add (global.get $__heap) (i32.const 65535))
+                (i32.mul (memory.size) (i32.const 65536))
+              )
+              (i32.const 65536)
+            )
+          )
+          (i32.const -1)
+        )
+        (then
+          unreachable
+        )
+      )
+    )
+  )
+  (local.get $pointer)
+)
+
+(func $reset
+  (global.set $__heap (i32.const {heap_base}))
+)",
+        heap_base = heap_base
+    )
+}
+
+/// Every capture gets a slot big enough for any number, after the table index
+const ENVIRONMENT_SLOT: i32 = 8;
+
+/// The names a closure uses from the functions around it, with their types.
+/// Calls to closures from around it capture the environment they're called
+/// through
+fn find_captures(
+    expressions: &[Expression],
+    closures: &[(String, usize)],
+    declared: &mut Vec<String>,
+    found: &mut Vec<(String, String)>,
+) {
+    fn capture(
+        name: &str,
+        type_name: &str,
+        declared: &[String],
+        found: &mut Vec<(String, String)>,
+    ) {
+        if !declared.iter().any(|local| local == name)
+            && !found.iter().any(|(captured, _)| captured == name)
+        {
+            found.push((name.to_string(), type_name.to_string()));
+        }
+    }
+
+    for expression in expressions {
+        match expression {
+            Expression::Variable { body, type_name } => capture(body, type_name, declared, found),
+            Expression::Index { name, index, .. } => {
+                capture(name, "i32", declared, found);
+                find_captures(std::slice::from_ref(index), closures, declared, found);
+            }
+            Expression::FunctionCall { name, args, .. } | Expression::TailCall { name, args } => {
+                if closures.iter().any(|(closure, _)| closure == name) {
+                    capture(name, "i32", declared, found);
+                }
+                find_captures(args, closures, declared, found);
+            }
+            Expression::LocalAssign {
+                name, expression, ..
+            } => {
+                find_captures(std::slice::from_ref(expression), closures, declared, found);
+                declared.push(name.to_string());
+            }
+            Expression::Return { expression, .. } | Expression::GlobalAssign { expression, .. } => {
+                find_captures(std::slice::from_ref(expression), closures, declared, found)
+            }
+            Expression::Addition { left, right, .. } => {
+                find_captures(std::slice::from_ref(left), closures, declared, found);
+                find_captures(std::slice::from_ref(right), closures, declared, found);
+            }
+            Expression::Conditional {
+                predicate,
+                success,
+                fail,
+                ..
+            } => {
+                find_captures(std::slice::from_ref(predicate), closures, declared, found);
+                find_captures(std::slice::from_ref(success), closures, declared, found);
+                find_captures(std::slice::from_ref(fail), closures, declared, found);
+            }
+            Expression::IfStatement {
+                predicate,
+                success,
+                fail,
+            } => {
+                find_captures(std::slice::from_ref(predicate), closures, declared, found);
+                find_captures(success, closures, declared, found);
+                find_captures(fail, closures, declared, found);
+            }
+            Expression::ForStatement {
+                initial_value,
+                incrementor,
+                break_condition,
+                body,
+                ..
+            } => {
+                find_captures(
+                    std::slice::from_ref(initial_value),
+                    closures,
+                    declared,
+                    found,
+                );
+                find_captures(std::slice::from_ref(incrementor), closures, declared, found);
+                find_captures(
+                    std::slice::from_ref(break_condition),
+                    closures,
+                    declared,
+                    found,
+                );
+                find_captures(body, closures, declared, found);
+            }
+            Expression::TryStatement { body, catch } => {
+                find_captures(body, closures, declared, found);
+                find_captures(catch, closures, declared, found);
+            }
+            Expression::MatchStatement {
+                subject,
+                arms,
+                default,
+                ..
+            } => {
+                find_captures(std::slice::from_ref(subject), closures, declared, found);
+                for body in arms.iter().map(|(_, body)| body).chain(default) {
+                    find_captures(body, closures, declared, found);
+                }
+            }
+            Expression::Assert { left, right, .. } => {
+                find_captures(std::slice::from_ref(left), closures, declared, found);
+                if let Some(right) = right {
+                    find_captures(std::slice::from_ref(right), closures, declared, found);
+                }
+            }
+            Expression::Closure { function } => {
+                let mut inner: Vec<String> = declared
+                    .iter()
+                    .cloned()
+                    .chain(function.params.iter().map(|param| param.name.to_string()))
+                    .collect();
+                find_captures(&function.expressions, closures, &mut inner, found);
+                declared.push(function.name.to_string());
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Closures lifted out into functions of their own so far
+#[derive(Default)]
+struct Lifting {
+    functions: Vec<Function>,
+    /// The functions closures call through, in table order
+    table: Vec<String>,
+    /// The params and return type of each kind of closure, which
+    /// `call_indirect` checks against
+    signatures: Vec<(Vec<String>, String)>,
+}
+
+impl Lifting {
+    /// The type `call_indirect` checks calls to functions of this kind against
+    fn signature_index(&mut self, params: Vec<String>, return_type: String) -> usize {
+        let signature = (params, return_type);
+
+        match self.signatures.iter().position(|found| *found == signature) {
+            Some(type_index) => type_index,
+            None => {
+                self.signatures.push(signature);
+                self.signatures.len() - 1
+            }
+        }
+    }
+
+    /// Params of function type, which are called through the table like
+    /// closures are
+    fn function_params(&mut self, params: &[Param]) -> Vec<(String, usize)> {
+        params
+            .iter()
+            .filter_map(|param| {
+                let (params, return_type) = split_function_type(&param.type_name)?;
+                Some((
+                    param.name.to_string(),
+                    self.signature_index(params, return_type),
+                ))
+            })
+            .collect()
+    }
+
+    /// Lifts the closures in a function's expressions, pointing calls to the
+    /// closures in scope through the table
+    fn lift_all(
+        &mut self,
+        outer: &Function,
+        expressions: Vec<Expression>,
+        closures: &mut Vec<(String, usize)>,
+    ) -> Result<Vec<Expression>, CompileError> {
+        let mut lifted: Vec<Expression> = vec![];
+
+        for expression in expressions {
+            match expression {
+                Expression::Closure { function } => {
+                    lifted.append(&mut self.lift_closure(outer, *function, closures)?)
+                }
+                expression => lifted.push(self.lift(outer, expression, closures)?),
+            }
+        }
+
+        Ok(lifted)
+    }
+
+    fn lift(
+        &mut self,
+        outer: &Function,
+        expression: Expression,
+        closures: &mut Vec<(String, usize)>,
+    ) -> Result<Expression, CompileError> {
+        let mut lift_boxed = |expression: Box<Expression>, closures: &mut Vec<(String, usize)>| {
+            self.lift(outer, *expression, closures).map(Box::new)
+        };
+
+        Ok(match expression {
+            Expression::FunctionCall { name, args, .. } | Expression::TailCall { name, args }
+                if closures.iter().any(|(closure, _)| *closure == name) =>
+            {
+                let type_index = closures
+                    .iter()
+                    .rev()
+                    .find(|(closure, _)| *closure == name)
+                    .map(|(_, type_index)| *type_index)
+                    .unwrap_or_default();
+
+                Expression::ClosureCall {
+                    name,
+                    type_index,
+                    args: self.lift_all(outer, args, closures)?,
+                }
+            }
+            Expression::FunctionCall { name, args, info } => Expression::FunctionCall {
+                name,
+                args: self.lift_all(outer, args, closures)?,
+                info,
+            },
+            Expression::TailCall { name, args } => Expression::TailCall {
+                name,
+                args: self.lift_all(outer, args, closures)?,
+            },
+            Expression::Return { expression, info } => Expression::Return {
+                expression: lift_boxed(expression, closures)?,
+                info,
+            },
+            Expression::LocalAssign {
+                name,
+                type_name,
+                expression,
+                info,
+            } => Expression::LocalAssign {
+                name,
+                type_name,
+                expression: lift_boxed(expression, closures)?,
+                info,
+            },
+            Expression::GlobalAssign {
+                name,
+                type_name,
+                expression,
+            } => Expression::GlobalAssign {
+                name,
+                type_name,
+                expression: lift_boxed(expression, closures)?,
+            },
+            Expression::Addition {
+                left,
+                right,
+                type_name,
+                info,
+            } => Expression::Addition {
+                left: lift_boxed(left, closures)?,
+                right: lift_boxed(right, closures)?,
+                type_name,
+                info,
+            },
+            Expression::Conditional {
+                predicate,
+                success,
+                fail,
+                type_name,
+            } => Expression::Conditional {
+                predicate: lift_boxed(predicate, closures)?,
+                success: lift_boxed(success, closures)?,
+                fail: lift_boxed(fail, closures)?,
+                type_name,
+            },
+            Expression::IfStatement {
+                predicate,
+                success,
+                fail,
+            } => Expression::IfStatement {
+                predicate: lift_boxed(predicate, closures)?,
+                success: self.lift_all(outer, success, closures)?,
+                fail: self.lift_all(outer, fail, closures)?,
+            },
+            Expression::ForStatement {
+                initial_value,
+                incrementor,
+                break_condition,
+                direction,
+                body,
+            } => Expression::ForStatement {
+                initial_value: lift_boxed(initial_value, closures)?,
+                incrementor: lift_boxed(incrementor, closures)?,
+                break_condition: lift_boxed(break_condition, closures)?,
+                direction,
+                body: self.lift_all(outer, body, closures)?,
+            },
+            Expression::TryStatement { body, catch } => Expression::TryStatement {
+                body: self.lift_all(outer, body, closures)?,
+                catch: self.lift_all(outer, catch, closures)?,
+            },
+            Expression::MatchStatement {
+                subject,
+                type_name,
+                arms,
+                default,
+                info,
+            } => Expression::MatchStatement {
+                subject: lift_boxed(subject, closures)?,
+                type_name,
+                arms: arms
+                    .into_iter()
+                    .map(|(pattern, body)| {
+                        self.lift_all(outer, body, closures)
+                            .map(|body| (pattern, body))
+                    })
+                    .collect::<Result<Vec<(i64, Vec<Expression>)>, CompileError>>()?,
+                default: match default {
+                    Some(default) => Some(self.lift_all(outer, default, closures)?),
+                    None => None,
+                },
+                info,
+            },
+            Expression::Assert {
+                left,
+                right,
+                type_name,
+                line,
+            } => Expression::Assert {
+                left: lift_boxed(left, closures)?,
+                right: match right {
+                    Some(right) => Some(lift_boxed(right, closures)?),
+                    None => None,
+                },
+                type_name,
+                line,
+            },
+            expression => expression,
+        })
+    }
+
+    /// Makes the closure a function taking its environment as the first
+    /// param, and replaces it with allocating and filling in the environment
+    fn lift_closure(
+        &mut self,
+        outer: &Function,
+        function: Function,
+        closures: &mut Vec<(String, usize)>,
+    ) -> Result<Vec<Expression>, CompileError> {
+        let mut declared: Vec<String> = function
+            .params
+            .iter()
+            .map(|param| param.name.to_string())
+            .collect();
+        let mut captures: Vec<(String, String)> = vec![];
+        find_captures(
+            &function.expressions,
+            closures,
+            &mut declared,
+            &mut captures,
+        );
+        // functions are passed around as their environment
+        for (_, type_name) in captures.iter_mut() {
+            if split_function_type(type_name).is_some() {
+                *type_name = String::from("i32");
+            }
+        }
+
+        if let Some((name, type_name)) = captures
+            .iter()
+            .find(|(_, type_name)| !["i32", "i64", "f32", "f64"].contains(&type_name.as_str()))
+        {
+            return Err(CompileError::new(format!(
+                "{} can't capture {}, which is {}, as closures only capture numbers",
+                function.name, name, type_name
+            )));
+        }
+
+        let type_index = self.signature_index(
+            function
+                .params
+                .iter()
+                .map(|param| param.type_name.to_string())
+                .collect(),
+            function.return_type.to_string(),
+        );
+
+        let offset = |index: usize| ENVIRONMENT_SLOT * (index as i32 + 1);
+        let name = format!("{}__{}", outer.name, function.name);
+        let table_index = self.table.len() as i32;
+        self.table.push(name.to_string());
+
+        // captured closures are locals inside, so their params hide them
+        let mut inner_closures: Vec<(String, usize)> = closures
+            .iter()
+            .filter(|(closure, _)| !function.params.iter().any(|param| param.name == *closure))
+            .cloned()
+            .chain(self.function_params(&function.params))
+            .collect();
+        let lifted = Function {
+            name,
+            params: [Param {
+                name: String::from("__env"),
+                type_name: String::from("i32"),
+                info: None,
+            }]
+            .into_iter()
+            .chain(function.params.iter().cloned())
+            .collect(),
+            expressions: vec![],
+            doc: None,
+            attributes: outer
+                .attributes
+                .iter()
+                .filter(|attribute| attribute.name == "memory")
+                .cloned()
+                .collect(),
+            ..function.clone()
+        };
+        let body = captures
+            .iter()
+            .enumerate()
+            .map(|(index, (name, type_name))| Expression::LocalAssign {
+                name: name.to_string(),
+                type_name: type_name.to_string(),
+                expression: Box::new(Expression::EnvironmentLoad {
+                    offset: offset(index),
+                    type_name: type_name.to_string(),
+                }),
+                info: None,
+            })
+            .collect::<Vec<Expression>>()
+            .into_iter()
+            .chain(self.lift_all(&lifted, function.expressions, &mut inner_closures)?)
+            .collect();
+        self.functions.push(Function {
+            expressions: body,
+            ..lifted
+        });
+
+        closures.push((function.name.to_string(), type_index));
+
+        let environment_size = offset(captures.len());
+        Ok([
+            Expression::LocalAssign {
+                name: function.name.to_string(),
+                type_name: String::from("i32"),
+                expression: Box::new(Expression::FunctionCall {
+                    name: String::from("alloc"),
+                    args: vec![Expression::Number {
+                        value: environment_size.to_string(),
+                        type_name: String::from("i32"),
+                    }],
+                    info: None,
+                }),
+                info: None,
+            },
+            Expression::EnvironmentStore {
+                name: function.name.to_string(),
+                offset: 0,
+                type_name: String::from("i32"),
+                value: Box::new(Expression::Number {
+                    value: table_index.to_string(),
+                    type_name: String::from("i32"),
+                }),
+            },
+        ]
+        .into_iter()
+        .chain(
+            captures
+                .into_iter()
+                .enumerate()
+                .map(|(index, (name, type_name))| Expression::EnvironmentStore {
+                    name: function.name.to_string(),
+                    offset: offset(index),
+                    type_name: type_name.to_string(),
+                    value: Box::new(Expression::Variable {
+                        body: name,
+                        type_name,
+                    }),
+                }),
+        )
+        .collect())
+    }
+}
+
+/// Lambda lifts every closure into a function after the one it was declared
+/// in, called through a table with an environment in memory holding what it
+/// captured
+fn lift_closures(program: Program) -> Result<(Program, Lifting), CompileError> {
+    let mut lifting = Lifting::default();
+    let mut blocks: Vec<Block> = vec![];
+
+    for block in program.blocks {
+        match block {
+            Block::Function(function) => {
+                let mut closures = lifting.function_params(&function.params);
+                let expressions =
+                    lifting.lift_all(&function, function.expressions.clone(), &mut closures)?;
+                blocks.push(Block::Function(Function {
+                    expressions,
+                    ..function
+                }));
+                blocks.extend(lifting.functions.drain(..).map(Block::Function));
+            }
+            block => blocks.push(block),
+        }
+    }
+
+    Ok((Program { blocks }, lifting))
+}
+
+pub fn generate(program: Program) -> Result<String, CompileError> {
+    generate_with(program, &GenerateOptions::default())
+}
+
+/// The wat for one function, generated with everything it needs to know
+/// about the rest of the program
+pub fn generate_function_with(
+    program: Program,
+    name: &str,
+    options: &GenerateOptions,
+) -> Result<String, CompileError> {
+    let Prepared {
+        program, context, ..
+    } = prepare(program, options)?;
+
+    generate_function(
+        &context,
+        find_function(program, &options.naming.symbol(name))?,
+    )
+    .map(|wat| layout::apply(optimize(wat, options), options.layout))
+}
+
+fn optimize(wat: String, options: &GenerateOptions) -> String {
+    if options.passes.peephole {
+        peephole::optimize(&wat)
+    } else {
+        wat
+    }
+}
+
+/// The wat target, or wat-gc when generating for WasmGC
+pub struct Wat {
+    pub options: GenerateOptions,
+}
+
+impl Backend for Wat {
+    fn extension(&self) -> &str {
+        if self.options.gc {
+            "gc.wat"
+        } else {
+            "wat"
+        }
+    }
+
+    fn reads_data(&self) -> bool {
+        true
+    }
+
+    fn generate_module(&self, program: Program) -> Result<Vec<u8>, CompileError> {
+        generate_with(program, &self.options).map(String::into_bytes)
+    }
+
+    fn generate_function(&self, program: Program, name: &str) -> Result<String, CompileError> {
+        generate_function_with(program, name, &self.options)
+    }
+}
+
+/// A program lowered and laid out in memory, ready to have its blocks generated
+struct Prepared {
+    program: Program,
+    context: Context,
+    closures: Lifting,
+    data: Vec<(String, i32, Vec<u8>)>,
+    panic_messages: Vec<(i32, String)>,
+    panic_messages_end: i32,
+    has_memory: bool,
+    /// A runtime function called without being defined, which pulls in the runtime
+    runtime_call: Option<String>,
+    /// The checks made by debug builds, along with the helper they call
+    checks: Vec<Check>,
+}
+
+fn prepare(program: Program, options: &GenerateOptions) -> Result<Prepared, CompileError> {
+    let program = monomorphize(resolve_aliases(naming::apply(program, &options.naming)))?;
+    let program = lower_arrays(program)?;
+    let (program, closures) = lift_closures(program)?;
+    // inlined calls wouldn't be counted
+    let program = if options.passes.inline && options.instrument.is_none() {
+        // with error codes, whether an import's error is caught depends on the function calling it
+        inlining::inline(program, options.exceptions == Exceptions::ErrorCodes)
+    } else {
+        program
+    };
+    let program = if options.passes.dead_code {
+        remove_dead_functions(program, &closures.table)
+    } else {
+        program
+    };
+    let constants = evaluate_constants(&program.blocks)?;
+    // strings only take up linear memory when they aren't WasmGC arrays
+    let strings_length = if options.gc {
+        0
+    } else {
+        local_strings_length(&program)
+    };
+    let (program, mut panic_messages) = layout_panic_messages(program)?;
+
+    let has_memory = has_memory(&program.blocks);
+
+    let (checks, check_messages) = if options.passes.debug {
+        let start = panic_messages
+            .iter()
+            .map(|(offset, message)| offset + message.len() as i32)
+            .fold(strings_length, i32::max);
+        layout_checks(&program, start)?
+    } else {
+        (vec![], vec![])
+    };
+    panic_messages.extend(check_messages.iter().cloned());
+
+    let panic_messages_end = panic_messages
+        .iter()
+        .map(|(offset, message)| offset + message.len() as i32)
+        .fold(strings_length, i32::max);
+    let data = layout_data(&program, panic_messages_end, options.passes.dedupe_data)?;
+
+    if let (Some((name, _, _)), false) = (data.first(), has_memory) {
+        return Err(CompileError::new(format!(
+            "Data {} is stored in memory, add an import memory",
+            name
+        )));
+    }
+
+    if !closures.signatures.is_empty() && !has_memory {
+        return Err(CompileError::new(String::from(
+            "Closures keep what they capture in memory, add an import memory",
+        )));
+    }
+
+    // builtins all work on memory
+    if !has_memory {
+        if let Some(builtin) = find_call_in_program(&program, &|name| find_builtin(name).is_some())
+        {
+            return Err(CompileError::new(format!(
+                "{} needs a memory, add an import memory",
+                builtin
+            )));
+        }
+    }
+
+    let imports: Vec<String> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::ImportFunction(import) => Some(import.name.to_string()),
+            _ => None,
+        })
+        .collect();
+
+    let find_tail_call_in_program = |matches: &dyn Fn(&str) -> bool| {
+        program.blocks.iter().find_map(|block| match block {
+            Block::Function(function) => find_tail_call(&function.expressions, matches),
+            _ => None,
+        })
+    };
+
+    if !options.tail_calls {
+        if let Some(name) = find_tail_call_in_program(&|_| true) {
+            return Err(CompileError::new(format!(
+                "tailcall {} needs the tail-call feature",
+                name
+            )));
+        }
+    }
+
+    // with error codes, imports return more than the caller does
+    if options.exceptions == Exceptions::ErrorCodes {
+        if let Some(name) =
+            find_tail_call_in_program(&|name| imports.iter().any(|import| import == name))
+        {
+            return Err(CompileError::new(format!(
+                "{} returns an error code, so can't be tail called",
+                name
+            )));
+        }
+    }
+
+    let defined: Vec<&str> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function) => Some(function.name.as_str()),
+            Block::ImportFunction(import) => Some(import.name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let runtime_call = find_call_in_program(&program, &|name| {
+        !defined.contains(&name)
+            && RUNTIME_FUNCTIONS
+                .iter()
+                .any(|function| function.name == name)
+    });
+
+    let mut strings: Vec<String> = vec![];
+    if options.gc {
+        for block in &program.blocks {
+            if let Block::Function(function) = block {
+                collect_strings(&function.expressions, &mut strings);
+            }
+        }
+    }
+
+    let context = Context {
+        gc: options.gc,
+        strings,
+        exceptions: options.exceptions,
+        imports,
+        try_depth: 0,
+        current_try: None,
+        match_depth: 0,
+        memory: None,
+        data: data
+            .iter()
+            .map(|(name, offset, bytes)| (name.to_string(), *offset, bytes.len() as i32))
+            .collect(),
+        string_exports: if options.gc {
+            vec![]
+        } else {
+            exported_functions(&program)
+                .into_iter()
+                .filter(|function| function.return_type == "string")
+                .map(|function| function.name.to_string())
+                .collect()
+        },
+        string_offsets: layout_local_strings(&program),
+        constants,
+        instrument: options.instrument,
+        coverage: options.coverage,
+        function_names: program
+            .blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::Function(function) => Some(function.name.to_string()),
+                _ => None,
+            })
+            .collect(),
+        return_types: program
+            .blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::Function(function) => {
+                    Some((function.name.to_string(), function.return_type.to_string()))
+                }
+                _ => None,
+            })
+            .collect(),
+        function: String::new(),
+        checks: if options.passes.debug {
+            Some((
+                find_panic_handler(&program).map(|handler| handler.name),
+                check_messages,
+            ))
+        } else {
+            None
+        },
+        mark_statements: false,
+    };
+
+    Ok(Prepared {
+        program,
+        context,
+        closures,
+        data,
+        panic_messages,
+        panic_messages_end,
+        has_memory,
+        runtime_call,
+        checks,
+    })
+}
+
+pub fn generate_with(program: Program, options: &GenerateOptions) -> Result<String, CompileError> {
+    generate_marked(program, options, false).map(|(wat, _)| wat)
+}
+
+/// The function and which of its statements each line of the wat was
+/// generated from, where a line comes from a statement
+pub type StatementLines = Vec<Option<(String, usize)>>;
+
+/// The wat along with where each of its lines comes from, for pointing
+/// errors about the wat at the gwe
+pub fn generate_with_statements(
+    program: Program,
+    options: &GenerateOptions,
+) -> Result<(String, StatementLines), CompileError> {
+    generate_marked(program, options, true)
+}
+
+fn generate_marked(
+    program: Program,
+    options: &GenerateOptions,
+    mark_statements: bool,
+) -> Result<(String, StatementLines), CompileError> {
+    let Prepared {
+        program,
+        context,
+        closures,
+        data,
+        panic_messages,
+        panic_messages_end,
+        has_memory,
+        runtime_call,
+        checks,
+    } = prepare(program, options)?;
+    let context = Context {
+        mark_statements,
+        ..context
+    };
+
+    let string_type = if context.gc && uses_string_type(&program) {
+        vec![String::from("(type $string (array (mut i8)))")]
+    } else {
+        vec![]
+    };
+    let closure_types: Vec<String> = closures
+        .signatures
+        .iter()
+        .enumerate()
+        .map(|(index, (params, return_type))| {
+            let results = if return_type == "void" {
+                String::new()
+            } else {
+                format!(" (result {})", context.value_type(return_type))
+            };
+
+            format!(
+                "(type $__closure_{} (func (param i32{}){}))",
+                index,
+                params
+                    .iter()
+                    .map(|param| format!(" {}", context.value_type(param)))
+                    .collect::<String>(),
+                results
+            )
+        })
+        .collect();
+    let types = [string_type, closure_types].concat();
+
+    // functions can be called through the table even when none are put in it
+    let table = match (closures.signatures.is_empty(), closures.table.is_empty()) {
+        (true, _) => vec![],
+        (false, true) => vec![String::from("(table $__closures 0 funcref)")],
+        (false, false) => vec![format!(
+            "(table $__closures {} funcref)\n(elem (table $__closures) (i32.const 0) func {})",
+            closures.table.len(),
+            closures
+                .table
+                .iter()
+                .map(|name| format!("${}", name))
+                .collect::<Vec<String>>()
+                .join(" ")
+        )],
+    };
+
+    let data_end = data
+        .iter()
+        .map(|(_, offset, bytes)| offset + bytes.len() as i32)
+        .fold(panic_messages_end, i32::max);
+
+    // the string buffer goes between the data and the heap
+    let (string_boundary, data_end) = if crosses_string_boundary(&context, &program) {
+        if !has_memory {
+            return Err(CompileError::new(String::from(
+                "Strings are passed to and from the host through memory, add an import memory",
+            )));
+        }
+
+        let buffer = data_end
+            .checked_add(7)
+            .map(|end| end & -8)
+            .and_then(|buffer| Some((buffer, buffer.checked_add(STRING_BUFFER_LENGTH)?)));
+
+        match buffer {
+            Some((buffer, end)) => (
+                vec![generate_string_boundary(&context, &program, buffer)],
+                end,
+            ),
+            None => {
+                return Err(CompileError::new(String::from(
+                    "There's no memory left for the string buffer after the module's data",
+                )))
+            }
+        }
+    } else {
+        (vec![], data_end)
+    };
+
+    let runtime = match runtime_call {
+        Some(name) if !options.runtime => {
+            return Err(CompileError::new(format!(
+                "{} is part of the runtime, which is turned off",
+                name
+            )))
+        }
+        Some(name) if !has_memory => {
+            return Err(CompileError::new(format!(
+                "{} needs a memory, add an import memory",
+                name
+            )))
+        }
+        Some(_) => {
+            // allocations are 8 byte aligned
+            let heap_base = match data_end.checked_add(7) {
+                Some(end) => end & -8,
+                None => {
+                    return Err(CompileError::new(String::from(
+                        "There's no memory left for the heap after the module's data",
+                    )))
+                }
+            };
+
+            vec![generate_runtime(heap_base)]
+        }
+        None => vec![],
+    };
+
+    let blocks: Vec<String> = program
+        .blocks
+        .clone()
+        .into_iter()
+        .map(|block| generate_block(&context, block))
+        .collect::<Result<Vec<String>, CompileError>>()?
+        .into_iter()
+        .filter(|block| !block.is_empty())
+        .collect();
+    let globals = match define_globals(&program)? {
+        globals if globals.is_empty() => vec![],
+        globals => vec![globals],
+    };
+
+    let asserts = program.blocks.iter().any(|block| match block {
+        Block::Function(function) => uses_asserts(&function.expressions),
+        _ => false,
+    });
+    let imports = if asserts {
+        let (module, name) = ASSERT_FAILED_IMPORT;
+        vec![format!(
+            "(import \"{}\" \"{}\" (func $__assert_failed (param i32)))",
+            module, name
+        )]
+    } else {
+        vec![]
+    };
+
+    let datas = if panic_messages.is_empty() && data.is_empty() {
+        vec![]
+    } else {
+        vec![panic_messages
+            .iter()
+            .map(|(offset, message)| {
+                format!(
+                    "(data (i32.const {}) \"{}\")",
+                    offset,
+                    escape_bytes(message.as_bytes())
+                )
+            })
+            .chain(
+                data.iter()
+                    .enumerate()
+                    // deduped blocks are written by the block they share with
+                    .filter(|(index, (_, offset, _))| {
+                        !data[..*index]
+                            .iter()
+                            .any(|(_, earlier, _)| earlier == offset)
+                    })
+                    .map(|(_, (_, offset, bytes))| {
+                        format!("(data (i32.const {}) \"{}\")", offset, escape_bytes(bytes))
+                    }),
+            )
+            .collect::<Vec<String>>()
+            .join("\n")]
+    };
+
+    let string_datas = if context.strings.is_empty() {
+        vec![]
+    } else {
+        vec![context
+            .strings
+            .iter()
+            .enumerate()
+            .map(|(index, string)| {
+                format!(
+                    "(data $__string_{} \"{}\")",
+                    index,
+                    escape_bytes(string.as_bytes())
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")]
+    };
+
+    let instrumentation = match context.instrument {
+        Some(instrument) => generate_instrumentation(&context, instrument)?,
+        None => vec![],
+    };
+
+    // checks can share a helper, with only their messages differing
+    let check_helpers: Vec<String> = match &context.checks {
+        Some((handler, _)) => {
+            let mut helpers: Vec<String> = vec![];
+            for check in checks {
+                let helper = check.generate_helper(handler.as_deref());
+                if !helpers.contains(&helper) {
+                    helpers.push(helper);
+                }
+            }
+            helpers
+        }
+        None => vec![],
+    };
+
+    let coverage = if options.coverage {
+        vec![String::from(COVERAGE_RUNTIME)]
+    } else {
+        vec![]
+    };
+
+    let globals_and_blocks = [
+        types,
+        imports,
+        globals,
+        blocks,
+        instrumentation,
+        coverage,
+        check_helpers,
+        table,
+        runtime,
+        string_boundary,
+        datas,
+        string_datas,
+    ]
+    .concat();
+
+    let wat = format!(
+        "(module
+{})",
+        indent(order_fields(globals_and_blocks).join("\n\n"))
+    );
+
+    let (wat, statements) = strip_statement_marks(&wat);
+
+    let optimized = optimize(wat.clone(), options);
+    let statements = follow_lines(&wat, &statements, &optimized);
+
+    if options.audit_stack {
+        stack::audit(&optimized)?;
+    }
+
+    let laid_out = layout::apply(optimized.clone(), options.layout);
+    let statements = follow_lines(&optimized, &statements, &laid_out);
+
+    if options.validate {
+        validate(&laid_out)?;
+    }
+
+    Ok((laid_out, statements))
+}
+
+/// Starts the comment generated functions put before each statement when
+/// marking statements, followed by the function and the statement's index,
+/// or `-` where the function's statements end
+const STATEMENT_MARK: &str = ";;@";
+
+/// Takes the statement marks back out of the wat, giving where each line that's
+/// left comes from
+fn strip_statement_marks(wat: &str) -> (String, StatementLines) {
+    let mut lines = vec![];
+    let mut statements = vec![];
+    let mut current = None;
+
+    for line in wat.lines() {
+        match line.trim_start().strip_prefix(STATEMENT_MARK) {
+            Some(mark) => {
+                current = mark.split_once(' ').and_then(|(function, index)| {
+                    Some((function.to_string(), index.parse::<usize>().ok()?))
+                })
+            }
+            None => {
+                lines.push(line);
+                statements.push(current.clone());
+            }
+        }
+    }
+
+    (lines.join("\n"), statements)
+}
+
+/// Carries what's known about each line of the wat across optimizing and
+/// laying it out, which only drop lines, merge a line into the one before it
+/// and reindent. A line that isn't found is a merged one, which keeps what's
+/// known about the first line merged into it
+fn follow_lines<T: Clone>(before: &str, known: &[Option<T>], after: &str) -> Vec<Option<T>> {
+    if before == after {
+        return known.to_vec();
+    }
+
+    if known.iter().all(Option::is_none) {
+        return vec![None; after.lines().count()];
+    }
+
+    let before: Vec<&str> = before.lines().map(str::trim).collect();
+    let mut position = 0;
+
+    after
+        .lines()
+        .map(|line| {
+            let line = line.trim();
+            let found = before[position..]
+                .iter()
+                .position(|earlier| *earlier == line)
+                .map_or(position, |offset| position + offset);
+
+            position = (found + 1).min(before.len());
+            known.get(found).cloned().flatten()
+        })
+        .collect()
+}
+
+/// The top-level fields in wat, like each `(func ...)` and `(data ...)`
+fn split_fields(wat: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut start = None;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, character) in wat.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => {
+                if depth == 0 {
+                    start = Some(index);
+                }
+                depth += 1;
+            }
+            ')' if !in_string => {
+                depth -= 1;
+                if let (0, Some(from)) = (depth, start) {
+                    fields.push(wat[from..=index].to_string());
+                    start = None;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fields
+}
+
+/// The function whose `(func ...)` holds a one-based line and column of the
+/// wat, for tracing errors about the wat back to the gwe function
+pub fn function_at(wat: &str, line: usize, column: usize) -> Option<String> {
+    let offset = wat
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum::<usize>()
+        + column.saturating_sub(1);
+
+    let mut start = None;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, character) in wat.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => {
+                // fields are inside the module
+                if depth == 1 {
+                    start = Some(index);
+                }
+                depth += 1;
+            }
+            ')' if !in_string => {
+                depth -= 1;
+                if let (1, Some(from)) = (depth, start) {
+                    if (from..=index).contains(&offset) {
+                        return wat[from..]
+                            .strip_prefix("(func $")
+                            .and_then(|rest| rest.split([' ', '\n', ')']).next())
+                            .map(str::to_string);
+                    }
+                    start = None;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// Where a kind of field goes in the module, so that the same program always
+/// gives the same output whatever order its blocks are written in
+fn field_rank(field: &str) -> usize {
+    let kind = field
+        .trim_start_matches('(')
+        .split(|character: char| character.is_whitespace() || character == ')')
+        .next()
+        .unwrap_or_default();
+
+    match kind {
+        "type" => 0,
+        "import" => 1,
+        "memory" => 2,
+        "table" => 3,
+        "global" => 4,
+        "data" => 5,
+        "elem" => 6,
+        "func" => 7,
+        "export" => 8,
+        _ => 9,
+    }
+}
+
+/// Groups fields as types, imports, memories, tables, globals, data, elems,
+/// funcs and then exports, keeping the order they were generated in otherwise
+fn order_fields(chunks: Vec<String>) -> Vec<String> {
+    let mut fields: Vec<String> = chunks
+        .iter()
+        .flat_map(|chunk| split_fields(chunk))
+        .collect();
+
+    fields.sort_by_key(|field| field_rank(field));
+    fields
+}
+
+/// Encodes the wat and checks the module with wasmparser, allowing every
+/// proposal as the options decide which ones the wat uses
+pub fn validate(wat: &str) -> Result<(), CompileError> {
+    let bytes = match wat::parse_str(wat) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            return Err(CompileError::new(format!(
+                "Generated wat that doesn't parse: {}",
+                error
+            )))
+        }
+    };
+
+    match Validator::new_with_features(WasmFeatures::all()).validate_all(&bytes) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(CompileError::new(format!(
+            "Generated invalid wasm: {}",
+            error
+        ))),
+    }
+}
+
+fn find_global_assigns<'a>(expressions: &'a [Expression], found: &mut Vec<&'a Expression>) {
+    for expression in expressions {
+        match expression {
+            Expression::GlobalAssign { .. } => found.push(expression),
+            Expression::IfStatement { success, fail, .. } => {
+                find_global_assigns(success, found);
+                find_global_assigns(fail, found);
+            }
+            Expression::ForStatement {
+                initial_value,
+                body,
+                ..
+            } => {
+                find_global_assigns(std::slice::from_ref(initial_value.as_ref()), found);
+                find_global_assigns(body, found);
+            }
+            Expression::TryStatement { body, catch } => {
+                find_global_assigns(body, found);
+                find_global_assigns(catch, found);
+            }
+            Expression::MatchStatement { arms, default, .. } => {
+                for body in arms.iter().map(|(_, body)| body).chain(default) {
+                    find_global_assigns(body, found);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Literal initializers are used as-is, anything else starts at zero until the
+/// function assigning it runs
+fn global_initializer(type_name: &str, expression: &Expression) -> String {
+    match expression {
+        Expression::Number {
+            value,
+            type_name: number_type,
+        } if number_type == type_name => format!("({}.const {})", type_name, value),
+        Expression::Boolean { value } if type_name == "i32" => {
+            format!("(i32.const {})", if *value { 1 } else { 0 })
+        }
+        Expression::Character { value } if type_name == "i32" => {
+            format!("(i32.const {})", *value as u32)
+        }
+        _ => format!("({}.const 0)", type_name),
+    }
+}
+
+/// Globals from every function in the program, declared once each at the top of the module
+fn define_globals(program: &Program) -> Result<String, CompileError> {
+    let mut assigns: Vec<&Expression> = vec![];
+
+    for block in &program.blocks {
+        if let Block::Function(function) = block {
+            find_global_assigns(&function.expressions, &mut assigns);
+        }
+    }
+
+    let mut declared: Vec<(&String, &String)> = vec![];
+    let mut globals: Vec<String> = vec![];
+
+    for assign in assigns {
+        let Expression::GlobalAssign {
+            name,
+            type_name,
+            expression,
+        } = assign
+        else {
+            continue;
+        };
+
+        match declared.iter().find(|(existing, _)| *existing == name) {
+            Some((_, existing_type)) if *existing_type != type_name => {
+                return Err(CompileError::new(format!(
+                    "Global {} is declared as both {} and {}",
+                    name, existing_type, type_name
+                )))
+            }
+            Some(_) => continue,
+            None => (),
+        }
+
+        if !["i32", "i64", "f32", "f64"].contains(&type_name.as_str()) {
+            return Err(CompileError::new(format!(
+                "Global {} has type {}, but globals can only be i32, i64, f32 or f64",
+                name, type_name
+            )));
+        }
+
+        declared.push((name, type_name));
+        globals.push(format!(
+            "(global ${} (mut {}) {})",
+            name,
+            type_name,
+            global_initializer(type_name, expression)
+        ));
+    }
+
+    Ok(globals.join("\n"))
+}
+
+fn find_local_assigns(expressions: &[Expression], found: &mut Vec<(String, String)>) {
+    for expression in expressions {
+        match expression {
+            Expression::LocalAssign {
+                name, type_name, ..
+            } => found.push((name.to_string(), type_name.to_string())),
+            Expression::IfStatement { success, fail, .. } => {
+                find_local_assigns(success, found);
+                find_local_assigns(fail, found);
+            }
+            Expression::ForStatement {
+                initial_value,
+                body,
+                ..
+            } => {
+                find_local_assigns(std::slice::from_ref(initial_value.as_ref()), found);
+                find_local_assigns(body, found);
+            }
+            Expression::TryStatement { body, catch } => {
+                find_local_assigns(body, found);
+                find_local_assigns(catch, found);
+            }
+            Expression::MatchStatement {
+                type_name,
+                arms,
+                default,
+                ..
+            } => {
+                // the subject is kept in a local when the arms are picked by ifs
+                if !uses_jump_table(type_name, arms) {
+                    found.push((format!("__match_{}", type_name), type_name.to_string()));
+                }
+                for body in arms.iter().map(|(_, body)| body).chain(default) {
+                    find_local_assigns(body, found);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Wasm locals belong to the whole function, so locals from every nested block
+/// are declared up front, once per name
+fn define_locals(context: &Context, expressions: Vec<Expression>) -> String {
+    let mut assigns: Vec<(String, String)> = vec![];
+    find_local_assigns(&expressions, &mut assigns);
+
+    let mut locals: Vec<(String, String)> = vec![];
+
+    for (name, type_name) in assigns {
+        // strings in linear memory are only ever left on the stack
+        let stored = type_name != "string" || context.gc;
+
+        if stored && !locals.iter().any(|(existing, _)| *existing == name) {
+            locals.push((name, type_name));
+        }
+    }
+
+    locals
+        .into_iter()
+        .map(|(name, type_name)| format!("(local ${} {})", name, context.value_type(&type_name)))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn generate_param(context: &Context, param: Param) -> String {
+    if param.type_name == "string" && !context.gc {
+        return format!(
+            "(param ${} i32) (param ${}__length i32)",
+            param.name, param.name
+        );
+    }
+
+    format!(
+        "(param ${} {})",
+        param.name,
+        context.value_type(&param.type_name)
+    )
+}
+
+type ExtractedStrings = (Option<String>, Vec<Expression>);
+
+/// Lays a string out in memory after the strings before it
+fn place_string(
+    body: &str,
+    strings: &mut Vec<(i32, String)>,
+    offset: &mut i32,
+) -> Result<Expression, CompileError> {
+    let end = i32::try_from(body.len())
+        .ok()
+        .and_then(|length| offset.checked_add(length).map(|end| (length, end)));
+
+    match end {
+        Some((length, end)) => {
+            strings.push((*offset, body.to_string()));
+            let reference = Expression::MemoryReference {
+                offset: *offset,
+                length,
+            };
+            *offset = end;
+            Ok(reference)
+        }
+        None => Err(CompileError::new(String::from(
+            "Strings are too long to fit in memory",
+        ))),
+    }
+}
+
+fn extract_strings(
+    context: &Context,
+    function_name: &str,
+    expressions: Vec<Expression>,
+) -> Result<ExtractedStrings, CompileError> {
+    let mut strings: Vec<(i32, String)> = vec![];
+    let mut offset: i32 = context
+        .string_offsets
+        .iter()
+        .find(|(name, _)| name == function_name)
+        .map(|(_, offset)| *offset)
+        .unwrap_or_default();
+
+    let new_expressions =
+        expressions
+            .iter()
+            .map(|exp| match exp {
+                Expression::LocalAssign {
+                    type_name,
+                    expression,
+                    ..
+                } if type_name == "string" => match expression.as_ref() {
+                    Expression::String { body } => place_string(body, &mut strings, &mut offset),
+                    _ => Ok(Expression::MemoryReference { offset, length: 0 }),
+                },
+                // returned strings are left on the stack for the caller
+                Expression::Return { expression, info } => match expression.as_ref() {
+                    Expression::String { body } => place_string(body, &mut strings, &mut offset)
+                        .map(|reference| Expression::Return {
+                            expression: Box::new(reference),
+                            info: info.clone(),
+                        }),
+                    _ => Ok(exp.clone()),
+                },
+                _ => Ok(exp.clone()),
+            })
+            .collect::<Result<Vec<Expression>, CompileError>>()?;
+
+    let output = if strings.is_empty() {
+        None
+    } else {
+        let datas: String = strings
+            .iter()
+            .map(|(offset, string)| match &context.memory {
+                Some(memory) => format!(
+                    "(data (memory ${}) (i32.const {}) \"{}\")",
+                    memory,
+                    offset,
+                    escape_bytes(string.as_bytes())
+                ),
+                None => format!(
+                    "(data (i32.const {}) \"{}\")",
+                    offset,
+                    escape_bytes(string.as_bytes())
+                ),
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        Some(format!("{}\n", datas))
+    };
+
+    Ok((output, new_expressions))
+}
+
+fn generate_expression(context: &Context, expression: Expression) -> String {
+    match expression {
+        Expression::Addition {
+            left,
+            right,
+            type_name,
+            ..
+        } => {
+            let generated_left = generate_expression(context, *left);
+            let generated_right = generate_expression(context, *right);
+
+            // literals nothing gave a type to stay f32
+            let type_name = if type_name.is_empty() {
+                String::from("f32")
+            } else {
+                type_name
+            };
+
+            format!("({}.add {} {})", type_name, generated_left, generated_right)
+        }
+        Expression::GlobalAssign {
+            name,
+            type_name: _,
+            expression,
+        } => {
+            format!(
+                "(global.set ${} {})",
+                name,
+                generate_expression(context, *expression)
+            )
+        }
+        Expression::LocalAssign {
+            name,
+            type_name: _,
+            expression,
+            ..
+        } => {
+            format!(
+                "(local.set ${} {})",
+                name,
+                generate_expression(context, *expression)
+            )
+        }
+        Expression::Number { value, type_name } => format!("({}.const {})", type_name, value),
+        Expression::Return { expression, .. } => generate_expression(context, *expression),
+        Expression::Variable { body, type_name } if type_name == "string" && !context.gc => {
+            format!("(local.get ${})\n(local.get ${}__length)", body, body)
+        }
+        Expression::Variable { body, type_name: _ } => format!("(local.get ${})", body),
+        Expression::String { body } if context.gc => {
+            let index = context
+                .strings
+                .iter()
+                .position(|string| *string == body)
+                .unwrap_or_default();
+
+            format!(
+                "(array.new_data $string $__string_{} (i32.const 0) (i32.const {}))",
+                index,
+                body.len()
+            )
+        }
+        Expression::String { body } => format!("\"{}\"", escape_bytes(body.as_bytes())),
+        Expression::FunctionCall { name, args, .. } if context.coverage && name == HIT => {
+            let offset = match args.first() {
+                Some(Expression::Number { value, .. }) => value.parse::<i32>().unwrap_or(0) * 4,
+                _ => 0,
+            };
+
+            format!(
+                "(i32.store $__coverage (i32.const {}) (i32.add (i32.load $__coverage (i32.const {})) (i32.const 1)))",
+                offset, offset
+            )
+        }
+        Expression::FunctionCall { name, args, .. } => {
+            let params = args
+                .iter()
+                .map(|e| generate_expression(context, e.clone()))
+                .collect::<Vec<String>>()
+                .join("\n");
+            let handle_error = match (context.exceptions, context.current_try) {
+                (Exceptions::ErrorCodes, Some(label)) if context.imports.contains(&name) => {
+                    format!("\n(br_if $__try_{})", label)
+                }
+                (Exceptions::ErrorCodes, None) if context.imports.contains(&name) => {
+                    String::from("\n(drop)")
+                }
+                _ => String::new(),
+            };
+
+            match find_builtin(&name) {
+                Some(builtin) => {
+                    // copies read from and write to the same memory
+                    let memories = if builtin.instruction == "memory.copy" {
+                        context.memory_index().repeat(2)
+                    } else {
+                        context.memory_index()
+                    };
+                    let check = Check::Bounds {
+                        builtin: name.to_string(),
+                        memory: context.memory.clone(),
+                    };
+
+                    match context.call_check(&check) {
+                        Some(call) if builtin.return_type == "void" => {
+                            format!("{}\n{}", params, call)
+                        }
+                        _ => format!("{}\n({}{})", params, builtin.instruction, memories),
+                    }
+                }
+                None => format!("{}\n(call ${}){}", params, name, handle_error),
+            }
+        }
+        Expression::TailCall { name, args } => {
+            let params = args
+                .iter()
+                .map(|e| generate_expression(context, e.clone()))
+                .collect::<Vec<String>>()
+                .join("\n");
+
+            format!("{}\n(return_call ${})", params, name)
+        }
+        Expression::MemoryReference { offset, length } => {
+            format!("(i32.const {})\n(i32.const {})", offset, length)
+        }
+        // arrays are moved into data, and loops over them become index loops,
+        // before generating
+        Expression::Array { .. } | Expression::ForEach { .. } => String::new(),
+        Expression::Index {
+            name,
+            type_name,
+            index,
+            length,
+            line,
+        } => {
+            let check = Check::Index {
+                array: name.to_string(),
+                line,
+            };
+            let checked = match context.call_check(&check) {
+                Some(call) => format!("\n(i32.const {})\n{}", length, call),
+                None => String::new(),
+            };
+
+            format!(
+                "(local.get ${})\n{}{}\n(i32.const {})\n(i32.mul)\n(i32.add)\n({}.load)",
+                name,
+                generate_expression(context, *index),
+                checked,
+                array_element_size(&type_name).unwrap_or(4),
+                type_name
+            )
+        }
+        Expression::DataField { name, field } => {
+            let (_, offset, length) = context
+                .data
+                .iter()
+                .find(|(found, _, _)| *found == name)
+                .cloned()
+                .unwrap_or_default();
+
+            format!(
+                "(i32.const {})",
+                if field == "offset" { offset } else { length }
+            )
+        }
+        Expression::Constant { name, type_name } => format!(
+            "({}.const {})",
+            type_name,
+            find_constant(&context.constants, &name).unwrap_or_default()
+        ),
+        // closures are lifted out before code is generated
+        Expression::Closure { .. } => String::new(),
+        Expression::ClosureCall {
+            name,
+            type_index,
+            args,
+        } => {
+            let params = args
+                .into_iter()
+                .map(|e| generate_expression(context, e))
+                .collect::<Vec<String>>()
+                .join("\n");
+
+            format!(
+                "(local.get ${name})\n{}\n(i32.load{} (local.get ${name}))\n(call_indirect $__closures (type $__closure_{}))",
+                params,
+                context.memory_index(),
+                type_index,
+                name = name
+            )
+        }
+        Expression::EnvironmentStore {
+            name,
+            offset,
+            type_name,
+            value,
+        } => format!(
+            "({}.store{} offset={} (local.get ${}) {})",
+            type_name,
+            context.memory_index(),
+            offset,
+            name,
+            generate_expression(context, *value)
+        ),
+        Expression::EnvironmentLoad { offset, type_name } => format!(
+            "({}.load{} offset={} (local.get $__env))",
+            type_name,
+            context.memory_index(),
+            offset
+        ),
+        Expression::Assert {
+            left,
+            right,
+            type_name,
+            line,
+        } => {
+            let failed = match right {
+                Some(right) => format!(
+                    "({}.ne {} {})",
+                    type_name,
+                    generate_expression(context, *left),
+                    generate_expression(context, *right)
+                ),
+                None => format!("(i32.eqz {})", generate_expression(context, *left)),
+            };
+
+            format!(
+                "(if
+  {}
+  (then
+    (call $__assert_failed (i32.const {}))
+    unreachable
+  )
+)",
+                failed,
+                line + 1
+            )
+        }
+        Expression::Panic { .. } => String::from("unreachable"),
+        // only the formatter keeps comments and blank lines
+        Expression::Trivia { .. } => String::new(),
+        Expression::TryStatement { body, catch } => {
+            let label = context.try_depth;
+            let body_context = Context {
+                try_depth: label + 1,
+                current_try: Some(label),
+                ..context.clone()
+            };
+            // errors in a catch block go to the try around it
+            let catch_context = Context {
+                try_depth: label + 1,
+                ..context.clone()
+            };
+
+            let body_expressions = body
+                .into_iter()
+                .map(|expression| generate_statement(&body_context, expression))
+                .collect::<Vec<String>>()
+                .join("\n");
+            let catch_expressions = catch
+                .into_iter()
+                .map(|expression| generate_statement(&catch_context, expression))
+                .collect::<Vec<String>>()
+                .join("\n");
+
+            match context.exceptions {
+                Exceptions::Proposal => format!(
+                    "try
+{}catch_all
+{}end",
+                    indent(body_expressions),
+                    indent(catch_expressions)
+                ),
+                Exceptions::ErrorCodes => format!(
+                    "(block $__catch_{label}
+  (block $__try_{label}
+{}
+    (br $__catch_{label})
+  )
+{}
+)",
+                    indent(indent(body_expressions)),
+                    indent(catch_expressions),
+                    label = label
+                ),
+            }
+        }
+        Expression::IfStatement {
+            predicate,
+            success,
+            fail,
+        } => {
+            let success_expressions = success
+                .iter()
+                .map(|expression| generate_statement(context, expression.clone()))
+                .collect::<Vec<String>>()
+                .join("\n");
+
+            // an if without an else doesn't need the arm at all
+            let fail_arm = if fail.is_empty() {
+                String::new()
+            } else {
+                let fail_expressions = fail
+                    .iter()
+                    .map(|expression| generate_statement(context, expression.clone()))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+
+                format!("\n  (else\n{}\n  )", indent(indent(fail_expressions)))
+            };
+
+            format!(
+                "(if
+  {}
+  (then
+{}
+  ){}
+)",
+                generate_expression(context, *predicate),
+                indent(indent(success_expressions)),
+                fail_arm
+            )
+        }
+        Expression::Conditional {
+            predicate,
+            success,
+            fail,
+            ..
+        } if is_pure(&success) && is_pure(&fail) => format!(
+            "(select {} {} {})",
+            generate_expression(context, *success),
+            generate_expression(context, *fail),
+            generate_expression(context, *predicate)
+        ),
+        Expression::Conditional {
+            predicate,
+            success,
+            fail,
+            type_name,
+        } => {
+            // literals nothing gave a type to stay f32
+            let type_name = if type_name.is_empty() {
+                String::from("f32")
+            } else {
+                type_name
+            };
+
+            format!(
+                "(if (result {})
+  {}
+  (then
+{}  )
+  (else
+{}  )
+)",
+                context.value_type(&type_name),
+                generate_expression(context, *predicate),
+                indent(indent(generate_expression(context, *success))),
+                indent(indent(generate_expression(context, *fail)))
+            )
+        }
+        Expression::MatchStatement {
+            subject,
+            type_name,
+            arms,
+            default,
+            ..
+        } => generate_match(
+            context,
+            *subject,
+            &type_name,
+            arms,
+            default.unwrap_or_default(),
+        ),
+        Expression::Boolean { value } => {
+            if value {
+                "(i32.const 1)".to_string()
+            } else {
+                "(i32.const 0)".to_string()
+            }
+        }
+        Expression::Character { value } => format!("(i32.const {})", value as u32),
+        Expression::ForStatement {
+            initial_value,
+            break_condition,
+            incrementor,
+            direction,
+            body,
+        } => {
+            let body_expressions = body
+                .iter()
+                .map(|expression| generate_statement(context, expression.clone()))
+                .collect::<Vec<String>>()
+                .join("\n");
+
+            let variable_name = match *initial_value.clone() {
+                Expression::LocalAssign {
+                    name,
+                    type_name: _,
+                    expression: _,
+                    ..
+                } => name,
+                _ => return String::from(""),
+            };
+
+            let type_name = match *initial_value.clone() {
+                Expression::LocalAssign {
+                    name: _,
+                    type_name,
+                    expression: _,
+                    ..
+                } => type_name,
+                _ => return String::from("i32"),
+            };
+
+            // floats compare without a sign suffix
+            let sign = match type_name.as_str() {
+                "f32" | "f64" => "",
+                _ => "_s",
+            };
+            let (step, compare) = match direction {
+                Direction::Up => ("add", "lt"),
+                Direction::Down => ("sub", "gt"),
+            };
+            let check = Check::Overflow {
+                type_name: type_name.to_string(),
+                direction,
+            };
+            let step = match context.call_check(&check) {
+                Some(call) if type_name == "i32" || type_name == "i64" => {
+                    call.replace('\n', "\n  ")
+                }
+                _ => format!("({}.{})", type_name, step),
+            };
+
+            format!(
+                "{}
+(loop $loop
+{}
+  (local.get ${variable_name})
+  {incrementor}
+  {step}
+  (local.set ${variable_name})
+
+  (local.get ${variable_name})
+  {break_condition}
+  ({type_name}.{compare}{sign})
+  (br_if $loop)
+)",
+                generate_expression(context, *initial_value),
+                indent(body_expressions),
+                incrementor = generate_expression(context, *incrementor),
+                variable_name = variable_name,
+                break_condition = generate_expression(context, *break_condition),
+                type_name = type_name
+            )
+        }
+    }
+}
+
+/// Whether an expression can be run without effects, so that a conditional
+/// can work out both of its values and `select` one
+fn is_pure(expression: &Expression) -> bool {
+    match expression {
+        Expression::Number { .. }
+        | Expression::Variable { .. }
+        | Expression::Constant { .. }
+        | Expression::Boolean { .. }
+        | Expression::Character { .. }
+        | Expression::DataField { .. } => true,
+        Expression::Addition { left, right, .. } => is_pure(left) && is_pure(right),
+        Expression::Conditional {
+            predicate,
+            success,
+            fail,
+            ..
+        } => is_pure(predicate) && is_pure(success) && is_pure(fail),
+        _ => false,
+    }
+}
+
+/// Matches with fewer arms than this pick them with ifs
+const JUMP_TABLE_MIN_ARMS: usize = 3;
+
+/// Whether a match jumps straight to its arm through a `br_table`, which is
+/// when there are enough arms and they fill at least half of the numbers
+/// between the smallest and largest pattern
+fn uses_jump_table(type_name: &str, arms: &[(i64, Vec<Expression>)]) -> bool {
+    let patterns = arms.iter().map(|(pattern, _)| *pattern);
+
+    match (patterns.clone().min(), patterns.max()) {
+        (Some(min), Some(max)) => {
+            type_name == "i32"
+                && arms.len() >= JUMP_TABLE_MIN_ARMS
+                && max
+                    .checked_sub(min)
+                    .is_some_and(|span| span < 2 * arms.len() as i64)
+        }
+        _ => false,
+    }
+}
+
+fn generate_match(
+    context: &Context,
+    subject: Expression,
+    type_name: &str,
+    arms: Vec<(i64, Vec<Expression>)>,
+    default: Vec<Expression>,
+) -> String {
+    let label = context.match_depth;
+    let arm_context = Context {
+        match_depth: label + 1,
+        ..context.clone()
+    };
+    let generate_body = |body: Vec<Expression>| {
+        body.into_iter()
+            .map(|expression| generate_statement(&arm_context, expression))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+    let subject = generate_expression(context, subject);
+
+    if !uses_jump_table(type_name, &arms) {
+        let local = format!("__match_{}", type_name);
+
+        let chain =
+            arms.into_iter()
+                .rev()
+                .fold(generate_body(default), |otherwise, (pattern, body)| {
+                    format!(
+                        "(if
+  ({type_name}.eq (local.get ${local}) ({type_name}.const {pattern}))
+  (then
+{}
+  )
+  (else
+{}
+  )
+)",
+                        indent(indent(generate_body(body))),
+                        indent(indent(otherwise)),
+                        type_name = type_name,
+                        local = local,
+                        pattern = pattern
+                    )
+                });
+
+        return format!("(local.set ${} {})\n{}", local, subject, chain);
+    }
+
+    let min = arms
+        .iter()
+        .map(|(pattern, _)| *pattern)
+        .min()
+        .unwrap_or_default();
+    let max = arms
+        .iter()
+        .map(|(pattern, _)| *pattern)
+        .max()
+        .unwrap_or_default();
+    let targets = (min..=max)
+        .map(
+            |value| match arms.iter().position(|(pattern, _)| *pattern == value) {
+                Some(index) => format!("$__match_{}_{}", label, index),
+                None => format!("$__match_{}_default", label),
+            },
+        )
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    // each arm's block ends where its code starts, so jumping out of the
+    // innermost block runs the first arm
+    let jump = format!(
+        "(br_table {} $__match_{label}_default\n  (i32.sub {} (i32.const {})))",
+        targets,
+        subject,
+        min,
+        label = label
+    );
+    let arms = arms
+        .into_iter()
+        .enumerate()
+        .fold(jump, |inner, (index, (_, body))| {
+            format!(
+                "(block $__match_{label}_{}
+{})
+{}
+(br $__match_{label})",
+                index,
+                indent(inner),
+                generate_body(body),
+                label = label
+            )
+        });
+
+    format!(
+        "(block $__match_{label}
+  (block $__match_{label}_default
+{}  )
+{})",
+        indent(indent(arms)),
+        indent(generate_body(default)),
+        label = label
+    )
+}
+
+fn generate_function(context: &Context, function: Function) -> Result<String, CompileError> {
+    let context = &Context {
+        memory: function.memory_name().map(str::to_string),
+        function: function.name.to_string(),
+        ..context.clone()
+    };
+    let params: String = if function.params.is_empty() {
+        String::from("")
+    } else {
+        String::from(" ")
+            + &function
+                .params
+                .clone()
+                .into_iter()
+                .map(|param| generate_param(context, param))
+                .collect::<Vec<String>>()
+                .join(" ")
+    };
 
     let return_value: String = if function.return_type == *"void" {
         String::from("")
     } else {
-        format!(" (result {})", function.return_type)
+        format!(" (result {})", context.value_type(&function.return_type))
+    };
+
+    let locals = define_locals(context, function.expressions.clone());
+
+    let export_name = function.export_name();
+
+    let (memory, extracted_expressions) = if context.gc {
+        (None, function.expressions)
+    } else {
+        extract_strings(context, &function.name, function.expressions)?
+    };
+
+    let counter = match context.instrument {
+        Some(instrument) => generate_counter(context, instrument, &function.name),
+        None => vec![],
+    };
+
+    let ending = function_ending(&function.return_type, extracted_expressions.last());
+    let last = extracted_expressions.len().saturating_sub(1);
+
+    let mark = |mark: String| {
+        context
+            .mark_statements
+            .then(|| STATEMENT_MARK.to_string() + &mark)
+    };
+
+    let expressions = counter
+        .into_iter()
+        .chain(
+            extracted_expressions
+                .into_iter()
+                .enumerate()
+                .flat_map(|(index, expression)| {
+                    // the value is left for the end of the function to return
+                    let code = if index == last && ending == Ending::FallsOff {
+                        generate_expression(context, expression)
+                    } else {
+                        generate_statement(context, expression)
+                    };
+
+                    mark(format!("{} {}", function.name, index))
+                        .into_iter()
+                        .chain([code])
+                }),
+        )
+        .chain((ending == Ending::Unreachable).then(|| String::from("unreachable")))
+        .chain(mark(String::from("-")))
+        .map(|line| format!("{}\n", line))
+        .collect::<Vec<String>>()
+        .join("");
+
+    let definitions = if locals.is_empty() {
+        indent(expressions)
+    } else {
+        indent(format!("{}\n{}", locals, expressions))
+    };
+
+    let maybe_memory = memory.unwrap_or_default();
+
+    let maybe_export = match export_name {
+        Some(external_name) => format!(
+            "\n{}",
+            generate_export(
+                context,
+                Export {
+                    external_name,
+                    function_name: function.name.to_string(),
+                    info: None,
+                },
+            )
+        ),
+        None => String::new(),
+    };
+
+    Ok(format!(
+        "{}(func ${}{}{}
+{}){}",
+        maybe_memory, function.name, params, return_value, definitions, maybe_export
+    ))
+}
+
+/// An expression written as a statement. A call's result is dropped when
+/// nothing uses it, so that the stack is left as it was
+fn generate_statement(context: &Context, expression: Expression) -> String {
+    let results = match &expression {
+        Expression::FunctionCall { name, .. } => context.result_count(name),
+        _ => 0,
+    };
+
+    let returning = returns(&expression);
+    let code = generate_expression(context, expression);
+
+    if returning {
+        format!("{}\n(return)", code)
+    } else if results == 0 {
+        code
+    } else {
+        format!("{}{}", code, "\n(drop)".repeat(results))
+    }
+}
+
+/// Whether a statement returns, which `return x + y` does through the left
+/// of the addition
+fn returns(expression: &Expression) -> bool {
+    match expression {
+        Expression::Return { .. } => true,
+        Expression::Addition { left, .. } => returns(left),
+        _ => false,
+    }
+}
+
+#[derive(PartialEq)]
+enum Ending {
+    /// The last statement is a return, whose value can be left on the stack
+    FallsOff,
+    /// Control flow which returns from inside itself, which wasm can't tell
+    /// always returns
+    Unreachable,
+    Plain,
+}
+
+fn function_ending(return_type: &str, last: Option<&Expression>) -> Ending {
+    match last {
+        Some(expression) if returns(expression) => Ending::FallsOff,
+        Some(
+            Expression::IfStatement { .. }
+            | Expression::ForStatement { .. }
+            | Expression::TryStatement { .. }
+            | Expression::MatchStatement { .. },
+        ) if return_type != "void" => Ending::Unreachable,
+        _ => Ending::Plain,
+    }
+}
+
+/// What a function does first when it's instrumented
+fn generate_counter(context: &Context, instrument: Instrument, name: &str) -> Vec<String> {
+    let id = context
+        .function_names
+        .iter()
+        .position(|function_name| function_name == name)
+        .unwrap_or_default();
+    let offset = id * 4;
+
+    let count = format!(
+        "(i32.store $__calls (i32.const {}) (i32.add (i32.load $__calls (i32.const {})) (i32.const 1)))",
+        offset, offset
+    );
+
+    match instrument {
+        Instrument::Calls => vec![count],
+        Instrument::Trace => vec![count, format!("(call $__trace (i32.const {}))", id)],
+    }
+}
+
+/// The memory calls are counted in, and the trace import
+fn generate_instrumentation(
+    context: &Context,
+    instrument: Instrument,
+) -> Result<Vec<String>, CompileError> {
+    let (module, name) = TRACE_IMPORT;
+    let names = context.function_names.join("\n");
+    let names_offset = context.function_names.len() * 4;
+    let length = names_offset + names.len();
+
+    // the memory is a single page
+    if length > 65536 {
+        return Err(CompileError::new(String::from(
+            "There are too many functions to count their calls",
+        )));
+    }
+
+    let memory = format!(
+        "(memory $__calls (export \"__calls\") 1)\n(data (memory $__calls) (i32.const {}) \"{}\")",
+        names_offset,
+        escape_bytes(names.as_bytes())
+    );
+
+    Ok(match instrument {
+        Instrument::Calls => vec![memory],
+        Instrument::Trace => vec![
+            format!(
+                "(import \"{}\" \"{}\" (func $__trace (param i32)))",
+                module, name
+            ),
+            memory,
+        ],
+    })
+}
+
+fn generate_export(context: &Context, export: Export) -> String {
+    let function_name = if context.string_exports.contains(&export.function_name) {
+        format!("__export_{}", export.function_name)
+    } else {
+        export.function_name
+    };
+
+    format!(
+        "(export \"{}\" (func ${}))",
+        export.external_name, function_name
+    )
+}
+
+fn generate_import_function(context: &Context, import: ImportFunction) -> String {
+    let params: Vec<String> = import
+        .params
+        .into_iter()
+        .map(|param| context.value_type(&param.type_name))
+        .collect();
+    let external_name = import
+        .external_name
+        .iter()
+        .map(|name| format!("\"{}\"", escape_bytes(name.as_bytes())))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let result = match context.exceptions {
+        Exceptions::Proposal => "",
+        Exceptions::ErrorCodes => " (result i32)",
+    };
+
+    format!(
+        "(import {} (func ${} (param {}){}))",
+        external_name,
+        import.name,
+        params.join(" "),
+        result
+    )
+}
+
+fn generate_import_memory(context: &Context, import: ImportMemory) -> Result<String, CompileError> {
+    let external_name = import
+        .external_name
+        .iter()
+        .map(|name| format!("\"{}\"", escape_bytes(name.as_bytes())))
+        .collect::<Vec<String>>()
+        .join(" ");
+    Ok(format!(
+        "(import {} (memory {}{}))",
+        external_name,
+        generate_memory_name(&import.name),
+        import.size.pages(&context.constants)?
+    ))
+}
+
+fn generate_import_table(import: ImportTable) -> String {
+    let external_name = import
+        .external_name
+        .iter()
+        .map(|name| format!("\"{}\"", escape_bytes(name.as_bytes())))
+        .collect::<Vec<String>>()
+        .join(" ");
+    format!(
+        "(import {} (table {} {}))",
+        external_name, import.size, import.element_type
+    )
+}
+
+fn generate_memory_name(name: &Option<String>) -> String {
+    match name {
+        Some(name) => format!("${} ", name),
+        None => String::new(),
+    }
+}
+
+fn generate_memory(context: &Context, memory: Memory) -> Result<String, CompileError> {
+    Ok(format!(
+        "(memory {}{})",
+        generate_memory_name(&memory.name),
+        memory.size.pages(&context.constants)?
+    ))
+}
+
+fn generate_block(context: &Context, block: Block) -> Result<String, CompileError> {
+    match block {
+        Block::Function(function) => generate_function(context, function),
+        Block::Export(export) => Ok(generate_export(context, export)),
+        Block::ImportFunction(import) => Ok(generate_import_function(context, import)),
+        Block::ImportMemory(import) => generate_import_memory(context, import),
+        Block::ImportTable(import) => Ok(generate_import_table(import)),
+        Block::Memory(memory) => generate_memory(context, memory),
+        // laid out in memory along with the module's other data
+        Block::Data(_) => Ok(String::new()),
+        // inlined wherever they're used
+        Block::Constant(_) => Ok(String::new()),
+        // only names for other types, which have already been resolved
+        Block::TypeAlias(_) => Ok(String::new()),
+        // the module's blocks were added to the program when it was parsed
+        Block::Use(_) | Block::Interface(_) => Ok(String::new()),
+        // tests are only built into the module made by `gwe test`
+        Block::Test(_) => Ok(String::new()),
+        // only the formatter keeps comments and blank lines
+        Block::Trivia(_) => Ok(String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{blocks::Data, errors::join_errors, options::Profile, parser::parse, typechecker};
+
+    use super::*;
+
+    #[test]
+    fn empty_function() {
+        let input = String::from(
+            "fn hello_world(): void {
+}",
+        );
+
+        let output = String::from(
+            "(module
+  (func $hello_world
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_with_an_arg_function() {
+        let input = String::from(
+            "fn hello_world(name: string): void {
+}",
+        );
+
+        let output = String::from(
+            "(module
+  (func $hello_world (param $name i32) (param $name__length i32)
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_with_several_args_function() {
+        let input = String::from(
+            "fn hello_world(name: string, age: i32): void {
+}",
+        );
+        let output = String::from(
+            "(module
+  (func $hello_world (param $name i32) (param $name__length i32) (param $age i32)
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn return_function() {
+        let input = String::from(
+            "fn hello_world(name: string): string {
+    return name;
+}",
+        );
+        let output = String::from(
+            "(module
+  (func $hello_world (param $name i32) (param $name__length i32) (result i32 i32)
+    (local.get $name)
+    (local.get $name__length)
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn local_var_and_addition_function() {
+        let input = String::from(
+            "fn hello_world(name: i32): i32 {
+    local message: i32 = name;
+    return message;
+}",
+        );
+        let output = String::from(
+            "(module
+  (func $hello_world (param $name i32) (result i32)
+    (local $message i32)
+    (local.set $message (local.get $name))
+    (local.get $message)
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn global_var_and_addition_function() {
+        let input = String::from(
+            "fn hello_world(): void {
+    global num: f32 = 123 + 3.14;
+}",
+        );
+        let output = String::from(
+            "(module
+  (global $num (mut f32) (f32.const 0))
+  (func $hello_world
+    (global.set $num (f32.add (f32.const 123) (f32.const 3.14)))
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn locals_in_nested_blocks_are_declared_once() {
+        let input = String::from(
+            "import fn log(number: i32) console.log
+
+fn main(n: i32): void {
+    if (n) {
+        local x: i32 = 1;
+        log(x);
+    } else {
+        local x: i32 = 2;
+        log(x);
+    };
+    for (local i: i32 = 0, 10, 1) {
+        local y: i32 = 3;
+        log(y);
+    };
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                let output = generate(program).unwrap();
+
+                assert_eq!(output.matches("(local $x i32)").count(), 1);
+                assert!(output.contains("(local $i i32)"));
+                assert!(output.contains("(local $y i32)"));
+            }
+        }
+    }
+
+    #[test]
+    fn globals_are_declared_once_across_functions() {
+        let input = String::from(
+            "fn first(): void {
+    global count: f32 = 1;
+}
+
+fn second(): void {
+    if (true) {
+        global count: f32 = 2;
+    } else {
+        global ready: i32 = true;
+    }
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                let output = generate(program).unwrap();
+
+                assert_eq!(output.matches("(global $count").count(), 1);
+                assert!(output.contains("(global $count (mut f32) (f32.const 1))"));
+                assert!(output.contains("(global $ready (mut i32) (i32.const 1))"));
+            }
+        }
+    }
+
+    #[test]
+    fn globals_with_conflicting_types_error() {
+        let input = String::from(
+            "fn first(): void {
+    global count: f32 = 1;
+}
+
+fn second(): void {
+    global count: i32 = 2;
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(
+                    generate(program),
+                    Err(CompileError::new(String::from(
+                        "Global count is declared as both f32 and i32"
+                    )))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn export_function() {
+        let input = String::from(
+            "fn hello_world(): f32 {
+    return 3.14;
+}
+
+export helloWorld hello_world",
+        );
+        let output = String::from(
+            "(module
+  (func $hello_world (result f32)
+    (f32.const 3.14)
+  )
+  (export \"helloWorld\" (func $hello_world))
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn import_function() {
+        let input = String::from("import fn log(number: i32) console.log");
+        let output = String::from(
+            "(module
+  (import \"console\" \"log\" (func $log (param i32)))
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn import_with_quoted_names() {
+        let input = String::from(
+            "import fn fd_write(fd: i32) \"wasi-snapshot/preview1\".fd_write
+import memory 1 \"./env.js\".\"memory\"",
+        );
+        let output = String::from(
+            "(module
+  (import \"wasi-snapshot/preview1\" \"fd_write\" (func $fd_write (param i32)))
+  (import \"./env.js\" \"memory\" (memory 1))
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn unused_results_are_dropped() {
+        let input = String::from(
+            "memory 1
+
+fn one(): i32 {
+    return 1;
+}
+
+fn name(): string {
+    return \"gwe\";
+}
+
+fn main(n: i32): void {
+    one();
+    if (n) {
+        name();
+    } else {
+    };
+    local kept: i32 = one();
+}",
+        );
+
+        let program = match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => program,
+        };
+        let generated = generate(program).unwrap();
+
+        assert_eq!(
+            generated,
+            "(module
+  (memory 1)
+  (data (i32.const 0) \"gwe\")
+  (func $one (result i32)
+    (i32.const 1)
+  )
+  (func $name (result i32 i32)
+    (i32.const 0)
+    (i32.const 3)
+  )
+  (func $main (param $n i32)
+    (local $kept i32)
+    (call $one)
+    (drop)
+    (if
+      (local.get $n)
+      (then
+        (call $name)
+        (drop)
+        (drop)
+      )
+    )
+    (local.set $kept 
+    (call $one))
+  )
+)"
+        );
+        assert_eq!(validate(&generated), Ok(()));
+    }
+
+    #[test]
+    fn call_function() {
+        let input = String::from(
+            "import fn log(number: i32) console.log
+
+fn main(): void {
+    log(3);
+}
+
+export main main",
+        );
+        let output = String::from(
+            "(module
+  (import \"console\" \"log\" (func $log (param i32)))
+  (func $main
+    (i32.const 3)
+    (call $log)
+  )
+  (export \"main\" (func $main))
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn call_with_a_fraction_for_an_integer_param_errors() {
+        let input = String::from(
+            "import fn log(number: i32) console.log
+
+fn main(): void {
+    log(3.14);
+}",
+        );
+
+        assert_eq!(
+            parse(input)
+                .map(|_| ())
+                .map_err(|errors| join_errors(&errors)),
+            Err(String::from(
+                "log expects number to be i32 but got 3.14 at line 4, index 4"
+            ))
+        );
+    }
+
+    #[test]
+    fn call_with_the_wrong_number_of_args_errors() {
+        let input = String::from(
+            "import fn log(number: i32) console.log
+
+fn main(x: f32): void {
+    log(1, 2);
+    log(x);
+}",
+        );
+
+        assert_eq!(
+            parse(input)
+                .map(|_| ())
+                .map_err(|errors| join_errors(&errors)),
+            Err(String::from(
+                "log expects 1 argument but got 2 at line 4, index 4\nlog expects number to be i32 but got x, which is f32 at line 5, index 4"
+            ))
+        );
+    }
+
+    #[test]
+    fn number_literals_take_their_type_from_context() {
+        let input = String::from(
+            "fn five(n: i32): i32 {
+    local x: i64 = 5;
+    return 5;
+}
+
+fn main(): i32 {
+    return five(5);
+}",
+        );
+        let output = String::from(
+            "(module
+  (func $five (param $n i32) (result i32)
+    (local $x i64)
+    (local.set $x (i64.const 5))
+    (i32.const 5)
+  )
+  (func $main (result i32)
+    (i32.const 5)
+    (call $five)
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn returning_a_fraction_from_an_integer_function_errors() {
+        let input = String::from(
+            "fn main(): i32 {
+    return 1.5;
+}",
+        );
+
+        assert_eq!(
+            parse(input)
+                .map(|_| ())
+                .map_err(|errors| join_errors(&errors)),
+            Err(String::from(
+                "main returns i32 but got 1.5 at line 2, index 4"
+            ))
+        );
+    }
+
+    #[test]
+    fn type_aliases_become_their_primitive() {
+        let input = String::from(
+            "type Seconds = i32;
+
+fn wait(delay: Seconds): Seconds {
+    return 2;
+}",
+        );
+        let output = String::from(
+            "(module
+  (func $wait (param $delay i32) (result i32)
+    (i32.const 2)
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn export_attribute() {
+        let input = String::from(
+            "@export
+fn one(): i32 {
+    return 1;
+}
+
+@export(\"twoAndMore\")
+fn two(): i32 {
+    return 2;
+}",
+        );
+        let output = String::from(
+            "(module
+  (func $one (result i32)
+    (i32.const 1)
+  )
+  (func $two (result i32)
+    (i32.const 2)
+  )
+  (export \"one\" (func $one))
+  (export \"twoAndMore\" (func $two))
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn asserts() {
+        let input = String::from(
+            "fn check(x: i32): void {
+    assert(x);
+    assert_eq(x, 2);
+}",
+        );
+        let output = String::from(
+            "(module
+  (import \"gwe\" \"assert_failed\" (func $__assert_failed (param i32)))
+  (func $check (param $x i32)
+    (if
+      (i32.eqz (local.get $x))
+      (then
+        (call $__assert_failed (i32.const 2))
+        unreachable
+      )
+    )
+    (if
+      (i32.ne (local.get $x) (i32.const 2))
+      (then
+        (call $__assert_failed (i32.const 3))
+        unreachable
+      )
+    )
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+    }
+
+    #[test]
+    fn assert_eq_needs_matching_numbers() {
+        let input = String::from(
+            "fn check(x: i32, y: f32, name: string): void {
+    assert_eq(x, y);
+    assert_eq(name, name);
+    assert_eq(x, 1.5);
+    assert(x, y);
+}",
+        );
+
+        assert_eq!(
+            parse(input)
+                .map(|_| ())
+                .map_err(|errors| join_errors(&errors)),
+            Err(String::from("assert takes 1 argument at line 5, index 10"))
+        );
+
+        let input = String::from(
+            "fn check(x: i32, y: f32, name: string): void {
+    assert_eq(x, y);
+    assert_eq(name, name);
+    assert_eq(x, 1.5);
+}",
+        );
+
+        assert_eq!(
+            parse(input)
+                .map(|_| ())
+                .map_err(|errors| join_errors(&errors)),
+            Err(String::from(
+                "assert_eq compares i32 with f32 at line 2, index 0\nassert_eq can only compare numbers, not string at line 3, index 0\nassert_eq compares i32 but got 1.5 at line 4, index 0"
+            ))
+        );
+    }
+
+    #[test]
+    fn panic_traps() {
+        let input = String::from(
+            "fn fail(): i32 {
+    panic(\"never\");
+}",
+        );
+        let output = String::from(
+            "(module
+  (func $fail (result i32)
+    unreachable
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+
+        assert_eq!(
+            parse(String::from("fn fail(): void {\n    panic(1);\n}"))
+                .map(|_| ())
+                .map_err(|errors| join_errors(&errors)),
+            Err(String::from(
+                "panic takes a message in quotes at line 2, index 9"
+            ))
+        );
+    }
+
+    #[test]
+    fn panic_calls_the_handler_with_its_message() {
+        let input = String::from(
+            "import fn on_panic(offset: i32, length: i32) gwe.panic
+import fn log(offset: i32, length: i32) console.log
+import memory 1 js.mem
+
+fn check(x: i32): void {
+    local name: string = \"check\";
+    log();
+    if (x) {
+        panic(\"x was set\");
+    } else {
     };
+}",
+        );
+        let output = String::from(
+            "(module
+  (import \"gwe\" \"panic\" (func $on_panic (param i32 i32)))
+  (import \"console\" \"log\" (func $log (param i32 i32)))
+  (import \"js\" \"mem\" (memory 1))
+  (data (i32.const 0) \"check\")
+  (data (i32.const 5) \"x was set\")
+  (func $check (param $x i32)
+    (i32.const 0)
+    (i32.const 5)
+    (call $log)
+    (if
+      (local.get $x)
+      (then
+        (i32.const 5)
+        (i32.const 9)
+        (call $on_panic)
+        unreachable
+      )
+    )
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+
+        let input = String::from(
+            "import fn on_panic(offset: i32, length: i32) gwe.panic
+
+fn fail(): void {
+    panic(\"never\");
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(
+                    generate(program),
+                    Err(CompileError::new(String::from(
+                        "Panic messages are passed to on_panic through memory, add an import memory"
+                    )))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn named_memories() {
+        let input = String::from(
+            "import fn log(offset: i32, length: i32) console.log
+import memory heap 1 js.heap
+memory scratch 1
+
+@memory(\"scratch\")
+fn clear(): void {
+    local message: string = \"Hi\";
+    log();
+    memory_fill(0, 0, 4);
+    memory_copy(0, 4, 4);
+}
+
+fn grow(): i32 {
+    return memory_grow(1);
+}",
+        );
+        let output = String::from(
+            "(module
+  (import \"console\" \"log\" (func $log (param i32 i32)))
+  (import \"js\" \"heap\" (memory $heap 1))
+  (memory $scratch 1)
+  (data (memory $scratch) (i32.const 0) \"Hi\")
+  (func $clear
+    (i32.const 0)
+    (i32.const 2)
+    (call $log)
+    (i32.const 0)
+    (i32.const 0)
+    (i32.const 4)
+    (memory.fill $scratch)
+    (i32.const 0)
+    (i32.const 4)
+    (i32.const 4)
+    (memory.copy $scratch $scratch)
+  )
+  (func $grow (result i32)
+    (i32.const 1)
+    (memory.grow)
+  )
+)",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn strings_cross_the_host_boundary_as_offset_and_length() {
+        let input = String::from(
+            "import fn log(message: string) console.log
+import memory 1 js.mem
+
+fn echo(name: string): string {
+    log(name);
+    return name;
+}
+
+export echo echo",
+        );
+        let output = String::from(
+            "(module
+  (import \"console\" \"log\" (func $log (param i32 i32)))
+  (import \"js\" \"mem\" (memory 1))
+  (global $__string_buffer i32 (i32.const 0))
+  (global $__string_buffer_length i32 (i32.const 1024))
+  (func $echo (param $name i32) (param $name__length i32) (result i32 i32)
+    (local.get $name)
+    (local.get $name__length)
+    (call $log)
+    (local.get $name)
+    (local.get $name__length)
+  )
+  (func $__export_echo (param $name i32) (param $name__length i32) (result i32)
+    (local $__offset i32)
+    (local $__length i32)
+    (local.get $name)
+    (local.get $name__length)
+    (call $echo)
+    (local.set $__length)
+    (local.set $__offset)
+    (if
+      (i32.gt_u (local.get $__length) (global.get $__string_buffer_length))
+      (then
+        unreachable
+      )
+    )
+    (memory.copy (global.get $__string_buffer) (local.get $__offset) (local.get $__length))
+    (local.get $__length)
+  )
+  (export \"echo\" (func $__export_echo))
+  (export \"__string_buffer\" (global $__string_buffer))
+  (export \"__string_buffer_length\" (global $__string_buffer_length))
+  (export \"__memory\" (memory 0))
+)",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
+            }
+        }
+
+        assert_eq!(
+            generate(
+                parse(String::from(
+                    "@export
+fn name(): string {
+    return \"gwe\";
+}"
+                ))
+                .unwrap()
+            ),
+            Err(CompileError::new(String::from(
+                "Strings are passed to and from the host through memory, add an import memory"
+            )))
+        );
+    }
+
+    #[test]
+    fn import_table() {
+        let input = String::from(
+            "import table 10 funcref js.table
+import table 2 externref js.objects",
+        );
+        let output = String::from(
+            "(module
+  (import \"js\" \"table\" (table 10 funcref))
+  (import \"js\" \"objects\" (table 2 externref))
+)",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn data_blocks() {
+        let input = String::from(
+            "import fn log(offset: i32, length: i32) console.log
+import memory 1 js.mem
+data \"logo.bin\" as logo
+data \"icon.bin\" as icon
+
+fn main(): void {
+    log(icon.offset, icon.length);
+    alloc(1);
+}",
+        );
+        let output = String::from(
+            "(module
+  (import \"console\" \"log\" (func $log (param i32 i32)))
+  (import \"js\" \"mem\" (memory 1))
+  (global $__heap (mut i32) (i32.const 8))
+  (data (i32.const 0) \"\\00\\01\\02\\03\")
+  (data (i32.const 4) \"\\22a\")
+  (func $main
+    (i32.const 4)
+    (i32.const 2)
+    (call $log)
+    (i32.const 1)
+    (call $alloc)
+    (drop)
+  )",
+        );
+
+        let program = match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => program,
+        };
+        let loaded = Program {
+            blocks: program
+                .blocks
+                .iter()
+                .map(|block| match block {
+                    Block::Data(data) => Block::Data(Data {
+                        bytes: if data.name == "logo" {
+                            vec![0, 1, 2, 3]
+                        } else {
+                            vec![b'"', b'a']
+                        },
+                        ..data.clone()
+                    }),
+                    block => block.clone(),
+                })
+                .collect(),
+        };
+
+        let generated = generate(loaded).unwrap();
+        assert!(generated.starts_with(&output));
+        assert!(wat::parse_str(&generated).is_ok());
+
+        assert_eq!(
+            generate(parse(String::from("data \"logo.bin\" as logo")).unwrap()),
+            Err(CompileError::new(String::from(
+                "Data logo is stored in memory, add an import memory"
+            )))
+        );
+    }
+
+    #[test]
+    fn size_passes() {
+        let program = parse(String::from(
+            "import memory 1 js.mem
+data \"a.bin\" as a
+data \"b.bin\" as b
+
+fn unused(): i32 {
+    return 1;
+}
+
+fn two(): i32 {
+    return b.offset;
+}
+
+@export
+fn main(): i32 {
+    return two();
+}",
+        ))
+        .unwrap();
+        let loaded = Program {
+            blocks: program
+                .blocks
+                .into_iter()
+                .map(|block| match block {
+                    Block::Data(data) => Block::Data(Data {
+                        bytes: vec![1, 2],
+                        ..data
+                    }),
+                    block => block,
+                })
+                .collect(),
+        };
+
+        let generated = generate_with(
+            loaded,
+            &GenerateOptions {
+                passes: Passes::profile(Profile::Size),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            generated,
+            "(module
+  (import \"js\" \"mem\" (memory 1))
+  (data (i32.const 0) \"\\01\\02\")
+  (func $two (result i32)
+    (i32.const 0)
+  )
+  (func $main (result i32)
+    (call $two)
+  )
+  (export \"main\" (func $main))
+)"
+        );
+
+        let wasm = wat::parse_str(&generated).unwrap();
+        let stripped = strip_names(&wasm);
+        assert!(stripped.len() < wasm.len());
+        assert!(!stripped.windows(4).any(|bytes| bytes == b"name"));
+        assert!(wasmparser::validate(&stripped).is_ok());
+    }
+
+    #[test]
+    fn custom_sections_are_added_at_the_end() {
+        let wasm = wat::parse_str("(module (func $main))").unwrap();
+        let contents = "x".repeat(200);
+        let added = add_custom_section(&wasm, "gwe", contents.as_bytes());
+
+        assert!(wasmparser::validate(&added).is_ok());
+        assert!(added.starts_with(&wasm));
+        assert_eq!(added[wasm.len()..wasm.len() + 3], [0, 0xcc, 0x01]);
+        assert!(added.ends_with(contents.as_bytes()));
+        assert_eq!(
+            strip_names(&added),
+            add_custom_section(&strip_names(&wasm), "gwe", contents.as_bytes())
+        );
+    }
+
+    #[test]
+    fn memory_builtins() {
+        let input = String::from(
+            "import memory 1 js.mem
+
+fn main(): i32 {
+    memory_fill(0, 255, 8);
+    memory_copy(8, 0, 8);
+    return memory_grow(1);
+}",
+        );
+        let output = String::from(
+            "(module
+  (import \"js\" \"mem\" (memory 1))
+  (func $main (result i32)
+    (i32.const 0)
+    (i32.const 255)
+    (i32.const 8)
+    (memory.fill)
+    (i32.const 8)
+    (i32.const 0)
+    (i32.const 8)
+    (memory.copy)
+    (i32.const 1)
+    (memory.grow)
+  )
+)",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output));
+            }
+        }
+
+        let input = String::from(
+            "fn main(): void {
+    memory_fill(0, 1.5, 8);
+}",
+        );
+
+        assert_eq!(
+            parse(input)
+                .map(|_| ())
+                .map_err(|errors| join_errors(&errors)),
+            Err(String::from(
+                "memory_fill expects val to be i32 but got 1.5 at line 2, index 4"
+            ))
+        );
+
+        let input = String::from(
+            "fn main(): i32 {
+    return memory_grow(1);
+}",
+        );
+
+        match parse(input.clone()) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(
+                    generate(program),
+                    Err(CompileError::new(String::from(
+                        "memory_grow needs a memory, add an import memory"
+                    )))
+                );
+            }
+        }
+    }
 
-    let locals = define_locals(function.expressions.clone());
+    #[test]
+    fn runtime_is_included_when_called() {
+        let input = String::from(
+            "import fn log(offset: i32, length: i32) console.log
+import memory 1 js.mem
 
-    let (memory, extracted_expressions) = extract_strings(function.expressions);
+fn main(): i32 {
+    local message: string = \"Hello world\";
+    log();
+    return alloc(4);
+}",
+        );
 
-    let expressions = extracted_expressions
-        .into_iter()
-        .map(generate_expression)
-        .map(|line| format!("{}\n", line))
-        .collect::<Vec<String>>()
-        .join("");
+        let program = match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => program,
+        };
 
-    let definitions = if locals.is_empty() {
-        indent(expressions)
-    } else {
-        indent(format!("{}\n{}", locals, expressions))
-    };
+        // the heap starts after the string, aligned to 8 bytes
+        let wat = generate(program.clone()).unwrap();
+        assert!(wat.contains("(global $__heap (mut i32) (i32.const 16))"));
+        assert!(wat.contains("(func $alloc (param $size i32) (result i32)"));
+        assert!(wat.contains("(func $reset"));
 
-    let maybe_memory = match memory {
-        Some(inner) => inner,
-        None => String::from(""),
-    };
+        assert_eq!(
+            generate_with(
+                program,
+                &GenerateOptions {
+                    runtime: false,
+                    ..GenerateOptions::default()
+                }
+            ),
+            Err(CompileError::new(String::from(
+                "alloc is part of the runtime, which is turned off"
+            )))
+        );
+    }
 
-    format!(
-        "{}(func ${}{}{}
-{})",
-        maybe_memory, function.name, params, return_value, definitions
-    )
+    #[test]
+    fn runtime_is_left_out_when_unused_or_defined() {
+        let input = String::from(
+            "fn alloc(size: i32): i32 {
+    return size;
 }
 
-fn generate_export(export: Export) -> String {
-    format!(
-        "(export \"{}\" (func ${}))",
-        export.external_name, export.function_name
-    )
-}
+fn main(): i32 {
+    return alloc(4);
+}",
+        );
 
-fn generate_import_function(import: ImportFunction) -> String {
-    let params: Vec<String> = import
-        .params
-        .into_iter()
-        .map(|param| param.type_name)
-        .collect();
-    let external_name = import
-        .external_name
-        .iter()
-        .map(|n| format!("\"{}\"", n))
-        .collect::<Vec<String>>()
-        .join(" ");
-    format!(
-        "(import {} (func ${} (param {})))",
-        external_name,
-        import.name,
-        params.join(" "),
-    )
-}
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                let wat = generate_with(
+                    program,
+                    &GenerateOptions {
+                        runtime: false,
+                        ..GenerateOptions::default()
+                    },
+                )
+                .unwrap();
+                assert!(!wat.contains("$__heap"));
+            }
+        }
 
-fn generate_import_memory(import: ImportMemory) -> String {
-    let external_name = import
-        .external_name
-        .iter()
-        .map(|n| format!("\"{}\"", n))
-        .collect::<Vec<String>>()
-        .join(" ");
-    format!("(import {} (memory {}))", external_name, import.size)
-}
+        let input = String::from(
+            "fn main(): void {
+    reset();
+}",
+        );
 
-fn generate_block(block: Block) -> String {
-    match block {
-        Block::Function(function) => generate_function(function),
-        Block::Export(export) => generate_export(export),
-        Block::ImportFunction(import) => generate_import_function(import),
-        Block::ImportMemory(import) => generate_import_memory(import),
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(
+                    generate(program),
+                    Err(CompileError::new(String::from(
+                        "reset needs a memory, add an import memory"
+                    )))
+                );
+            }
+        }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::parser::parse;
-
-    use super::*;
 
     #[test]
-    fn empty_function() {
+    fn gc_strings_are_byte_arrays() {
         let input = String::from(
-            "fn hello_world(): void {
+            "import fn log(message: string) console.log
+
+fn greet(name: string): string {
+    local greeting: string = \"Hello\";
+    log(greeting);
+    return name;
 }",
         );
-
         let output = String::from(
             "(module
-  (func $hello_world
+  (type $string (array (mut i8)))
+  (import \"console\" \"log\" (func $log (param (ref null $string))))
+  (data $__string_0 \"Hello\")
+  (func $greet (param $name (ref null $string)) (result (ref null $string))
+    (local $greeting (ref null $string))
+    (local.set $greeting (array.new_data $string $__string_0 (i32.const 0) (i32.const 5)))
+    (local.get $greeting)
+    (call $log)
+    (local.get $name)
   )
 )",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), output);
+                let options = GenerateOptions {
+                    gc: true,
+                    ..GenerateOptions::default()
+                };
+                assert_eq!(generate_with(program, &options), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
             }
         }
     }
 
     #[test]
-    fn empty_with_an_arg_function() {
+    fn try_catch() {
         let input = String::from(
-            "fn hello_world(name: string): void {
+            "import fn fetch(id: i32) host.fetch
+import fn log(number: i32) console.log
+
+fn main(): void {
+    try {
+        fetch(1);
+        log(1);
+    } catch {
+        log(2);
+    }
+    fetch(3);
 }",
         );
 
-        let output = String::from(
+        let program = match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => program,
+        };
+
+        let proposal = String::from(
             "(module
-  (func $hello_world (param $name string)
+  (import \"host\" \"fetch\" (func $fetch (param i32)))
+  (import \"console\" \"log\" (func $log (param i32)))
+  (func $main
+    try
+      (i32.const 1)
+      (call $fetch)
+      (i32.const 1)
+      (call $log)
+    catch_all
+      (i32.const 2)
+      (call $log)
+    end
+    (i32.const 3)
+    (call $fetch)
   )
 )",
         );
 
-        match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
-            Ok(program) => {
-                assert_eq!(generate(program), output);
-            }
-        }
+        assert_eq!(generate(program.clone()), Ok(proposal.clone()));
+        assert_eq!(validate(&proposal), Ok(()));
+
+        let error_codes = String::from(
+            "(module
+  (import \"host\" \"fetch\" (func $fetch (param i32) (result i32)))
+  (import \"console\" \"log\" (func $log (param i32) (result i32)))
+  (func $main
+    (block $__catch_0
+      (block $__try_0
+        (i32.const 1)
+        (call $fetch)
+        (br_if $__try_0)
+        (i32.const 1)
+        (call $log)
+        (br_if $__try_0)
+        (br $__catch_0)
+      )
+      (i32.const 2)
+      (call $log)
+      (drop)
+    )
+    (i32.const 3)
+    (call $fetch)
+    (drop)
+  )
+)",
+        );
+
+        let options = GenerateOptions {
+            exceptions: Exceptions::ErrorCodes,
+            ..GenerateOptions::default()
+        };
+        assert_eq!(generate_with(program, &options), Ok(error_codes));
     }
 
     #[test]
-    fn empty_with_several_args_function() {
+    fn tail_calls() {
         let input = String::from(
-            "fn hello_world(name: string, age: i32): void {
+            "fn add(x: f32, y: f32): f32 {
+    return x + y;
+}
+
+fn twice(x: f32): f32 {
+    tailcall add(x, x);
 }",
         );
+
+        let program = match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => program,
+        };
+
+        assert_eq!(
+            generate(program.clone()),
+            Err(CompileError::new(String::from(
+                "tailcall add needs the tail-call feature"
+            )))
+        );
+
         let output = String::from(
             "(module
-  (func $hello_world (param $name string) (param $age i32)
+  (func $add (param $x f32) (param $y f32) (result f32)
+    (f32.add (local.get $x) (local.get $y))
+  )
+  (func $twice (param $x f32) (result f32)
+    (local.get $x)
+    (local.get $x)
+    (return_call $add)
   )
 )",
         );
 
-        match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
-            Ok(program) => {
-                assert_eq!(generate(program), output);
-            }
-        }
+        let options = GenerateOptions {
+            tail_calls: true,
+            ..GenerateOptions::default()
+        };
+        assert_eq!(generate_with(program, &options), Ok(output.clone()));
+        assert_eq!(validate(&output), Ok(()));
     }
 
     #[test]
-    fn return_function() {
+    fn string_function() {
         let input = String::from(
-            "fn hello_world(name: string): string {
-    return name;
-}",
+            "import fn log(offset: i32, length: i32) console.log
+import memory 1 js.mem
+
+fn main(): void {
+    local message: string = \"Hello world\";
+    log();
+}
+
+export main main",
         );
         let output = String::from(
             "(module
-  (func $hello_world (param $name string) (result string)
-    (local.get $name)
+  (import \"console\" \"log\" (func $log (param i32 i32)))
+  (import \"js\" \"mem\" (memory 1))
+  (data (i32.const 0) \"Hello world\")
+  (func $main
+    (i32.const 0)
+    (i32.const 11)
+    (call $log)
   )
+  (export \"main\" (func $main))
 )",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), output);
+                assert_eq!(
+                    generate(program.clone()),
+                    Ok(output.clone()),
+                    "Generated:\n{}\n\n\n========\nExpected:\n{}",
+                    generate(program.clone()).unwrap(),
+                    output
+                );
             }
         }
     }
 
     #[test]
-    fn local_var_and_addition_function() {
+    fn non_ascii_strings_are_escaped_by_the_byte() {
         let input = String::from(
-            "fn hello_world(name: i32): i32 {
-    local message: i32 = name;
-    return message;
+            "import fn log(offset: i32, length: i32) console.log
+import memory 1 js.mem
+
+fn main(): void {
+    local message: string = \"héllo \\ 世界\";
+    log();
 }",
         );
         let output = String::from(
             "(module
-  (func $hello_world (param $name i32) (result i32)
-    (local $message i32)
-    (local.set $message (local.get $name))
-    (local.get $message)
+  (import \"console\" \"log\" (func $log (param i32 i32)))
+  (import \"js\" \"mem\" (memory 1))
+  (data (i32.const 0) \"h\\c3\\a9llo \\5c \\e4\\b8\\96\\e7\\95\\8c\")
+  (func $main
+    (i32.const 0)
+    (i32.const 15)
+    (call $log)
   )
 )",
         );
 
-        match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), output);
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
             }
         }
     }
 
     #[test]
-    fn global_var_and_addition_function() {
+    fn generic_functions_are_copied_for_each_type() {
         let input = String::from(
-            "fn hello_world(): void {
-    global num: f32 = 123 + 3.14;
+            "fn identity<T>(x: T): T {
+    return x;
+}
+
+fn first<A, B>(a: A, b: B): A {
+    local kept: A = identity(a);
+    return kept;
+}
+
+@export
+fn main(n: i32, big: i64): i64 {
+    local same: i32 = identity(n);
+    local half: f32 = identity(0.5);
+    return first(big, n);
 }",
         );
         let output = String::from(
             "(module
-  (global $num (mut f32))
-  (func $hello_world
-    (global.set $num (f32.add (f32.const 123) (f32.const 3.14)))
+  (func $identity__i32 (param $x i32) (result i32)
+    (local.get $x)
+  )
+  (func $identity__f32 (param $x f32) (result f32)
+    (local.get $x)
+  )
+  (func $identity__i64 (param $x i64) (result i64)
+    (local.get $x)
   )
+  (func $first__i64__i32 (param $a i64) (param $b i32) (result i64)
+    (local $kept i64)
+    (local.set $kept (local.get $a)
+    (call $identity__i64))
+    (local.get $kept)
+  )
+  (func $main (param $n i32) (param $big i64) (result i64)
+    (local $same i32)
+    (local $half f32)
+    (local.set $same (local.get $n)
+    (call $identity__i32))
+    (local.set $half (f32.const 0.5)
+    (call $identity__f32))
+    (local.get $big)
+    (local.get $n)
+    (call $first__i64__i32)
+  )
+  (export \"main\" (func $main))
 )",
         );
 
-        match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), output);
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
             }
         }
     }
 
     #[test]
-    fn export_function() {
+    fn constants_are_inlined() {
         let input = String::from(
-            "fn hello_world(): f32 {
-    return 3.14;
-}
+            "const PAGES: i32 = 2 * (1 + 1);
+const BYTES: i64 = PAGES * 65536;
 
-export helloWorld hello_world",
+memory PAGES
+
+@export
+fn size(): i64 {
+    return BYTES;
+}",
         );
         let output = String::from(
             "(module
-  (func $hello_world (result f32)
-    (f32.const 3.14)
+  (memory 4)
+  (func $size (result i64)
+    (i64.const 262144)
   )
-  (export \"helloWorld\" (func $hello_world))
+  (export \"size\" (func $size))
 )",
         );
 
-        match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
-                assert_eq!(generate(program), output);
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
             }
         }
     }
 
     #[test]
-    fn import_function() {
-        let input = String::from("import fn log(number: i32) console.log");
+    fn fields_are_grouped_whatever_order_blocks_are_written_in() {
         let output = String::from(
             "(module
   (import \"console\" \"log\" (func $log (param i32)))
+  (memory 1)
+  (func $main
+    (i32.const 1)
+    (call $log)
+  )
+  (export \"main\" (func $main))
 )",
         );
 
-        match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
-            Ok(program) => {
-                assert_eq!(generate(program), output);
+        for input in [
+            "export main main
+
+fn main(): void {
+    log(1);
+}
+
+memory 1
+
+import fn log(x: i32) console.log",
+            "import fn log(x: i32) console.log
+
+memory 1
+
+fn main(): void {
+    log(1);
+}
+
+export main main",
+        ] {
+            match parse(String::from(input)) {
+                Err(err) => panic!("{}", join_errors(&err)),
+                Ok(program) => assert_eq!(generate(program), Ok(output.clone())),
             }
         }
+        assert_eq!(validate(&output), Ok(()));
     }
 
     #[test]
-    fn call_function() {
-        let input = String::from(
-            "import fn log(number: i32) console.log
-
-fn main(): void {
-    log(3.14);
+    fn instrumented_functions_count_their_calls() {
+        let program = parse(String::from(
+            "fn one(): i32 {
+    return 1;
 }
 
-export main main",
+fn main(): i32 {
+    return one();
+}",
+        ))
+        .unwrap();
+        let output = generate_with(
+            program,
+            &GenerateOptions {
+                instrument: Some(Instrument::Trace),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            output,
+            "(module
+  (import \"gwe\" \"trace\" (func $__trace (param i32)))
+  (memory $__calls (export \"__calls\") 1)
+  (data (memory $__calls) (i32.const 8) \"one\\0amain\")
+  (func $one (result i32)
+    (i32.store $__calls (i32.const 0) (i32.add (i32.load $__calls (i32.const 0)) (i32.const 1)))
+    (call $__trace (i32.const 0))
+    (i32.const 1)
+  )
+  (func $main (result i32)
+    (i32.store $__calls (i32.const 4) (i32.add (i32.load $__calls (i32.const 4)) (i32.const 1)))
+    (call $__trace (i32.const 1))
+    (call $one)
+  )
+)"
+        );
+        assert_eq!(validate(&output), Ok(()));
+    }
+
+    #[test]
+    fn examples_balance_the_stack() {
+        for file in fs::read_dir("examples/").unwrap() {
+            let path = file.unwrap().path();
+            if !path.to_string_lossy().ends_with(".gwe") {
+                continue;
+            }
+
+            let Ok(program) = parse(fs::read_to_string(&path).unwrap()) else {
+                continue;
+            };
+            // examples the checker rejects aren't generated by the compiler
+            if typechecker::check(&program).is_err() {
+                continue;
+            }
+
+            for profile in [Profile::Dev, Profile::Release] {
+                let options = GenerateOptions {
+                    passes: Passes::profile(profile),
+                    ..GenerateOptions::default()
+                };
+
+                if let Ok(wat) = generate_with(program.clone(), &options) {
+                    assert_eq!(stack::audit(&wat), Ok(()), "{:?}\n{}", path, wat);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wat_positions_are_traced_to_functions() {
+        let wat = "(module
+  (import \"console\" \"log\" (func $log (param i32)))
+  (data (i32.const 0) \"(func $fake\")
+  (func $one (result i32)
+    (i32.const 1)
+  )
+  (func $main
+    (call $log (call $one))
+  )
+)";
+
+        assert_eq!(function_at(wat, 5, 5), Some(String::from("one")));
+        assert_eq!(function_at(wat, 8, 16), Some(String::from("main")));
+        assert_eq!(function_at(wat, 3, 25), None);
+        assert_eq!(function_at(wat, 1, 1), None);
+        assert_eq!(
+            function_at(&layout::minify(wat), 1, 110),
+            Some(String::from("one"))
+        );
+    }
+
+    #[test]
+    fn wat_lines_are_traced_to_statements() {
+        let program = parse(String::from(
+            "import fn log(n: i32) console.log
+
+fn main(): i32 {
+    local x: i32 = 1;
+    log(x);
+    return x;
+}",
+        ))
+        .unwrap();
+        let options = GenerateOptions {
+            passes: Passes {
+                peephole: true,
+                ..Passes::default()
+            },
+            layout: Layout::Pretty,
+            ..GenerateOptions::default()
+        };
+
+        let (wat, statements) = generate_with_statements(program.clone(), &options).unwrap();
+        let statement = |code: &str| {
+            wat.lines()
+                .position(|line| line.contains(code))
+                .and_then(|index| statements[index].clone())
+        };
+
+        assert_eq!(generate_with(program, &options), Ok(wat.to_string()));
+        assert_eq!(wat.lines().count(), statements.len());
+        assert_eq!(statement("(func $main"), None);
+        assert_eq!(statement("(local.tee $x"), Some((String::from("main"), 0)));
+        assert_eq!(statement("(call $log"), Some((String::from("main"), 1)));
+        assert_eq!(statement("(local.get $x)"), Some((String::from("main"), 2)));
+    }
+
+    #[test]
+    fn invalid_output_is_reported() {
+        assert_eq!(
+            validate("(module\n  (func $one (result i32)\n    (i32.const 1)\n  )\n)"),
+            Ok(())
         );
-        let output = String::from(
-            "(module
-  (import \"console\" \"log\" (func $log (param i32)))
-  (func $main
-    (f32.const 3.14)
-    (call $log)
-  )
-  (export \"main\" (func $main))
-)",
+        assert_eq!(
+            validate("(module\n  (func $one (result i32)\n  )\n)"),
+            Err(CompileError::new(String::from(
+                "Generated invalid wasm: type mismatch: expected i32 but nothing on stack (at offset 0x18)"
+            )))
+        );
+        assert!(
+            validate("(module\n  (func $one\n    (local.get $missing)\n  )\n)").is_err_and(
+                |error| error
+                    .message
+                    .starts_with("Generated wat that doesn't parse")
+            )
         );
-
-        match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
-            Ok(program) => {
-                assert_eq!(generate(program), output);
-            }
-        }
     }
 
     #[test]
-    fn string_function() {
+    fn single_functions_know_about_the_rest_of_the_program() {
         let input = String::from(
-            "import fn log(offset: i32, length: i32) console.log
-import memory 1 js.mem
+            "const PAGES: i32 = 2;
 
-fn main(): void {
-    local message: string = \"Hello world\";
-    log();
+fn helper(): i32 {
+    return 1;
 }
 
-export main main",
-        );
-        let output = String::from(
-            "(module
-  (import \"console\" \"log\" (func $log (param i32 i32)))
-  (import \"js\" \"mem\" (memory 1))
-  (data (i32.const 0) \"Hello world\")
-  (func $main
-    (i32.const 0)
-    (i32.const 11)
-    (call $log)
-  )
-  (export \"main\" (func $main))
-)",
+fn size(): i32 {
+    return PAGES;
+}",
         );
 
-        match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
                 assert_eq!(
-                    generate(program.clone()),
-                    output,
-                    "Generated:\n{}\n\n\n========\nExpected:\n{}",
-                    generate(program.clone()),
-                    output
+                    generate_function_with(program.clone(), "size", &GenerateOptions::default()),
+                    Ok(String::from(
+                        "(func $size (result i32)
+  (i32.const 2)
+)"
+                    ))
+                );
+                assert_eq!(
+                    generate_function_with(program, "missing", &GenerateOptions::default()),
+                    Err(CompileError::new(String::from(
+                        "There's no function called missing"
+                    )))
                 );
             }
         }
@@ -667,13 +5456,13 @@ export main main",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
                 assert_eq!(
                     generate(program.clone()),
-                    output,
+                    Ok(output.clone()),
                     "Generated:\n{}\n\n\n========\nExpected:\n{}",
-                    generate(program.clone()),
+                    generate(program.clone()).unwrap(),
                     output
                 );
             }
@@ -686,7 +5475,7 @@ export main main",
             "import memory 1 js.mem
 
 fn main(): void {
-    if (0) { log(3.14) } { log(42) };
+    if (0) { log(3.14) } else { log(42) };
 }
 
 export main main",
@@ -696,7 +5485,7 @@ export main main",
   (import \"js\" \"mem\" (memory 1))
   (func $main
     (if
-      (f32.const 0)
+      (i32.const 0)
       (then
         (f32.const 3.14)
         (call $log)
@@ -712,13 +5501,13 @@ export main main",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
                 assert_eq!(
                     generate(program.clone()),
-                    output,
+                    Ok(output.clone()),
                     "Generated:\n{}\n\n\n========\nExpected:\n{}",
-                    generate(program.clone()),
+                    generate(program.clone()).unwrap(),
                     output
                 );
             }
@@ -731,7 +5520,7 @@ export main main",
             "import memory 1 js.mem
 
 fn main(): void {
-    if (true) { log(true) } { log(false) };
+    if (true) { log(true) } else { log(false) };
 }
 
 export main main",
@@ -742,13 +5531,13 @@ export main main",
   (import \"js\" \"mem\" (memory 1))
   (func $main
     (if
-      (i32.const 0)
+      (i32.const 1)
       (then
-        (i32.const 0)
+        (i32.const 1)
         (call $log)
       )
       (else
-        (i32.const 1)
+        (i32.const 0)
         (call $log)
       )
     )
@@ -758,13 +5547,13 @@ export main main",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
                 assert_eq!(
                     generate(program.clone()),
-                    output,
+                    Ok(output.clone()),
                     "Generated:\n{}\n\n\n========\nExpected:\n{}",
-                    generate(program.clone()),
+                    generate(program.clone()).unwrap(),
                     output
                 );
             }
@@ -809,16 +5598,409 @@ export main main",
         );
 
         match parse(input.clone()) {
-            Err(err) => panic!("{}", err),
+            Err(err) => panic!("{}", join_errors(&err)),
             Ok(program) => {
                 assert_eq!(
                     generate(program.clone()),
-                    output,
+                    Ok(output.clone()),
                     "Generated:\n{}\n\n\n========\nExpected:\n{}",
-                    generate(program.clone()),
+                    generate(program.clone()).unwrap(),
                     output
                 );
             }
         }
     }
+
+    #[test]
+    fn for_loops_step_with_the_type_of_their_variable() {
+        let input = String::from(
+            "import fn log(number: f32) console.log
+
+fn main(): void {
+    for (local x: f32 = 10.0, 0.0, 2.5, down) {
+        log(x);
+    };
+}",
+        );
+
+        let output = String::from(
+            "(module
+  (import \"console\" \"log\" (func $log (param f32)))
+  (func $main
+    (local $x f32)
+    (local.set $x (f32.const 10.0))
+    (loop $loop
+      (local.get $x)
+      (call $log)
+      (local.get $x)
+      (f32.const 2.5)
+      (f32.sub)
+      (local.set $x)
+      (local.get $x)
+      (f32.const 0.0)
+      (f32.gt)
+      (br_if $loop)
+    )
+  )
+)",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn closures_are_lifted_into_the_table() {
+        let input = String::from(
+            "import memory 1 js.mem
+
+@export
+fn main(n: i32): i32 {
+    fn get(): i32 {
+        return n;
+    }
+    return get();
+}",
+        );
+
+        let program = match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => program,
+        };
+
+        // the environment holds the table index, then each capture
+        let wat = generate(program).unwrap();
+        assert!(wat.contains("(type $__closure_0 (func (param i32) (result i32)))"));
+        assert!(wat.contains(
+            "    (local.set $get (i32.const 16)
+    (call $alloc))
+    (i32.store offset=0 (local.get $get) (i32.const 0))
+    (i32.store offset=8 (local.get $get) (local.get $n))
+    (local.get $get)
+    (i32.load (local.get $get))
+    (call_indirect $__closures (type $__closure_0))"
+        ));
+        assert!(wat.contains(
+            "  (func $main__get (param $__env i32) (result i32)
+    (local $n i32)
+    (local.set $n (i32.load offset=8 (local.get $__env)))
+    (local.get $n)
+  )"
+        ));
+        assert!(wat.contains("(table $__closures 1 funcref)"));
+        assert!(wat.contains("(elem (table $__closures) (i32.const 0) func $main__get)"));
+        assert_eq!(validate(&wat), Ok(()));
+    }
+
+    #[test]
+    fn closures_capture_numbers_in_memory() {
+        let input = String::from(
+            "fn main(n: i32): i32 {
+    fn get(): i32 {
+        return n;
+    }
+    return get();
+}",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(
+                    generate(program),
+                    Err(CompileError::new(String::from(
+                        "Closures keep what they capture in memory, add an import memory"
+                    )))
+                );
+            }
+        }
+
+        let input = String::from(
+            "import memory 1 js.mem
+
+fn main(s: string): void {
+    fn get(): string {
+        return s;
+    }
+}",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(
+                    generate(program),
+                    Err(CompileError::new(String::from(
+                        "get can't capture s, which is string, as closures only capture numbers"
+                    )))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn function_params_are_called_through_the_table() {
+        let input = String::from(
+            "import memory 1 js.mem
+
+@export
+fn apply(f: fn(i32): i32, x: i32): i32 {
+    return f(x);
+}",
+        );
+        let output = String::from(
+            "(module
+  (type $__closure_0 (func (param i32 i32) (result i32)))
+  (import \"js\" \"mem\" (memory 1))
+  (table $__closures 0 funcref)
+  (func $apply (param $f i32) (param $x i32) (result i32)
+    (local.get $f)
+    (local.get $x)
+    (i32.load (local.get $f))
+    (call_indirect $__closures (type $__closure_0))
+  )
+  (export \"apply\" (func $apply))
+)",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn dense_matches_use_a_jump_table() {
+        let input = String::from(
+            "import fn log(x: i32) console.log
+
+fn main(n: i32): void {
+    match n {
+        1 => log(10),
+        2 => log(20),
+        4 => log(40),
+        _ => log(0)
+    }
+}",
+        );
+        let output = String::from(
+            "(module
+  (import \"console\" \"log\" (func $log (param i32)))
+  (func $main (param $n i32)
+    (block $__match_0
+      (block $__match_0_default
+        (block $__match_0_2
+          (block $__match_0_1
+            (block $__match_0_0
+              (br_table $__match_0_0 $__match_0_1 $__match_0_default $__match_0_2 $__match_0_default
+                (i32.sub (local.get $n) (i32.const 1)))
+            )
+            (i32.const 10)
+            (call $log)
+            (br $__match_0)
+          )
+          (i32.const 20)
+          (call $log)
+          (br $__match_0)
+        )
+        (i32.const 40)
+        (call $log)
+        (br $__match_0)
+      )
+      (i32.const 0)
+      (call $log)
+    )
+  )
+)",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn sparse_matches_use_ifs() {
+        let input = String::from(
+            "import fn log(x: i32) console.log
+
+fn main(n: i64): void {
+    match n {
+        1 => log(10),
+        100 => log(20),
+        _ => log(0)
+    }
+}",
+        );
+        let output = String::from(
+            "(module
+  (import \"console\" \"log\" (func $log (param i32)))
+  (func $main (param $n i64)
+    (local $__match_i64 i64)
+    (local.set $__match_i64 (local.get $n))
+    (if
+      (i64.eq (local.get $__match_i64) (i64.const 1))
+      (then
+        (i32.const 10)
+        (call $log)
+      )
+      (else
+        (if
+          (i64.eq (local.get $__match_i64) (i64.const 100))
+          (then
+            (i32.const 20)
+            (call $log)
+          )
+          (else
+            (i32.const 0)
+            (call $log)
+          )
+        )
+      )
+    )
+  )
+)",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn conditionals_select_values_without_effects() {
+        let input = String::from(
+            "fn main(c: i32, x: i64): i64 {
+    return c ? x : 2;
+}",
+        );
+        let output = String::from(
+            "(module
+  (func $main (param $c i32) (param $x i64) (result i64)
+    (select (local.get $x) (i64.const 2) (local.get $c))
+  )
+)",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn arrays_are_laid_out_as_data() {
+        let input = String::from(
+            "import memory 1 js.mem
+const LAST: i64 = 8;
+
+fn main(i: i32): i64 {
+    local table: [i64] = [1, 2, 3, 5, LAST];
+    return table[i];
+}",
+        );
+        let output = String::from(
+            "(module
+  (import \"js\" \"mem\" (memory 1))
+  (data (i32.const 0) \"\\01\\00\\00\\00\\00\\00\\00\\00\\02\\00\\00\\00\\00\\00\\00\\00\\03\\00\\00\\00\\00\\00\\00\\00\\05\\00\\00\\00\\00\\00\\00\\00\\08\\00\\00\\00\\00\\00\\00\\00\")
+  (func $main (param $i i32) (result i64)
+    (local $table i32)
+    (local.set $table (i32.const 0))
+    (local.get $table)
+    (local.get $i)
+    (i32.const 8)
+    (i32.mul)
+    (i32.add)
+    (i64.load)
+  )
+)",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn arrays_hold_numbers_known_when_compiling() {
+        let program = parse(String::from(
+            "import memory 1 js.mem
+
+fn main(x: i32): i32 {
+    local table: [i32] = [1, x];
+    return table[0];
+}",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            generate(program),
+            Err(CompileError::new(String::from(
+                "Array table can only hold i32 numbers known when compiling"
+            )))
+        );
+    }
+
+    #[test]
+    fn conditionals_with_calls_use_if() {
+        let input = String::from(
+            "fn double(x: f32): f32 {
+    return x + x;
+}
+
+fn main(c: i32, x: f32): f32 {
+    return c ? double(x) : x;
+}",
+        );
+        let output = String::from(
+            "(module
+  (func $double (param $x f32) (result f32)
+    (f32.add (local.get $x) (local.get $x))
+  )
+  (func $main (param $c i32) (param $x f32) (result f32)
+    (if (result f32)
+      (local.get $c)
+      (then
+        (local.get $x)
+        (call $double)
+      )
+      (else
+        (local.get $x)
+      )
+    )
+  )
+)",
+        );
+
+        match parse(input) {
+            Err(err) => panic!("{}", join_errors(&err)),
+            Ok(program) => {
+                assert_eq!(generate(program), Ok(output.clone()));
+                assert_eq!(validate(&output), Ok(()));
+            }
+        }
+    }
 }
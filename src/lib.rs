@@ -0,0 +1,26 @@
+#![allow(irrefutable_let_patterns)]
+
+pub mod blocks;
+pub mod cache;
+pub mod config;
+pub mod coverage;
+pub mod encoding;
+pub mod errors;
+pub mod expressions;
+pub mod generators;
+pub mod options;
+pub mod output;
+pub mod parser;
+pub mod pipeline;
+pub mod repl;
+pub mod scaffold;
+pub mod serve;
+pub mod stdlib;
+pub mod testing;
+pub mod tokenizer;
+pub mod transform;
+pub mod typechecker;
+pub mod visit;
+pub mod visit_mut;
+
+pub use pipeline::compile;
@@ -1,28 +1,216 @@
-#![allow(irrefutable_let_patterns)]
-
-mod blocks;
-mod expressions;
-mod generators;
-mod parser;
-mod tokenizer;
-
 mod cli {
-    use super::*;
-    use clap::Parser;
+    use chrono::{DateTime, Local, NaiveTime, Utc};
+    use clap::{ColorChoice, CommandFactory, Parser, Subcommand, ValueEnum};
+    use clap_complete::{generate, Shell};
+    use clap_mangen::Man;
+    use globset::{Glob, GlobSet, GlobSetBuilder};
+    use gwe::errors::{join_errors, CompileError, Diagnostic, ErrorCode};
+    use gwe::generators::docs::DocFormat;
+    use gwe::generators::gwe::{BraceStyle, FormatOptions, Gwe};
+    use gwe::generators::layout::Layout;
+    use gwe::generators::naming::{Case, NamingPolicy};
+    use gwe::generators::web_assembly::{
+        add_custom_section, Exceptions, GenerateOptions, Instrument, Wat,
+    };
+    use gwe::generators::Backend;
+    use gwe::options::{CompilerOptions, Define, Optimize, Passes, Profile};
+    use gwe::pipeline::{self, Failure, Timings, WasmBinary};
+    use gwe::repl::{is_complete, Session};
+    use gwe::scaffold::create_project;
+    use gwe::serve::DevServer;
+    use gwe::testing::{run_tests, run_tests_with, TestOutcome};
+    use gwe::{
+        cache::BuildCache,
+        config,
+        coverage::{self, Instrumented},
+        encoding::decode,
+        generators,
+        output::write_atomically,
+        parser::{load_data, parse_with_defines, parse_with_trivia, Program},
+        tokenizer::tokenize,
+        typechecker,
+    };
+    use log::{debug, error, info, warn, LevelFilter, Log, Metadata, Record};
     use notify::RecursiveMode;
-    use parser::parse;
-    use std::{env::current_dir, fs, path::Path, process::Command, time::Duration};
+    use std::{
+        env::{self, current_dir},
+        fs,
+        io::{self, IsTerminal, Write},
+        path::{Path, PathBuf},
+        process::{exit, Command},
+        time::{Duration, Instant},
+    };
+
+    #[derive(Parser, Debug)]
+    #[command(
+        author,
+        version,
+        about,
+        long_about = None,
+        args_conflicts_with_subcommands = true,
+        subcommand_negates_reqs = true
+    )]
+    pub struct Cli {
+        #[command(subcommand)]
+        pub command: Option<Commands>,
+
+        /// Print more about what the compiler is doing, -vv for even more
+        #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+        pub verbose: u8,
+
+        /// Only print errors
+        #[arg(short, long, global = true, conflicts_with = "verbose")]
+        pub quiet: bool,
+
+        /// Print a man page for gwe in roff, for `man -l`
+        #[arg(long, exclusive = true)]
+        pub help_man: bool,
+
+        #[command(flatten)]
+        pub args: Option<Args>,
+    }
+
+    #[derive(Subcommand, Debug)]
+    pub enum Commands {
+        /// Evaluate expressions and build up a module interactively
+        Repl,
+        /// Print a script completing gwe's subcommands and flags in a shell
+        Completions {
+            #[arg(value_enum)]
+            shell: Shell,
+        },
+        /// Report errors in a file without generating or writing any output
+        Check {
+            /// Path to the .gwe file
+            #[arg(long)]
+            file: String,
+
+            /// How errors are printed
+            #[arg(long, value_enum, default_value_t = ErrorFormat::Human)]
+            error_format: ErrorFormat,
+
+            /// When to color errors, auto respects NO_COLOR
+            #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+            color: ColorChoice,
+        },
+        /// Print documentation for the functions a module exports and imports
+        Doc {
+            /// Path to the .gwe file
+            #[arg(long)]
+            file: String,
+
+            /// markdown or html
+            #[arg(long, default_value = "markdown")]
+            format: DocFormat,
+        },
+        /// Run the test blocks and @test functions in a file
+        Test {
+            /// Path to the .gwe file
+            #[arg(long)]
+            file: String,
+        },
+        /// Run the tests in a file and print an lcov report of the lines they ran
+        Coverage {
+            /// Path to the .gwe file
+            #[arg(long)]
+            file: String,
+        },
+        /// Start a project in a new directory, with a hello world, a page loading it and a gwe.toml
+        New {
+            /// Name of the directory to create
+            name: PathBuf,
+        },
+        /// Compile to wasm and serve a page that runs it, reloading on every rebuild
+        Serve {
+            /// Path to the .gwe file
+            #[arg(long)]
+            file: String,
+
+            /// wat2wasm to use, instead of searching PATH
+            #[arg(long, env = "GWE_WAT2WASM")]
+            wat2wasm: Option<PathBuf>,
+
+            #[arg(long, default_value_t = 8000)]
+            port: u16,
+        },
+    }
 
-    /// Simple program to greet a person
-    #[derive(Parser, Debug, Clone)]
-    #[command(author, version, about, long_about = None)]
+    /// Writes log messages to stderr, so they never mix with generated code on stdout
+    struct Logger;
+
+    impl Log for Logger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= log::max_level()
+        }
+
+        fn log(&self, record: &Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+
+            match record.level() {
+                log::Level::Info => eprintln!("{}", record.args()),
+                log::Level::Error => eprintln!("error: {}", record.args()),
+                log::Level::Warn => eprintln!("warning: {}", record.args()),
+                level => eprintln!("[{}] {}", level.to_string().to_lowercase(), record.args()),
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: Logger = Logger;
+
+    fn init_logging(verbose: u8, quiet: bool) {
+        let level = match (quiet, verbose) {
+            (true, _) => LevelFilter::Error,
+            (false, 0) => LevelFilter::Info,
+            (false, 1) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        };
+
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(level);
+        }
+    }
+
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+    pub enum ErrorFormat {
+        /// Readable messages on stderr
+        Human,
+        /// One JSON diagnostic per line on stderr
+        Json,
+    }
+
+    /// Intermediate forms that can be written out instead of generated code
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+    pub enum Emit {
+        /// The parsed program as an indented tree
+        AstText,
+        /// The parsed program as JSON, for other tools to read
+        #[cfg(feature = "serde")]
+        AstJson,
+    }
+
+    /// Wasm proposals generated code can use
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+    pub enum Feature {
+        /// tailcall becomes return_call
+        TailCall,
+    }
+
+    /// Compile a gwe file
+    #[derive(clap::Args, Debug, Clone)]
     pub struct Args {
-        /// Name of the person to greet
-        #[arg(long)]
+        /// Path to the .gwe file
+        #[arg(long, required_unless_present = "watch_dir", default_value = "")]
         pub file: String,
 
-        #[arg(long, default_value_t = String::from("wat"))]
-        pub target: String,
+        /// wat, wasm, gwe, js glue converting strings for the module's imports and exports,
+        /// dts types for that glue, or the experimental wat-gc which keeps strings as WasmGC
+        /// arrays. Comma separated to generate several from one parse
+        #[arg(long = "target", value_delimiter = ',', default_value = "wat")]
+        pub targets: Vec<String>,
 
         #[arg(long, default_value_t = false)]
         pub format: bool,
@@ -32,165 +220,1205 @@ mod cli {
 
         #[arg(long, default_value_t = false)]
         pub watch: bool,
+
+        /// With --watch, watch a directory tree instead of a single file,
+        /// compiling each changed file that matches --include and not --exclude
+        #[arg(long, requires = "watch")]
+        pub watch_dir: Option<PathBuf>,
+
+        /// Globs of the files to compile in --watch-dir, relative to it
+        #[arg(long, requires = "watch_dir", default_value = "**/*.gwe")]
+        pub include: Vec<String>,
+
+        /// Globs of the files to leave alone in --watch-dir, relative to it
+        #[arg(long, requires = "watch_dir")]
+        pub exclude: Vec<String>,
+
+        /// Shell command to run after each successful recompile in watch mode,
+        /// with the written file in $GWE_OUTPUT
+        #[arg(long, requires = "watch")]
+        pub on_success: Option<String>,
+
+        /// Spaces per indentation level when formatting, overrides gwe.toml
+        #[arg(long)]
+        pub indent_width: Option<usize>,
+
+        /// Line length after which signatures and calls are split when formatting, overrides gwe.toml
+        #[arg(long)]
+        pub max_line_length: Option<usize>,
+
+        /// same-line or next-line, overrides gwe.toml
+        #[arg(long)]
+        pub brace_style: Option<BraceStyle>,
+
+        /// How errors are printed
+        #[arg(long, value_enum, default_value_t = ErrorFormat::Human)]
+        pub error_format: ErrorFormat,
+
+        /// When to color errors, auto respects NO_COLOR
+        #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+        pub color: ColorChoice,
+
+        /// wat2wasm to use for the wasm target, instead of searching PATH
+        #[arg(long, env = "GWE_WAT2WASM")]
+        pub wat2wasm: Option<PathBuf>,
+
+        /// Keep the wat the wasm target is encoded from, as .wasm.wat next to the .wasm
+        #[arg(long, default_value_t = false)]
+        pub keep_intermediate: bool,
+
+        /// Constants for the source to use, like --define MAX=64, replacing any the
+        /// source declares with the same name. A name on its own is 1
+        #[arg(long = "define", value_name = "NAME=VALUE")]
+        pub defines: Vec<Define>,
+
+        /// Leave out the alloc and reset runtime, for programs that manage their own memory
+        #[arg(long, default_value_t = false)]
+        pub no_runtime: bool,
+
+        /// How try blocks catch errors from imports, proposal or error-codes
+        #[arg(long, default_value = "proposal")]
+        pub exceptions: Exceptions,
+
+        /// Wasm proposals to use, comma separated
+        #[arg(long, value_enum, value_delimiter = ',')]
+        pub features: Vec<Feature>,
+
+        /// Check the generated wasm is valid before writing it
+        #[arg(long, default_value_t = false)]
+        pub validate: bool,
+
+        /// Check each generated function balances the stack, reporting problems by function
+        #[arg(long, default_value_t = false)]
+        pub audit_stack: bool,
+
+        /// Optimization level, -O1 cleans up the generated wat, -O2 also inlines small functions and -Os runs every size pass
+        #[arg(short = 'O', default_value = "0")]
+        pub optimize: Optimize,
+
+        /// Build with the release profile: every pass, for the fastest modules
+        #[arg(long, default_value_t = false, conflicts_with_all = ["optimize", "debug"])]
+        pub release: bool,
+
+        /// Wasm plugins which rewrite the program before it's generated, run in order
+        #[arg(long)]
+        pub transform: Vec<PathBuf>,
+
+        /// Count calls to each function in the exported __calls memory, trace also calls the imported gwe.trace
+        #[arg(long)]
+        pub instrument: Option<Instrument>,
+
+        /// Count how many times each statement runs, read back through the exported __coverage_dump
+        #[arg(long, default_value_t = false)]
+        pub coverage: bool,
+
+        /// Check memory builtins and array indexes stay in bounds and integer for loops don't overflow, reporting failures to gwe.panic
+        #[arg(long, default_value_t = false)]
+        pub debug: bool,
+
+        /// How export names are written, keep or camel to turn say_hello into sayHello
+        #[arg(long, default_value = "keep")]
+        pub export_case: Case,
+
+        /// Put before every export name, like the name of the library
+        #[arg(long, default_value = "")]
+        pub export_prefix: String,
+
+        /// Put before the wat name of every function, so the wat of several modules can be combined
+        #[arg(long, default_value = "")]
+        pub symbol_prefix: String,
+
+        /// Put the generated wat on one line with as little whitespace as possible
+        #[arg(long, default_value_t = false, conflicts_with = "pretty")]
+        pub minify: bool,
+
+        /// Reindent the generated wat two spaces for each level it's nested
+        #[arg(long, default_value_t = false)]
+        pub pretty: bool,
+
+        /// Write out what the file parses to instead of generating code
+        #[arg(long, value_enum)]
+        pub emit: Option<Emit>,
+
+        /// Keep generated code in .gwe-cache, so unchanged files aren't compiled again
+        #[arg(long, default_value_t = false)]
+        pub cache: bool,
+
+        /// Print the time spent in each step of the compile, with counts of what it worked on
+        #[arg(long, default_value_t = false)]
+        pub timings: bool,
+
+        /// Leave the compiler version, source file and time out of wat and wasm,
+        /// so the same source always builds the same bytes
+        #[arg(long, default_value_t = false)]
+        pub reproducible: bool,
     }
 
-    pub fn compile_to_wasm(args: &Args) {
-        let original_file_path = &args.file;
+    impl Args {
+        /// The switches deciding what the file compiles to
+        pub fn compiler_options(&self) -> CompilerOptions {
+            let passes = if self.release {
+                Passes::profile(Profile::Release)
+            } else {
+                Passes::optimize(self.optimize)
+            };
 
-        let mut path = Path::new("gwe_build").join(Path::new(&original_file_path));
-        path.set_extension("wat");
-        let path_as_string = path.as_os_str().to_string_lossy().to_string();
+            CompilerOptions {
+                targets: self.targets.clone(),
+                passes: Passes {
+                    debug: self.debug,
+                    ..passes
+                },
+                runtime: !self.no_runtime,
+                exceptions: self.exceptions,
+                tail_calls: self.features.contains(&Feature::TailCall),
+                validate: self.validate,
+                audit_stack: self.audit_stack,
+                instrument: self.instrument,
+                coverage: self.coverage,
+                transforms: self.transform.clone(),
+                wat2wasm: self.wat2wasm.clone(),
+                layout: match (self.minify, self.pretty) {
+                    (true, _) => Layout::Minified,
+                    (false, true) => Layout::Pretty,
+                    (false, false) => Layout::Generated,
+                },
+                naming: NamingPolicy {
+                    export_case: self.export_case,
+                    export_prefix: self.export_prefix.clone(),
+                    symbol_prefix: self.symbol_prefix.clone(),
+                },
+                cache: self.cache.then(|| PathBuf::from(".gwe-cache")),
+                defines: self.defines.clone(),
+                ..CompilerOptions::default()
+            }
+        }
+    }
 
-        let mut output_path = path.clone();
-        output_path.set_extension("wasm");
-        let output_path_as_string = output_path.as_os_str().to_string_lossy().to_string();
+    impl Default for Args {
+        fn default() -> Self {
+            Args {
+                file: String::new(),
+                targets: vec![String::from("wat")],
+                format: false,
+                stdout: false,
+                watch: false,
+                watch_dir: None,
+                include: vec![String::from("**/*.gwe")],
+                exclude: vec![],
+                on_success: None,
+                indent_width: None,
+                max_line_length: None,
+                brace_style: None,
+                error_format: ErrorFormat::Human,
+                color: ColorChoice::Auto,
+                wat2wasm: None,
+                keep_intermediate: false,
+                defines: vec![],
+                no_runtime: false,
+                exceptions: Exceptions::Proposal,
+                features: vec![],
+                validate: false,
+                audit_stack: false,
+                optimize: Optimize::Level(0),
+                release: false,
+                transform: vec![],
+                instrument: None,
+                coverage: false,
+                debug: false,
+                export_case: Case::Keep,
+                export_prefix: String::new(),
+                symbol_prefix: String::new(),
+                minify: false,
+                pretty: false,
+                emit: None,
+                cache: false,
+                timings: false,
+                reproducible: false,
+            }
+        }
+    }
 
-        match Command::new("wat2wasm")
-            .args([
-                path_as_string.as_str(),
-                "-o",
-                output_path_as_string.as_str(),
-            ])
-            .output()
-        {
-            Err(err) => println!("Failed to generate wasm: {}", err),
-            Ok(value) => {
-                match std::str::from_utf8(&value.stdout) {
-                    Ok(_) => (),
-                    Err(e) => println!("Invalid UTF-8 sequence in wat2wasm output: {}", e),
-                };
-                if !value.stderr.is_empty() {
-                    match std::str::from_utf8(&value.stderr) {
-                        Ok(v) => println!("Failed to generate wasm:\n{}", String::from(v)),
-                        Err(e) => println!("Invalid UTF-8 sequence in wat2wasm output: {}", e),
-                    };
-                } else {
-                    println!("File written to {} ", output_path_as_string);
-                }
+    /// Generated code is the only thing written to stdout, so that it can be
+    /// piped. Status goes through the logger and errors through report_errors,
+    /// both on stderr
+    fn print_code(code: &[u8]) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(code);
+        if !code.ends_with(b"\n") && std::str::from_utf8(code).is_ok() {
+            let _ = stdout.write_all(b"\n");
+        }
+    }
+
+    fn use_color(color: ColorChoice) -> bool {
+        match color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                !no_color && io::stderr().is_terminal()
             }
         }
     }
 
-    pub fn write_file(args: &Args) {
-        let output = compile_file(args);
+    /// Prints errors in the requested format, returning them as a single message
+    fn report_errors(
+        args: &Args,
+        source: Option<&str>,
+        code: ErrorCode,
+        errors: &[CompileError],
+    ) -> String {
+        let error = match code {
+            ErrorCode::ReadError => join_errors(errors),
+            ErrorCode::EncodingError => format!("Error decoding: {}", join_errors(errors)),
+            ErrorCode::ParseError => format!("Error parsing: {}", join_errors(errors)),
+            ErrorCode::TypeError => format!("Error typechecking: {}", join_errors(errors)),
+            ErrorCode::TransformError => format!("Error transforming: {}", join_errors(errors)),
+            ErrorCode::GenerateError => format!("Error generating: {}", join_errors(errors)),
+        };
 
-        if args.target == "wasm" {
-            return;
+        for error in errors {
+            let diagnostic = Diagnostic::from_error(&args.file, error, code);
+
+            match args.error_format {
+                ErrorFormat::Human => {
+                    eprintln!("{}\n", diagnostic.render(source, use_color(args.color)))
+                }
+                ErrorFormat::Json => eprintln!("{}", diagnostic.to_json()),
+            }
         }
 
-        match output {
-            Ok(code) => {
-                let original_file_path = &args.file;
-                let mut path = Path::new("gwe_build").join(Path::new(&original_file_path));
-                path.set_extension(&args.target);
+        error
+    }
+
+    fn read_source(args: &Args) -> Result<String, String> {
+        match fs::read(&args.file) {
+            Ok(bytes) => decode(&bytes)
+                .map_err(|error| report_errors(args, None, ErrorCode::EncodingError, &[error])),
+            Err(error) => Err(report_errors(
+                args,
+                None,
+                ErrorCode::ReadError,
+                &[CompileError::new(format!(
+                    "Unable to read file due to {}",
+                    error
+                ))],
+            )),
+        }
+    }
+
+    /// The directory holding the source file, which the files embedded by
+    /// data blocks are relative to
+    fn source_directory(args: &Args) -> &Path {
+        Path::new(&args.file)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+    }
+
+    /// Reads the files embedded by data blocks, relative to the source file
+    fn read_data(args: &Args, body: &str, program: Program) -> Result<Program, String> {
+        load_data(program, source_directory(args))
+            .map_err(|error| report_errors(args, Some(body), ErrorCode::ReadError, &[error]))
+    }
+
+    pub fn format_options(args: &Args) -> Result<FormatOptions, String> {
+        let config = config::read_config(source_directory(args))?;
 
-                let _ = fs::create_dir_all(path.as_path().parent().unwrap());
+        let options = FormatOptions {
+            indent_width: args.indent_width.unwrap_or(config.format.indent_width),
+            max_line_length: args
+                .max_line_length
+                .unwrap_or(config.format.max_line_length),
+            brace_style: args.brace_style.unwrap_or(config.format.brace_style),
+        };
+
+        options.validate()?;
+
+        Ok(options)
+    }
 
-                match fs::write(path.clone(), code) {
-                    Ok(_) => println!("File written to {}", path.as_os_str().to_string_lossy()),
-                    Err(error) => println!("Error writing file due to {}", error),
+    /// Where a module came from, so that a deployed one can be traced back to
+    /// the build that made it
+    pub struct BuildInfo {
+        pub version: String,
+        pub source: String,
+        pub built: DateTime<Utc>,
+    }
+
+    impl BuildInfo {
+        pub fn new(source: &str, built: DateTime<Utc>) -> Self {
+            BuildInfo {
+                version: String::from(env!("CARGO_PKG_VERSION")),
+                source: String::from(source),
+                built,
+            }
+        }
+
+        fn describe(&self, separator: &str) -> String {
+            format!(
+                "gwe {}{}source {}{}built {}",
+                self.version,
+                separator,
+                self.source,
+                separator,
+                self.built.format("%Y-%m-%dT%H:%M:%SZ")
+            )
+        }
+
+        /// The code with the build info added, as a comment at the top of
+        /// wat or a gwe custom section in wasm. Other targets are left alone
+        pub fn stamp(&self, extension: &str, code: Vec<u8>, layout: Layout) -> Vec<u8> {
+            match extension {
+                "wasm" => add_custom_section(&code, "gwe", self.describe("\n").as_bytes()),
+                "wat" | "gc.wat" => {
+                    // minified wat stays on one line
+                    let header = match layout {
+                        Layout::Minified => format!("(; {} ;) ", self.describe(", ")),
+                        _ => format!(";; {}\n", self.describe(", ")),
+                    };
+                    [header.into_bytes(), code].concat()
                 }
+                _ => code,
             }
-            Err(error) => println!("Not writing file due to {}", error),
         }
     }
 
-    pub fn compile_file(args: &Args) -> Result<String, String> {
-        let contents = fs::read_to_string(&args.file);
+    /// The code for every target as it's written out, stamped with the build
+    /// info unless the build is reproducible
+    pub fn stamped_targets(
+        args: &Args,
+        options: &CompilerOptions,
+    ) -> Result<Vec<(String, Vec<u8>)>, String> {
+        let outputs = compile_targets(args, options)?;
 
-        match contents {
-            Ok(body) => match parse(body) {
-                Ok(program) => {
-                    println!("Parsed successfully");
+        if args.reproducible || args.emit.is_some() {
+            return Ok(outputs);
+        }
+
+        let info = BuildInfo::new(&args.file, Utc::now());
+
+        Ok(outputs
+            .into_iter()
+            .map(|(extension, code)| {
+                let code = info.stamp(&extension, code, options.layout);
+                (extension, code)
+            })
+            .collect())
+    }
+
+    /// Writes the code for every target, giving back the paths written to
+    pub fn write_file(args: &Args) -> Result<Vec<PathBuf>, String> {
+        let options = args.compiler_options();
+
+        match stamped_targets(args, &options) {
+            Ok(outputs) => outputs
+                .into_iter()
+                .map(|(extension, code)| {
                     if args.format {
-                        let output = generators::gwe::generate(program);
-                        println!("{}", output);
-                        return Ok(output);
+                        print_code(&code);
                     }
-                    match args.target.as_str() {
-                        "wat" => {
-                            let output = generators::web_assembly::generate(program);
-                            Ok(output)
-                        }
-                        "wasm" => {
-                            write_file(&Args {
-                                target: String::from("wat"),
-                                ..args.clone()
-                            });
-                            compile_to_wasm(args);
-                            Ok(String::from(""))
-                        }
-                        "gwe" => {
-                            let output = generators::gwe::generate(program);
-                            Ok(output)
+
+                    let path = options.output_path(&args.file, &extension);
+
+                    if let Some(parent) = path.parent() {
+                        let _ = fs::create_dir_all(parent);
+                    }
+
+                    match write_atomically(&path, &code) {
+                        Ok(_) => {
+                            info!("File written to {}", path.as_os_str().to_string_lossy());
+                            Ok(path)
                         }
-                        _ => {
-                            let error = format!("Unknown target {}", args.target);
-                            println!("{}", error);
+                        Err(error) => {
+                            let error = format!("Error writing file due to {}", error);
+                            error!("{}", error);
                             Err(error)
                         }
                     }
+                })
+                .collect(),
+            Err(error) => {
+                warn!("Not writing file for {}", args.file);
+                Err(error)
+            }
+        }
+    }
+
+    /// The backend generating code for a target from the source, formatting
+    /// gwe with gwe.toml and keeping the wat wasm is encoded from on request
+    pub fn backend(
+        args: &Args,
+        options: &CompilerOptions,
+        target: &str,
+        source: &str,
+    ) -> Result<Box<dyn Backend>, String> {
+        match target {
+            "gwe" => Ok(Box::new(Gwe {
+                options: format_options(args)?,
+            })),
+            "wasm" => Ok(Box::new(WasmBinary {
+                wat: Wat {
+                    options: options.generate_options(target),
+                },
+                wat2wasm: options.wat2wasm.clone(),
+                file: args.file.clone(),
+                source: source.to_string(),
+                intermediate: args
+                    .keep_intermediate
+                    .then(|| options.output_path(&args.file, "wasm.wat")),
+            })),
+            target => pipeline::backend(target, options, &args.file, source)
+                .map_err(|error| error.message),
+        }
+    }
+
+    /// A backend for each target, or just gwe when formatting
+    fn backends(
+        args: &Args,
+        options: &CompilerOptions,
+        source: &str,
+    ) -> Result<Vec<Box<dyn Backend>>, String> {
+        if args.format {
+            // formatting writes gwe whatever the target
+            format_options(args).map(|options| vec![Box::new(Gwe { options }) as Box<dyn Backend>])
+        } else {
+            options
+                .targets
+                .iter()
+                .map(|target| backend(args, options, target, source))
+                .collect()
+        }
+    }
+
+    /// The generated code for a single target, as text for gwe and wat or binary for wasm
+    pub fn compile_file(args: &Args) -> Result<Vec<u8>, String> {
+        if !args.format && args.emit.is_none() && args.targets.len() > 1 {
+            let error = format!(
+                "Only one target can be generated at a time here, not {}",
+                args.targets.join(",")
+            );
+            error!("{}", error);
+            return Err(error);
+        }
+
+        stamped_targets(args, &args.compiler_options()).map(|mut outputs| outputs.remove(0).1)
+    }
+
+    /// The generated code for each target along with its extension. The file
+    /// is parsed and checked once, however many targets there are
+    pub fn compile_targets(
+        args: &Args,
+        options: &CompilerOptions,
+    ) -> Result<Vec<(String, Vec<u8>)>, String> {
+        let body = read_source(args)?;
+        let body = if args.format {
+            body
+        } else {
+            pipeline::instrument(&body, options).map_err(|error| {
+                report_errors(args, Some(&body), ErrorCode::GenerateError, &[error])
+            })?
+        };
+
+        let cache = build_cache(args, options, &body);
+        if let Some(outputs) = cache
+            .as_ref()
+            .and_then(|(cache, key)| cached_outputs(args, options, &body, cache, key))
+        {
+            debug!("Using cached output for {}", args.file);
+            return Ok(outputs);
+        }
+
+        let outputs = generate_targets(args, options, body)?;
+
+        if let Some((cache, key)) = cache {
+            for (extension, code) in &outputs {
+                if let Err(error) = cache.put(&key, extension, code) {
+                    warn!("Unable to cache {} due to {}", args.file, error);
                 }
-                Err(err) => {
-                    let error = format!("Error parsing: {}", err);
-                    println!("{}", error);
-                    Err(error)
-                }
+            }
+        }
+
+        Ok(outputs)
+    }
+
+    /// The cache and the key for the file's output, when it can be cached.
+    /// Data blocks and transforms read files the key doesn't cover, so
+    /// programs using them are always compiled, as are those being timed
+    pub fn build_cache(
+        args: &Args,
+        options: &CompilerOptions,
+        body: &str,
+    ) -> Option<(BuildCache, String)> {
+        let directory = options.cache.clone()?;
+
+        if args.format
+            || args.emit.is_some()
+            || args.timings
+            || !options.transforms.is_empty()
+            || body.lines().any(|line| line.starts_with("data "))
+        {
+            return None;
+        }
+
+        let settings = format!("{:?} {:?}", options, format_options(args).ok());
+
+        Some((BuildCache::new(directory), BuildCache::key(body, &settings)))
+    }
+
+    /// The output of every target, if each one is in the cache
+    fn cached_outputs(
+        args: &Args,
+        options: &CompilerOptions,
+        body: &str,
+        cache: &BuildCache,
+        key: &str,
+    ) -> Option<Vec<(String, Vec<u8>)>> {
+        backends(args, options, body)
+            .ok()?
+            .iter()
+            .map(|backend| {
+                cache
+                    .get(key, backend.extension())
+                    .map(|code| (backend.extension().to_string(), code))
+            })
+            .collect()
+    }
+
+    fn generate_targets(
+        args: &Args,
+        options: &CompilerOptions,
+        body: String,
+    ) -> Result<Vec<(String, Vec<u8>)>, String> {
+        let mut timings = Timings::default();
+
+        if args.timings {
+            let started = Instant::now();
+            timings.tokens = tokenize(body.clone()).len();
+            timings.tokenize = started.elapsed();
+        }
+
+        let started = Instant::now();
+        // formatting keeps the comments and blank lines, and the program as
+        // it was written
+        let parsed = if args.format {
+            parse_with_trivia(body.clone())
+                .map_err(|errors| Failure::new(ErrorCode::ParseError, errors))
+        } else {
+            pipeline::parse(&body, options)
+        };
+        timings.parse = started.elapsed();
+        debug!("Parsed {} in {:?}", args.file, timings.parse);
+
+        let report =
+            |failure: Failure| report_errors(args, Some(&body), failure.code, &failure.errors);
+        let program = parsed.map_err(report)?;
+
+        // the tree is wanted most when the program doesn't check
+        match args.emit {
+            Some(Emit::AstText) => {
+                return Ok(vec![(
+                    String::from("ast.txt"),
+                    program.to_string().into_bytes(),
+                )])
+            }
+            #[cfg(feature = "serde")]
+            Some(Emit::AstJson) => {
+                return serde_json::to_vec_pretty(&program)
+                    .map(|json| vec![(String::from("ast.json"), json)])
+                    .map_err(|error| format!("Unable to write the AST due to {}", error))
+            }
+            None => (),
+        }
+
+        let backends = match backends(args, options, &body) {
+            Ok(backends) => backends,
+            Err(error) => {
+                error!("{}", error);
+                return Err(error);
+            }
+        };
+
+        let outputs = pipeline::generate(program, &backends, source_directory(args), &mut timings)
+            .map_err(report)?;
+
+        if args.timings {
+            info!("{}", timings.report(&args.file));
+        }
+
+        Ok(outputs)
+    }
+
+    pub fn check_file(args: &Args) -> Result<(), String> {
+        let body = read_source(args)?;
+
+        match parse_with_defines(body.clone(), &args.defines) {
+            Ok(program) => match typechecker::check(&program) {
+                Ok(_) => Ok(()),
+                Err(errors) => Err(report_errors(
+                    args,
+                    Some(&body),
+                    ErrorCode::TypeError,
+                    &errors,
+                )),
             },
-            Err(file_read_error) => {
-                let error = format!("Unable to read file due to {}", file_read_error);
-                println!("{}", error);
-                Err(error)
+            Err(errors) => Err(report_errors(
+                args,
+                Some(&body),
+                ErrorCode::ParseError,
+                &errors,
+            )),
+        }
+    }
+
+    pub fn doc_file(args: &Args, format: DocFormat) -> Result<String, String> {
+        let body = read_source(args)?;
+
+        match parse_with_defines(body.clone(), &args.defines) {
+            Ok(program) => {
+                let title = Path::new(&args.file)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_else(|| args.file.to_string());
+
+                Ok(generators::docs::generate(&program, &title, format))
             }
+            Err(errors) => Err(report_errors(
+                args,
+                Some(&body),
+                ErrorCode::ParseError,
+                &errors,
+            )),
+        }
+    }
+
+    pub fn test_file(args: &Args) -> Result<Vec<TestOutcome>, String> {
+        let body = read_source(args)?;
+
+        match parse_with_defines(body.clone(), &args.defines) {
+            Ok(program) => match typechecker::check(&program) {
+                Ok(_) => run_tests(&read_data(args, &body, program)?).map_err(|error| {
+                    report_errors(
+                        args,
+                        Some(&body),
+                        ErrorCode::GenerateError,
+                        &[CompileError::new(error)],
+                    )
+                }),
+                Err(errors) => Err(report_errors(
+                    args,
+                    Some(&body),
+                    ErrorCode::TypeError,
+                    &errors,
+                )),
+            },
+            Err(errors) => Err(report_errors(
+                args,
+                Some(&body),
+                ErrorCode::ParseError,
+                &errors,
+            )),
+        }
+    }
+
+    fn instrument_source(args: &Args, body: &str) -> Result<Instrumented, String> {
+        coverage::instrument(body)
+            .map_err(|error| report_errors(args, Some(body), ErrorCode::GenerateError, &[error]))
+    }
+
+    /// Runs the tests with coverage, giving back an lcov report
+    pub fn coverage_file(args: &Args) -> Result<String, String> {
+        let source = read_source(args)?;
+        let Instrumented {
+            source: body,
+            lines,
+        } = instrument_source(args, &source)?;
+
+        match parse_with_defines(body.clone(), &args.defines) {
+            Ok(program) => match typechecker::check(&program) {
+                Ok(_) => {
+                    let options = GenerateOptions {
+                        coverage: true,
+                        ..GenerateOptions::default()
+                    };
+                    let outcomes =
+                        run_tests_with(&read_data(args, &body, program)?, &options, lines.len())
+                            .map_err(|error| {
+                                report_errors(
+                                    args,
+                                    Some(&source),
+                                    ErrorCode::GenerateError,
+                                    &[CompileError::new(error)],
+                                )
+                            })?;
+
+                    for outcome in &outcomes {
+                        if let Err(error) = &outcome.result {
+                            warn!("Test {} failed: {}", outcome.name, error);
+                        }
+                    }
+
+                    let hits: Vec<i32> = (0..lines.len())
+                        .map(|statement| {
+                            outcomes
+                                .iter()
+                                .filter_map(|outcome| outcome.hits.get(statement))
+                                .sum()
+                        })
+                        .collect();
+
+                    Ok(coverage::lcov(&args.file, &lines, &hits))
+                }
+                Err(errors) => Err(report_errors(
+                    args,
+                    Some(&source),
+                    ErrorCode::TypeError,
+                    &errors,
+                )),
+            },
+            Err(errors) => Err(report_errors(
+                args,
+                Some(&source),
+                ErrorCode::ParseError,
+                &errors,
+            )),
         }
     }
 
-    fn compile_or_write(args: &Args) {
+    /// One line per test, then a summary in the style of cargo test
+    pub fn test_report(outcomes: &[TestOutcome]) -> String {
+        let failed = outcomes
+            .iter()
+            .filter(|outcome| outcome.result.is_err())
+            .count();
+
+        let lines: String = outcomes
+            .iter()
+            .map(|outcome| match &outcome.result {
+                Ok(_) => format!("test {} ... ok\n", outcome.name),
+                Err(error) => format!("test {} ... FAILED\n    {}\n", outcome.name, error),
+            })
+            .collect();
+
+        format!(
+            "{}\ntest result: {}. {} passed; {} failed\n",
+            lines,
+            if failed == 0 { "ok" } else { "FAILED" },
+            outcomes.len() - failed,
+            failed
+        )
+    }
+
+    /// Returns the paths written to, with none when the code went to stdout
+    fn compile_or_write(args: &Args) -> Result<Vec<PathBuf>, String> {
         if args.stdout {
-            if let Ok(code) = compile_file(args) {
-                println!("{}", code)
-            };
+            let code = compile_file(args)?;
+            print_code(&code);
+            Ok(vec![])
         } else {
-            write_file(args);
+            write_file(args)
         }
     }
 
-    pub fn run() {
-        let args = Args::parse();
+    /// Runs the --on-success command through the shell, with the source file in
+    /// GWE_SOURCE and the written files in GWE_OUTPUT, separated like PATH
+    fn run_hook(command: &str, args: &Args, outputs: &[PathBuf]) {
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.args(["/C", command]);
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.args(["-c", command]);
+            shell
+        };
 
-        if args.watch {
-            println!("Watching file {}", args.file);
-            let (tx, rx) = std::sync::mpsc::channel();
-
-            let mut debouncer =
-                notify_debouncer_mini::new_debouncer(Duration::from_secs(1), tx).unwrap();
-
-            debouncer
-                .watcher()
-                .watch(Path::new(&args.file), RecursiveMode::Recursive)
-                .unwrap();
-
-            let cwd = current_dir().unwrap().to_string_lossy().to_string();
-
-            for events in rx.into_iter().flatten() {
-                for event in events {
-                    let path: String = event
-                        .path
-                        .to_string_lossy()
-                        .to_string()
-                        .chars()
-                        .skip(cwd.len() + 1)
-                        .collect();
+        shell.env("GWE_SOURCE", &args.file);
+        shell.env("GWE_OUTPUT", env::join_paths(outputs).unwrap_or_default());
+
+        match shell.status() {
+            Ok(status) if status.success() => debug!("Ran {}", command),
+            Ok(status) => warn!("{} exited with {}", command, status),
+            Err(error) => error!("Unable to run {} due to {}", command, error),
+        }
+    }
 
-                    compile_or_write(&Args {
-                        file: path.to_string(),
+    /// The path relative to the directory when it's inside it, so that output
+    /// keeps the same layout under gwe_build. Paths are compared by component
+    /// rather than by length, which works with either separator
+    pub fn relative_to(path: &Path, directory: &Path) -> String {
+        let canonical = directory.canonicalize().ok();
+        let relative = path
+            .strip_prefix(directory)
+            .ok()
+            .or_else(|| {
+                canonical
+                    .as_deref()
+                    .and_then(|directory| path.strip_prefix(directory).ok())
+            })
+            .unwrap_or(path);
+
+        relative.to_string_lossy().to_string()
+    }
+
+    /// Which files in a watched directory are compiled when they change
+    pub struct WatchFilter {
+        include: GlobSet,
+        exclude: GlobSet,
+    }
+
+    impl WatchFilter {
+        pub fn new(include: &[String], exclude: &[String]) -> Result<Self, String> {
+            let build = |globs: &[String]| {
+                let mut set = GlobSetBuilder::new();
+                for glob in globs {
+                    match Glob::new(glob) {
+                        Ok(glob) => set.add(glob),
+                        Err(error) => {
+                            return Err(format!("Invalid glob {} due to {}", glob, error))
+                        }
+                    };
+                }
+                set.build()
+                    .map_err(|error| format!("Invalid globs due to {}", error))
+            };
+
+            Ok(WatchFilter {
+                include: build(include)?,
+                exclude: build(exclude)?,
+            })
+        }
+
+        /// Whether a path, relative to the watched directory, should be compiled
+        pub fn matches(&self, path: &Path) -> bool {
+            self.include.is_match(path) && !self.exclude.is_match(path)
+        }
+    }
+
+    /// What's watched for a file or --watch-dir. Editors often save by
+    /// writing a new file over the old one, which ends a watch on the file
+    /// itself, so a single file is watched through its directory
+    pub fn watched(args: &Args) -> Result<(PathBuf, RecursiveMode, WatchFilter), String> {
+        match &args.watch_dir {
+            Some(directory) => Ok((
+                directory.clone(),
+                RecursiveMode::Recursive,
+                WatchFilter::new(&args.include, &args.exclude)?,
+            )),
+            None => {
+                let file = Path::new(&args.file);
+                let directory = match file.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                };
+                let name = file
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+
+                Ok((
+                    directory.to_path_buf(),
+                    RecursiveMode::NonRecursive,
+                    WatchFilter::new(&[globset::escape(&name)], &[])?,
+                ))
+            }
+        }
+    }
+
+    /// `[12:03:04] ok main.gwe in 3ms`, one line per rebuild
+    pub fn status_line(time: NaiveTime, file: &str, succeeded: bool, took: Duration) -> String {
+        format!(
+            "[{}] {} {} in {}ms",
+            time.format("%H:%M:%S"),
+            if succeeded { "ok" } else { "failed" },
+            file,
+            took.as_millis()
+        )
+    }
+
+    /// Recompiles the file, or the matching files in --watch-dir, on every
+    /// change, calling `on_compiled` with the written paths after each
+    /// successful compile
+    fn watch(args: &Args, on_compiled: impl Fn(&Args, &[PathBuf])) -> Result<(), String> {
+        let (directory, mode, filter) = watched(args)?;
+
+        info!("Watching {}", directory.to_string_lossy());
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let mut debouncer = match notify_debouncer_mini::new_debouncer(Duration::from_secs(1), tx) {
+            Ok(debouncer) => debouncer,
+            Err(error) => return Err(format!("Unable to start watcher due to {}", error)),
+        };
+
+        if let Err(error) = debouncer.watcher().watch(&directory, mode) {
+            return Err(format!(
+                "Unable to watch {} due to {}",
+                directory.to_string_lossy(),
+                error
+            ));
+        }
+
+        let cwd = match current_dir() {
+            Ok(cwd) => cwd,
+            Err(error) => return Err(format!("Unable to read current directory due to {}", error)),
+        };
+
+        for result in rx {
+            let events = match result {
+                Ok(events) => events,
+                Err(error) => {
+                    warn!("Watching {} failed: {}", directory.to_string_lossy(), error);
+
+                    // the directory may have been replaced, in which case
+                    // the new one is watched
+                    let watcher = debouncer.watcher();
+                    let _ = watcher.unwatch(&directory);
+                    if let Err(error) = watcher.watch(&directory, mode) {
+                        error!(
+                            "Unable to watch {} again due to {}",
+                            directory.to_string_lossy(),
+                            error
+                        );
+                    }
+                    continue;
+                }
+            };
+
+            for event in events {
+                // directories and deleted files change too
+                if !event.path.is_file()
+                    || !filter.matches(Path::new(&relative_to(&event.path, &directory)))
+                {
+                    continue;
+                }
+
+                let args = match args.watch_dir {
+                    Some(_) => Args {
+                        file: relative_to(&event.path, &cwd),
                         ..args.clone()
-                    })
+                    },
+                    None => args.clone(),
+                };
+
+                debug!("Change detected in {}", args.file);
+
+                let started = Instant::now();
+                let result = compile_or_write(&args);
+                info!(
+                    "{}",
+                    status_line(
+                        Local::now().time(),
+                        &args.file,
+                        result.is_ok(),
+                        started.elapsed()
+                    )
+                );
+
+                if let Ok(outputs) = result {
+                    on_compiled(&args, &outputs);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn repl() {
+        let mut session = Session::new();
+        let stdin = io::stdin();
+        let mut input = String::new();
+
+        println!("gwe repl, type :help for commands");
+
+        loop {
+            print!("{}", if input.is_empty() { "> " } else { "| " });
+            let _ = io::stdout().flush();
+
+            let mut line = String::new();
+            match stdin.read_line(&mut line) {
+                Ok(0) => break,
+                Ok(_) => input.push_str(&line),
+                Err(error) => {
+                    error!("Unable to read input due to {}", error);
+                    break;
+                }
+            }
+
+            if !is_complete(&input) {
+                continue;
+            }
+
+            if input.trim() == ":quit" {
+                break;
+            }
+
+            match session.eval(&input) {
+                Ok(lines) => {
+                    for line in lines {
+                        println!("{}", line);
+                    }
                 }
+                Err(error) => eprintln!("{}", error),
+            }
+
+            input.clear();
+        }
+    }
+
+    fn serve(args: Args, port: u16) -> Result<(), String> {
+        let address = format!("127.0.0.1:{}", port);
+        let server = DevServer::start(
+            &address,
+            args.compiler_options().output_path(&args.file, "wasm"),
+        )?;
+
+        info!("Serving {} at http://{}", args.file, server.address());
+        let _ = compile_or_write(&args);
+
+        watch(&args, |_, _| server.reload())
+    }
+
+    fn compile(args: Args) {
+        if args.watch {
+            let result = watch(&args, |args, outputs| {
+                if let Some(command) = &args.on_success {
+                    run_hook(command, args, outputs);
+                }
+            });
+
+            if let Err(error) = result {
+                error!("{}", error);
+                exit(1);
             }
         } else {
-            println!("Compiling file {}", args.file);
-            compile_or_write(&args);
+            info!("Compiling file {}", args.file);
+            let _ = compile_or_write(&args);
+        }
+    }
+
+    pub fn run() {
+        let cli = Cli::parse();
+
+        init_logging(cli.verbose, cli.quiet);
+
+        if cli.help_man {
+            let mut page: Vec<u8> = vec![];
+            match Man::new(Cli::command()).render(&mut page) {
+                Ok(_) => print_code(&page),
+                Err(error) => {
+                    error!("Unable to write the man page due to {}", error);
+                    exit(1);
+                }
+            }
+            return;
+        }
+
+        match (cli.command, cli.args) {
+            (Some(Commands::Repl), _) => repl(),
+            (Some(Commands::Completions { shell }), _) => {
+                let mut script: Vec<u8> = vec![];
+                generate(shell, &mut Cli::command(), "gwe", &mut script);
+                print_code(&script);
+            }
+            (
+                Some(Commands::Check {
+                    file,
+                    error_format,
+                    color,
+                }),
+                _,
+            ) => {
+                let args = Args {
+                    file,
+                    error_format,
+                    color,
+                    ..Args::default()
+                };
+
+                match check_file(&args) {
+                    Ok(_) => {
+                        if error_format == ErrorFormat::Human {
+                            info!("No errors found in {}", args.file)
+                        }
+                    }
+                    Err(_) => exit(1),
+                }
+            }
+            (Some(Commands::Doc { file, format }), _) => {
+                let args = Args {
+                    file,
+                    ..Args::default()
+                };
+
+                match doc_file(&args, format) {
+                    Ok(docs) => print_code(docs.as_bytes()),
+                    Err(_) => exit(1),
+                }
+            }
+            (Some(Commands::Test { file }), _) => {
+                let args = Args {
+                    file,
+                    ..Args::default()
+                };
+
+                match test_file(&args) {
+                    Ok(outcomes) => {
+                        print_code(test_report(&outcomes).as_bytes());
+
+                        if outcomes.iter().any(|outcome| outcome.result.is_err()) {
+                            exit(1);
+                        }
+                    }
+                    Err(_) => exit(1),
+                }
+            }
+            (Some(Commands::Coverage { file }), _) => {
+                let args = Args {
+                    file,
+                    ..Args::default()
+                };
+
+                match coverage_file(&args) {
+                    Ok(report) => print_code(report.as_bytes()),
+                    Err(_) => exit(1),
+                }
+            }
+            (
+                Some(Commands::New { name }),
+                _,
+            ) => match create_project(&name) {
+                Ok(_) => info!(
+                    "Created {}. Build it there with gwe --file main.gwe --target wasm, then serve the directory and open index.html",
+                    name.to_string_lossy()
+                ),
+                Err(error) => {
+                    error!("{}", error);
+                    exit(1);
+                }
+            },
+            (
+                Some(Commands::Serve {
+                    file,
+                    wat2wasm,
+                    port,
+                }),
+                _,
+            ) => {
+                let args = Args {
+                    file,
+                    targets: vec![String::from("wasm")],
+                    wat2wasm,
+                    ..Args::default()
+                };
+
+                if let Err(error) = serve(args, port) {
+                    error!("{}", error);
+                    exit(1);
+                }
+            }
+            (None, Some(args)) => compile(args),
+            (None, None) => {
+                let _ = Cli::command().print_help();
+            }
         }
     }
 }
@@ -202,6 +1430,11 @@ fn main() {
 #[cfg(test)]
 mod tests {
     use std::fs::{self};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use gwe::generators::layout::Layout;
+    use gwe::options::{CompilerOptions, Optimize};
 
     use super::cli::*;
 
@@ -214,13 +1447,18 @@ mod tests {
         for file in files.unwrap() {
             match file {
                 Ok(entry) => {
-                    if entry.path().to_string_lossy().to_string().ends_with("gwe") {
+                    // examples the compiler rejects are snapshotted with their error
+                    let rejected = Path::new("tests/snapshots")
+                        .join(entry.file_name())
+                        .with_extension("err")
+                        .exists();
+
+                    if entry.path().to_string_lossy().to_string().ends_with("gwe") && !rejected {
                         match compile_file(&Args {
                             file: entry.path().to_string_lossy().to_string(),
-                            target: String::from("gwe"),
-                            format: false,
+                            targets: vec![String::from("gwe")],
                             stdout: true,
-                            watch: false,
+                            ..Args::default()
                         }) {
                             Ok(_) => (),
                             Err(err) => panic!("Failed to compile file {:?} due to {}", entry, err),
@@ -231,4 +1469,537 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn check_reports_parse_errors() {
+        assert_eq!(
+            check_file(&Args {
+                file: String::from("examples/hello_world.gwe"),
+                ..Args::default()
+            }),
+            Ok(())
+        );
+
+        let path = std::env::temp_dir().join("gwe_check_reports_parse_errors.gwe");
+        fs::write(&path, "fn say_hello {}").unwrap();
+
+        assert_eq!(
+            check_file(&Args {
+                file: path.to_string_lossy().to_string(),
+                ..Args::default()
+            }),
+            Err(String::from(
+                "Error parsing: Expected parens but got { at line 1, index 13"
+            ))
+        );
+    }
+
+    #[test]
+    fn coverage_reports_the_lines_tests_ran() {
+        let path = std::env::temp_dir().join("gwe_coverage_reports_the_lines_tests_ran.gwe");
+        fs::write(
+            &path,
+            "import fn log(n: i32) console.log\n\nfn pick(n: i32): void {\n    if (n) {\n        log(1);\n    } else {\n        log(2);\n    };\n}\n\ntest \"picks\" {\n    pick(0);\n    pick(0);\n}\n",
+        )
+        .unwrap();
+        let file = path.to_string_lossy().to_string();
+
+        assert_eq!(
+            coverage_file(&Args {
+                file: file.to_string(),
+                ..Args::default()
+            }),
+            Ok(format!(
+                "TN:\nSF:{}\nDA:4,2\nDA:5,0\nDA:7,2\nDA:12,1\nDA:13,1\nLF:5\nLH:4\nend_of_record\n",
+                file
+            ))
+        );
+    }
+
+    #[test]
+    fn doc_titles_the_page_with_the_file_name() {
+        let docs = doc_file(
+            &Args {
+                file: String::from("examples/hello_world.gwe"),
+                ..Args::default()
+            },
+            gwe::generators::docs::DocFormat::Markdown,
+        );
+
+        assert!(docs.is_ok_and(|docs| docs.starts_with("# hello_world\n")));
+    }
+
+    #[test]
+    fn test_reports_each_test() {
+        let path = std::env::temp_dir().join("gwe_test_reports_each_test.gwe");
+        fs::write(
+            &path,
+            "fn forever(): void {\n    forever();\n}\n\ntest \"passes\" {\n}\n\ntest \"fails\" {\n    forever();\n}\n",
+        )
+        .unwrap();
+
+        let outcomes = test_file(&Args {
+            file: path.to_string_lossy().to_string(),
+            ..Args::default()
+        })
+        .unwrap();
+
+        assert!(test_report(&outcomes).starts_with("test passes ... ok\ntest fails ... FAILED\n"));
+        assert!(test_report(&outcomes).ends_with("\ntest result: FAILED. 1 passed; 1 failed\n"));
+    }
+
+    #[test]
+    fn on_success_requires_watch() {
+        use clap::Parser;
+
+        assert!(Cli::try_parse_from(["gwe", "--file", "main.gwe", "--on-success", "ls"]).is_err());
+        assert!(Cli::try_parse_from([
+            "gwe",
+            "--file",
+            "main.gwe",
+            "--watch",
+            "--on-success",
+            "ls"
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn data_is_read_next_to_the_source() {
+        let directory = std::env::temp_dir().join("gwe_data_is_read_next_to_the_source");
+        fs::create_dir_all(directory.join("assets")).unwrap();
+        fs::write(
+            directory.join("assets").join("logo.bin"),
+            [0x89, b'P', b'"'],
+        )
+        .unwrap();
+        let path = directory.join("main.gwe");
+        fs::write(
+            &path,
+            "import memory 1 js.mem\ndata \"assets/logo.bin\" as logo\n\nfn size(): i32 {\n    return logo.length;\n}\n",
+        )
+        .unwrap();
+
+        let output = compile_file(&Args {
+            file: path.to_string_lossy().to_string(),
+            ..Args::default()
+        })
+        .map(|bytes| String::from_utf8(bytes).unwrap())
+        .unwrap();
+
+        assert!(output.contains("(data (i32.const 0) \"\\89P\\22\")"));
+        assert!(output.contains("(i32.const 3)"));
+
+        let missing = directory.join("missing.gwe");
+        fs::write(&missing, "data \"assets/missing.bin\" as missing\n").unwrap();
+
+        assert!(compile_file(&Args {
+            file: missing.to_string_lossy().to_string(),
+            ..Args::default()
+        })
+        .unwrap_err()
+        .starts_with("Unable to read data missing from assets/missing.bin"));
+    }
+
+    #[test]
+    fn targets_pick_a_backend() {
+        let extension = |target: &str| {
+            backend(&Args::default(), &CompilerOptions::default(), target, "")
+                .map(|backend| backend.extension().to_string())
+        };
+
+        assert_eq!(extension("wat"), Ok(String::from("wat")));
+        assert_eq!(extension("wat-gc"), Ok(String::from("gc.wat")));
+        assert_eq!(extension("wasm"), Ok(String::from("wasm")));
+        assert_eq!(extension("js"), Ok(String::from("js")));
+        assert_eq!(extension("dts"), Ok(String::from("d.ts")));
+        assert_eq!(extension("gwe"), Ok(String::from("gwe")));
+        assert_eq!(extension("exe"), Err(String::from("Unknown target exe")));
+    }
+
+    #[test]
+    fn several_targets_are_written_from_one_parse() {
+        let path = std::env::temp_dir().join("gwe_several_targets.gwe");
+        fs::write(&path, "@export\nfn one(): f32 {\n    return 1;\n}\n").unwrap();
+        let args = Args {
+            file: path.to_string_lossy().to_string(),
+            targets: vec![String::from("wat"), String::from("js"), String::from("dts")],
+            ..Args::default()
+        };
+
+        let written = write_file(&args).unwrap();
+
+        assert_eq!(
+            written,
+            vec![
+                args.compiler_options().output_path(&args.file, "wat"),
+                args.compiler_options().output_path(&args.file, "js"),
+                args.compiler_options().output_path(&args.file, "d.ts")
+            ]
+        );
+        assert!(fs::read_to_string(&written[2])
+            .unwrap()
+            .contains("\"one\": () => number;"));
+        assert!(compile_file(&args).is_err());
+    }
+
+    #[test]
+    fn intermediate_wat_is_only_kept_on_request() {
+        let path = std::env::temp_dir().join("gwe_keep_intermediate.gwe");
+        fs::write(&path, "@export\nfn one(): f32 {\n    return 1;\n}\n").unwrap();
+        let args = Args {
+            file: path.to_string_lossy().to_string(),
+            targets: vec![String::from("wasm")],
+            ..Args::default()
+        };
+        let intermediate = args.compiler_options().output_path(&args.file, "wasm.wat");
+        let _ = fs::remove_file(&intermediate);
+
+        assert_eq!(
+            write_file(&args),
+            Ok(vec![args
+                .compiler_options()
+                .output_path(&args.file, "wasm")])
+        );
+        assert!(!intermediate.exists());
+
+        let keeping = Args {
+            keep_intermediate: true,
+            ..args
+        };
+
+        assert_eq!(
+            write_file(&keeping),
+            Ok(vec![keeping
+                .compiler_options()
+                .output_path(&keeping.file, "wasm")])
+        );
+        assert!(fs::read_to_string(&intermediate)
+            .unwrap()
+            .starts_with("(module"));
+    }
+
+    #[test]
+    fn minify_puts_the_wat_on_one_line() {
+        use clap::Parser;
+
+        let output = compile_file(&Args {
+            file: String::from("examples/hello_world.gwe"),
+            minify: true,
+            reproducible: true,
+            ..Args::default()
+        })
+        .unwrap();
+
+        assert!(output.starts_with(b"(module (func $hello_world (result f32) (f32.const 1))"));
+        assert!(!output.contains(&b'\n'));
+        assert!(
+            Cli::try_parse_from(["gwe", "--file", "main.gwe", "--minify", "--pretty"]).is_err()
+        );
+    }
+
+    #[test]
+    fn emit_ast_text_prints_the_tree() {
+        let output = compile_file(&Args {
+            file: String::from("examples/if_statement.gwe"),
+            emit: Some(Emit::AstText),
+            ..Args::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "ImportFunction log(n: f32) from console.log
+Function main(n: i32): void
+  IfStatement
+    Variable n: i32
+    then
+      FunctionCall log
+        Number 3.14: f32
+    else
+      FunctionCall log
+        Number 42: f32
+Export main as main
+"
+        );
+    }
+
+    #[test]
+    fn defines_replace_constants() {
+        let path = std::env::temp_dir().join("gwe_defines_replace_constants.gwe");
+        fs::write(
+            &path,
+            "const SIZE: i32 = 4;\n\nfn size(): i32 {\n    return SIZE;\n}\n\nfn max(): i32 {\n    return MAX;\n}\n",
+        )
+        .unwrap();
+        let args = Args {
+            file: path.to_string_lossy().to_string(),
+            defines: vec!["SIZE=8".parse().unwrap(), "MAX = 64".parse().unwrap()],
+            ..Args::default()
+        };
+
+        let wat = String::from_utf8(compile_file(&args).unwrap()).unwrap();
+
+        assert!(wat.contains("(i32.const 8)"));
+        assert!(wat.contains("(i32.const 64)"));
+        assert!(!wat.contains("(i32.const 4)"));
+    }
+
+    #[test]
+    fn unchanged_files_come_from_the_cache() {
+        let path = std::env::temp_dir().join("gwe_unchanged_files_come_from_the_cache.gwe");
+        let source = "fn cached(): f32 {\n    return 1;\n}\n";
+        fs::write(&path, source).unwrap();
+
+        let args = Args {
+            file: path.to_string_lossy().to_string(),
+            reproducible: true,
+            ..Args::default()
+        };
+        let directory = std::env::temp_dir().join("gwe_unchanged_files_come_from_the_cache");
+        let _ = fs::remove_dir_all(&directory);
+        let options = CompilerOptions {
+            cache: Some(directory.clone()),
+            ..args.compiler_options()
+        };
+        let (cache, key) = build_cache(&args, &options, source).unwrap();
+
+        let output = compile_targets(&args, &options).unwrap().remove(0).1;
+        assert_eq!(cache.get(&key, "wat"), Some(output));
+
+        cache.put(&key, "wat", b"(module)").unwrap();
+        assert_eq!(
+            compile_targets(&args, &options).unwrap().remove(0).1,
+            b"(module)"
+        );
+
+        let optimized = Args {
+            optimize: Optimize::Level(1),
+            ..args.clone()
+        };
+        let optimized_options = CompilerOptions {
+            cache: Some(directory.clone()),
+            ..optimized.compiler_options()
+        };
+        assert!(build_cache(&optimized, &optimized_options, source)
+            .is_some_and(|(_, other)| other != key));
+        assert!(build_cache(&args, &options, "data \"logo.bin\" as logo\n").is_none());
+        assert!(build_cache(&args, &args.compiler_options(), source).is_none());
+
+        // --cache keeps the cache in the directory gwe is run from
+        let cached = Args {
+            cache: true,
+            ..args
+        };
+        assert_eq!(
+            cached.compiler_options().cache,
+            Some(PathBuf::from(".gwe-cache"))
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn files_with_a_byte_order_mark_compile() {
+        let path = std::env::temp_dir().join("gwe_files_with_a_byte_order_mark_compile.gwe");
+        fs::write(&path, b"\xEF\xBB\xBFfn one(): f32 {\n    return 1;\n}\n").unwrap();
+
+        let args = Args {
+            file: path.to_string_lossy().to_string(),
+            reproducible: true,
+            ..Args::default()
+        };
+
+        assert!(compile_file(&args)
+            .unwrap()
+            .starts_with(b"(module\n  (func $one"));
+
+        fs::write(&path, b"fn one(): f32 {\n    return \xFF;\n}\n").unwrap();
+        assert_eq!(
+            compile_file(&args),
+            Err(String::from(
+                "Error decoding: File is not valid UTF-8, save it as UTF-8 to compile it at line 2, index 11"
+            ))
+        );
+    }
+
+    #[test]
+    fn watch_dir_compiles_matching_files() {
+        use clap::Parser;
+
+        let filter =
+            WatchFilter::new(&[String::from("**/*.gwe")], &[String::from("vendor/**")]).unwrap();
+
+        assert!(filter.matches(Path::new("main.gwe")));
+        assert!(filter.matches(Path::new("lib/math.gwe")));
+        assert!(!filter.matches(Path::new("lib/math.wat")));
+        assert!(!filter.matches(Path::new("vendor/other.gwe")));
+        assert!(WatchFilter::new(&[String::from("[")], &[]).is_err());
+
+        let cli = Cli::try_parse_from([
+            "gwe",
+            "--watch",
+            "--watch-dir",
+            "src",
+            "--exclude",
+            "vendor/**",
+            "--on-success",
+            "true",
+        ])
+        .unwrap();
+        let args = cli.args.unwrap();
+        assert_eq!(args.watch_dir, Some(PathBuf::from("src")));
+        assert_eq!(args.include, vec!["**/*.gwe"]);
+
+        assert!(Cli::try_parse_from(["gwe", "--watch-dir", "src"]).is_err());
+        assert!(Cli::try_parse_from(["gwe", "--file", "main.gwe", "--include", "*.gwe"]).is_err());
+    }
+
+    #[test]
+    fn single_files_are_watched_through_their_directory() {
+        let (directory, _, filter) = watched(&Args {
+            file: String::from("examples/if_statement.gwe"),
+            ..Args::default()
+        })
+        .unwrap();
+
+        assert_eq!(directory, Path::new("examples"));
+        assert!(filter.matches(Path::new("if_statement.gwe")));
+        assert!(!filter.matches(Path::new("addition.gwe")));
+
+        let (directory, _, _) = watched(&Args {
+            file: String::from("main[1].gwe"),
+            ..Args::default()
+        })
+        .unwrap();
+        assert_eq!(directory, Path::new("."));
+
+        assert_eq!(
+            status_line(
+                chrono::NaiveTime::from_hms_opt(9, 5, 3).unwrap(),
+                "main.gwe",
+                false,
+                Duration::from_millis(12)
+            ),
+            "[09:05:03] failed main.gwe in 12ms"
+        );
+    }
+
+    #[test]
+    fn watched_paths_are_relative_to_the_directory() {
+        let directory = std::env::current_dir().unwrap();
+
+        assert_eq!(
+            relative_to(&directory.join("examples").join("addition.gwe"), &directory),
+            Path::new("examples").join("addition.gwe").to_string_lossy()
+        );
+        assert_eq!(
+            relative_to(Path::new("/elsewhere/main.gwe"), Path::new("/project")),
+            "/elsewhere/main.gwe"
+        );
+    }
+
+    #[test]
+    fn crlf_files_compile_like_lf_files() {
+        let path = std::env::temp_dir().join("gwe_crlf_files_compile_like_lf_files.gwe");
+        let source = fs::read_to_string("examples/if_statement.gwe").unwrap();
+
+        let args = Args {
+            file: path.to_string_lossy().to_string(),
+            reproducible: true,
+            ..Args::default()
+        };
+
+        fs::write(&path, &source).unwrap();
+        let lf = compile_file(&args).unwrap();
+
+        fs::write(&path, source.replace('\n', "\r\n")).unwrap();
+        assert_eq!(compile_file(&args).unwrap(), lf);
+    }
+
+    #[test]
+    fn builds_are_stamped_unless_reproducible() {
+        let built = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let info = BuildInfo::new("main.gwe", built);
+        let version = env!("CARGO_PKG_VERSION");
+
+        assert_eq!(
+            info.stamp("wat", b"(module)".to_vec(), Layout::Generated),
+            format!(";; gwe {version}, source main.gwe, built 2023-11-14T22:13:20Z\n(module)")
+                .into_bytes()
+        );
+        assert_eq!(
+            info.stamp("wat", b"(module)".to_vec(), Layout::Minified),
+            format!("(; gwe {version}, source main.gwe, built 2023-11-14T22:13:20Z ;) (module)")
+                .into_bytes()
+        );
+        assert_eq!(
+            info.stamp("js", b"export {}".to_vec(), Layout::Generated),
+            b"export {}"
+        );
+
+        let wasm = info.stamp("wasm", b"\0asm\x01\0\0\0".to_vec(), Layout::Generated);
+        assert!(wasm.ends_with(
+            format!("gwe {version}\nsource main.gwe\nbuilt 2023-11-14T22:13:20Z").as_bytes()
+        ));
+
+        let args = Args {
+            file: String::from("examples/hello_world.gwe"),
+            ..Args::default()
+        };
+        assert!(compile_file(&args).unwrap().starts_with(b";; gwe "));
+        assert!(compile_file(&Args {
+            reproducible: true,
+            ..args
+        })
+        .unwrap()
+        .starts_with(b"(module"));
+    }
+
+    #[test]
+    fn completions_and_man_page_come_from_the_cli() {
+        use clap::{CommandFactory, Parser};
+
+        let cli = Cli::try_parse_from(["gwe", "completions", "bash"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Completions {
+                shell: clap_complete::Shell::Bash
+            })
+        ));
+
+        let mut script: Vec<u8> = vec![];
+        clap_complete::generate(
+            clap_complete::Shell::Bash,
+            &mut Cli::command(),
+            "gwe",
+            &mut script,
+        );
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("completions"));
+        assert!(script.contains("--watch-dir"));
+
+        assert!(Cli::try_parse_from(["gwe", "--help-man"]).unwrap().help_man);
+        assert!(Cli::try_parse_from(["gwe", "--help-man", "--file", "main.gwe"]).is_err());
+
+        let mut page: Vec<u8> = vec![];
+        clap_mangen::Man::new(Cli::command())
+            .render(&mut page)
+            .unwrap();
+        assert!(String::from_utf8(page).unwrap().contains(".TH gwe 1"));
+    }
+
+    #[test]
+    fn wasm_target_returns_bytes() {
+        let path = std::env::temp_dir().join("gwe_wasm_target_returns_bytes.gwe");
+        fs::write(&path, "fn one(): f32 {\n    return 1;\n}\n").unwrap();
+
+        let output = compile_file(&Args {
+            file: path.to_string_lossy().to_string(),
+            targets: vec![String::from("wasm")],
+            ..Args::default()
+        });
+
+        assert!(output.unwrap().starts_with(b"\0asm"));
+    }
 }
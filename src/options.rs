@@ -0,0 +1,311 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    generators::{
+        layout::Layout,
+        naming::NamingPolicy,
+        web_assembly::{Exceptions, GenerateOptions, Instrument},
+    },
+    tokenizer::{check_name, tokenize, Token},
+};
+
+/// What a build is for, which decides the passes the compiler runs
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Profile {
+    /// No passes, so the output follows the source closely and builds fast
+    Dev,
+    /// Every pass. Inlining saves calls at the cost of copying function
+    /// bodies, so modules come out faster but can be bigger than with `Size`
+    Release,
+    /// Every pass apart from inlining, for the smallest modules
+    Size,
+}
+
+/// `-O` levels, where `s` is the size profile
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Optimize {
+    Level(u8),
+    Size,
+}
+
+impl FromStr for Optimize {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "0" => Ok(Optimize::Level(0)),
+            "1" => Ok(Optimize::Level(1)),
+            "2" => Ok(Optimize::Level(2)),
+            "s" => Ok(Optimize::Size),
+            _ => Err(format!(
+                "Unknown optimization level {}, expected 0, 1, 2 or s",
+                str
+            )),
+        }
+    }
+}
+
+/// `--define MAX=64`, a constant given when building rather than in the
+/// source. A name on its own, like `--define DEBUG`, is 1
+#[derive(PartialEq, Debug, Clone)]
+pub struct Define {
+    pub name: String,
+    pub value: i64,
+}
+
+impl FromStr for Define {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match str.split_once('=') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => (str.trim(), "1"),
+        };
+
+        let tokens = tokenize(name.to_string());
+        if let [fqt] = tokens.as_slice() {
+            check_name(fqt, "define").map_err(|error| error.message)?;
+        }
+        if !matches!(tokens.as_slice(), [fqt] if matches!(fqt.token, Token::Identifier { .. })) {
+            return Err(format!("{} isn't a name that can be defined", name));
+        }
+
+        let value = value.parse::<i64>().map_err(|_| {
+            format!(
+                "{} is defined as {}, which isn't a whole number",
+                name, value
+            )
+        })?;
+
+        Ok(Define {
+            name: name.to_string(),
+            value,
+        })
+    }
+}
+
+/// The passes run over a program. Each pass can be turned on by itself, or a
+/// set picked through a profile or an `-O` level
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct Passes {
+    /// Clean up the generated wat, like turning a set and get into a tee
+    pub peephole: bool,
+    /// Copy small functions into their callers
+    pub inline: bool,
+    /// Leave out functions which can't be reached from an export
+    pub dead_code: bool,
+    /// Leave the name section out of binary wasm, which only debuggers and
+    /// stack traces read
+    pub strip_names: bool,
+    /// Store data blocks with the same bytes once
+    pub dedupe_data: bool,
+    /// Check memory builtins and array indexes stay in bounds and for loops
+    /// don't overflow, passing where a check failed to the panic handler
+    /// before trapping
+    pub debug: bool,
+}
+
+impl Passes {
+    pub fn profile(profile: Profile) -> Self {
+        match profile {
+            Profile::Dev => Passes::default(),
+            Profile::Release => Passes {
+                peephole: true,
+                inline: true,
+                dead_code: true,
+                strip_names: true,
+                dedupe_data: true,
+                debug: false,
+            },
+            Profile::Size => Passes {
+                inline: false,
+                ..Passes::profile(Profile::Release)
+            },
+        }
+    }
+
+    /// 1 runs the peephole passes, 2 also inlines small functions
+    pub fn optimize(optimize: Optimize) -> Self {
+        match optimize {
+            Optimize::Level(level) => Passes {
+                peephole: level >= 1,
+                inline: level >= 2,
+                ..Passes::default()
+            },
+            Optimize::Size => Passes::profile(Profile::Size),
+        }
+    }
+}
+
+/// Everything deciding what a file compiles to, apart from how errors are
+/// shown. The CLI builds one from its arguments, so the library API, tests
+/// and config files use the same switches
+#[derive(PartialEq, Debug, Clone)]
+pub struct CompilerOptions {
+    /// Any of gwe, js, dts, wat, wat-gc or wasm, all generated from one parse
+    pub targets: Vec<String>,
+    pub passes: Passes,
+    /// Include the runtime functions, like alloc
+    pub runtime: bool,
+    pub exceptions: Exceptions,
+    /// Use return_call for tailcall
+    pub tail_calls: bool,
+    /// Check the generated wasm is valid
+    pub validate: bool,
+    /// Check each generated function balances the stack
+    pub audit_stack: bool,
+    pub instrument: Option<Instrument>,
+    pub coverage: bool,
+    /// Wasm plugins which rewrite the program, run in order
+    pub transforms: Vec<PathBuf>,
+    /// wat2wasm to encode wasm with, instead of searching PATH
+    pub wat2wasm: Option<PathBuf>,
+    /// How wat is laid out, pretty or minified
+    pub layout: Layout,
+    /// How functions are named in the wat and exports are named for the host
+    pub naming: NamingPolicy,
+    /// Where generated files are written
+    pub output_directory: PathBuf,
+    /// Where generated code is kept between builds, keyed by the source and
+    /// these options. Nothing is cached without one
+    pub cache: Option<PathBuf>,
+    /// Constants given from outside the source, which replace any the source
+    /// declares with the same name
+    pub defines: Vec<Define>,
+}
+
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        CompilerOptions {
+            targets: vec![String::from("wat")],
+            passes: Passes::default(),
+            runtime: true,
+            exceptions: Exceptions::Proposal,
+            tail_calls: false,
+            validate: false,
+            audit_stack: false,
+            instrument: None,
+            coverage: false,
+            transforms: vec![],
+            wat2wasm: None,
+            layout: Layout::Generated,
+            naming: NamingPolicy::default(),
+            output_directory: PathBuf::from("gwe_build"),
+            cache: None,
+            defines: vec![],
+        }
+    }
+}
+
+impl CompilerOptions {
+    /// The options for the wat generator, which the wat, wat-gc and wasm
+    /// targets use
+    pub fn generate_options(&self, target: &str) -> GenerateOptions {
+        GenerateOptions {
+            runtime: self.runtime,
+            gc: target == "wat-gc",
+            exceptions: self.exceptions,
+            tail_calls: self.tail_calls,
+            passes: self.passes,
+            validate: self.validate,
+            audit_stack: self.audit_stack,
+            instrument: self.instrument,
+            coverage: self.coverage,
+            layout: self.layout,
+            naming: self.naming.clone(),
+        }
+    }
+
+    /// Where the output for a file is written, keeping the file's path
+    /// inside the output directory
+    pub fn output_path(&self, file: &str, extension: &str) -> PathBuf {
+        let mut path = self.output_directory.join(Path::new(file));
+        path.set_extension(extension);
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_is_release_without_inlining() {
+        assert_eq!(
+            Passes::profile(Profile::Size),
+            Passes {
+                inline: false,
+                ..Passes::profile(Profile::Release)
+            }
+        );
+        assert_eq!(
+            "s".parse::<Optimize>().map(Passes::optimize),
+            Ok(Passes::profile(Profile::Size))
+        );
+        assert_eq!(
+            "3".parse::<Optimize>(),
+            Err(String::from(
+                "Unknown optimization level 3, expected 0, 1, 2 or s"
+            ))
+        );
+    }
+
+    #[test]
+    fn defines_are_names_and_whole_numbers() {
+        assert_eq!(
+            "MAX=64".parse::<Define>(),
+            Ok(Define {
+                name: String::from("MAX"),
+                value: 64
+            })
+        );
+        assert_eq!(
+            "DEBUG".parse::<Define>(),
+            Ok(Define {
+                name: String::from("DEBUG"),
+                value: 1
+            })
+        );
+        assert_eq!(
+            "MAX=1.5".parse::<Define>(),
+            Err(String::from(
+                "MAX is defined as 1.5, which isn't a whole number"
+            ))
+        );
+        assert_eq!(
+            "fn=1".parse::<Define>(),
+            Err(String::from(
+                "fn is a reserved word, so it can't be used as a define name"
+            ))
+        );
+        assert_eq!(
+            "A B=1".parse::<Define>(),
+            Err(String::from("A B isn't a name that can be defined"))
+        );
+    }
+
+    #[test]
+    fn wat_gc_generates_with_gc() {
+        let options = CompilerOptions {
+            targets: vec![String::from("wat-gc")],
+            passes: Passes::profile(Profile::Release),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            options.generate_options("wat-gc"),
+            GenerateOptions {
+                gc: true,
+                passes: Passes::profile(Profile::Release),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            options.output_path("examples/main.gwe", "wat"),
+            Path::new("gwe_build").join("examples/main.wat")
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// A name next to `path` for writing to before it's moved into place. It's in
+/// the same directory so the rename doesn't cross filesystems
+fn temporary_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name,
+        process::id(),
+        TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Writes the whole file or nothing. The contents go to a temporary file which
+/// is renamed over `path`, so anything watching the output, like a dev server
+/// or a wasm loader, never reads a file that's only partly written
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temporary = temporary_path(path);
+
+    let written = fs::write(&temporary, contents).and_then(|_| fs::rename(&temporary, path));
+
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_replaced_whole() {
+        let directory = std::env::temp_dir().join("gwe_files_are_replaced_whole");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("main.wasm");
+
+        write_atomically(&path, b"first").unwrap();
+        write_atomically(&path, b"second").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+
+        assert!(write_atomically(&directory.join("missing/main.wasm"), b"").is_err());
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+    }
+}
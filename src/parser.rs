@@ -1,42 +1,1343 @@
-use crate::blocks::{into_blocks, parse_block, Block};
+use std::{
+    fmt::{Display, Formatter},
+    fs,
+    path::Path,
+};
+
+use crate::{
+    blocks::{
+        into_blocks_with_lines, parse_block_with_trivia, resolve_type, split_function_type, Block,
+        Constant, ConstantValue, Data, Export, Function, Param, Test, TypeAlias,
+    },
+    errors::CompileError,
+    expressions::{Expression, BUILTINS, RUNTIME_FUNCTIONS},
+    options::Define,
+    stdlib::{find_module, module_names},
+    tokenizer::{character_literal, TokenInfo},
+    visit_mut::{walk_block_mut, walk_expression_mut, walk_function_mut, VisitorMut},
+};
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program {
     pub blocks: Vec<Block>,
 }
 
-pub fn parse(body: String) -> Result<Program, String> {
-    let unparsed_blocks = into_blocks(body);
+/// Every block as an indented tree, one after another
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        for block in &self.blocks {
+            write!(f, "{}", block)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse(body: String) -> Result<Program, Vec<CompileError>> {
+    parse_with_defines(body, &[])
+}
+
+/// Parses with constants given from outside the source, like `--define MAX=64`
+pub fn parse_with_defines(body: String, defines: &[Define]) -> Result<Program, Vec<CompileError>> {
+    Ok(remove_trivia(Program {
+        blocks: parse_defined_sections(body, defines)?
+            .into_iter()
+            .flat_map(|section| section.blocks)
+            .collect(),
+    }))
+}
+
+/// Parses keeping comments and blank lines, for the formatter to write back out.
+/// Only the file's own blocks are kept, not those of the modules it uses
+pub fn parse_with_trivia(body: String) -> Result<Program, Vec<CompileError>> {
+    Ok(Program {
+        blocks: parse_sections(body)?
+            .into_iter()
+            .filter(|section| section.module.is_none())
+            .flat_map(|section| section.blocks)
+            .collect(),
+    })
+}
+
+/// The source of a block as split out of the file, along with what it parsed to
+#[derive(PartialEq, Debug, Clone)]
+pub struct Section {
+    /// Zero-based line of the file the section starts on
+    pub start_line: i32,
+    pub source: String,
+    /// The block itself, with the comments and blank lines around it
+    pub blocks: Vec<Block>,
+    /// Where the section comes from when it isn't the file, a standard
+    /// library module or a `--define`. Its lines aren't lines of the file
+    pub module: Option<String>,
+}
+
+impl Section {
+    /// Zero-based line of the file the section ends on
+    pub fn end_line(&self) -> i32 {
+        self.start_line + self.source.split('\n').count() as i32 - 1
+    }
+
+    /// Zero-based line of the file the code starts on, after any comments
+    pub fn code_line(&self) -> i32 {
+        let comments = self
+            .source
+            .split('\n')
+            .take_while(|line| line.trim().is_empty() || line.trim_start().starts_with("//"))
+            .count();
+
+        self.start_line + comments as i32
+    }
+}
+
+/// Functions and imports share one namespace, as do the names things are
+/// exported as, so each name can only be given once
+fn check_duplicates(sections: &[Section]) -> Vec<CompileError> {
+    let mut functions: Vec<(String, String)> = vec![];
+    let mut exports: Vec<(String, String)> = vec![];
+    let mut errors: Vec<CompileError> = vec![];
+
+    for section in sections {
+        let line = section.code_line();
+        let place = match &section.module {
+            Some(module) => format!("in {}", module),
+            None => format!("on line {}", line + 1),
+        };
+
+        for block in &section.blocks {
+            let (function, export) = match block {
+                Block::Function(function) => (Some(function.name.clone()), function.export_name()),
+                Block::ImportFunction(import) => (Some(import.name.clone()), None),
+                Block::Export(export) => (None, Some(export.external_name.clone())),
+                _ => (None, None),
+            };
+
+            if let Some(name) = function {
+                errors.extend(declare(&mut functions, name, &place, line, "defined"));
+            }
+            if let Some(name) = export {
+                errors.extend(declare(&mut exports, name, &place, line, "exported"));
+            }
+        }
+    }
+
+    errors
+}
+
+/// An error pointing at both places when the name was already given,
+/// otherwise remembering the place it's given
+fn declare(
+    declared: &mut Vec<(String, String)>,
+    name: String,
+    place: &str,
+    line: i32,
+    verb: &str,
+) -> Option<CompileError> {
+    match declared.iter().find(|(existing, _)| *existing == name) {
+        Some((_, first)) => Some(CompileError::with_info(
+            format!("{} is {} {} and again", name, verb, first),
+            TokenInfo { line, index: 0 },
+        )),
+        None => {
+            declared.push((name, place.to_string()));
+            None
+        }
+    }
+}
+
+/// Parses each block of the source on its own, keeping the errors of the
+/// blocks which don't parse
+fn split_sections(body: String, module: Option<String>) -> (Vec<Section>, Vec<CompileError>) {
+    let mut sections: Vec<Section> = vec![];
+    let mut errors: Vec<CompileError> = vec![];
+
+    for (start_line, unparsed_block) in into_blocks_with_lines(body) {
+        match parse_block_with_trivia(unparsed_block.clone()) {
+            Ok(mut blocks) => {
+                for block in blocks.iter_mut() {
+                    OffsetLines(start_line).visit_block_mut(block);
+                }
+                sections.push(Section {
+                    start_line,
+                    source: unparsed_block,
+                    blocks,
+                    module: module.clone(),
+                })
+            }
+            // blocks are tokenized on their own, so lines are relative to the block
+            Err(mut error) => {
+                if let Some(info) = error.info.as_mut() {
+                    info.line += start_line;
+                }
+                errors.push(error)
+            }
+        }
+    }
+
+    (sections, errors)
+}
+
+/// Puts the sections of every standard library module that's used, directly
+/// or by another module, before the file's own. Each module is only added once
+fn add_modules(sections: Vec<Section>) -> (Vec<Section>, Vec<CompileError>) {
+    let mut modules: Vec<Section> = vec![];
+    let mut added: Vec<String> = vec![];
+    let mut errors: Vec<CompileError> = vec![];
+    let mut uses: Vec<(String, i32)> = used_modules(&sections);
+
+    while let Some((name, line)) = uses.pop() {
+        if added.contains(&name) {
+            continue;
+        }
+
+        match find_module(&name) {
+            Some(source) => {
+                let (module_sections, module_errors) =
+                    split_sections(source.to_string(), Some(name.clone()));
+                errors.extend(module_errors);
+                uses.extend(used_modules(&module_sections));
+                modules.extend(module_sections);
+                added.push(name);
+            }
+            None => errors.push(CompileError::with_info(
+                format!(
+                    "Unknown module {}, the modules are {}",
+                    name,
+                    module_names().join(", ")
+                ),
+                TokenInfo { line, index: 0 },
+            )),
+        }
+    }
+
+    modules.extend(sections);
+    (modules, errors)
+}
+
+/// The module each `use` names, along with the line it's on
+fn used_modules(sections: &[Section]) -> Vec<(String, i32)> {
+    sections
+        .iter()
+        .flat_map(|section| {
+            section.blocks.iter().filter_map(|block| match block {
+                Block::Use(module) => Some((module.path.join("."), section.code_line())),
+                _ => None,
+            })
+        })
+        .rev()
+        .collect()
+}
+
+/// Gives each constant its defined value, adding a constant for each define
+/// the source doesn't declare. Defines that fit are i32, like number literals
+fn apply_defines(sections: &mut Vec<Section>, defines: &[Define]) {
+    for define in defines {
+        let mut declared = false;
+
+        for section in sections.iter_mut() {
+            for block in section.blocks.iter_mut() {
+                if let Block::Constant(constant) = block {
+                    if constant.name == define.name {
+                        constant.value = ConstantValue::Number(define.value);
+                        declared = true;
+                    }
+                }
+            }
+        }
+
+        if !declared {
+            let type_name = if i32::try_from(define.value).is_ok() {
+                "i32"
+            } else {
+                "i64"
+            };
 
-    if unparsed_blocks.is_empty() {
-        return Ok(Program { blocks: vec![] });
+            sections.push(Section {
+                start_line: 0,
+                source: String::new(),
+                blocks: vec![Block::Constant(Constant {
+                    name: define.name.clone(),
+                    type_name: type_name.to_string(),
+                    value: ConstantValue::Number(define.value),
+                })],
+                module: Some(format!("--define {}", define.name)),
+            });
+        }
     }
+}
 
-    let parsed_blocks = unparsed_blocks.into_iter().map(parse_block);
+/// Parses keeping comments and blank lines, grouped by where each block came
+/// from in the file
+pub fn parse_sections(body: String) -> Result<Vec<Section>, Vec<CompileError>> {
+    parse_defined_sections(body, &[])
+}
+
+fn parse_defined_sections(
+    body: String,
+    defines: &[Define],
+) -> Result<Vec<Section>, Vec<CompileError>> {
+    let (sections, mut errors) = split_sections(body, None);
+    let (mut sections, module_errors) = add_modules(sections);
+    errors.extend(module_errors);
+    apply_defines(&mut sections, defines);
+
+    errors.extend(check_duplicates(&sections));
+
+    if errors.is_empty() {
+        // types are inferred across the whole file, then handed back to
+        // the section each block came from
+        let counts: Vec<usize> = sections
+            .iter()
+            .map(|section| section.blocks.len())
+            .collect();
+        let mut blocks: Vec<Block> = sections
+            .iter_mut()
+            .flat_map(|section| std::mem::take(&mut section.blocks))
+            .collect();
 
-    let mut blocks: Vec<Block> = vec![];
-    let mut errors: Vec<String> = vec![];
+        errors = infer_types(&mut blocks);
 
-    for parsed_block in parsed_blocks {
-        match parsed_block {
-            Ok(block) => blocks.push(block),
-            Err(error) => errors.push(error),
+        let mut blocks = blocks.into_iter();
+        for (section, count) in sections.iter_mut().zip(counts) {
+            section.blocks = blocks.by_ref().take(count).collect();
         }
     }
 
     if errors.is_empty() {
-        Ok(Program { blocks })
+        Ok(sections)
     } else {
-        Err(errors.join("\n"))
+        Err(errors)
+    }
+}
+
+/// Takes out the comments and blank lines, which nothing but the formatter uses
+pub fn remove_trivia(program: Program) -> Program {
+    Program {
+        blocks: program
+            .blocks
+            .into_iter()
+            .filter_map(|block| match block {
+                Block::Trivia(_) => None,
+                Block::Function(function) => Some(Block::Function(Function {
+                    expressions: remove_statement_trivia(function.expressions),
+                    ..function
+                })),
+                Block::Test(test) => Some(Block::Test(Test {
+                    expressions: remove_statement_trivia(test.expressions),
+                    ..test
+                })),
+                block => Some(block),
+            })
+            .collect(),
+    }
+}
+
+fn remove_statement_trivia(expressions: Vec<Expression>) -> Vec<Expression> {
+    expressions
+        .into_iter()
+        .filter_map(|expression| match expression {
+            Expression::Trivia { .. } => None,
+            Expression::IfStatement {
+                predicate,
+                success,
+                fail,
+            } => Some(Expression::IfStatement {
+                predicate,
+                success: remove_statement_trivia(success),
+                fail: remove_statement_trivia(fail),
+            }),
+            Expression::ForEach {
+                name,
+                type_name,
+                array,
+                body,
+                line,
+            } => Some(Expression::ForEach {
+                name,
+                type_name,
+                array,
+                body: remove_statement_trivia(body),
+                line,
+            }),
+            Expression::ForStatement {
+                initial_value,
+                break_condition,
+                incrementor,
+                direction,
+                body,
+            } => Some(Expression::ForStatement {
+                initial_value,
+                break_condition,
+                incrementor,
+                direction,
+                body: remove_statement_trivia(body),
+            }),
+            Expression::MatchStatement {
+                subject,
+                arms,
+                default,
+                type_name,
+                info,
+            } => Some(Expression::MatchStatement {
+                subject,
+                arms: arms
+                    .into_iter()
+                    .map(|(pattern, body)| (pattern, remove_statement_trivia(body)))
+                    .collect(),
+                default: default.map(remove_statement_trivia),
+                type_name,
+                info,
+            }),
+            Expression::TryStatement { body, catch } => Some(Expression::TryStatement {
+                body: remove_statement_trivia(body),
+                catch: remove_statement_trivia(catch),
+            }),
+            Expression::Closure { function } => Some(Expression::Closure {
+                function: Box::new(Function {
+                    expressions: remove_statement_trivia(function.expressions),
+                    ..*function
+                }),
+            }),
+            expression => Some(expression),
+        })
+        .collect()
+}
+
+/// Reads the file of each data block, with paths relative to the directory
+/// of the source
+pub fn load_data(program: Program, directory: &Path) -> Result<Program, CompileError> {
+    program
+        .blocks
+        .into_iter()
+        .map(|block| match block {
+            Block::Data(data) => match fs::read(directory.join(&data.path)) {
+                Ok(bytes) => Ok(Block::Data(Data { bytes, ..data })),
+                Err(error) => Err(CompileError::new(format!(
+                    "Unable to read data {} from {} due to {}",
+                    data.name, data.path, error
+                ))),
+            },
+            block => Ok(block),
+        })
+        .collect::<Result<Vec<Block>, CompileError>>()
+        .map(|blocks| Program { blocks })
+}
+
+/// Asserts and indexes report the line they're on, params and locals where
+/// they're declared and exports where their function is named, which like
+/// errors starts out relative to the block
+struct OffsetLines(i32);
+
+impl OffsetLines {
+    fn offset_params(&self, params: &mut [Param]) {
+        for info in params.iter_mut().filter_map(|param| param.info.as_mut()) {
+            info.line += self.0;
+        }
+    }
+}
+
+impl VisitorMut for OffsetLines {
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        match block {
+            Block::ImportFunction(import) => self.offset_params(&mut import.params),
+            Block::Export(Export {
+                info: Some(info), ..
+            }) => info.line += self.0,
+            Block::Interface(interface) => {
+                for function in interface.functions.iter_mut() {
+                    self.offset_params(&mut function.params);
+                }
+            }
+            _ => (),
+        }
+        walk_block_mut(self, block)
+    }
+
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        self.offset_params(&mut function.params);
+        walk_function_mut(self, function)
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        match expression {
+            Expression::Assert { line, .. }
+            | Expression::Index { line, .. }
+            | Expression::ForEach { line, .. } => *line += self.0,
+            Expression::LocalAssign {
+                info: Some(info), ..
+            }
+            | Expression::Addition {
+                info: Some(info), ..
+            }
+            | Expression::Return {
+                info: Some(info), ..
+            }
+            | Expression::FunctionCall {
+                info: Some(info), ..
+            }
+            | Expression::MatchStatement {
+                info: Some(info), ..
+            } => info.line += self.0,
+            _ => (),
+        }
+        walk_expression_mut(self, expression)
+    }
+}
+
+fn is_integer(type_name: &str) -> bool {
+    type_name == "i32" || type_name == "i64"
+}
+
+fn is_numeric(type_name: &str) -> bool {
+    ["i32", "i64", "f32", "f64"].contains(&type_name)
+}
+
+/// A function's name with the types of its params, for functions and imports alike
+type Signature = (String, Vec<Param>);
+
+/// Gives a number literal the expected type, returning the literal back when
+/// it can't be that type
+fn infer_number(expression: &mut Expression, expected: &str) -> Option<String> {
+    match expression {
+        // a conditional between two literals takes the type they're both given
+        Expression::Conditional {
+            success,
+            fail,
+            type_name,
+            ..
+        } if type_name.is_empty() && is_numeric(expected) => {
+            let found = infer_number(success, expected).or_else(|| infer_number(fail, expected));
+            if found.is_none() {
+                *type_name = expected.to_string();
+            }
+            found
+        }
+        // so does an addition of two literals
+        Expression::Addition {
+            left,
+            right,
+            type_name,
+            ..
+        } if type_name.is_empty() && is_numeric(expected) => {
+            let found = infer_number(left, expected).or_else(|| infer_number(right, expected));
+            if found.is_none() {
+                *type_name = expected.to_string();
+            }
+            found
+        }
+        Expression::Number { value, type_name } if is_numeric(expected) => {
+            if is_integer(expected) && value.contains('.') {
+                Some(value.to_string())
+            } else {
+                *type_name = expected.to_string();
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Gives a number literal the type of the param it's passed to, or checks that
+/// anything else already has that type
+fn check_argument(
+    aliases: &[TypeAlias],
+    (name, params): &Signature,
+    param_index: usize,
+    argument: &mut Expression,
+) -> Option<String> {
+    let param = &params[param_index];
+
+    let found = match argument {
+        Expression::Number { .. } => infer_number(argument, &param.type_name)?,
+        Expression::Conditional { type_name, .. } if type_name.is_empty() => {
+            infer_number(argument, &param.type_name)?
+        }
+        Expression::Conditional { type_name, .. }
+            if resolve_type(aliases, type_name) != param.type_name =>
+        {
+            format!("a conditional, which is {}", type_name)
+        }
+        Expression::Variable { body, type_name }
+            if resolve_type(aliases, type_name) != param.type_name =>
+        {
+            format!("{}, which is {}", body, type_name)
+        }
+        Expression::Boolean { value } if param.type_name != "i32" => {
+            format!("{}, which is i32", value)
+        }
+        Expression::Character { value } if param.type_name != "i32" => {
+            format!("{}, which is i32", character_literal(*value))
+        }
+        Expression::Index {
+            name, type_name, ..
+        } if resolve_type(aliases, type_name) != param.type_name => {
+            format!("an element of {}, which is {}", name, type_name)
+        }
+        Expression::Addition { type_name, .. } if type_name.is_empty() => {
+            infer_number(argument, &param.type_name)?
+        }
+        Expression::Addition { type_name, .. }
+            if resolve_type(aliases, type_name) != param.type_name =>
+        {
+            format!("an addition, which is {}", type_name)
+        }
+        Expression::String { body } => format!("the string \"{}\"", body),
+        _ => return None,
+    };
+
+    Some(format!(
+        "{} expects {} to be {} but got {}",
+        name, param.name, param.type_name, found
+    ))
+}
+
+/// Params of function type, which are called like closures
+fn function_params(aliases: &[TypeAlias], params: &[Param]) -> Vec<(Signature, String)> {
+    params
+        .iter()
+        .filter_map(|param| {
+            let (params, return_type) =
+                split_function_type(&resolve_type(aliases, &param.type_name))?;
+
+            Some((
+                (
+                    param.name.to_string(),
+                    params
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, type_name)| Param {
+                            name: format!("argument {}", index + 1),
+                            type_name,
+                            info: None,
+                        })
+                        .collect(),
+                ),
+                return_type,
+            ))
+        })
+        .collect()
+}
+
+struct Inference<'a> {
+    aliases: &'a [TypeAlias],
+    signatures: &'a [Signature],
+    return_types: &'a [(String, String)],
+    function: &'a Function,
+    /// Names of the data blocks, whose fields can be used anywhere
+    data: &'a [String],
+    /// Names and types of the constants
+    constants: &'a [(String, String)],
+    /// Functions with type params, which each call fills in
+    generics: &'a [Function],
+    /// The signature and return type of each closure declared so far, and of
+    /// each param of function type
+    closures: Vec<(Signature, String)>,
+    /// Locals holding arrays, whose offset and length are read like data
+    arrays: Vec<String>,
+    /// Where the innermost expression being inferred that has a position is
+    /// written, which errors are reported at
+    info: Option<TokenInfo>,
+    errors: Vec<CompileError>,
+}
+
+/// Where an expression is written, for those that know
+fn written_at(expression: &Expression) -> Option<TokenInfo> {
+    match expression {
+        Expression::LocalAssign { info, .. }
+        | Expression::Addition { info, .. }
+        | Expression::Return { info, .. }
+        | Expression::FunctionCall { info, .. } => info.clone(),
+        Expression::Assert { line, .. } => Some(TokenInfo {
+            line: *line,
+            index: 0,
+        }),
+        _ => None,
+    }
+}
+
+impl Inference<'_> {
+    fn error(&mut self, message: String) {
+        self.errors.push(match &self.info {
+            Some(info) => CompileError::with_info(message, info.clone()),
+            None => CompileError::new(message),
+        });
+    }
+
+    /// Runs a check with errors reported at the given position, when there is one
+    fn at(&mut self, info: Option<TokenInfo>, check: impl FnOnce(&mut Self)) {
+        let outer = self.info.clone();
+        if info.is_some() {
+            self.info = info;
+        }
+        check(self);
+        self.info = outer;
+    }
+
+    fn expect(
+        &mut self,
+        expression: &mut Expression,
+        expected: &str,
+        describe: impl Fn() -> String,
+    ) {
+        if let Some(value) = infer_number(expression, &resolve_type(self.aliases, expected)) {
+            self.error(format!("{} but got {}", describe(), value));
+        }
+    }
+
+    /// The type an expression has regardless of where it's used, which number
+    /// literals don't have
+    fn known_type(&self, expression: &Expression) -> Option<String> {
+        match expression {
+            Expression::Number { .. } => None,
+            Expression::FunctionCall { name, .. }
+                if self.closures.iter().any(|((found, _), _)| found == name) =>
+            {
+                self.closures
+                    .iter()
+                    .find(|((found, _), _)| found == name)
+                    .map(|(_, return_type)| return_type.to_string())
+            }
+            Expression::FunctionCall { name, args, .. } => self
+                .return_types
+                .iter()
+                .find(|(found, _)| found == name)
+                .map(|(_, return_type)| {
+                    match self.generics.iter().find(|generic| generic.name == *name) {
+                        Some(generic) => self
+                            .bind_generic(generic, args)
+                            .map(|bound| resolve_type(&bound, return_type))
+                            .unwrap_or(return_type.to_string()),
+                        None => return_type.to_string(),
+                    }
+                }),
+            expression => expression.type_name(),
+        }
+        .map(|type_name| resolve_type(self.aliases, &type_name))
     }
+
+    /// The types a call to a generic function fills its type params with.
+    /// Number literals are f32 unless another argument decides the type
+    fn bind_generic(
+        &self,
+        generic: &Function,
+        args: &[Expression],
+    ) -> Result<Vec<TypeAlias>, CompileError> {
+        let arg_types: Vec<Option<String>> = args.iter().map(|arg| self.known_type(arg)).collect();
+        let mut bound = generic.bind_type_params(&arg_types)?;
+
+        for type_param in &generic.type_params {
+            if !bound.iter().any(|alias| alias.name == *type_param) {
+                bound.push(TypeAlias {
+                    name: type_param.to_string(),
+                    type_name: String::from("f32"),
+                });
+            }
+        }
+
+        Ok(bound)
+    }
+
+    fn infer_call(&mut self, name: &str, args: &mut [Expression]) {
+        // closures are found before functions of the same name
+        let Some(signature) = self
+            .closures
+            .iter()
+            .map(|(signature, _)| signature)
+            .chain(self.signatures)
+            .find(|(found, _)| found == name)
+            .cloned()
+        else {
+            return;
+        };
+
+        // values can also be left on the stack before a call with no
+        // arguments, like strings are
+        if args.is_empty() {
+            return;
+        }
+
+        if args.len() != signature.1.len() {
+            let expected = signature.1.len();
+            self.error(format!(
+                "{} expects {} argument{} but got {}",
+                name,
+                expected,
+                if expected == 1 { "" } else { "s" },
+                args.len()
+            ));
+            return;
+        }
+
+        // a generic function's params have the types this call fills in
+        let signature = match self.generics.iter().find(|generic| generic.name == name) {
+            Some(generic) => match self.bind_generic(generic, args) {
+                Ok(bound) => (
+                    signature.0.to_string(),
+                    signature
+                        .1
+                        .iter()
+                        .map(|param| Param {
+                            name: param.name.to_string(),
+                            type_name: resolve_type(&bound, &param.type_name),
+                            info: param.info.clone(),
+                        })
+                        .collect(),
+                ),
+                Err(error) => {
+                    self.error(error.message);
+                    return;
+                }
+            },
+            None => signature.clone(),
+        };
+
+        for (index, arg) in args.iter_mut().enumerate() {
+            if let Some(error) = check_argument(self.aliases, &signature, index, arg) {
+                self.error(error);
+            }
+        }
+    }
+
+    fn infer(&mut self, expression: &mut Expression) {
+        self.at(written_at(expression), |inference| {
+            inference.infer_expression(expression)
+        });
+    }
+
+    fn infer_expression(&mut self, expression: &mut Expression) {
+        match expression {
+            Expression::FunctionCall { name, args, .. } => {
+                for arg in args.iter_mut() {
+                    self.infer(arg);
+                }
+                self.infer_call(name, args);
+            }
+            Expression::Closure { function } => {
+                let mut expressions = std::mem::take(&mut function.expressions);
+                let closure = function.as_ref().clone();
+
+                let mut inference = Inference {
+                    function: &closure,
+                    closures: self
+                        .closures
+                        .iter()
+                        .cloned()
+                        .chain(function_params(self.aliases, &closure.params))
+                        .collect(),
+                    arrays: self.arrays.clone(),
+                    info: self.info.clone(),
+                    errors: vec![],
+                    ..*self
+                };
+                for expression in expressions.iter_mut() {
+                    inference.infer(expression);
+                }
+                self.errors.append(&mut inference.errors);
+
+                function.expressions = expressions;
+                self.closures.push((
+                    (
+                        function.name.to_string(),
+                        function
+                            .params
+                            .iter()
+                            .map(|param| Param {
+                                name: param.name.to_string(),
+                                type_name: resolve_type(self.aliases, &param.type_name),
+                                info: param.info.clone(),
+                            })
+                            .collect(),
+                    ),
+                    resolve_type(self.aliases, &function.return_type),
+                ));
+            }
+            Expression::DataField { name, .. }
+                if !self.data.contains(name) && !self.arrays.contains(name) =>
+            {
+                self.error(format!("Unknown data {}", name));
+            }
+            Expression::Constant { name, type_name } => {
+                match self.constants.iter().find(|(found, _)| found == name) {
+                    Some((_, found)) => *type_name = found.to_string(),
+                    None => self.error(format!("Couldn't find type for variable {}", name)),
+                }
+            }
+            Expression::TailCall { name, args } => {
+                for arg in args.iter_mut() {
+                    self.infer(arg);
+                }
+                self.infer_call(name, args);
+
+                // the callee's result is returned as-is
+                let function = self.function;
+                if let Some((_, return_type)) = self
+                    .return_types
+                    .iter()
+                    .find(|(found, _)| found == name)
+                    .filter(|(_, return_type)| {
+                        resolve_type(self.aliases, return_type)
+                            != resolve_type(self.aliases, &function.return_type)
+                    })
+                {
+                    self.error(format!(
+                        "tailcall {} returns {} but {} returns {}",
+                        name, return_type, function.name, function.return_type
+                    ));
+                }
+            }
+            Expression::Return { expression, .. } => {
+                self.infer(expression);
+                let function = self.function;
+                self.expect(expression, &function.return_type, || {
+                    format!("{} returns {}", function.name, function.return_type)
+                });
+                // void functions don't return a value, and a type param's type
+                // is only known at each call
+                let variable = matches!(expression.as_ref(), Expression::Variable { .. });
+                let checked = variable
+                    && function.return_type != "void"
+                    && !function.type_params.contains(&function.return_type);
+                if let Some(found) = self.known_type(expression).filter(|found| {
+                    checked && *found != resolve_type(self.aliases, &function.return_type)
+                }) {
+                    self.error(format!(
+                        "{} returns {} but got {}",
+                        function.name, function.return_type, found
+                    ));
+                }
+            }
+            Expression::LocalAssign {
+                name,
+                type_name,
+                expression,
+                ..
+            } => {
+                self.infer(expression);
+                self.expect(expression, type_name, || {
+                    format!("Local {} is declared as {}", name, type_name)
+                });
+                if type_name.starts_with('[') {
+                    self.arrays.push(name.to_string());
+                }
+
+                match expression.as_ref() {
+                    Expression::Number { .. } => (),
+                    initializer => {
+                        if let Some(found) = initializer.type_name().filter(|found| {
+                            resolve_type(self.aliases, found)
+                                != resolve_type(self.aliases, type_name)
+                        }) {
+                            self.error(format!(
+                                "Local {} is declared as {} but got {}",
+                                name, type_name, found
+                            ));
+                        }
+                    }
+                }
+            }
+            Expression::GlobalAssign {
+                name,
+                type_name,
+                expression,
+            } => {
+                self.infer(expression);
+                self.expect(expression, type_name, || {
+                    format!("Global {} is declared as {}", name, type_name)
+                });
+            }
+            Expression::Addition {
+                left,
+                right,
+                type_name,
+                ..
+            } => {
+                self.infer(left);
+                self.infer(right);
+
+                if type_name.is_empty() {
+                    if let Some(found) = self.known_type(left).or_else(|| self.known_type(right)) {
+                        *type_name = found;
+                    }
+                }
+
+                if !type_name.is_empty() {
+                    for side in [left, right] {
+                        self.expect(side, type_name, || {
+                            format!("Both sides of + are {}", type_name)
+                        });
+                        if let Some(found) = self
+                            .known_type(side)
+                            .filter(|found| *found != resolve_type(self.aliases, type_name))
+                        {
+                            self.error(format!(
+                                "Both sides of + are {} but got {}",
+                                type_name, found
+                            ));
+                        }
+                    }
+                }
+            }
+            Expression::Array { type_name, values } => {
+                for value in values.iter_mut() {
+                    self.infer(value);
+                    self.expect(value, type_name, || {
+                        format!("The values of an array of {} are {}", type_name, type_name)
+                    });
+                }
+            }
+            Expression::Index { name, index, .. } => {
+                self.infer(index);
+                self.expect(index, "i32", || format!("The index into {} is i32", name));
+                if let Some(found) = self.known_type(index).filter(|found| found != "i32") {
+                    self.error(format!("The index into {} is i32, but got {}", name, found));
+                }
+            }
+            Expression::Conditional {
+                predicate,
+                success,
+                fail,
+                type_name,
+            } => {
+                self.infer(predicate);
+                self.expect(predicate, "i32", || {
+                    String::from("A conditional's condition is i32")
+                });
+                if let Some(found) = self.known_type(predicate).filter(|found| found != "i32") {
+                    self.error(format!(
+                        "A conditional's condition is i32, but got {}",
+                        found
+                    ));
+                }
+                self.infer(success);
+                self.infer(fail);
+
+                let known = self
+                    .known_type(success)
+                    .into_iter()
+                    .chain(self.known_type(fail))
+                    .collect::<Vec<String>>();
+                match known.as_slice() {
+                    [] => (),
+                    [first, second] if first != second => {
+                        self.error(format!(
+                            "A conditional picks between {} and {}, which need to be the same type",
+                            first, second
+                        ));
+                    }
+                    [known, ..] if known == "string" => {
+                        self.error(String::from(
+                            "A conditional picks between numbers, not strings",
+                        ));
+                    }
+                    [known, ..] => {
+                        *type_name = known.to_string();
+                        let picked = known.to_string();
+                        self.expect(success, &picked, || {
+                            format!("Both sides of a conditional are {}", picked)
+                        });
+                        self.expect(fail, &picked, || {
+                            format!("Both sides of a conditional are {}", picked)
+                        });
+                    }
+                }
+            }
+            Expression::Assert {
+                left, right: None, ..
+            } => {
+                self.infer(left);
+                self.expect(left, "i32", || String::from("An assert condition is i32"));
+            }
+            Expression::Assert {
+                left,
+                right: Some(right),
+                type_name,
+                ..
+            } => {
+                self.infer(left);
+                self.infer(right);
+
+                // number literals are compared as whatever the other side is
+                match (self.known_type(left), self.known_type(right)) {
+                    (Some(left_type), Some(right_type)) if left_type != right_type => {
+                        self.error(format!(
+                            "assert_eq compares {} with {}",
+                            left_type, right_type
+                        ));
+                        return;
+                    }
+                    (Some(found), _) | (_, Some(found)) => *type_name = found,
+                    (None, None) => (),
+                }
+
+                if !is_numeric(type_name) {
+                    self.error(format!(
+                        "assert_eq can only compare numbers, not {}",
+                        type_name
+                    ));
+                    return;
+                }
+
+                self.expect(left, type_name, || {
+                    format!("assert_eq compares {}", type_name)
+                });
+                self.expect(right, type_name, || {
+                    format!("assert_eq compares {}", type_name)
+                });
+            }
+            Expression::IfStatement {
+                predicate,
+                success,
+                fail,
+            } => {
+                self.infer(predicate);
+                self.expect(predicate, "i32", || String::from("An if condition is i32"));
+                for expression in success.iter_mut().chain(fail.iter_mut()) {
+                    self.infer(expression);
+                }
+            }
+            Expression::MatchStatement {
+                subject,
+                type_name,
+                arms,
+                default,
+                ..
+            } => {
+                self.infer(subject);
+                // a literal subject is an i32 unless it says otherwise
+                *type_name = self
+                    .known_type(subject)
+                    .unwrap_or_else(|| String::from("i32"));
+                let subject_type = type_name.to_string();
+                self.expect(subject, &subject_type, || {
+                    format!("A match compares {}", subject_type)
+                });
+
+                for expression in arms
+                    .iter_mut()
+                    .flat_map(|(_, body)| body.iter_mut())
+                    .chain(default.iter_mut().flatten())
+                {
+                    self.infer(expression);
+                }
+            }
+            Expression::ForEach { body, .. } => {
+                for expression in body.iter_mut() {
+                    self.infer(expression);
+                }
+            }
+            Expression::ForStatement {
+                initial_value,
+                incrementor,
+                break_condition,
+                body,
+                ..
+            } => {
+                self.infer(initial_value);
+
+                // the break condition and incrementor are used with the loop variable
+                if let Expression::LocalAssign {
+                    name,
+                    type_name,
+                    info,
+                    ..
+                } = initial_value.as_ref()
+                {
+                    self.at(info.clone(), |inference| {
+                        inference.expect(incrementor, type_name, || {
+                            format!("The incrementor for {} is {}", name, type_name)
+                        });
+                        inference.expect(break_condition, type_name, || {
+                            format!("The break condition for {} is {}", name, type_name)
+                        });
+                    });
+                }
+
+                self.infer(incrementor);
+                self.infer(break_condition);
+                for expression in body.iter_mut() {
+                    self.infer(expression);
+                }
+            }
+            Expression::TryStatement { body, catch } => {
+                for expression in body.iter_mut().chain(catch.iter_mut()) {
+                    self.infer(expression);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Number literals take their type from where they're used, and calls have to
+/// match the params of the function they call
+pub(crate) fn infer_types(blocks: &mut [Block]) -> Vec<CompileError> {
+    let aliases: Vec<TypeAlias> = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::TypeAlias(alias) => Some(alias.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let resolve_params = |params: &[Param]| -> Vec<Param> {
+        params
+            .iter()
+            .map(|param| Param {
+                name: param.name.to_string(),
+                type_name: resolve_type(&aliases, &param.type_name),
+                info: param.info.clone(),
+            })
+            .collect()
+    };
+
+    let signatures: Vec<Signature> = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::ImportFunction(import) => {
+                Some((import.name.to_string(), resolve_params(&import.params)))
+            }
+            Block::Function(function) => {
+                Some((function.name.to_string(), resolve_params(&function.params)))
+            }
+            _ => None,
+        })
+        .chain(
+            BUILTINS
+                .iter()
+                .chain(RUNTIME_FUNCTIONS.iter())
+                .map(|builtin| {
+                    let params = builtin
+                        .params
+                        .iter()
+                        .map(|name| Param {
+                            name: name.to_string(),
+                            type_name: String::from("i32"),
+                            info: None,
+                        })
+                        .collect();
+
+                    (builtin.name.to_string(), params)
+                }),
+        )
+        .collect();
+
+    let return_types: Vec<(String, String)> = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function) => Some((
+                function.name.to_string(),
+                resolve_type(&aliases, &function.return_type),
+            )),
+            _ => None,
+        })
+        .chain(
+            BUILTINS
+                .iter()
+                .map(|builtin| (builtin.name.to_string(), builtin.return_type.to_string())),
+        )
+        .collect();
+
+    let data: Vec<String> = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Data(data) => Some(data.name.to_string()),
+            _ => None,
+        })
+        .collect();
+
+    let constants: Vec<(String, String)> = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Constant(constant) => Some((
+                constant.name.to_string(),
+                resolve_type(&aliases, &constant.type_name),
+            )),
+            _ => None,
+        })
+        .collect();
+
+    let generics: Vec<Function> = blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function) if !function.type_params.is_empty() => Some(function.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut errors: Vec<CompileError> = vec![];
+
+    for block in blocks.iter_mut() {
+        let (function, mut expressions) = match block {
+            Block::Function(function) => {
+                let expressions = std::mem::take(&mut function.expressions);
+                (function.clone(), expressions)
+            }
+            Block::Test(test) => (
+                test.as_function(&format!("test {}", test.name)),
+                std::mem::take(&mut test.expressions),
+            ),
+            _ => continue,
+        };
+
+        let mut inference = Inference {
+            aliases: &aliases,
+            signatures: &signatures,
+            return_types: &return_types,
+            function: &function,
+            data: &data,
+            constants: &constants,
+            generics: &generics,
+            closures: function_params(&aliases, &function.params),
+            arrays: vec![],
+            info: None,
+            errors: vec![],
+        };
+
+        for expression in expressions.iter_mut() {
+            inference.infer(expression);
+        }
+
+        errors.append(&mut inference.errors);
+
+        match block {
+            Block::Function(function) => function.expressions = expressions,
+            Block::Test(test) => test.expressions = expressions,
+            _ => (),
+        }
+    }
+
+    errors
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::blocks::*;
+    use crate::errors::join_errors;
     use crate::expressions::*;
 
+    // compares against the rendered messages rather than building CompileErrors by hand
+    fn parse(body: String) -> Result<Program, String> {
+        super::parse(body).map_err(|errors| join_errors(&errors))
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn programs_round_trip_through_json() {
+        let source = fs::read_to_string("examples/hello_world_console_log_for_loop.gwe").unwrap();
+        let program = parse_with_trivia(format!("// looping\n{}", source)).unwrap();
+
+        let json = serde_json::to_string(&program).unwrap();
+        assert_eq!(serde_json::from_str::<Program>(&json).unwrap(), program);
+
+        let tokens = crate::tokenizer::tokenize_with_comments(source);
+        let json = serde_json::to_string(&tokens).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<crate::tokenizer::FullyQualifiedToken>>(&json).unwrap(),
+            tokens
+        );
+    }
+
     #[test]
     fn an_empty_file_passes() {
         assert_eq!(parse(String::from("")), Ok(Program { blocks: vec![] }))
@@ -60,9 +1361,13 @@ mod tests {
                     expressions: vec![],
                     params: vec![Param {
                         name: String::from("name"),
-                        type_name: String::from("string")
+                        type_name: String::from("string"),
+                        info: Some(TokenInfo { line: 0, index: 13 }),
                     }],
                     return_type: String::from("void"),
+                    doc: None,
+                    attributes: vec![],
+                    type_params: vec![],
                 })]
             })
         )
@@ -81,13 +1386,18 @@ mod tests {
                         expression: Box::new(Expression::Variable {
                             body: String::from("name"),
                             type_name: String::from("string")
-                        })
+                        }),
+                        info: Some(TokenInfo { line: 0, index: 37 }),
                     }],
                     params: vec![Param {
                         name: String::from("name"),
-                        type_name: String::from("string")
+                        type_name: String::from("string"),
+                        info: Some(TokenInfo { line: 0, index: 13 }),
                     }],
                     return_type: String::from("string"),
+                    doc: None,
+                    attributes: vec![],
+                    type_params: vec![],
                 })]
             })
         )
@@ -113,20 +1423,26 @@ fn say_hello(name: string): string {
                             expression: Box::new(Expression::Variable {
                                 body: String::from("name"),
                                 type_name: String::from("string")
-                            })
+                            }),
+                            info: Some(TokenInfo { line: 2, index: 10 }),
                         },
                         Expression::Return {
                             expression: Box::new(Expression::Variable {
                                 body: String::from("name"),
                                 type_name: String::from("string")
-                            })
+                            }),
+                            info: Some(TokenInfo { line: 3, index: 4 }),
                         }
                     ],
                     params: vec![Param {
                         name: String::from("name"),
-                        type_name: String::from("string")
+                        type_name: String::from("string"),
+                        info: Some(TokenInfo { line: 1, index: 13 }),
                     }],
                     return_type: String::from("string"),
+                    doc: None,
+                    attributes: vec![],
+                    type_params: vec![],
                 })]
             })
         )
@@ -158,14 +1474,19 @@ fn say_hello(name: string): string {
                             expression: Box::new(Expression::Variable {
                                 body: String::from("name"),
                                 type_name: String::from("string")
-                            })
+                            }),
+                            info: Some(TokenInfo { line: 3, index: 4 }),
                         }
                     ],
                     params: vec![Param {
                         name: String::from("name"),
-                        type_name: String::from("string")
+                        type_name: String::from("string"),
+                        info: Some(TokenInfo { line: 1, index: 13 }),
                     }],
                     return_type: String::from("string"),
+                    doc: None,
+                    attributes: vec![],
+                    type_params: vec![],
                 })]
             })
         )
@@ -195,21 +1516,29 @@ fn say_hello(name: string): string {
                                 right: Box::new(Expression::Variable {
                                     body: String::from("name"),
                                     type_name: String::from("string")
-                                })
-                            })
+                                }),
+                                type_name: String::from("string"),
+                                info: Some(TokenInfo { line: 2, index: 31 }),
+                            }),
+                            info: Some(TokenInfo { line: 2, index: 10 }),
                         },
                         Expression::Return {
                             expression: Box::new(Expression::Variable {
                                 body: String::from("name"),
                                 type_name: String::from("string")
-                            })
+                            }),
+                            info: Some(TokenInfo { line: 3, index: 4 }),
                         }
                     ],
                     params: vec![Param {
                         name: String::from("name"),
-                        type_name: String::from("string")
+                        type_name: String::from("string"),
+                        info: Some(TokenInfo { line: 1, index: 13 }),
                     }],
                     return_type: String::from("string"),
+                    doc: None,
+                    attributes: vec![],
+                    type_params: vec![],
                 })]
             })
         )
@@ -240,18 +1569,25 @@ fn say_hello(): void {
                                 right: Box::new(Expression::Number {
                                     value: String::from("3.14"),
                                     type_name: String::from("f32"),
-                                })
-                            })
+                                }),
+                                type_name: String::new(),
+                                info: Some(TokenInfo { line: 2, index: 26 }),
+                            }),
+                            info: Some(TokenInfo { line: 2, index: 10 }),
                         },
                         Expression::Return {
                             expression: Box::new(Expression::Variable {
                                 body: String::from("x"),
                                 type_name: String::from("number")
-                            })
+                            }),
+                            info: Some(TokenInfo { line: 3, index: 4 }),
                         }
                     ],
                     params: vec![],
                     return_type: String::from("void"),
+                    doc: None,
+                    attributes: vec![],
+                    type_params: vec![],
                 })]
             })
         )
@@ -336,41 +1672,783 @@ fn say_hello(): void {
     }
 
     #[test]
-    fn a_function_with_return_type_but_missing_everything_errors() {
+    fn a_function_with_missing_close_bracket_errors() {
         assert_eq!(
-            parse(String::from("fn say_hello (name: string): string")),
-            Err(String::from("Expected { but got nothing"))
+            parse(String::from("fn say_hello (name: string): string {")),
+            Err(String::from(
+                "Expected } at the end of say_hello but got nothing"
+            ))
+        );
+        assert_eq!(
+            parse(String::from(
+                "fn say_hello (name: string): string { return name;"
+            )),
+            Err(String::from(
+                "Expected } at the end of say_hello but got ; at line 1, index 49"
+            ))
         )
     }
 
     #[test]
-    fn an_export_without_an_external_name_errors() {
+    fn match_arms_are_numbers_or_underscore() {
         assert_eq!(
-            parse(String::from("export")),
-            Err(String::from("Expected external name in export"))
-        )
+            parse(String::from(
+                "fn main(n: i64): void {
+    match n {
+        0 => log(1),
+        _ => {
+            log(2);
+        },
     }
-
-    #[test]
-    fn an_export_without_an_external_name_but_a_bracket_errors() {
+}"
+            )),
+            Ok(Program {
+                blocks: vec![Block::Function(Function {
+                    name: String::from("main"),
+                    expressions: vec![Expression::MatchStatement {
+                        subject: Box::new(Expression::Variable {
+                            body: String::from("n"),
+                            type_name: String::from("i64")
+                        }),
+                        type_name: String::from("i64"),
+                        arms: vec![(
+                            0,
+                            vec![Expression::FunctionCall {
+                                name: String::from("log"),
+                                args: vec![Expression::Number {
+                                    value: String::from("1"),
+                                    type_name: String::from("f32")
+                                }],
+                                info: Some(TokenInfo { line: 2, index: 13 }),
+                            }]
+                        )],
+                        default: Some(vec![Expression::FunctionCall {
+                            name: String::from("log"),
+                            args: vec![Expression::Number {
+                                value: String::from("2"),
+                                type_name: String::from("f32")
+                            }],
+                            info: Some(TokenInfo { line: 4, index: 12 }),
+                        }]),
+                        info: Some(TokenInfo { line: 1, index: 4 }),
+                    }],
+                    params: vec![Param {
+                        name: String::from("n"),
+                        type_name: String::from("i64"),
+                        info: Some(TokenInfo { line: 0, index: 8 }),
+                    }],
+                    return_type: String::from("void"),
+                    doc: None,
+                    attributes: vec![],
+                    type_params: vec![],
+                })]
+            })
+        );
         assert_eq!(
-            parse(String::from("export {")),
+            parse(String::from(
+                "fn main(n: i32): void {
+    match n {
+        0.5 => log(1),
+        _ => log(2)
+    }
+}"
+            )),
             Err(String::from(
-                "Expected external name in export, got { at line 1, index 7"
+                "Match patterns are whole numbers or _, not 0.5 at line 3, index 11"
             ))
-        )
-    }
-
-    #[test]
-    fn an_export_without_an_internal_name_errors() {
+        );
         assert_eq!(
-            parse(String::from("export sayHello")),
-            Err(String::from("Expected function name in export"))
-        )
+            parse(String::from(
+                "fn main(n: i32): void {
+    match n {
+        _ => log(1),
+        _ => log(2)
+    }
+}"
+            )),
+            Err(String::from(
+                "A match can only have one _ arm at line 4, index 9"
+            ))
+        );
     }
 
     #[test]
-    fn an_export_without_an_internal_name_but_a_bracket_errors() {
+    fn conditionals_take_the_type_of_their_sides() {
+        assert_eq!(
+            parse(String::from(
+                "fn main(c: i32): i64 {
+    return c ? 1 : 2;
+}"
+            )),
+            Ok(Program {
+                blocks: vec![Block::Function(Function {
+                    name: String::from("main"),
+                    expressions: vec![Expression::Return {
+                        expression: Box::new(Expression::Conditional {
+                            predicate: Box::new(Expression::Variable {
+                                body: String::from("c"),
+                                type_name: String::from("i32")
+                            }),
+                            success: Box::new(Expression::Number {
+                                value: String::from("1"),
+                                type_name: String::from("i64")
+                            }),
+                            fail: Box::new(Expression::Number {
+                                value: String::from("2"),
+                                type_name: String::from("i64")
+                            }),
+                            type_name: String::from("i64")
+                        }),
+                        info: Some(TokenInfo { line: 1, index: 4 }),
+                    }],
+                    params: vec![Param {
+                        name: String::from("c"),
+                        type_name: String::from("i32"),
+                        info: Some(TokenInfo { line: 0, index: 8 }),
+                    }],
+                    return_type: String::from("i64"),
+                    doc: None,
+                    attributes: vec![],
+                    type_params: vec![],
+                })]
+            })
+        );
+        assert_eq!(
+            parse(String::from(
+                "fn main(c: i32, x: f32): f32 {
+    return c ? x : \"no\";
+}"
+            )),
+            Err(String::from(
+                "A conditional picks between f32 and string, which need to be the same type at line 2, index 4"
+            ))
+        );
+        assert_eq!(
+            parse(String::from(
+                "fn main(c: f32, x: f32): f32 {
+    return c ? x : 1.0;
+}"
+            )),
+            Err(String::from(
+                "A conditional's condition is i32, but got f32 at line 2, index 4"
+            ))
+        );
+        assert_eq!(
+            parse(String::from(
+                "fn main(c: i32, x: f32): f32 {
+    return c ? x;
+}"
+            )),
+            Err(String::from(
+                "Expected : after the first value of a conditional at line 2, index 13"
+            ))
+        );
+    }
+
+    #[test]
+    fn nested_blocks_parse_their_own_tokens() {
+        assert_eq!(
+            parse(String::from(
+                "fn main(n: f32): void {
+    if (n) {
+        if (n) { log(n + 1); } else { log(2); };
+    } else {
+        log(3);
+    };
+}"
+            )),
+            Ok(Program {
+                blocks: vec![Block::Function(Function {
+                    name: String::from("main"),
+                    expressions: vec![Expression::IfStatement {
+                        predicate: Box::new(Expression::Variable {
+                            body: String::from("n"),
+                            type_name: String::from("f32")
+                        }),
+                        success: vec![Expression::IfStatement {
+                            predicate: Box::new(Expression::Variable {
+                                body: String::from("n"),
+                                type_name: String::from("f32")
+                            }),
+                            success: vec![Expression::FunctionCall {
+                                name: String::from("log"),
+                                args: vec![Expression::Addition {
+                                    left: Box::new(Expression::Variable {
+                                        body: String::from("n"),
+                                        type_name: String::from("f32")
+                                    }),
+                                    right: Box::new(Expression::Number {
+                                        value: String::from("1"),
+                                        type_name: String::from("f32")
+                                    }),
+                                    type_name: String::from("f32"),
+                                    info: Some(TokenInfo { line: 2, index: 23 }),
+                                }],
+                                info: Some(TokenInfo { line: 2, index: 17 }),
+                            }],
+                            fail: vec![Expression::FunctionCall {
+                                name: String::from("log"),
+                                args: vec![Expression::Number {
+                                    value: String::from("2"),
+                                    type_name: String::from("f32")
+                                }],
+                                info: Some(TokenInfo { line: 2, index: 38 }),
+                            }]
+                        }],
+                        fail: vec![Expression::FunctionCall {
+                            name: String::from("log"),
+                            args: vec![Expression::Number {
+                                value: String::from("3"),
+                                type_name: String::from("f32")
+                            }],
+                            info: Some(TokenInfo { line: 4, index: 8 }),
+                        }]
+                    }],
+                    params: vec![Param {
+                        name: String::from("n"),
+                        type_name: String::from("f32"),
+                        info: Some(TokenInfo { line: 0, index: 8 }),
+                    }],
+                    return_type: String::from("void"),
+                    doc: None,
+                    attributes: vec![],
+                    type_params: vec![],
+                })]
+            })
+        )
+    }
+
+    #[test]
+    fn a_local_is_only_visible_inside_its_block() {
+        assert_eq!(
+            parse(String::from(
+                "fn main(n: i32): i32 {
+    if (n) {
+        local x: i32 = 1;
+        return x;
+    } else {
+        return n;
+    };
+    return x;
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "Couldn't find type for variable x at line 8, index 4"
+            ))
+        )
+    }
+
+    #[test]
+    fn closure_calls_have_the_closures_types() {
+        assert_eq!(
+            parse(String::from(
+                "fn main(n: i64, m: i32): void {
+    fn get(): i64 {
+        return n;
+    }
+    assert_eq(get(), m);
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "assert_eq compares i64 with i32 at line 5, index 0"
+            ))
+        );
+    }
+
+    #[test]
+    fn functions_passed_to_params_have_the_params_type() {
+        assert_eq!(
+            parse(String::from(
+                "fn apply(f: fn(i32): i32, x: i32): i32 {
+    return f(x);
+}
+
+fn main(): f32 {
+    fn half(x: f32): f32 {
+        return x;
+    }
+    return apply(half, 2);
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "apply expects f to be fn(i32): i32 but got half, which is fn(f32): f32 at line 9, index 11"
+            ))
+        );
+        assert_eq!(
+            parse(String::from(
+                "fn apply(f: fn(i64): i64): i64 {
+    return f(0.5);
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "f expects argument 1 to be i64 but got 0.5 at line 2, index 11"
+            ))
+        );
+    }
+
+    #[test]
+    fn generic_calls_fill_in_one_type_per_type_param() {
+        assert_eq!(
+            parse(String::from(
+                "fn pick<T>(left: T, right: T): T {
+    return left;
+}
+
+fn main(small: i32, big: i64): i64 {
+    return pick(small, big);
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "pick expects T to be one type but got i32 and i64 at line 6, index 11"
+            ))
+        );
+        assert_eq!(
+            parse(String::from(
+                "fn identity<T>(x: T): T {
+    return x;
+}
+
+fn main(n: i64, m: i32): void {
+    assert_eq(identity(n), m);
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "assert_eq compares i64 with i32 at line 6, index 0"
+            ))
+        );
+    }
+
+    #[test]
+    fn constants_have_the_type_they_are_declared_with() {
+        assert_eq!(
+            parse(String::from(
+                "const BIG: i64 = 1024;
+
+fn main(): void {
+    local x: i32 = BIG;
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "Local x is declared as i32 but got i64 at line 4, index 10"
+            ))
+        )
+    }
+
+    #[test]
+    fn statements_can_follow_an_if_or_for_without_a_semicolon() {
+        let expressions = parse(String::from(
+            "fn main(n: i32): i32 {
+    if (n) {
+        log(n);
+    } else {
+        log(0);
+    }
+    for (local i: i32 = 0, 3, 1) {
+        log(i);
+    }
+    return n;
+}",
+        ))
+        .map(|program| match &program.blocks[0] {
+            Block::Function(function) => function.expressions.clone(),
+            _ => vec![],
+        });
+
+        assert!(matches!(
+            expressions.as_deref(),
+            Ok([
+                Expression::IfStatement { .. },
+                Expression::ForStatement { .. },
+                Expression::Return { .. }
+            ])
+        ))
+    }
+
+    #[test]
+    fn ifs_need_else_between_their_branches() {
+        let body = |branches: &str| {
+            parse(format!(
+                "fn main(n: i32): void {{\n    if (n) {{\n        log(n);\n    }}{}\n}}",
+                branches
+            ))
+            .map(|program| match &program.blocks[0] {
+                Block::Function(function) => match &function.expressions[0] {
+                    Expression::IfStatement { fail, .. } => fail.len(),
+                    _ => 0,
+                },
+                _ => 0,
+            })
+        };
+
+        assert_eq!(body(" else {\n        log(0);\n    };"), Ok(1));
+        assert_eq!(body(";"), Ok(0));
+        assert_eq!(body(""), Ok(0));
+        assert_eq!(
+            body(" {\n        log(0);\n    };"),
+            Err(String::from(
+                "Expected else or ; after the first branch of an if but got { at line 4, index 6"
+            ))
+        );
+        assert_eq!(
+            body(" else log(0);"),
+            Err(String::from(
+                "Expected { after else but got log at line 4, index 14"
+            ))
+        );
+    }
+
+    #[test]
+    fn for_directions_are_up_or_down() {
+        let direction = |input: &str| {
+            parse(String::from(input)).map(|program| match &program.blocks[0] {
+                Block::Function(function) => match &function.expressions[0] {
+                    Expression::ForStatement { direction, .. } => Some(direction.clone()),
+                    _ => None,
+                },
+                _ => None,
+            })
+        };
+
+        assert_eq!(
+            direction(
+                "fn main(): void {
+    for (local i: i32 = 0, 3, 1) {
+        log(i);
+    };
+}"
+            ),
+            Ok(Some(Direction::Up))
+        );
+        assert_eq!(
+            direction(
+                "fn main(): void {
+    for (local i: i32 = 3, 0, 1, down) {
+        log(i);
+    };
+}"
+            ),
+            Ok(Some(Direction::Down))
+        );
+        assert_eq!(
+            direction(
+                "fn main(): void {
+    for (local i: i32 = 3, 0, 1, sideways) {
+        log(i);
+    };
+}"
+            ),
+            Err(String::from(
+                "Expected up or down as the direction of a for, not sideways at line 2, index 41"
+            ))
+        );
+    }
+
+    #[test]
+    fn calls_with_no_args_nested_calls_and_parens() {
+        let number = |value: &str| Expression::Number {
+            value: String::from(value),
+            type_name: String::from("f32"),
+        };
+
+        assert_eq!(
+            parse(String::from(
+                "fn main(): void {
+    tick();
+    log(add(1, 2), (3 + 4), ((5)));
+}"
+            ))
+            .map(|program| match &program.blocks[0] {
+                Block::Function(function) => function.expressions.clone(),
+                _ => vec![],
+            }),
+            Ok(vec![
+                Expression::FunctionCall {
+                    name: String::from("tick"),
+                    args: vec![],
+                    info: Some(TokenInfo { line: 1, index: 4 }),
+                },
+                Expression::FunctionCall {
+                    name: String::from("log"),
+                    args: vec![
+                        Expression::FunctionCall {
+                            name: String::from("add"),
+                            args: vec![number("1"), number("2")],
+                            info: Some(TokenInfo { line: 2, index: 8 }),
+                        },
+                        Expression::Addition {
+                            left: Box::new(number("3")),
+                            right: Box::new(number("4")),
+                            type_name: String::from(""),
+                            info: Some(TokenInfo { line: 2, index: 22 }),
+                        },
+                        number("5")
+                    ],
+                    info: Some(TokenInfo { line: 2, index: 4 }),
+                }
+            ])
+        )
+    }
+
+    #[test]
+    fn a_call_with_an_empty_argument_errors() {
+        assert_eq!(
+            parse(String::from(
+                "fn main(): void {
+    log(1, , 2);
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from("Expected an argument between commas"))
+        )
+    }
+
+    #[test]
+    fn unexpected_characters_error() {
+        assert_eq!(
+            parse(String::from(
+                "fn one(): f32 {
+    return 1;
+}
+
+fn main(): f32 {
+    # one
+    return one();
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from("Unexpected character `#` at line 6, index 4"))
+        );
+        assert!(parse(String::from("fn one():\tf32 {\n\treturn 1;\n}")).is_ok());
+    }
+
+    #[test]
+    fn unterminated_strings_error() {
+        assert_eq!(
+            parse(String::from(
+                "import fn log(message: string) console.log
+
+fn main(): void {
+    log(\"hello);
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "Unterminated string literal at line 4, index 8"
+            ))
+        );
+    }
+
+    #[test]
+    fn reserved_words_can_not_be_names() {
+        assert_eq!(
+            parse(String::from("fn main(for: i32): i32 {\n    return for;\n}")),
+            Err(String::from(
+                "for is a reserved word, so it can't be used as a param name at line 1, index 8"
+            ))
+        );
+        assert_eq!(
+            parse(String::from("fn return(): i32 {\n    return 1;\n}")),
+            Err(String::from(
+                "return is a reserved word, so it can't be used as a function name at line 1, index 3"
+            ))
+        );
+        assert_eq!(
+            parse(String::from(
+                "fn main(): i32 {\n    local fn: i32 = 1;\n    return fn;\n}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "fn is a reserved word, so it can't be used as a local name at line 2, index 10"
+            ))
+        );
+        assert_eq!(
+            parse(String::from("fn main(): i32 {\n    local 2x: i32 = 1;\n    return 2x;\n}"))
+                .map(|_| ()),
+            Err(String::from(
+                "2x can't be used as a local name, as names can't start with a digit at line 2, index 10"
+            ))
+        );
+        assert_eq!(
+            parse(String::from("const if: i32 = 1")).map(|_| ()),
+            Err(String::from(
+                "if is a reserved word, so it can't be used as a constant name at line 1, index 6"
+            ))
+        );
+    }
+
+    #[test]
+    fn names_are_only_given_once() {
+        assert_eq!(
+            parse(String::from(
+                "import fn log(n: i32) console.log
+
+fn main(): void {
+}
+
+// logs twice
+fn log(n: i32): void {
+}
+
+export main main
+export main log"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "log is defined on line 1 and again at line 7, index 0
+main is exported on line 10 and again at line 11, index 0"
+            ))
+        );
+        assert_eq!(
+            parse(String::from(
+                "@export(\"run\")
+fn main(): void {
+}
+
+export run main"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "run is exported on line 1 and again at line 5, index 0"
+            ))
+        );
+    }
+
+    #[test]
+    fn used_modules_are_compiled_in() {
+        let program = parse(String::from(
+            "use std.math;
+use std.math;
+
+fn main(): f32 {
+    local x: f32 = double(2.0);
+    return x;
+}",
+        ))
+        .unwrap();
+        let functions: Vec<&str> = program
+            .blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::Function(function) => Some(function.name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(functions, vec!["double", "sum3", "pick", "main"]);
+
+        assert_eq!(
+            parse(String::from("fn main(): void {\n}\n\nuse std.maths;")).map(|_| ()),
+            Err(String::from(
+                "Unknown module std.maths, the modules are std.math, std.memory, std.string at line 4, index 0"
+            ))
+        );
+        assert_eq!(
+            parse(String::from(
+                "use std.math;
+
+fn double(x: f32): f32 {
+    return x;
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "double is defined in std.math and again at line 3, index 0"
+            ))
+        );
+    }
+
+    #[test]
+    fn defines_are_constants() {
+        let defines = [Define {
+            name: String::from("LIMIT"),
+            value: 4294967296,
+        }];
+        let constants = |source: &str| {
+            parse_with_defines(String::from(source), &defines).map(|program| {
+                program
+                    .blocks
+                    .into_iter()
+                    .filter_map(|block| match block {
+                        Block::Constant(constant) => Some(format!(
+                            "{}: {} = {}",
+                            constant.name, constant.type_name, constant.value
+                        )),
+                        _ => None,
+                    })
+                    .collect::<Vec<String>>()
+            })
+        };
+
+        assert_eq!(
+            constants("const LIMIT: i64 = 10;"),
+            Ok(vec![String::from("LIMIT: i64 = 4294967296")])
+        );
+        assert_eq!(
+            constants("fn main(): i64 {\n    return LIMIT;\n}"),
+            Ok(vec![String::from("LIMIT: i64 = 4294967296")])
+        );
+    }
+
+    #[test]
+    fn errors_report_the_line_in_the_file() {
+        assert_eq!(
+            parse(String::from(
+                "import fn log(number: i32) console.log
+
+fn main(): void {
+    log(3.14);
+}
+
+fn say_hello {}"
+            )),
+            Err(String::from(
+                "Expected parens but got { at line 7, index 13"
+            ))
+        )
+    }
+
+    #[test]
+    fn a_function_with_return_type_but_missing_everything_errors() {
+        assert_eq!(
+            parse(String::from("fn say_hello (name: string): string")),
+            Err(String::from("Expected { but got nothing"))
+        )
+    }
+
+    #[test]
+    fn an_export_without_an_external_name_errors() {
+        assert_eq!(
+            parse(String::from("export")),
+            Err(String::from("Expected external name in export"))
+        )
+    }
+
+    #[test]
+    fn an_export_without_an_external_name_but_a_bracket_errors() {
+        assert_eq!(
+            parse(String::from("export {")),
+            Err(String::from(
+                "Expected external name in export, got { at line 1, index 7"
+            ))
+        )
+    }
+
+    #[test]
+    fn an_export_without_an_internal_name_errors() {
+        assert_eq!(
+            parse(String::from("export sayHello")),
+            Err(String::from("Expected function name in export"))
+        )
+    }
+
+    #[test]
+    fn an_export_without_an_internal_name_but_a_bracket_errors() {
         assert_eq!(
             parse(String::from("export sayHello {")),
             Err(String::from(
@@ -380,14 +2458,203 @@ fn say_hello(): void {
     }
 
     #[test]
-    fn a_local_without_a_type_errors() {
+    fn a_local_without_a_type_infers_it() {
         assert_eq!(
             parse(String::from(
-                "fn sayHello(): string {
-    local var = 5;
+                "fn sayHello(flag: i32): void {
+    local count = 5;
+    local greeting = \"hi\";
+    local copy = flag;
 }"
-            )),
-            Err(String::from("Expected : but got = at line 2, index 14"))
+            ))
+            .map(|program| match &program.blocks[0] {
+                Block::Function(function) => function
+                    .expressions
+                    .iter()
+                    .filter_map(|expression| match expression {
+                        Expression::LocalAssign { type_name, .. } => Some(type_name.to_string()),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            }),
+            Ok(vec![
+                String::from("f32"),
+                String::from("string"),
+                String::from("i32")
+            ])
+        )
+    }
+
+    #[test]
+    fn a_local_without_a_type_needs_a_known_initializer() {
+        assert_eq!(
+            parse(String::from(
+                "fn sayHello(): void {
+    local var = five();
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "Couldn't infer the type of local var, add a type annotation"
+            ))
+        )
+    }
+
+    #[test]
+    fn a_local_with_a_type_must_match_its_initializer() {
+        assert_eq!(
+            parse(String::from(
+                "fn sayHello(flag: i32): void {
+    local var: f32 = flag;
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "Local var is declared as f32 but got i32 at line 2, index 10"
+            ))
+        )
+    }
+
+    #[test]
+    fn a_returned_variable_must_match_the_return_type() {
+        assert_eq!(
+            parse(String::from(
+                "fn c(x: i32): i64 {
+    return x;
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from("c returns i64 but got i32 at line 2, index 4"))
+        )
+    }
+
+    #[test]
+    fn a_tailcall_must_return_what_its_caller_does() {
+        assert_eq!(
+            parse(String::from(
+                "fn seconds(): i32 {
+    tailcall minutes();
+}
+
+fn minutes(): f32 {
+    return 1;
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "tailcall minutes returns f32 but seconds returns i32"
+            ))
+        )
+    }
+
+    #[test]
+    fn data_fields_need_a_data_block() {
+        assert_eq!(
+            parse(String::from(
+                "fn size(): i32 {
+    return logo.length;
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from("Unknown data logo at line 2, index 4"))
+        );
+        assert!(parse(String::from(
+            "data \"logo.bin\" as logo
+
+fn size(): i32 {
+    return logo.length;
+}"
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    fn arrays_are_indexed_by_i32() {
+        assert_eq!(
+            parse(String::from(
+                "fn main(): i32 {
+    local size: i32 = 4;
+    return size[0];
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "size isn't an array, so can't be indexed at line 3, index 15"
+            ))
+        );
+        assert_eq!(
+            parse(String::from(
+                "fn main(x: f32): i32 {
+    local table: [i32] = [1, 2.5];
+    return table[x];
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "The values of an array of i32 are i32 but got 2.5 at line 2, index 10
+The index into table is i32, but got f32 at line 3, index 4"
+            ))
+        );
+    }
+
+    #[test]
+    fn only_arrays_are_looped_over() {
+        assert_eq!(
+            parse(String::from(
+                "fn main(): void {
+    local size: i32 = 4;
+    for (n in size) {
+        log(n);
+    };
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "size isn't an array, so can't be looped over at line 3, index 18"
+            ))
+        );
+    }
+
+    #[test]
+    fn both_sides_of_an_addition_have_one_type() {
+        assert_eq!(
+            parse(String::from(
+                "fn main(n: i32, x: f32): i32 {
+    local next: i32 = n + 1;
+    return n + x;
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "Both sides of + are i32 but got f32 at line 3, index 13"
+            ))
+        );
+        assert_eq!(
+            parse(String::from(
+                "fn main(n: i32): i32 {
+    return n + 1.5;
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "Both sides of + are i32 but got 1.5 at line 2, index 13"
+            ))
+        );
+    }
+
+    #[test]
+    fn a_tailcall_must_call_a_function() {
+        assert_eq!(
+            parse(String::from(
+                "fn seconds(): f32 {
+    tailcall 1;
+}"
+            ))
+            .map(|_| ()),
+            Err(String::from(
+                "tailcall needs a function call, like tailcall f(x) at line 2, index 12"
+            ))
         )
     }
 
@@ -0,0 +1,770 @@
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    blocks::Block,
+    coverage,
+    errors::{CompileError, ErrorCode},
+    generators::{
+        gwe::{FormatOptions, Gwe},
+        js::{Dts, Js},
+        web_assembly::{function_at, generate_with_statements, strip_names, StatementLines, Wat},
+        Backend,
+    },
+    options::CompilerOptions,
+    output::write_atomically,
+    parser::{load_data, parse_sections, parse_with_defines, remove_trivia, Program},
+    tokenizer::{split_by_semicolon_within_brackets, take_until_closing, tokenize, Token},
+    transform::{self, Transform, WasmPlugin},
+    typechecker,
+};
+
+/// Errors from one stage of a compile, along with which stage it was
+#[derive(PartialEq, Debug, Clone)]
+pub struct Failure {
+    pub code: ErrorCode,
+    pub errors: Vec<CompileError>,
+}
+
+impl Failure {
+    pub fn new(code: ErrorCode, errors: Vec<CompileError>) -> Self {
+        Failure { code, errors }
+    }
+}
+
+/// The generated code for each of the options' targets along with its
+/// extension. Data blocks are read relative to the current directory
+pub fn compile(
+    source: &str,
+    options: &CompilerOptions,
+) -> Result<Vec<(String, Vec<u8>)>, Vec<CompileError>> {
+    let source = instrument(source, options).map_err(|error| vec![error])?;
+    let program = parse(&source, options).map_err(|failure| failure.errors)?;
+    let backends = options
+        .targets
+        .iter()
+        .map(|target| backend(target, options, "<source>", &source))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| vec![error])?;
+
+    generate(program, &backends, Path::new(""), &mut Timings::default())
+        .map_err(|failure| failure.errors)
+}
+
+/// The source with a counter before each statement when the options ask
+/// for coverage, otherwise as it is
+pub fn instrument(source: &str, options: &CompilerOptions) -> Result<String, CompileError> {
+    if options.coverage {
+        coverage::instrument(source).map(|instrumented| instrumented.source)
+    } else {
+        Ok(source.to_string())
+    }
+}
+
+/// Parses the source with the options' defines, then runs their transforms
+pub fn parse(source: &str, options: &CompilerOptions) -> Result<Program, Failure> {
+    let program = parse_with_defines(source.to_string(), &options.defines)
+        .map_err(|errors| Failure::new(ErrorCode::ParseError, errors))?;
+
+    options
+        .transforms
+        .iter()
+        .map(|path| WasmPlugin::load(path).map(|plugin| Box::new(plugin) as Box<dyn Transform>))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|transforms| transform::apply(program, &transforms))
+        .map_err(|error| Failure::new(ErrorCode::TransformError, vec![error]))
+}
+
+/// The backend generating code for a target. `file` and `source` are where
+/// the program came from, which wat2wasm's errors are pointed back at
+pub fn backend(
+    target: &str,
+    options: &CompilerOptions,
+    file: &str,
+    source: &str,
+) -> Result<Box<dyn Backend>, CompileError> {
+    let wat = Wat {
+        options: options.generate_options(target),
+    };
+
+    match target {
+        "gwe" => Ok(Box::new(Gwe {
+            options: FormatOptions::default(),
+        })),
+        "js" => Ok(Box::new(Js {
+            naming: options.naming.clone(),
+        })),
+        "dts" => Ok(Box::new(Dts {
+            naming: options.naming.clone(),
+        })),
+        "wat" | "wat-gc" => Ok(Box::new(wat)),
+        "wasm" => Ok(Box::new(WasmBinary {
+            wat,
+            wat2wasm: options.wat2wasm.clone(),
+            file: file.to_string(),
+            source: source.to_string(),
+            intermediate: None,
+        })),
+        target => Err(CompileError::new(format!("Unknown target {}", target))),
+    }
+}
+
+/// Typechecks the program, then generates it with each backend. Files
+/// embedded by data blocks are read from `directory`
+pub fn generate(
+    program: Program,
+    backends: &[Box<dyn Backend>],
+    directory: &Path,
+    timings: &mut Timings,
+) -> Result<Vec<(String, Vec<u8>)>, Failure> {
+    timings.blocks = program.blocks.len();
+    timings.functions = program
+        .blocks
+        .iter()
+        .filter(|block| matches!(block, Block::Function(_)))
+        .count();
+
+    let started = Instant::now();
+    typechecker::check(&remove_trivia(program.clone()))
+        .map_err(|errors| Failure::new(ErrorCode::TypeError, errors))?;
+    timings.typecheck = started.elapsed();
+
+    let with_data = if backends.iter().any(|backend| backend.reads_data()) {
+        Some(
+            load_data(program.clone(), directory)
+                .map_err(|error| Failure::new(ErrorCode::ReadError, vec![error]))?,
+        )
+    } else {
+        None
+    };
+
+    let started = Instant::now();
+    let wat2wasm_before = WAT2WASM_NANOS.load(Ordering::Relaxed);
+
+    let outputs = backends
+        .iter()
+        .map(|backend| {
+            let program = match (&with_data, backend.reads_data()) {
+                (Some(with_data), true) => with_data.clone(),
+                _ => program.clone(),
+            };
+
+            backend
+                .generate_module(program)
+                .map(|output| (backend.extension().to_string(), output))
+                .map_err(|error| Failure::new(ErrorCode::GenerateError, vec![error]))
+        })
+        .collect::<Result<Vec<(String, Vec<u8>)>, Failure>>()?;
+
+    // other compiles running at the same time, like in tests, can add to the
+    // wat2wasm time
+    timings.wat2wasm =
+        Duration::from_nanos(WAT2WASM_NANOS.load(Ordering::Relaxed) - wat2wasm_before)
+            .min(started.elapsed());
+    timings.codegen = started.elapsed() - timings.wat2wasm;
+    timings.output_bytes = outputs.iter().map(|(_, code)| code.len()).sum();
+
+    Ok(outputs)
+}
+
+/// Time spent in each step of a compile, along with how much each step
+/// worked on, printed by --timings
+#[derive(Debug, Default, PartialEq)]
+pub struct Timings {
+    /// Measured on a pass of its own, as blocks are tokenized while parsing
+    pub tokenize: Duration,
+    pub parse: Duration,
+    pub typecheck: Duration,
+    /// Generating every target, apart from the time in wat2wasm
+    pub codegen: Duration,
+    pub wat2wasm: Duration,
+    pub tokens: usize,
+    pub blocks: usize,
+    pub functions: usize,
+    pub output_bytes: usize,
+}
+
+impl Timings {
+    pub fn report(&self, file: &str) -> String {
+        let step = |name: &str, duration: Duration, counts: String| {
+            format!(
+                "  {:<10} {:>10.3}ms  {}",
+                name,
+                duration.as_secs_f64() * 1000.0,
+                counts
+            )
+            .trim_end()
+            .to_string()
+        };
+
+        [
+            format!("Timings for {}", file),
+            step("tokenize", self.tokenize, format!("{} tokens", self.tokens)),
+            step(
+                "parse",
+                self.parse,
+                format!("{} blocks, {} functions", self.blocks, self.functions),
+            ),
+            step("typecheck", self.typecheck, String::new()),
+            step(
+                "codegen",
+                self.codegen,
+                format!("{} bytes", self.output_bytes),
+            ),
+            step("wat2wasm", self.wat2wasm, String::new()),
+            step(
+                "total",
+                self.tokenize + self.parse + self.typecheck + self.codegen + self.wat2wasm,
+                String::new(),
+            ),
+        ]
+        .join("\n")
+    }
+}
+
+/// How .wat files are turned into .wasm
+#[derive(Debug, PartialEq)]
+pub enum WasmEncoder {
+    Wat2Wasm(PathBuf),
+    Builtin,
+}
+
+/// Looks for an executable called `name` in each directory of a PATH-style list
+pub fn find_in_path(name: &str, path_list: &OsStr) -> Option<PathBuf> {
+    let file_name = if cfg!(windows) {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    };
+
+    env::split_paths(path_list)
+        .map(|directory| directory.join(&file_name))
+        .find(|path| path.is_file())
+}
+
+/// Uses --wat2wasm or GWE_WAT2WASM when given, otherwise wat2wasm from PATH,
+/// falling back to the encoder built into gwe
+pub fn wasm_encoder(wat2wasm: Option<&Path>) -> Result<WasmEncoder, String> {
+    if let Some(path) = wat2wasm {
+        return if path.is_file() {
+            Ok(WasmEncoder::Wat2Wasm(path.to_path_buf()))
+        } else {
+            Err(format!(
+                "wat2wasm not found at {}, check --wat2wasm or GWE_WAT2WASM",
+                path.to_string_lossy()
+            ))
+        };
+    }
+
+    let found = env::var_os("PATH").and_then(|path_list| find_in_path("wat2wasm", &path_list));
+
+    match found {
+        Some(path) => Ok(WasmEncoder::Wat2Wasm(path)),
+        None => Ok(WasmEncoder::Builtin),
+    }
+}
+
+static WAT2WASM_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// wat2wasm only works on files, so the wat goes through a temporary directory
+fn run_wat2wasm(wat2wasm: &Path, wat: &str) -> Result<Vec<u8>, String> {
+    let directory = env::temp_dir().join(format!(
+        "gwe-{}-{}",
+        process::id(),
+        WAT2WASM_RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let input = directory.join("main.wat");
+    let output = directory.join("main.wasm");
+
+    if let Err(error) = fs::create_dir_all(&directory).and_then(|_| fs::write(&input, wat)) {
+        return Err(format!("Unable to write temporary wat due to {}", error));
+    }
+
+    let result = match Command::new(wat2wasm)
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .output()
+    {
+        Err(err) => Err(format!(
+            "Failed to run {} due to {}",
+            wat2wasm.to_string_lossy(),
+            err
+        )),
+        Ok(value) if !value.status.success() => Err(format!(
+            "Failed to generate wasm:\n{}",
+            String::from_utf8_lossy(&value.stderr)
+        )),
+        Ok(_) => match fs::read(&output) {
+            Ok(bytes) => Ok(bytes),
+            Err(error) => Err(format!("Unable to read wat2wasm output due to {}", error)),
+        },
+    };
+
+    let _ = fs::remove_dir_all(&directory);
+
+    result
+}
+
+/// The wat is named like wat2wasm's input, so the errors of either encoder are
+/// pointed at the gwe the same way
+fn run_builtin(wat: &str) -> Result<Vec<u8>, String> {
+    match wat::Parser::new().parse_str(Some(Path::new("main.wat")), wat) {
+        Ok(bytes) => Ok(bytes),
+        Err(error) => Err(format!("Failed to generate wasm:\n{}", error)),
+    }
+}
+
+/// Time spent encoding wasm, for --timings
+static WAT2WASM_NANOS: AtomicU64 = AtomicU64::new(0);
+
+pub fn encode_wasm(wat2wasm: Option<&Path>, wat: &str) -> Result<Vec<u8>, String> {
+    let started = Instant::now();
+
+    let encoded = match wasm_encoder(wat2wasm)? {
+        WasmEncoder::Wat2Wasm(wat2wasm) => run_wat2wasm(&wat2wasm, wat),
+        WasmEncoder::Builtin => run_builtin(wat),
+    };
+
+    WAT2WASM_NANOS.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+
+    encoded
+}
+
+/// The one-based line each statement of a function in the file starts on, not
+/// counting the statements inside its ifs and loops
+fn statement_lines(source: &str) -> Vec<(String, Vec<i32>)> {
+    parse_sections(source.to_string())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|section| section.module.is_none())
+        .filter_map(|section| {
+            let name = section.blocks.iter().find_map(|block| match block {
+                Block::Function(function) => Some(function.name.to_string()),
+                _ => None,
+            })?;
+
+            let tokens = tokenize(section.source.clone());
+            let mut tokens = tokens.iter();
+            tokens.find(|fqt| fqt.token == Token::LeftBracket)?;
+            let body =
+                take_until_closing(&mut tokens, Token::LeftBracket, Token::RightBracket).ok()?;
+
+            let lines = split_by_semicolon_within_brackets(body)
+                .iter()
+                .filter_map(|statement| statement.first())
+                .map(|first| section.start_line + first.info.line + 1)
+                .collect();
+
+            Some((name, lines))
+        })
+        .collect()
+}
+
+/// The line of the gwe each line of the wat comes from, where it comes from a
+/// statement of a function in the file. Passes which add statements to a
+/// function, like inlining, leave it with more than the gwe has, so its
+/// lines aren't known
+pub fn source_lines(statements: &StatementLines, source: &str) -> Vec<Option<i32>> {
+    let functions = statement_lines(source);
+
+    statements
+        .iter()
+        .map(|statement| {
+            let (function, index) = statement.as_ref()?;
+            let (_, lines) = functions.iter().find(|(name, _)| name == function)?;
+            let generated = statements
+                .iter()
+                .flatten()
+                .filter(|(name, _)| name == function)
+                .map(|(_, index)| index + 1)
+                .max()?;
+
+            if generated == lines.len() {
+                lines.get(*index).copied()
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Rewrites wat2wasm's `main.wat:5:6: error: ...` lines, and the builtin
+/// encoder's `--> main.wat:5:6` lines, to point at the line of the gwe the wat
+/// came from, which is the statement's line where
+/// `lines` knows it and otherwise the function's. The wat position is kept
+/// for when the generated code itself is wanted
+pub fn map_wat_errors(
+    stderr: &str,
+    wat: &str,
+    file: &str,
+    source: &str,
+    lines: &[Option<i32>],
+) -> String {
+    let functions = parse_sections(source.to_string())
+        .unwrap_or_default()
+        .into_iter()
+        // functions from the standard library aren't in the file
+        .filter(|section| section.module.is_none())
+        .flat_map(|section| {
+            let line = section.code_line() + 1;
+            section
+                .blocks
+                .into_iter()
+                .filter_map(move |block| match block {
+                    Block::Function(function) => Some((function.name, line)),
+                    _ => None,
+                })
+        })
+        .collect::<Vec<(String, i32)>>();
+
+    stderr
+        .lines()
+        .map(|line| {
+            // the builtin encoder gives the position on a line of its own
+            let arrow = line
+                .split_once("--> ")
+                .map(|(indent, _)| format!("{}--> ", indent))
+                .unwrap_or_default();
+            let position = line.split_once(".wat:").and_then(|(_, rest)| {
+                let mut parts = rest.splitn(3, ':');
+                let wat_line = parts.next()?.parse::<usize>().ok()?;
+                let wat_column = parts.next()?.parse::<usize>().ok()?;
+                let message = parts
+                    .next()
+                    .map(|message| format!(":{}", message))
+                    .unwrap_or_default();
+
+                Some((wat_line, wat_column, message))
+            });
+
+            let Some((wat_line, wat_column, message)) = position else {
+                return line.to_string();
+            };
+
+            let function = function_at(wat, wat_line, wat_column);
+            let statement_line = lines.get(wat_line.saturating_sub(1)).copied().flatten();
+            let source_line = statement_line.or_else(|| {
+                let function = function.as_ref()?;
+
+                functions
+                    .iter()
+                    .find(|(name, _)| name == function)
+                    .map(|(_, line)| *line)
+            });
+
+            match (function, source_line) {
+                (Some(function), Some(source_line)) => format!(
+                    "{}{}:{}{} (in fn {}, wat line {}:{})",
+                    arrow, file, source_line, message, function, wat_line, wat_column
+                ),
+                (Some(function), None) => format!(
+                    "{}{}{} (in generated {}, wat line {}:{})",
+                    arrow, file, message, function, wat_line, wat_column
+                ),
+                _ => format!(
+                    "{}{}{} (wat line {}:{})",
+                    arrow, file, message, wat_line, wat_column
+                ),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Binary wasm, encoded from what the wat target generates
+pub struct WasmBinary {
+    pub wat: Wat,
+    pub wat2wasm: Option<PathBuf>,
+    pub file: String,
+    /// The gwe the wat was generated from, for pointing the encoder's errors at it
+    pub source: String,
+    /// Where to keep the wat, which otherwise only lives in a temporary
+    /// directory while it's encoded
+    pub intermediate: Option<PathBuf>,
+}
+
+impl Backend for WasmBinary {
+    fn extension(&self) -> &str {
+        "wasm"
+    }
+
+    fn reads_data(&self) -> bool {
+        true
+    }
+
+    fn generate_module(&self, program: Program) -> Result<Vec<u8>, CompileError> {
+        let (wat, statements) = generate_with_statements(program, &self.wat.options)?;
+
+        // written before encoding, so it's there to look at when wat2wasm fails
+        if let Some(path) = &self.intermediate {
+            if let Err(error) = path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| write_atomically(path, wat.as_bytes()))
+            {
+                return Err(CompileError::new(format!(
+                    "Unable to write intermediate wat due to {}",
+                    error
+                )));
+            }
+        }
+
+        let wasm = encode_wasm(self.wat2wasm.as_deref(), &wat).map_err(|error| {
+            let lines = source_lines(&statements, &self.source);
+
+            CompileError::new(map_wat_errors(
+                &error,
+                &wat,
+                &self.file,
+                &self.source,
+                &lines,
+            ))
+        })?;
+
+        if self.wat.options.passes.strip_names {
+            Ok(strip_names(&wasm))
+        } else {
+            Ok(wasm)
+        }
+    }
+
+    fn generate_function(&self, program: Program, name: &str) -> Result<String, CompileError> {
+        self.wat.generate_function(program, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generators::web_assembly::GenerateOptions,
+        options::{Passes, Profile},
+        parser::parse,
+    };
+
+    #[test]
+    fn compile_uses_the_options_switches() {
+        let source = "@export\nfn one(): f32 {\n    return 1;\n}\n";
+
+        let outputs = compile(
+            source,
+            &CompilerOptions {
+                targets: vec![String::from("wat"), String::from("dts")],
+                ..CompilerOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            outputs
+                .iter()
+                .map(|(extension, _)| extension.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["wat", "d.ts"]
+        );
+        assert!(String::from_utf8_lossy(&outputs[0].1).contains("(func $one"));
+
+        let wasm = |passes: Passes| {
+            compile(
+                source,
+                &CompilerOptions {
+                    targets: vec![String::from("wasm")],
+                    passes,
+                    ..CompilerOptions::default()
+                },
+            )
+            .unwrap()
+            .remove(0)
+            .1
+        };
+        let dev = wasm(Passes::profile(Profile::Dev));
+        let release = wasm(Passes::profile(Profile::Release));
+
+        assert!(dev.starts_with(b"\0asm"));
+        // release leaves the name section out
+        assert!(release.len() < dev.len());
+
+        let errors = compile(
+            "fn one(): f32 {\n    return x;\n}\n",
+            &CompilerOptions::default(),
+        )
+        .unwrap_err();
+        assert!(!errors.is_empty());
+        assert!(compile(
+            source,
+            &CompilerOptions {
+                targets: vec![String::from("exe")],
+                ..CompilerOptions::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn wat2wasm_discovery() {
+        let directory = env::temp_dir().join("gwe_wat2wasm_discovery");
+        fs::create_dir_all(&directory).unwrap();
+        let wat2wasm = directory.join(if cfg!(windows) {
+            "wat2wasm.exe"
+        } else {
+            "wat2wasm"
+        });
+        fs::write(&wat2wasm, "").unwrap();
+
+        let path_list = env::join_paths([env::temp_dir(), directory]).unwrap();
+        assert_eq!(find_in_path("wat2wasm", &path_list), Some(wat2wasm));
+        assert_eq!(find_in_path("wat2wasm_missing", &path_list), None);
+
+        assert!(wasm_encoder(Some(Path::new("missing/wat2wasm"))).is_err());
+    }
+
+    #[test]
+    fn wat2wasm_errors_point_at_the_gwe() {
+        let source = "import fn log(n: i32) console.log
+
+/// Logs one
+fn main(): void {
+    log(1);
+}";
+        let wat = "(module
+  (import \"console\" \"log\" (func $log (param i32)))
+  (func $main
+    (call $log (i32.const 1))
+    (i32.const 2)
+  )
+  (func $__helper
+    (nop)
+  )
+)";
+        let stderr = "Failed to generate wasm:
+/tmp/gwe-1-0/main.wat:5:6: error: type mismatch in function, expected [] but got [i32]
+    (i32.const 2)
+     ^^^^^^^^^
+/tmp/gwe-1-0/main.wat:8:6: error: unknown instruction
+/tmp/gwe-1-0/main.wat:1:1: error: bad module";
+
+        assert_eq!(
+            map_wat_errors(stderr, wat, "main.gwe", source, &[]),
+            "Failed to generate wasm:
+main.gwe:4: error: type mismatch in function, expected [] but got [i32] (in fn main, wat line 5:6)
+    (i32.const 2)
+     ^^^^^^^^^
+main.gwe: error: unknown instruction (in generated __helper, wat line 8:6)
+main.gwe: error: bad module (wat line 1:1)"
+        );
+    }
+
+    #[test]
+    fn wat2wasm_errors_point_at_the_statement() {
+        let source = "import fn log(n: i32) console.log
+
+fn main(): void {
+    log(1);
+    // the third call
+    log(2);
+
+    log(3);
+}";
+        let (wat, statements) = generate_with_statements(
+            parse(String::from(source)).unwrap(),
+            &GenerateOptions::default(),
+        )
+        .unwrap();
+        let wat_line = wat
+            .lines()
+            .position(|line| line.contains("(i32.const 3)"))
+            .unwrap()
+            + 1;
+        let stderr = format!(
+            "/tmp/gwe-1-0/main.wat:{}:5: error: type mismatch in call",
+            wat_line
+        );
+
+        assert_eq!(
+            map_wat_errors(
+                &stderr,
+                &wat,
+                "main.gwe",
+                source,
+                &source_lines(&statements, source)
+            ),
+            format!(
+                "main.gwe:8: error: type mismatch in call (in fn main, wat line {}:5)",
+                wat_line
+            )
+        );
+        assert_eq!(
+            map_wat_errors(&stderr, &wat, "main.gwe", source, &[]),
+            format!(
+                "main.gwe:3: error: type mismatch in call (in fn main, wat line {}:5)",
+                wat_line
+            )
+        );
+    }
+
+    #[test]
+    fn builtin_encoder_errors_point_at_the_statement() {
+        let source = "import fn log(n: i32) console.log
+
+fn main(): void {
+    log(1);
+    log(2);
+}";
+        let (wat, statements) = generate_with_statements(
+            parse(String::from(source)).unwrap(),
+            &GenerateOptions::default(),
+        )
+        .unwrap();
+        let wat = wat.replace("(i32.const 2)", "(i32.bogus 2)");
+        let wat_line = wat
+            .lines()
+            .position(|line| line.contains("(i32.bogus 2)"))
+            .unwrap()
+            + 1;
+        let error = run_builtin(&wat).unwrap_err();
+
+        assert_eq!(
+            map_wat_errors(
+                &error,
+                &wat,
+                "main.gwe",
+                source,
+                &source_lines(&statements, source)
+            )
+            .lines()
+            .nth(2),
+            Some(format!("     --> main.gwe:5 (in fn main, wat line {}:6)", wat_line).as_str())
+        );
+    }
+
+    #[test]
+    fn timings_report_each_step() {
+        let timings = Timings {
+            parse: Duration::from_micros(1500),
+            tokens: 45,
+            blocks: 3,
+            functions: 1,
+            output_bytes: 392,
+            ..Timings::default()
+        };
+
+        assert_eq!(
+            timings.report("main.gwe"),
+            "Timings for main.gwe
+  tokenize        0.000ms  45 tokens
+  parse           1.500ms  3 blocks, 1 functions
+  typecheck       0.000ms
+  codegen         0.000ms  392 bytes
+  wat2wasm        0.000ms
+  total           1.500ms"
+        );
+    }
+}
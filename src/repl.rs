@@ -0,0 +1,396 @@
+use wasmtime::{Engine, ExternType, Linker, Memory, Module, Ref, Store, Table, Val};
+
+use crate::{
+    blocks::{parse_block, Block, Function},
+    errors::join_errors,
+    expressions::{parse_expression, Expression},
+    generators::web_assembly,
+    parser::parse,
+    tokenizer::{tokenize, Token},
+};
+
+const ENTRY_POINT: &str = "__repl";
+
+const HELP: &str = "Enter a fn, import, export or type block to add it to the session,
+or an expression to evaluate it.
+
+:wat    print the session module as WAT
+:reset  remove every definition from the session
+:help   print this message
+:quit   leave the repl";
+
+/// The blocks defined so far in a repl, recompiled and run for every expression
+pub struct Session {
+    blocks: Vec<(String, Block)>,
+    engine: Engine,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Input is complete once every opened bracket has been closed
+pub fn is_complete(input: &str) -> bool {
+    let mut depth = 0;
+
+    for fqt in tokenize(input.to_string()) {
+        match fqt.token {
+            Token::LeftBracket => depth += 1,
+            Token::RightBracket => depth -= 1,
+            _ => (),
+        }
+    }
+
+    depth <= 0
+}
+
+fn block_key(block: &Block) -> String {
+    match block {
+        Block::Function(function) => format!("fn {}", function.name),
+        Block::Export(export) => format!("export {}", export.external_name),
+        Block::ImportFunction(import) => format!("fn {}", import.name),
+        Block::ImportMemory(import) => format!("memory {}", import.external_name.join(".")),
+        Block::Data(data) => format!("data {}", data.name),
+        Block::Constant(constant) => format!("const {}", constant.name),
+        Block::ImportTable(import) => format!("table {}", import.external_name.join(".")),
+        Block::Memory(memory) => format!("memory {}", memory.name.as_deref().unwrap_or_default()),
+        Block::TypeAlias(alias) => format!("type {}", alias.name),
+        Block::Use(module) => format!("use {}", module.path.join(".")),
+        Block::Interface(interface) => format!("interface {}", interface.name),
+        Block::Test(test) => format!("test {}", test.name),
+        Block::Trivia(_) => String::new(),
+    }
+}
+
+fn format_value(value: &Val) -> String {
+    match value {
+        Val::I32(value) => value.to_string(),
+        Val::I64(value) => value.to_string(),
+        Val::F32(bits) => f32::from_bits(*bits).to_string(),
+        Val::F64(bits) => f64::from_bits(*bits).to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Provides every import of a module that the linker doesn't have yet, with
+/// functions that record their calls as `module.name(args)` in the store, and
+/// fresh memories and empty tables
+pub(crate) fn link_imports(
+    module: &Module,
+    store: &mut Store<Vec<String>>,
+    linker: &mut Linker<Vec<String>>,
+) -> Result<(), String> {
+    for import in module.imports() {
+        if linker
+            .get(&mut *store, import.module(), import.name())
+            .is_ok()
+        {
+            continue;
+        }
+
+        let defined = match import.ty() {
+            ExternType::Func(function_type) => {
+                let name = format!("{}.{}", import.module(), import.name());
+
+                linker
+                    .func_new(
+                        import.module(),
+                        import.name(),
+                        function_type,
+                        move |mut caller, params, _| {
+                            let args = params
+                                .iter()
+                                .map(format_value)
+                                .collect::<Vec<String>>()
+                                .join(", ");
+                            caller.data_mut().push(format!("{}({})", name, args));
+                            Ok(())
+                        },
+                    )
+                    .map(|_| ())
+            }
+            ExternType::Memory(memory_type) => {
+                Memory::new(&mut *store, memory_type).and_then(|memory| {
+                    linker
+                        .define(&*store, import.module(), import.name(), memory)
+                        .map(|_| ())
+                })
+            }
+            ExternType::Table(table_type) => {
+                let init = Ref::null(table_type.element().heap_type());
+
+                Table::new(&mut *store, table_type, init).and_then(|table| {
+                    linker
+                        .define(&*store, import.module(), import.name(), table)
+                        .map(|_| ())
+                })
+            }
+            _ => return Err(format!("Unsupported import {}", import.name())),
+        };
+
+        if let Err(error) = defined {
+            return Err(format!("Unable to provide import: {:#}", error));
+        }
+    }
+
+    Ok(())
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            blocks: vec![],
+            engine: Engine::default(),
+        }
+    }
+
+    fn functions(&self) -> Vec<&Function> {
+        self.blocks
+            .iter()
+            .filter_map(|(_, block)| match block {
+                Block::Function(function) => Some(function),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn source(&self) -> String {
+        self.blocks
+            .iter()
+            .map(|(source, _)| source.to_string())
+            .collect::<Vec<String>>()
+            .join("\n\n")
+    }
+
+    fn compile(&self, source: String) -> Result<Module, String> {
+        let program = match parse(source) {
+            Ok(program) => program,
+            Err(errors) => return Err(format!("Error parsing: {}", join_errors(&errors))),
+        };
+
+        let wat = match web_assembly::generate(program) {
+            Ok(wat) => wat,
+            Err(error) => return Err(format!("Error generating: {}", error)),
+        };
+
+        match Module::new(&self.engine, wat) {
+            Ok(module) => Ok(module),
+            Err(error) => Err(format!("Invalid module: {:#}", error)),
+        }
+    }
+
+    /// The type returned by an expression, or None when it doesn't produce a value
+    fn result_type(&self, expression: &Expression) -> Result<Option<String>, String> {
+        match expression {
+            Expression::Number { type_name, .. } => Ok(Some(type_name.to_string())),
+            Expression::Boolean { .. } | Expression::Character { .. } => {
+                Ok(Some(String::from("i32")))
+            }
+            Expression::Addition { type_name, .. } if type_name.is_empty() => {
+                Ok(Some(String::from("f32")))
+            }
+            Expression::Addition { type_name, .. } => Ok(Some(type_name.to_string())),
+            Expression::FunctionCall { name, .. } => {
+                match self
+                    .functions()
+                    .iter()
+                    .find(|function| &function.name == name)
+                {
+                    Some(function) if function.return_type == "void" => Ok(None),
+                    Some(function) => Ok(Some(function.return_type.to_string())),
+                    None => Ok(None),
+                }
+            }
+            Expression::String { .. } => Err(String::from("Strings can't be evaluated yet")),
+            _ => Err(String::from(
+                "Only expressions can be evaluated, declare locals inside a function",
+            )),
+        }
+    }
+
+    fn define(&mut self, input: &str) -> Result<Vec<String>, String> {
+        let block = match parse_block(input.to_string()) {
+            Ok(block) => block,
+            Err(error) => return Err(format!("Error parsing: {}", error)),
+        };
+        let key = block_key(&block);
+
+        let mut blocks: Vec<(String, Block)> = self
+            .blocks
+            .iter()
+            .filter(|(_, existing)| block_key(existing) != key)
+            .cloned()
+            .collect();
+        blocks.push((input.to_string(), block));
+
+        let session = Session {
+            blocks,
+            engine: self.engine.clone(),
+        };
+        session.compile(session.source())?;
+
+        self.blocks = session.blocks;
+
+        Ok(vec![format!("defined {}", key)])
+    }
+
+    fn evaluate(&self, input: &str) -> Result<Vec<String>, String> {
+        let input = input.trim_end_matches(';');
+        let tokens = tokenize(input.to_string());
+
+        if tokens.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let expression = match parse_expression(&mut tokens.iter(), vec![], vec![]) {
+            Ok(expression) => expression,
+            Err(error) => return Err(format!("Error parsing: {}", error)),
+        };
+
+        let entry_point = match self.result_type(&expression)? {
+            Some(type_name) => format!(
+                "fn {}(): {} {{\n    return {};\n}}",
+                ENTRY_POINT, type_name, input
+            ),
+            None => format!("fn {}(): void {{\n    {};\n}}", ENTRY_POINT, input),
+        };
+
+        let module = self.compile(format!(
+            "{}\n\n{}\n\nexport {} {}",
+            self.source(),
+            entry_point,
+            ENTRY_POINT,
+            ENTRY_POINT
+        ))?;
+
+        self.run(&module)
+    }
+
+    /// Instantiates the module with imported functions that record their
+    /// arguments, then calls the entry point
+    fn run(&self, module: &Module) -> Result<Vec<String>, String> {
+        let mut store: Store<Vec<String>> = Store::new(&self.engine, vec![]);
+        let mut linker: Linker<Vec<String>> = Linker::new(&self.engine);
+
+        link_imports(module, &mut store, &mut linker)?;
+
+        let instance = match linker.instantiate(&mut store, module) {
+            Ok(instance) => instance,
+            Err(error) => return Err(format!("Unable to instantiate module: {:#}", error)),
+        };
+
+        let function = match instance.get_func(&mut store, ENTRY_POINT) {
+            Some(function) => function,
+            None => return Err(String::from("Missing repl entry point")),
+        };
+
+        let mut results = vec![Val::I32(0); function.ty(&store).results().len()];
+
+        if let Err(error) = function.call(&mut store, &[], &mut results) {
+            return Err(format!("Error running: {:#}", error));
+        }
+
+        let mut output = store.into_data();
+        output.extend(results.iter().map(format_value));
+
+        Ok(output)
+    }
+
+    /// Runs a line (or several lines, for blocks) of input, returning what should be printed
+    pub fn eval(&mut self, input: &str) -> Result<Vec<String>, String> {
+        let input = input.trim();
+
+        match input {
+            ":help" => Ok(vec![String::from(HELP)]),
+            ":reset" => {
+                self.blocks.clear();
+                Ok(vec![String::from("session cleared")])
+            }
+            ":wat" => match parse(self.source()) {
+                Ok(program) => match web_assembly::generate(program) {
+                    Ok(wat) => Ok(vec![wat]),
+                    Err(error) => Err(format!("Error generating: {}", error)),
+                },
+                Err(errors) => Err(format!("Error parsing: {}", join_errors(&errors))),
+            },
+            _ if input.starts_with("fn ")
+                || input.starts_with("import ")
+                || input.starts_with("export ")
+                || input.starts_with("type ") =>
+            {
+                self.define(input)
+            }
+            _ => self.evaluate(input),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incomplete_input() {
+        assert!(!is_complete("fn main(): void {"));
+        assert!(is_complete("fn main(): void {\n}"));
+        assert!(is_complete("1 + 2"));
+    }
+
+    #[test]
+    fn evaluate_number() {
+        let mut session = Session::new();
+
+        assert_eq!(session.eval("3.5"), Ok(vec![String::from("3.5")]));
+        assert_eq!(session.eval("1 + 2;"), Ok(vec![String::from("3")]));
+        assert_eq!(session.eval("true"), Ok(vec![String::from("1")]));
+    }
+
+    #[test]
+    fn define_and_call_function() {
+        let mut session = Session::new();
+
+        assert_eq!(
+            session.eval("fn three(): f32 {\n    return 3;\n}"),
+            Ok(vec![String::from("defined fn three")])
+        );
+        assert_eq!(session.eval("three()"), Ok(vec![String::from("3")]));
+
+        assert_eq!(
+            session.eval("fn three(): f32 {\n    return 3.5;\n}"),
+            Ok(vec![String::from("defined fn three")])
+        );
+        assert_eq!(session.eval("three()"), Ok(vec![String::from("3.5")]));
+    }
+
+    #[test]
+    fn imports_are_recorded() {
+        let mut session = Session::new();
+
+        assert!(session.eval("import fn log(n: f32) console.log").is_ok());
+        assert_eq!(
+            session.eval("log(1.5)"),
+            Ok(vec![String::from("console.log(1.5)")])
+        );
+    }
+
+    #[test]
+    fn invalid_definition_is_not_kept() {
+        let mut session = Session::new();
+
+        assert!(session
+            .eval("fn broken(): i32 {\n    return missing();\n}")
+            .is_err());
+        assert_eq!(session.eval(":wat"), Ok(vec![String::from("(module\n)")]));
+    }
+
+    #[test]
+    fn reset() {
+        let mut session = Session::new();
+
+        assert!(session.eval("fn one(): f32 {\n    return 1;\n}").is_ok());
+        assert!(session.eval(":reset").is_ok());
+        assert!(session.eval("one()").is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::config::CONFIG_FILE_NAME;
+
+const MAIN: &str = r#"// Imports are functions the page gives the module
+import fn log(n: i32) console.log
+
+/// Counts to three through the page's console.log
+@export
+fn main(): void {
+    for (local i: i32 = 1, 4, 1) {
+        log(i);
+    };
+}
+"#;
+
+const LOADER: &str = r#"// Build the module first with: gwe --file main.gwe --target wasm
+const output = document.getElementById("output");
+
+const imports = {
+    console: {
+        log: (n) => {
+            console.log(n);
+            output.textContent += "Hello from gwe, " + n + "\n";
+        },
+    },
+};
+
+WebAssembly.instantiateStreaming(fetch("gwe_build/main.wasm"), imports)
+    .then(({ instance }) => instance.exports.main())
+    .catch((error) => (output.textContent = error.toString()));
+"#;
+
+const CONFIG: &str = r#"[format]
+indent_width = 4
+max_line_length = 100
+brace_style = "same-line"
+"#;
+
+fn page(name: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{}</title>
+</head>
+<body>
+<pre id="output"></pre>
+<script src="main.js"></script>
+</body>
+</html>
+"#,
+        name
+    )
+}
+
+/// Writes a starter project to `directory`: a hello world module, a page with
+/// a loader which gives it the imports it needs, and a gwe.toml. The directory
+/// has to be new or empty, so nothing is overwritten
+pub fn create_project(directory: &Path) -> Result<Vec<PathBuf>, String> {
+    let name = directory
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    if name.is_empty() {
+        return Err(String::from("A new project needs a name"));
+    }
+
+    if fs::read_dir(directory).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(format!(
+            "{} already exists and isn't empty",
+            directory.to_string_lossy()
+        ));
+    }
+
+    let files = [
+        ("main.gwe", MAIN.to_string()),
+        ("main.js", LOADER.to_string()),
+        ("index.html", page(&name)),
+        (CONFIG_FILE_NAME, CONFIG.to_string()),
+    ];
+
+    fs::create_dir_all(directory)
+        .map_err(|error| format!("Unable to create {} due to {}", name, error))?;
+
+    files
+        .into_iter()
+        .map(|(file, contents)| {
+            let path = directory.join(file);
+
+            match fs::write(&path, contents) {
+                Ok(_) => Ok(path),
+                Err(error) => Err(format!("Unable to write {} due to {}", file, error)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::read_config,
+        generators::web_assembly::{generate, validate},
+        parser::parse,
+    };
+
+    #[test]
+    fn new_projects_build() {
+        let directory = std::env::temp_dir().join("gwe_new_projects_build");
+        let _ = fs::remove_dir_all(&directory);
+
+        let written = create_project(&directory).unwrap();
+
+        assert_eq!(written.len(), 4);
+        assert!(read_config(&directory).is_ok());
+        assert!(fs::read_to_string(directory.join("index.html"))
+            .unwrap()
+            .contains("<title>gwe_new_projects_build</title>"));
+
+        let source = fs::read_to_string(directory.join("main.gwe")).unwrap();
+        let wat = generate(parse(source).unwrap()).unwrap();
+        assert_eq!(validate(&wat), Ok(()));
+        assert!(wat.contains("(export \"main\" (func $main))"));
+
+        assert_eq!(
+            create_project(&directory),
+            Err(format!(
+                "{} already exists and isn't empty",
+                directory.to_string_lossy()
+            ))
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+const RELOAD_PATH: &str = "/__reload";
+const WASM_PATH: &str = "/main.wasm";
+
+// from RFC 6455, appended to the client's key before hashing
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const HARNESS: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>gwe</title>
+</head>
+<body>
+<pre id="output"></pre>
+<script>
+const output = document.getElementById("output");
+const print = (line) => { output.textContent += line + "\n"; };
+
+async function load() {
+    const response = await fetch("/main.wasm");
+    if (!response.ok) {
+        print(await response.text());
+        return;
+    }
+
+    const module = await WebAssembly.compile(await response.arrayBuffer());
+    const imports = {};
+
+    for (const entry of WebAssembly.Module.imports(module)) {
+        imports[entry.module] = imports[entry.module] || {};
+
+        if (entry.kind === "function") {
+            imports[entry.module][entry.name] = (...args) => {
+                print(entry.module + "." + entry.name + "(" + args.join(", ") + ")");
+                const host = globalThis[entry.module];
+                if (host && typeof host[entry.name] === "function") {
+                    host[entry.name](...args);
+                }
+            };
+        } else if (entry.kind === "memory") {
+            imports[entry.module][entry.name] = new WebAssembly.Memory({ initial: 256 });
+        }
+    }
+
+    const instance = await WebAssembly.instantiate(module, imports);
+
+    for (const [name, value] of Object.entries(instance.exports)) {
+        if (typeof value === "function" && value.length === 0) {
+            print(name + "() = " + value());
+        }
+    }
+}
+
+load().catch((error) => print(error.toString()));
+
+const socket = new WebSocket("ws://" + location.host + "/__reload");
+socket.onmessage = () => location.reload();
+</script>
+</body>
+</html>
+"#;
+
+/// Serves a page that runs the compiled module, and tells open pages to
+/// reload when `reload` is called
+pub struct DevServer {
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+    address: SocketAddr,
+}
+
+/// The Sec-WebSocket-Accept header for a client's Sec-WebSocket-Key
+pub fn websocket_accept(key: &str) -> String {
+    STANDARD.encode(Sha1::digest(format!("{}{}", key, WEBSOCKET_GUID)))
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) {
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+
+    let _ = stream.write_all(header.as_bytes());
+    let _ = stream.write_all(body);
+}
+
+fn handle(
+    mut stream: TcpStream,
+    wasm_path: &PathBuf,
+    clients: &Arc<Mutex<Vec<TcpStream>>>,
+) -> Result<(), String> {
+    let mut reader = match stream.try_clone() {
+        Ok(reader) => BufReader::new(reader),
+        Err(error) => return Err(format!("Unable to read request due to {}", error)),
+    };
+
+    let mut request_line = String::new();
+    if let Err(error) = reader.read_line(&mut request_line) {
+        return Err(format!("Unable to read request due to {}", error));
+    }
+
+    let mut websocket_key: Option<String> = None;
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) if line.trim().is_empty() => break,
+            Ok(_) => {
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                        websocket_key = Some(value.trim().to_string());
+                    }
+                }
+            }
+            Err(error) => return Err(format!("Unable to read request due to {}", error)),
+        }
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+
+    match (path, websocket_key) {
+        (RELOAD_PATH, Some(key)) => {
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                websocket_accept(&key)
+            );
+
+            // held while writing, so that a reload can't be sent before the handshake
+            let mut clients = match clients.lock() {
+                Ok(clients) => clients,
+                Err(_) => return Err(String::from("Unable to accept websocket")),
+            };
+
+            if let Err(error) = stream.write_all(response.as_bytes()) {
+                return Err(format!("Unable to accept websocket due to {}", error));
+            }
+
+            clients.push(stream);
+        }
+        (WASM_PATH, _) => match fs::read(wasm_path) {
+            Ok(bytes) => respond(&mut stream, "200 OK", "application/wasm", &bytes),
+            Err(error) => respond(
+                &mut stream,
+                "500 Internal Server Error",
+                "text/plain",
+                format!(
+                    "Unable to read {} due to {}",
+                    wasm_path.to_string_lossy(),
+                    error
+                )
+                .as_bytes(),
+            ),
+        },
+        ("/" | "/index.html", _) => respond(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            HARNESS.as_bytes(),
+        ),
+        _ => respond(&mut stream, "404 Not Found", "text/plain", b"Not found"),
+    }
+
+    Ok(())
+}
+
+impl DevServer {
+    /// Starts serving in the background, with the module read from `wasm_path`
+    /// on every request so that rebuilds are picked up
+    pub fn start(address: &str, wasm_path: PathBuf) -> Result<DevServer, String> {
+        let listener = match TcpListener::bind(address) {
+            Ok(listener) => listener,
+            Err(error) => return Err(format!("Unable to listen on {} due to {}", address, error)),
+        };
+        let address = match listener.local_addr() {
+            Ok(address) => address,
+            Err(error) => return Err(format!("Unable to listen on {} due to {}", address, error)),
+        };
+
+        let clients: Arc<Mutex<Vec<TcpStream>>> = Arc::new(Mutex::new(vec![]));
+        let server_clients = clients.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let clients = server_clients.clone();
+                let wasm_path = wasm_path.clone();
+
+                thread::spawn(move || {
+                    let _ = handle(stream, &wasm_path, &clients);
+                });
+            }
+        });
+
+        Ok(DevServer { clients, address })
+    }
+
+    /// Where the server is listening, with the port picked when asked for port 0
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Tells every open page to reload, forgetting pages that have gone away
+    pub fn reload(&self) {
+        let message = b"reload";
+        let mut frame = vec![0x81, message.len() as u8];
+        frame.extend_from_slice(message);
+
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.retain_mut(|client| client.write_all(&frame).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn accept_key_from_rfc() {
+        assert_eq!(
+            websocket_accept("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        )
+    }
+
+    #[test]
+    fn serves_harness_and_reloads() {
+        let wasm_path = std::env::temp_dir().join("gwe_serves_harness_and_reloads.wasm");
+        fs::write(&wasm_path, b"\0asm").unwrap();
+
+        let server = DevServer::start("127.0.0.1:0", wasm_path).unwrap();
+        let address = server.address();
+
+        let mut page = TcpStream::connect(address).unwrap();
+        page.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut body = String::new();
+        page.read_to_string(&mut body).unwrap();
+        assert!(body.starts_with("HTTP/1.1 200 OK"));
+        assert!(body.contains("/__reload"));
+
+        let mut wasm = TcpStream::connect(address).unwrap();
+        wasm.write_all(b"GET /main.wasm HTTP/1.1\r\n\r\n").unwrap();
+        let mut bytes = vec![];
+        wasm.read_to_end(&mut bytes).unwrap();
+        assert!(bytes.ends_with(b"\0asm"));
+
+        let mut socket = TcpStream::connect(address).unwrap();
+        socket
+            .write_all(
+                b"GET /__reload HTTP/1.1\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            )
+            .unwrap();
+        let mut reader = BufReader::new(socket.try_clone().unwrap());
+        let mut handshake = String::new();
+        while !handshake.ends_with("\r\n\r\n") {
+            reader.read_line(&mut handshake).unwrap();
+        }
+        assert!(handshake.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+        server.reload();
+        let mut frame = [0u8; 8];
+        reader.read_exact(&mut frame).unwrap();
+        assert_eq!(&frame, b"\x81\x06reload");
+    }
+}
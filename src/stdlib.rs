@@ -0,0 +1,35 @@
+/// The standard library, compiled into the binary so that `use std.math;`
+/// works without anything installed next to the compiler
+const MODULES: [(&str, &str); 3] = [
+    ("std.math", include_str!("std/math.gwe")),
+    ("std.memory", include_str!("std/memory.gwe")),
+    ("std.string", include_str!("std/string.gwe")),
+];
+
+/// The source of a standard library module, by its dotted name
+pub fn find_module(name: &str) -> Option<&'static str> {
+    MODULES
+        .iter()
+        .find(|(module, _)| *module == name)
+        .map(|(_, source)| *source)
+}
+
+/// The names of every module that can be used
+pub fn module_names() -> Vec<&'static str> {
+    MODULES.iter().map(|(name, _)| *name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn modules_parse_on_their_own() {
+        for name in module_names() {
+            let source = find_module(name).unwrap();
+            assert!(parse(source.to_string()).is_ok(), "{} doesn't parse", name);
+        }
+        assert_eq!(find_module("std.missing"), None);
+    }
+}
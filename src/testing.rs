@@ -0,0 +1,304 @@
+use wasmtime::{Caller, Engine, Instance, Linker, Module, Store};
+
+use crate::{
+    blocks::{Block, Export},
+    generators::web_assembly::{self, GenerateOptions, ASSERT_FAILED_IMPORT},
+    parser::Program,
+    repl::link_imports,
+};
+
+/// How a single test went, along with the calls it made to imports
+#[derive(PartialEq, Debug, Clone)]
+pub struct TestOutcome {
+    pub name: String,
+    pub result: Result<(), String>,
+    pub output: Vec<String>,
+    /// How many times each statement ran, when the tests were generated with
+    /// coverage
+    pub hits: Vec<i32>,
+}
+
+/// Builds the module that `gwe test` runs, where every test block and `@test`
+/// function is exported under a hidden name. Returns the program along with
+/// each test's name and export
+pub fn test_program(program: &Program) -> Result<(Program, Vec<(String, String)>), String> {
+    let mut blocks: Vec<Block> = vec![];
+    let mut tests: Vec<(String, String)> = vec![];
+
+    for block in &program.blocks {
+        match block {
+            Block::Test(test) => {
+                let export_name = format!("__test_{}", tests.len());
+                blocks.push(Block::Function(test.as_function(&export_name)));
+                blocks.push(Block::Export(Export {
+                    external_name: export_name.to_string(),
+                    function_name: export_name.to_string(),
+                    info: None,
+                }));
+                tests.push((test.name.to_string(), export_name));
+            }
+            Block::Function(function) if function.has_attribute("test") => {
+                if !function.params.is_empty() || function.return_type != "void" {
+                    return Err(format!(
+                        "Test function {} can't take params or return a value",
+                        function.name
+                    ));
+                }
+
+                let name = function
+                    .attributes
+                    .iter()
+                    .find(|attribute| attribute.name == "test")
+                    .and_then(|attribute| attribute.args.first())
+                    .unwrap_or(&function.name)
+                    .to_string();
+                let export_name = format!("__test_{}", tests.len());
+
+                blocks.push(block.clone());
+                blocks.push(Block::Export(Export {
+                    external_name: export_name.to_string(),
+                    function_name: function.name.to_string(),
+                    info: None,
+                }));
+                tests.push((name, export_name));
+            }
+            block => blocks.push(block.clone()),
+        }
+    }
+
+    Ok((Program { blocks }, tests))
+}
+
+/// Reads back what coverage counted in an instance
+fn read_hits(
+    instance: &Instance,
+    store: &mut Store<Vec<String>>,
+    statements: usize,
+) -> Result<Vec<i32>, String> {
+    let dump = match instance.get_typed_func::<i32, i32>(&mut *store, "__coverage_dump") {
+        Ok(dump) => dump,
+        Err(error) => return Err(format!("Unable to find coverage: {:#}", error)),
+    };
+
+    (0..statements as i32)
+        .map(|statement| {
+            dump.call(&mut *store, statement)
+                .map_err(|error| format!("Unable to read coverage: {:#}", error))
+        })
+        .collect()
+}
+
+fn run_test(
+    engine: &Engine,
+    module: &Module,
+    export_name: &str,
+    statements: usize,
+) -> (Result<(), String>, Vec<String>, Vec<i32>) {
+    let mut store: Store<Vec<String>> = Store::new(engine, vec![]);
+    let mut linker: Linker<Vec<String>> = Linker::new(engine);
+
+    // a failed assert ends the test with where it was, rather than the trap
+    let (assert_module, assert_name) = ASSERT_FAILED_IMPORT;
+    let assert_failed = linker.func_wrap(
+        assert_module,
+        assert_name,
+        |_: Caller<'_, Vec<String>>, line: i32| -> wasmtime::Result<()> {
+            Err(wasmtime::Error::msg(format!(
+                "assert failed at line {}",
+                line
+            )))
+        },
+    );
+
+    if let Err(error) = assert_failed {
+        return (
+            Err(format!("Unable to provide import: {:#}", error)),
+            vec![],
+            vec![],
+        );
+    }
+
+    let mut hits: Vec<i32> = vec![];
+    let result = link_imports(module, &mut store, &mut linker).and_then(|_| {
+        let instance = match linker.instantiate(&mut store, module) {
+            Ok(instance) => instance,
+            Err(error) => return Err(format!("Unable to instantiate module: {:#}", error)),
+        };
+
+        let function = match instance.get_typed_func::<(), ()>(&mut store, export_name) {
+            Ok(function) => function,
+            Err(error) => return Err(format!("Unable to find test: {:#}", error)),
+        };
+
+        let result = match function.call(&mut store, ()) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(error.root_cause().to_string()),
+        };
+
+        // failed tests still count what ran before they failed
+        if statements > 0 {
+            hits = read_hits(&instance, &mut store, statements)?;
+        }
+
+        result
+    });
+
+    (result, store.into_data(), hits)
+}
+
+/// Runs every test in the program, each in a fresh instance of the module
+pub fn run_tests(program: &Program) -> Result<Vec<TestOutcome>, String> {
+    run_tests_with(program, &GenerateOptions::default(), 0)
+}
+
+/// Runs the tests generated with `options`, reading back the hits of each of
+/// the `statements` counted by coverage
+pub fn run_tests_with(
+    program: &Program,
+    options: &GenerateOptions,
+    statements: usize,
+) -> Result<Vec<TestOutcome>, String> {
+    let (test_program, tests) = test_program(program)?;
+
+    let wat = match web_assembly::generate_with(test_program, options) {
+        Ok(wat) => wat,
+        Err(error) => return Err(format!("Error generating: {}", error)),
+    };
+
+    let engine = Engine::default();
+    let module = match Module::new(&engine, wat) {
+        Ok(module) => module,
+        Err(error) => return Err(format!("Invalid module: {:#}", error)),
+    };
+
+    Ok(tests
+        .into_iter()
+        .map(|(name, export_name)| {
+            let (result, output, hits) = run_test(&engine, &module, &export_name, statements);
+
+            TestOutcome {
+                name,
+                result,
+                output,
+                hits,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::join_errors, parser::parse};
+
+    fn run(source: &str) -> Vec<TestOutcome> {
+        match parse(String::from(source)) {
+            Ok(program) => run_tests(&program).unwrap(),
+            Err(errors) => panic!("{}", join_errors(&errors)),
+        }
+    }
+
+    #[test]
+    fn passing_and_failing_tests() {
+        let outcomes = run("import fn log(number: i32) console.log
+
+fn forever(): void {
+    forever();
+}
+
+fn add(x: f32, y: f32): f32 {
+    return x + y;
+}
+
+test \"logs\" {
+    log(1);
+}
+
+test \"never returns\" {
+    forever();
+}
+
+test \"adds numbers\" {
+    assert_eq(add(1, 2), 3);
+    assert(true);
+}
+
+test \"adds wrong\" {
+    assert_eq(add(1, 2), 4);
+}
+
+@test(\"attribute\")
+fn logs_twice(): void {
+    log(2);
+    log(3);
+}");
+
+        assert_eq!(
+            outcomes
+                .iter()
+                .map(|outcome| (outcome.name.as_str(), outcome.result.is_ok()))
+                .collect::<Vec<(&str, bool)>>(),
+            vec![
+                ("logs", true),
+                ("never returns", false),
+                ("adds numbers", true),
+                ("adds wrong", false),
+                ("attribute", true)
+            ]
+        );
+        assert_eq!(
+            outcomes[3].result,
+            Err(String::from("assert failed at line 25"))
+        );
+        assert_eq!(outcomes[0].output, vec![String::from("console.log(1)")]);
+        assert_eq!(
+            outcomes[4].output,
+            vec![
+                String::from("console.log(2)"),
+                String::from("console.log(3)")
+            ]
+        );
+    }
+
+    #[test]
+    fn imported_tables_are_provided() {
+        let outcomes = run("import table 4 funcref js.table
+
+test \"runs\" {
+    assert(true);
+}");
+
+        assert_eq!(outcomes[0].result, Ok(()));
+    }
+
+    #[test]
+    fn tests_are_left_out_of_normal_builds() {
+        let program = parse(String::from(
+            "test \"nothing\" {
+}",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            web_assembly::generate(program),
+            Ok(String::from("(module\n)"))
+        );
+    }
+
+    #[test]
+    fn test_functions_take_nothing() {
+        let program = parse(String::from(
+            "@test
+fn check(n: i32): void {
+}",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            run_tests(&program),
+            Err(String::from(
+                "Test function check can't take params or return a value"
+            ))
+        );
+    }
+}
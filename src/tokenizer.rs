@@ -1,31 +1,53 @@
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::slice::Iter;
+
+use crate::errors::CompileError;
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TokenInfo {
     pub line: i32,
     pub index: i32,
 }
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Token {
     LeftParen,
     RightParen,
-    Identifier { body: String },
-    Number { body: String },
+    Identifier {
+        body: String,
+    },
+    Number {
+        body: String,
+    },
     Fn,
     Memory,
     Colon,
     LeftBracket,
     RightBracket,
+    LeftSquare,
+    RightSquare,
     Comma,
     Return,
+    TailCall,
     Semicolon,
     Local,
     Global,
     Assign,
-    Text { body: String },
+    Text {
+        body: String,
+    },
+    /// `'a'`, kept as written with its quotes
+    Character {
+        body: String,
+    },
     Plus,
+    Star,
+    Question,
+    LessThan,
+    GreaterThan,
     Export,
     Import,
     Dot,
@@ -34,14 +56,38 @@ pub enum Token {
     True,
     False,
     For,
+    Try,
+    Catch,
+    Type,
+    DocComment {
+        body: String,
+    },
+    /// `// text`, which is only kept for the formatter. Trailing comments
+    /// follow code on the same line
+    Comment {
+        body: String,
+        trailing: bool,
+    },
+    At,
 }
 
 #[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FullyQualifiedToken {
     pub token: Token,
     pub info: TokenInfo,
 }
 
+impl FullyQualifiedToken {
+    /// Where a word starts, as words are given the position just after them
+    pub fn start(&self) -> TokenInfo {
+        TokenInfo {
+            line: self.info.line,
+            index: self.info.index - self.token.to_string().chars().count() as i32,
+        }
+    }
+}
+
 impl Display for Token {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         write!(
@@ -56,14 +102,22 @@ impl Display for Token {
                 Token::Colon => ":",
                 Token::LeftBracket => "{",
                 Token::RightBracket => "}",
+                Token::LeftSquare => "[",
+                Token::RightSquare => "]",
                 Token::Comma => ",",
                 Token::Return => "return",
+                Token::TailCall => "tailcall",
                 Token::Semicolon => ";",
                 Token::Local => "local",
                 Token::Global => "global",
                 Token::Assign => "=",
                 Token::Text { body } => body,
+                Token::Character { body } => body,
                 Token::Plus => "+",
+                Token::Star => "*",
+                Token::Question => "?",
+                Token::LessThan => "<",
+                Token::GreaterThan => ">",
                 Token::Number { body } => body,
                 Token::Export => "export",
                 Token::Import => "import",
@@ -73,18 +127,66 @@ impl Display for Token {
                 Token::True => "true",
                 Token::False => "false",
                 Token::For => "for",
+                Token::Try => "try",
+                Token::Catch => "catch",
+                Token::Type => "type",
+                Token::DocComment { .. } => "///",
+                Token::Comment { .. } => "//",
+                Token::At => "@",
             }
         )
     }
 }
 
-pub fn error_with_info<A>(error: String, token: &FullyQualifiedToken) -> Result<A, String> {
-    Err(format!(
-        "{} at line {}, index {}",
-        error,
-        token.info.line + 1,
-        token.info.index
-    ))
+pub fn error_with_info<A>(error: String, token: &FullyQualifiedToken) -> Result<A, CompileError> {
+    Err(CompileError::with_info(error, token.info.clone()))
+}
+
+pub fn error_without_info<A>(error: String) -> Result<A, CompileError> {
+    Err(CompileError::new(error))
+}
+
+/// Errors when a name being declared is a keyword or starts with a digit,
+/// which would otherwise only fail later on in a confusing way. `kind` is
+/// what's being named, like param or local. The error points at the start of
+/// the name
+pub fn check_name(fqt: &FullyQualifiedToken, kind: &str) -> Result<(), CompileError> {
+    match &fqt.token {
+        Token::Identifier { body } | Token::Number { body }
+            if body.starts_with(|char: char| char.is_ascii_digit()) =>
+        {
+            Err(CompileError::with_info(
+                format!(
+                    "{} can't be used as a {} name, as names can't start with a digit",
+                    body, kind
+                ),
+                fqt.start(),
+            ))
+        }
+        Token::Fn
+        | Token::Memory
+        | Token::Return
+        | Token::TailCall
+        | Token::Local
+        | Token::Global
+        | Token::Export
+        | Token::Import
+        | Token::If
+        | Token::Else
+        | Token::True
+        | Token::False
+        | Token::For
+        | Token::Try
+        | Token::Catch
+        | Token::Type => Err(CompileError::with_info(
+            format!(
+                "{} is a reserved word, so it can't be used as a {} name",
+                fqt.token, kind
+            ),
+            fqt.start(),
+        )),
+        _ => Ok(()),
+    }
 }
 
 fn is_identifier_char(char: char) -> bool {
@@ -95,6 +197,99 @@ fn is_number_string(str: &str) -> bool {
     str.chars().all(|char| char.is_numeric() || char == '.')
 }
 
+/// The code point of a character literal like `'a'` or `'\n'`, quotes and all
+pub fn character_value(body: &str) -> Result<char, String> {
+    let inner = body
+        .strip_prefix('\'')
+        .and_then(|inner| inner.strip_suffix('\''))
+        .unwrap_or(body);
+    let mut chars = inner.chars();
+
+    let value = match chars.next() {
+        None => return Err(String::from("A character literal needs a character")),
+        Some('\\') => match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('\'') => '\'',
+            Some('"') => '"',
+            // `\u{1F600}`, a code point in hex
+            Some('u') => {
+                let value = chars
+                    .as_str()
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.strip_suffix('}'))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32);
+                chars = "".chars();
+
+                match value {
+                    Some(value) => value,
+                    None => {
+                        return Err(format!(
+                            "{} isn't a unicode escape, which is written like \\u{{1F600}}",
+                            body
+                        ))
+                    }
+                }
+            }
+            Some(escape) => return Err(format!("Unknown escape \\{} in {}", escape, body)),
+            None => return Err(format!("{} ends in the middle of an escape", body)),
+        },
+        Some(value) => value,
+    };
+
+    if chars.next().is_some() {
+        return Err(format!("{} has more than one character", body));
+    }
+
+    Ok(value)
+}
+
+/// A character as a literal which `character_value` reads back, escaping
+/// the characters which would be hard to see
+pub fn character_literal(value: char) -> String {
+    let escaped = match value {
+        '\n' => String::from("\\n"),
+        '\t' => String::from("\\t"),
+        '\r' => String::from("\\r"),
+        '\0' => String::from("\\0"),
+        '\\' => String::from("\\\\"),
+        '\'' => String::from("\\'"),
+        value if value.is_control() => format!("\\u{{{:x}}}", value as u32),
+        value => value.to_string(),
+    };
+
+    format!("'{}'", escaped)
+}
+
+/// Numbers are only taken as far as their digits and dots go, so `1.2.3`
+/// comes through as one number. Anything but digits with at most one decimal
+/// point between them is an error pointing at the start of the number
+fn check_number(fqt: &FullyQualifiedToken) -> Option<CompileError> {
+    let body = match &fqt.token {
+        Token::Number { body } => body,
+        _ => return None,
+    };
+
+    let problem = match body.split('.').collect::<Vec<&str>>().as_slice() {
+        [_] => return None,
+        [whole, fraction] if !whole.is_empty() && !fraction.is_empty() => return None,
+        [_, _] => "needs digits on both sides of its decimal point",
+        _ => "can only have one decimal point",
+    };
+
+    Some(CompileError::with_info(
+        format!("The number {} {}", body, problem),
+        TokenInfo {
+            line: fqt.info.line,
+            index: fqt.info.index - body.chars().count() as i32,
+        },
+    ))
+}
+
 fn possibly_push_current_buffer(
     tokens: &mut Vec<FullyQualifiedToken>,
     current_buffer: &mut Vec<char>,
@@ -108,6 +303,7 @@ fn possibly_push_current_buffer(
             "fn" => Token::Fn,
             "memory" => Token::Memory,
             "return" => Token::Return,
+            "tailcall" => Token::TailCall,
             "local" => Token::Local,
             "global" => Token::Global,
             "export" => Token::Export,
@@ -117,6 +313,9 @@ fn possibly_push_current_buffer(
             "true" => Token::True,
             "false" => Token::False,
             "for" => Token::For,
+            "try" => Token::Try,
+            "catch" => Token::Catch,
+            "type" => Token::Type,
             x if is_number_string(x) => Token::Number { body: chars },
             _ => Token::Identifier { body: chars },
         };
@@ -152,14 +351,48 @@ fn push_text(
 }
 
 pub fn tokenize(body: String) -> Vec<FullyQualifiedToken> {
-    let chars = body.chars();
+    tokenize_with_comments(body)
+        .into_iter()
+        .filter(|fqt| !matches!(fqt.token, Token::Comment { .. }))
+        .collect()
+}
+
+/// Windows line endings as `\n`, so that nothing after this has to look out
+/// for a `\r`. Line numbers are the same either way
+pub fn normalize_line_endings(body: &str) -> String {
+    body.replace("\r\n", "\n")
+}
+
+/// Tokens along with the `//` comments between them
+pub fn tokenize_with_comments(body: String) -> Vec<FullyQualifiedToken> {
+    scan(body).0
+}
+
+/// Tokens along with the `//` comments between them, or an error for the
+/// first character which isn't part of any token
+pub fn tokenize_checked(body: String) -> Result<Vec<FullyQualifiedToken>, CompileError> {
+    let (tokens, mut errors) = scan(body);
+
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors.remove(0))
+    }
+}
+
+/// The tokens, along with errors for anything the tokenizer had to skip
+fn scan(body: String) -> (Vec<FullyQualifiedToken>, Vec<CompileError>) {
+    let body = normalize_line_endings(&body);
+    let mut chars = body.chars().peekable();
     let mut tokens: Vec<FullyQualifiedToken> = vec![];
+    let mut errors: Vec<CompileError> = vec![];
     let mut current_buffer: Vec<char> = vec![];
     let mut is_in_quotes = false;
+    let mut quote_start = TokenInfo { line: 0, index: 0 };
     let mut line_number = 0;
     let mut char_index = 0;
 
-    for char in chars {
+    while let Some(char) = chars.next() {
         match char {
             '"' => {
                 if is_in_quotes {
@@ -172,10 +405,111 @@ pub fn tokenize(body: String) -> Vec<FullyQualifiedToken> {
                         line_number,
                         char_index,
                     );
-                    is_in_quotes = true
+                    is_in_quotes = true;
+                    quote_start = TokenInfo {
+                        line: line_number,
+                        index: char_index,
+                    };
                 }
             }
+            // strings stop at the end of the line, rather than taking the
+            // rest of the file with them
+            '\n' if is_in_quotes => {
+                errors.push(CompileError::with_info(
+                    String::from("Unterminated string literal"),
+                    quote_start.clone(),
+                ));
+                push_text(&mut tokens, &mut current_buffer, line_number, char_index);
+                is_in_quotes = false;
+            }
             char if is_in_quotes => current_buffer.push(char),
+            '\'' => {
+                possibly_push_current_buffer(
+                    &mut tokens,
+                    &mut current_buffer,
+                    line_number,
+                    char_index,
+                );
+
+                let start = TokenInfo {
+                    line: line_number,
+                    index: char_index,
+                };
+                let mut body = String::from("'");
+                let mut escaped = false;
+                let mut closed = false;
+                while let Some(next) = chars.next_if(|next| *next != '\n') {
+                    body.push(next);
+                    char_index += 1;
+
+                    match next {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '\'' => {
+                            closed = true;
+                            break;
+                        }
+                        _ => (),
+                    }
+                }
+
+                if !closed {
+                    errors.push(CompileError::with_info(
+                        String::from("Unterminated character literal"),
+                        start,
+                    ));
+                } else if let Err(error) = character_value(&body) {
+                    errors.push(CompileError::with_info(error, start));
+                } else {
+                    tokens.push(FullyQualifiedToken {
+                        token: Token::Character { body },
+                        info: TokenInfo {
+                            line: line_number,
+                            index: char_index,
+                        },
+                    });
+                }
+            }
+            // comments run to the end of the line, with `///` being documentation
+            '/' if chars.peek() == Some(&'/') => {
+                possibly_push_current_buffer(
+                    &mut tokens,
+                    &mut current_buffer,
+                    line_number,
+                    char_index,
+                );
+
+                let start = char_index;
+                let mut comment = String::new();
+                while let Some(next) = chars.next_if(|next| *next != '\n') {
+                    comment.push(next);
+                    char_index += 1;
+                }
+
+                let token = match comment.strip_prefix("//") {
+                    Some(body) => Token::DocComment {
+                        body: body.strip_prefix(' ').unwrap_or(body).to_string(),
+                    },
+                    None => {
+                        let body = comment.strip_prefix('/').unwrap_or(&comment);
+
+                        Token::Comment {
+                            body: body.trim().to_string(),
+                            trailing: tokens
+                                .last()
+                                .is_some_and(|fqt| fqt.info.line == line_number),
+                        }
+                    }
+                };
+
+                tokens.push(FullyQualifiedToken {
+                    token,
+                    info: TokenInfo {
+                        line: line_number,
+                        index: start,
+                    },
+                })
+            }
             '(' => {
                 possibly_push_current_buffer(
                     &mut tokens,
@@ -229,6 +563,25 @@ pub fn tokenize(body: String) -> Vec<FullyQualifiedToken> {
                     char_index,
                 );
             }
+            '[' | ']' => {
+                possibly_push_current_buffer(
+                    &mut tokens,
+                    &mut current_buffer,
+                    line_number,
+                    char_index,
+                );
+                tokens.push(FullyQualifiedToken {
+                    token: if char == '[' {
+                        Token::LeftSquare
+                    } else {
+                        Token::RightSquare
+                    },
+                    info: TokenInfo {
+                        line: line_number,
+                        index: char_index,
+                    },
+                })
+            }
             '{' => {
                 possibly_push_current_buffer(
                     &mut tokens,
@@ -304,6 +657,21 @@ pub fn tokenize(body: String) -> Vec<FullyQualifiedToken> {
                     },
                 })
             }
+            '@' => {
+                possibly_push_current_buffer(
+                    &mut tokens,
+                    &mut current_buffer,
+                    line_number,
+                    char_index,
+                );
+                tokens.push(FullyQualifiedToken {
+                    token: Token::At,
+                    info: TokenInfo {
+                        line: line_number,
+                        index: char_index,
+                    },
+                })
+            }
             '+' => {
                 possibly_push_current_buffer(
                     &mut tokens,
@@ -319,13 +687,75 @@ pub fn tokenize(body: String) -> Vec<FullyQualifiedToken> {
                     },
                 })
             }
-            '.' if is_number_string(
-                current_buffer
-                    .as_slice()
-                    .iter()
-                    .collect::<String>()
-                    .as_str(),
-            ) =>
+            '<' => {
+                possibly_push_current_buffer(
+                    &mut tokens,
+                    &mut current_buffer,
+                    line_number,
+                    char_index,
+                );
+                tokens.push(FullyQualifiedToken {
+                    token: Token::LessThan,
+                    info: TokenInfo {
+                        line: line_number,
+                        index: char_index,
+                    },
+                })
+            }
+            '>' => {
+                possibly_push_current_buffer(
+                    &mut tokens,
+                    &mut current_buffer,
+                    line_number,
+                    char_index,
+                );
+                tokens.push(FullyQualifiedToken {
+                    token: Token::GreaterThan,
+                    info: TokenInfo {
+                        line: line_number,
+                        index: char_index,
+                    },
+                })
+            }
+            '?' => {
+                possibly_push_current_buffer(
+                    &mut tokens,
+                    &mut current_buffer,
+                    line_number,
+                    char_index,
+                );
+                tokens.push(FullyQualifiedToken {
+                    token: Token::Question,
+                    info: TokenInfo {
+                        line: line_number,
+                        index: char_index,
+                    },
+                })
+            }
+            '*' => {
+                possibly_push_current_buffer(
+                    &mut tokens,
+                    &mut current_buffer,
+                    line_number,
+                    char_index,
+                );
+                tokens.push(FullyQualifiedToken {
+                    token: Token::Star,
+                    info: TokenInfo {
+                        line: line_number,
+                        index: char_index,
+                    },
+                })
+            }
+            // a decimal point, rather than the dot after a quoted name
+            '.' if !current_buffer.is_empty()
+                && is_number_string(
+                    current_buffer
+                        .as_slice()
+                        .iter()
+                        .collect::<String>()
+                        .as_str(),
+                ) =>
             {
                 current_buffer.push(char)
             }
@@ -345,7 +775,30 @@ pub fn tokenize(body: String) -> Vec<FullyQualifiedToken> {
                 })
             }
             char if is_identifier_char(char) => current_buffer.push(char),
-            _ => (),
+            // tabs and the like separate tokens just as spaces do
+            char if char.is_whitespace() => {
+                possibly_push_current_buffer(
+                    &mut tokens,
+                    &mut current_buffer,
+                    line_number,
+                    char_index,
+                );
+            }
+            _ => {
+                possibly_push_current_buffer(
+                    &mut tokens,
+                    &mut current_buffer,
+                    line_number,
+                    char_index,
+                );
+                errors.push(CompileError::with_info(
+                    format!("Unexpected character `{}`", char),
+                    TokenInfo {
+                        line: line_number,
+                        index: char_index,
+                    },
+                ));
+            }
         }
         char_index += 1;
         if char == '\n' {
@@ -354,18 +807,157 @@ pub fn tokenize(body: String) -> Vec<FullyQualifiedToken> {
         }
     }
 
-    possibly_push_current_buffer(&mut tokens, &mut current_buffer, line_number, char_index);
+    if is_in_quotes {
+        errors.push(CompileError::with_info(
+            String::from("Unterminated string literal"),
+            quote_start,
+        ));
+        push_text(&mut tokens, &mut current_buffer, line_number, char_index);
+    } else {
+        possibly_push_current_buffer(&mut tokens, &mut current_buffer, line_number, char_index);
+    }
+
+    errors.extend(tokens.iter().filter_map(check_number));
+    errors.sort_by_key(|error| {
+        error
+            .info
+            .as_ref()
+            .map(|info| (info.line, info.index))
+            .unwrap_or_default()
+    });
+
+    (tokens, errors)
+}
+
+/// Takes the tokens up to the `close` matching an `open` that has already been
+/// consumed, keeping any nested pairs
+pub fn take_until_closing(
+    tokens: &mut Iter<'_, FullyQualifiedToken>,
+    open: Token,
+    close: Token,
+) -> Result<Vec<FullyQualifiedToken>, CompileError> {
+    let mut depth = 0;
+    let mut taken: Vec<FullyQualifiedToken> = vec![];
+
+    for fqt in tokens.by_ref() {
+        if fqt.token == close {
+            if depth == 0 {
+                return Ok(taken);
+            }
+            depth -= 1;
+        } else if fqt.token == open {
+            depth += 1;
+        }
+
+        taken.push(fqt.clone());
+    }
+
+    error_without_info(format!("Expected {} but got nothing", close))
+}
+
+/// Splits on `separator` where it isn't nested inside parens or brackets
+pub fn split_top_level(
+    tokens: Vec<FullyQualifiedToken>,
+    separator: Token,
+) -> Vec<Vec<FullyQualifiedToken>> {
+    let mut groups: Vec<Vec<FullyQualifiedToken>> = vec![vec![]];
+    let mut depth = 0;
+
+    for fqt in tokens {
+        match fqt.token {
+            Token::LeftParen | Token::LeftBracket | Token::LeftSquare => depth += 1,
+            Token::RightParen | Token::RightBracket | Token::RightSquare => depth -= 1,
+            ref token if depth == 0 && *token == separator => {
+                groups.push(vec![]);
+                continue;
+            }
+            _ => (),
+        }
+
+        if let Some(group) = groups.last_mut() {
+            group.push(fqt);
+        }
+    }
+
+    groups
+}
+
+/// Takes a comma separated list up to the `)` matching a `(` that has already
+/// been consumed, with one group of tokens per argument
+pub fn take_argument_list(
+    tokens: &mut Iter<'_, FullyQualifiedToken>,
+) -> Result<Vec<Vec<FullyQualifiedToken>>, CompileError> {
+    let list_tokens = take_until_closing(tokens, Token::LeftParen, Token::RightParen)?;
+
+    if list_tokens.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let arguments = split_top_level(list_tokens, Token::Comma);
 
-    tokens
+    if arguments.iter().any(|argument| argument.is_empty()) {
+        return error_without_info(String::from("Expected an argument between commas"));
+    }
+
+    Ok(arguments)
 }
 
+/// Splits a block's tokens into statements, on semicolons outside of brackets,
+/// and after the last block of an if or for even when no semicolon follows it
 pub fn split_by_semicolon_within_brackets(
     tokens: Vec<FullyQualifiedToken>,
 ) -> Vec<Vec<FullyQualifiedToken>> {
     let mut groups: Vec<Vec<FullyQualifiedToken>> = vec![];
     let mut current_group: Vec<FullyQualifiedToken> = vec![];
+    // comments written inside a statement, which go before it
+    let mut hoisted: Vec<FullyQualifiedToken> = vec![];
     let mut bracket_depth = 0;
-    for fqt in tokens {
+
+    let next_code = |index: usize| {
+        tokens[index + 1..]
+            .iter()
+            .find(|fqt| !matches!(fqt.token, Token::Comment { .. }))
+            .map(|fqt| &fqt.token)
+    };
+
+    fn finish(
+        groups: &mut Vec<Vec<FullyQualifiedToken>>,
+        hoisted: &mut Vec<FullyQualifiedToken>,
+        group: Vec<FullyQualifiedToken>,
+    ) {
+        groups.extend(hoisted.drain(..).map(|comment| vec![comment]));
+        groups.push(group);
+    }
+
+    for (index, fqt) in tokens.iter().cloned().enumerate() {
+        if let Token::Comment { body, .. } = &fqt.token {
+            if bracket_depth == 0 && current_group.is_empty() {
+                groups.push(vec![fqt]);
+            } else if bracket_depth > 0
+                && matches!(
+                    current_group.last().map(|fqt| &fqt.token),
+                    Some(Token::LeftBracket) | Some(Token::Semicolon) | Some(Token::RightBracket)
+                )
+                && !matches!(
+                    next_code(index),
+                    Some(Token::Else) | Some(Token::Catch) | Some(Token::Comma)
+                )
+            {
+                // between statements of a nested block, which splits them itself
+                current_group.push(fqt);
+            } else {
+                // moved to its own line, so it no longer trails anything
+                hoisted.push(FullyQualifiedToken {
+                    token: Token::Comment {
+                        body: body.to_string(),
+                        trailing: false,
+                    },
+                    info: fqt.info.clone(),
+                });
+            }
+            continue;
+        }
+
         if bracket_depth == 0 {
             match fqt.token {
                 Token::LeftBracket => {
@@ -373,7 +965,7 @@ pub fn split_by_semicolon_within_brackets(
                     current_group.push(fqt);
                 }
                 Token::Semicolon => {
-                    groups.push(current_group);
+                    finish(&mut groups, &mut hoisted, current_group);
                     current_group = vec![];
                 }
                 _ => {
@@ -382,9 +974,33 @@ pub fn split_by_semicolon_within_brackets(
             }
         } else {
             match fqt.token {
+                Token::LeftBracket => {
+                    bracket_depth += 1;
+                    current_group.push(fqt);
+                }
                 Token::RightBracket => {
                     bracket_depth -= 1;
                     current_group.push(fqt);
+
+                    let is_control_flow = matches!(
+                        current_group.first().map(|fqt| &fqt.token),
+                        Some(Token::If) | Some(Token::For) | Some(Token::Try) | Some(Token::Fn)
+                    ) || matches!(
+                        current_group.first().map(|fqt| &fqt.token),
+                        Some(Token::Identifier { body }) if body == "match"
+                    );
+                    let continues = matches!(
+                        next_code(index),
+                        Some(Token::Else)
+                            | Some(Token::Catch)
+                            | Some(Token::LeftBracket)
+                            | Some(Token::Semicolon)
+                    );
+
+                    if bracket_depth == 0 && is_control_flow && !continues {
+                        finish(&mut groups, &mut hoisted, current_group);
+                        current_group = vec![];
+                    }
                 }
                 _ => {
                     current_group.push(fqt);
@@ -394,8 +1010,9 @@ pub fn split_by_semicolon_within_brackets(
     }
 
     if !current_group.is_empty() {
-        groups.push(current_group);
+        finish(&mut groups, &mut hoisted, current_group);
     }
+    groups.extend(hoisted.into_iter().map(|comment| vec![comment]));
 
     groups
 }
@@ -537,6 +1154,56 @@ mod tests {
         )
     }
 
+    #[test]
+    fn comments_pass() {
+        assert_eq!(
+            tokenize(String::from("/// Says hi\nfn // not a doc\n")),
+            vec![
+                FullyQualifiedToken {
+                    token: DocComment {
+                        body: String::from("Says hi")
+                    },
+                    info: TokenInfo { line: 0, index: 0 }
+                },
+                FullyQualifiedToken {
+                    token: Fn,
+                    info: TokenInfo { line: 1, index: 2 }
+                }
+            ]
+        )
+    }
+
+    #[test]
+    fn crlf_line_endings_tokenize_like_lf() {
+        let lf = "/// Doc\nfn main(): void {\n    log(\"a\"); // note\n}";
+
+        assert_eq!(
+            tokenize_with_comments(lf.replace('\n', "\r\n")),
+            tokenize_with_comments(lf.to_string())
+        );
+    }
+
+    #[test]
+    fn comments_can_be_kept() {
+        assert_eq!(
+            tokenize_with_comments(String::from("// first\nfn // second\n"))
+                .into_iter()
+                .map(|fqt| fqt.token)
+                .collect::<Vec<Token>>(),
+            vec![
+                Comment {
+                    body: String::from("first"),
+                    trailing: false
+                },
+                Fn,
+                Comment {
+                    body: String::from("second"),
+                    trailing: true
+                }
+            ]
+        )
+    }
+
     #[test]
     fn import_passes() {
         assert_eq!(
@@ -593,4 +1260,142 @@ mod tests {
             ]
         )
     }
+
+    #[test]
+    fn unexpected_characters_error() {
+        assert_eq!(
+            tokenize_checked(String::from("local x = 1;\nlocal y = x $ 2;")),
+            Err(CompileError::with_info(
+                String::from("Unexpected character `$`"),
+                TokenInfo { line: 1, index: 12 }
+            ))
+        );
+        assert_eq!(
+            tokenize(String::from("x#y"))
+                .into_iter()
+                .map(|fqt| fqt.token)
+                .collect::<Vec<Token>>(),
+            vec![
+                Identifier {
+                    body: String::from("x")
+                },
+                Identifier {
+                    body: String::from("y")
+                }
+            ]
+        );
+        assert_eq!(
+            tokenize_checked(String::from("\"#\" // #")).map(|tokens| tokens.len()),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn characters_are_read_with_their_escapes() {
+        let values = |source: &str| -> Vec<Result<char, String>> {
+            tokenize(String::from(source))
+                .into_iter()
+                .filter_map(|fqt| match fqt.token {
+                    Token::Character { body } => Some(character_value(&body)),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            values("log('a', '\\n', '\\'', '\\\\', '\\u{1F600}', 'é');"),
+            vec![
+                Ok('a'),
+                Ok('\n'),
+                Ok('\''),
+                Ok('\\'),
+                Ok('\u{1F600}'),
+                Ok('é')
+            ]
+        );
+        for value in ['a', '\n', '\'', '\\', '\u{7}', '😀'] {
+            assert_eq!(character_value(&character_literal(value)), Ok(value));
+        }
+
+        assert_eq!(
+            tokenize_checked(String::from("log('ab');")),
+            Err(CompileError::with_info(
+                String::from("'ab' has more than one character"),
+                TokenInfo { line: 0, index: 4 }
+            ))
+        );
+        assert_eq!(
+            tokenize_checked(String::from("log('\\q');")),
+            Err(CompileError::with_info(
+                String::from("Unknown escape \\q in '\\q'"),
+                TokenInfo { line: 0, index: 4 }
+            ))
+        );
+        assert_eq!(
+            tokenize_checked(String::from("log('');")),
+            Err(CompileError::with_info(
+                String::from("A character literal needs a character"),
+                TokenInfo { line: 0, index: 4 }
+            ))
+        );
+        assert_eq!(
+            tokenize_checked(String::from("log('a);\nlog(1);")),
+            Err(CompileError::with_info(
+                String::from("Unterminated character literal"),
+                TokenInfo { line: 0, index: 4 }
+            ))
+        );
+    }
+
+    #[test]
+    fn invalid_numbers_error_at_their_start() {
+        assert_eq!(
+            tokenize_checked(String::from("local x: f32 = 1.2.3;")),
+            Err(CompileError::with_info(
+                String::from("The number 1.2.3 can only have one decimal point"),
+                TokenInfo { line: 0, index: 15 }
+            ))
+        );
+        assert_eq!(
+            tokenize_checked(String::from("log(1...);")),
+            Err(CompileError::with_info(
+                String::from("The number 1... can only have one decimal point"),
+                TokenInfo { line: 0, index: 4 }
+            ))
+        );
+        assert_eq!(
+            tokenize_checked(String::from("\nreturn 3.;")),
+            Err(CompileError::with_info(
+                String::from("The number 3. needs digits on both sides of its decimal point"),
+                TokenInfo { line: 1, index: 7 }
+            ))
+        );
+        assert!(tokenize_checked(String::from("return 3.25 + 10;")).is_ok());
+    }
+
+    #[test]
+    fn unterminated_strings_error() {
+        assert_eq!(
+            tokenize_checked(String::from("log(\"hello);\nlog(\"world\");")),
+            Err(CompileError::with_info(
+                String::from("Unterminated string literal"),
+                TokenInfo { line: 0, index: 4 }
+            ))
+        );
+        assert_eq!(
+            tokenize(String::from("log(\"hello);\nlog(1);"))
+                .into_iter()
+                .map(|fqt| fqt.token)
+                .filter(|token| *token == LeftParen)
+                .count(),
+            2
+        );
+        assert_eq!(
+            tokenize_checked(String::from("x = \"hello")),
+            Err(CompileError::with_info(
+                String::from("Unterminated string literal"),
+                TokenInfo { line: 0, index: 4 }
+            ))
+        );
+    }
 }
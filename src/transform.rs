@@ -0,0 +1,197 @@
+use std::{fs, path::Path};
+
+use wasmtime::{Engine, Instance, Module, Store};
+
+use crate::{
+    errors::{join_errors, CompileError},
+    generators::gwe::{generate, FormatOptions},
+    parser::{parse, Program},
+};
+
+/// Rewrites a program after it's parsed and before it's checked and
+/// generated, for macros, instrumentation and codemods
+pub trait Transform {
+    fn transform(&self, program: Program) -> Result<Program, CompileError>;
+}
+
+impl<F> Transform for F
+where
+    F: Fn(Program) -> Result<Program, CompileError>,
+{
+    fn transform(&self, program: Program) -> Result<Program, CompileError> {
+        self(program)
+    }
+}
+
+/// Runs each transform on what the one before gave back
+pub fn apply(program: Program, transforms: &[Box<dyn Transform>]) -> Result<Program, CompileError> {
+    transforms
+        .iter()
+        .try_fold(program, |program, transform| transform.transform(program))
+}
+
+/// A transform compiled to wasm, which is given the program as gwe source and
+/// gives back the source of the rewritten program. Plugins export:
+///
+/// - `memory`
+/// - `alloc(length: i32): i32`, where the source is written
+/// - `transform(offset: i32, length: i32): i32`, pointing to the new source's
+///   length as a little endian i32, followed by the source
+pub struct WasmPlugin {
+    name: String,
+    engine: Engine,
+    module: Module,
+}
+
+impl WasmPlugin {
+    /// A plugin from wasm, or wat
+    pub fn new(name: &str, bytes: &[u8]) -> Result<Self, CompileError> {
+        let engine = Engine::default();
+
+        match Module::new(&engine, bytes) {
+            Ok(module) => Ok(WasmPlugin {
+                name: name.to_string(),
+                engine,
+                module,
+            }),
+            Err(error) => Err(CompileError::new(format!(
+                "Unable to load transform {} due to {}",
+                name, error
+            ))),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, CompileError> {
+        let name = path.to_string_lossy();
+
+        match fs::read(path) {
+            Ok(bytes) => WasmPlugin::new(&name, &bytes),
+            Err(error) => Err(CompileError::new(format!(
+                "Unable to read transform {} due to {}",
+                name, error
+            ))),
+        }
+    }
+
+    fn run(&self, source: &str) -> wasmtime::Result<Vec<u8>> {
+        let mut store = Store::new(&self.engine, ());
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("it doesn't export memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let transform = instance.get_typed_func::<(i32, i32), i32>(&mut store, "transform")?;
+
+        let length = source.len() as i32;
+        let offset = alloc.call(&mut store, length)?;
+        memory.write(&mut store, offset as usize, source.as_bytes())?;
+
+        let result = transform.call(&mut store, (offset, length))? as usize;
+        let mut result_length = [0; 4];
+        memory.read(&store, result, &mut result_length)?;
+        let mut bytes = vec![0; u32::from_le_bytes(result_length) as usize];
+        memory.read(&store, result + 4, &mut bytes)?;
+
+        Ok(bytes)
+    }
+}
+
+impl Transform for WasmPlugin {
+    fn transform(&self, program: Program) -> Result<Program, CompileError> {
+        let source = generate(program, &FormatOptions::default());
+
+        let bytes = self.run(&source).map_err(|error| {
+            CompileError::new(format!("Transform {} failed: {}", self.name, error))
+        })?;
+        let source = String::from_utf8(bytes).map_err(|_| {
+            CompileError::new(format!("Transform {} gave back invalid UTF-8", self.name))
+        })?;
+
+        parse(source).map_err(|errors| {
+            CompileError::new(format!(
+                "Transform {} gave back a program that doesn't parse: {}",
+                self.name,
+                join_errors(&errors)
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::Block;
+
+    fn names(program: &Program) -> Vec<String> {
+        program
+            .blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::Function(function) => Some(function.name.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn transforms_run_in_order() {
+        let program = parse(String::from("fn main(): void {\n}")).unwrap();
+        let rename = |to: &'static str| {
+            Box::new(move |program: Program| {
+                Ok(Program {
+                    blocks: program
+                        .blocks
+                        .into_iter()
+                        .map(|block| match block {
+                            Block::Function(function) => Block::Function(crate::blocks::Function {
+                                name: format!("{}_{}", function.name, to),
+                                ..function
+                            }),
+                            block => block,
+                        })
+                        .collect(),
+                })
+            }) as Box<dyn Transform>
+        };
+
+        let transformed = apply(program, &[rename("first"), rename("second")]).unwrap();
+
+        assert_eq!(names(&transformed), vec!["main_first_second"]);
+    }
+
+    #[test]
+    fn wasm_plugins_rewrite_the_source() {
+        // gives back a program of its own, whatever it's given
+        let plugin = WasmPlugin::new(
+            "one.wat",
+            b"(module
+  (memory (export \"memory\") 1)
+  (data (i32.const 0) \"\\1f\\00\\00\\00fn one(): i32 {\\0a    return 1;\\0a}\")
+  (func (export \"alloc\") (param i32) (result i32)
+    (i32.const 64)
+  )
+  (func (export \"transform\") (param i32 i32) (result i32)
+    (i32.const 0)
+  )
+)",
+        )
+        .unwrap();
+        let program = parse(String::from("fn main(): void {\n}")).unwrap();
+
+        assert_eq!(names(&plugin.transform(program).unwrap()), vec!["one"]);
+    }
+
+    #[test]
+    fn plugins_missing_exports_are_reported() {
+        let plugin = WasmPlugin::new("empty.wat", b"(module)").unwrap();
+        let program = parse(String::from("fn main(): void {\n}")).unwrap();
+
+        assert_eq!(
+            plugin.transform(program),
+            Err(CompileError::new(String::from(
+                "Transform empty.wat failed: it doesn't export memory"
+            )))
+        );
+    }
+}
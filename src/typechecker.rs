@@ -0,0 +1,1556 @@
+use crate::{
+    blocks::{
+        evaluate_constants, function_type, resolve_type, Block, Function, ImportFunction,
+        ImportMemory, Interface, Memory, Param, Test, TypeAlias,
+    },
+    errors::CompileError,
+    expressions::{find_builtin, Expression, BUILTINS},
+    parser::{infer_types, Program},
+    tokenizer::TokenInfo,
+    visit::{walk_expression, Visitor},
+};
+
+/// An error at the place given, when there is one
+fn error_at(message: String, info: &Option<TokenInfo>) -> CompileError {
+    match info {
+        Some(info) => CompileError::with_info(message, info.clone()),
+        None => CompileError::new(message),
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Declaration {
+    Param,
+    Local,
+}
+
+/// Names visible at a point in a function, innermost block last
+struct Scopes {
+    function_name: String,
+    scopes: Vec<Vec<(String, Declaration)>>,
+    // every local in the function, as they all share one wasm local each
+    local_types: Vec<(String, String)>,
+}
+
+impl Scopes {
+    fn find(&self, name: &str) -> Option<(usize, Declaration)> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(depth, scope)| {
+                scope
+                    .iter()
+                    .find(|(declared, _)| declared == name)
+                    .map(|(_, declaration)| (depth, *declaration))
+            })
+    }
+
+    fn declare(
+        &mut self,
+        name: &str,
+        declaration: Declaration,
+        info: &Option<TokenInfo>,
+    ) -> Result<(), CompileError> {
+        let innermost = self.scopes.len() - 1;
+
+        let error = match (self.find(name), declaration) {
+            (None, _) => None,
+            (Some(_), Declaration::Param) => Some(format!(
+                "Param {} is declared twice in {}",
+                name, self.function_name
+            )),
+            (Some((_, Declaration::Param)), Declaration::Local) => Some(format!(
+                "Local {} shadows the param {} in {}",
+                name, name, self.function_name
+            )),
+            (Some((depth, Declaration::Local)), Declaration::Local) if depth == innermost => {
+                Some(format!(
+                    "Local {} is already declared in {}",
+                    name, self.function_name
+                ))
+            }
+            (Some((_, Declaration::Local)), Declaration::Local) => Some(format!(
+                "Local {} shadows a local from an outer block in {}",
+                name, self.function_name
+            )),
+        };
+
+        if let Some(error) = error {
+            return Err(error_at(error, info));
+        }
+
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((name.to_string(), declaration));
+        }
+
+        Ok(())
+    }
+
+    fn check_local_type(
+        &mut self,
+        name: &str,
+        type_name: &str,
+        info: &Option<TokenInfo>,
+    ) -> Result<(), CompileError> {
+        match self.local_types.iter().find(|(local, _)| local == name) {
+            Some((_, existing)) if existing != type_name => Err(error_at(
+                format!(
+                    "Local {} is declared as both {} and {} in {}",
+                    name, existing, type_name, self.function_name
+                ),
+                info,
+            )),
+            Some(_) => Ok(()),
+            None => {
+                self.local_types
+                    .push((name.to_string(), type_name.to_string()));
+                Ok(())
+            }
+        }
+    }
+
+    fn check_block(&mut self, expressions: &[Expression]) -> Result<(), CompileError> {
+        self.scopes.push(vec![]);
+        let result = self.check_expressions(expressions);
+        self.scopes.pop();
+        result
+    }
+
+    fn check_expressions(&mut self, expressions: &[Expression]) -> Result<(), CompileError> {
+        for expression in expressions {
+            match expression {
+                Expression::LocalAssign {
+                    name,
+                    type_name,
+                    info,
+                    ..
+                } => {
+                    self.declare(name, Declaration::Local, info)?;
+                    self.check_local_type(name, type_name, info)?
+                }
+                Expression::IfStatement { success, fail, .. } => {
+                    self.check_block(success)?;
+                    self.check_block(fail)?;
+                }
+                Expression::TryStatement { body, catch } => {
+                    self.check_block(body)?;
+                    self.check_block(catch)?;
+                }
+                Expression::MatchStatement {
+                    type_name,
+                    arms,
+                    default,
+                    info,
+                    ..
+                } => {
+                    if !["i32", "i64"].contains(&type_name.as_str()) {
+                        return Err(error_at(
+                            format!(
+                                "A match in {} is on {}, but only i32 and i64 can be matched",
+                                self.function_name, type_name
+                            ),
+                            info,
+                        ));
+                    }
+
+                    for (index, (pattern, _)) in arms.iter().enumerate() {
+                        if arms[..index].iter().any(|(earlier, _)| earlier == pattern) {
+                            return Err(error_at(
+                                format!(
+                                    "A match in {} has the pattern {} twice",
+                                    self.function_name, pattern
+                                ),
+                                info,
+                            ));
+                        }
+                    }
+
+                    // numbers can be anything, so only a _ arm covers them all
+                    let Some(default) = default else {
+                        return Err(error_at(
+                            format!(
+                                "A match in {} needs a _ arm for the numbers it doesn't list",
+                                self.function_name
+                            ),
+                            info,
+                        ));
+                    };
+
+                    for (_, body) in arms {
+                        self.check_block(body)?;
+                    }
+                    self.check_block(default)?;
+                }
+                // a closure is a local holding its environment, with its own
+                // params and locals
+                Expression::Closure { function } => {
+                    self.declare(&function.name, Declaration::Local, &None)?;
+                    self.check_local_type(&function.name, "i32", &None)?;
+                    check_function(function)?
+                }
+                Expression::ForEach {
+                    name,
+                    type_name,
+                    body,
+                    ..
+                } => {
+                    self.scopes.push(vec![]);
+                    let result = self
+                        .declare(name, Declaration::Local, &None)
+                        .and_then(|_| self.check_local_type(name, type_name, &None))
+                        .and_then(|_| self.check_expressions(body));
+                    self.scopes.pop();
+                    result?
+                }
+                Expression::ForStatement {
+                    initial_value,
+                    body,
+                    ..
+                } => {
+                    // the loop variable belongs to the loop, alongside its body
+                    self.scopes.push(vec![]);
+                    let result = self
+                        .check_expressions(std::slice::from_ref(initial_value.as_ref()))
+                        .and_then(|_| self.check_expressions(body));
+                    self.scopes.pop();
+                    result?
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn check_function(function: &Function) -> Result<(), CompileError> {
+    let mut scopes = Scopes {
+        function_name: function.name.to_string(),
+        scopes: vec![vec![]],
+        local_types: vec![],
+    };
+
+    for param in &function.params {
+        scopes.declare(&param.name, Declaration::Param, &param.info)?;
+    }
+
+    scopes.check_block(&function.expressions)
+}
+
+/// Where each `+` that adds strings is written
+struct FindStringAdditions<'a> {
+    aliases: &'a [TypeAlias],
+    found: Vec<Option<TokenInfo>>,
+}
+
+impl Visitor for FindStringAdditions<'_> {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::Addition {
+            type_name, info, ..
+        } = expression
+        {
+            if resolve_type(self.aliases, type_name) == "string" {
+                self.found.push(info.clone());
+            }
+        }
+
+        walk_expression(self, expression)
+    }
+}
+
+/// `+` is only generated for numbers, so joining strings with it is an error
+/// rather than wat which doesn't validate
+fn check_string_additions(program: &Program) -> Vec<CompileError> {
+    let aliases = aliases(program);
+    let mut errors: Vec<CompileError> = vec![];
+
+    for block in &program.blocks {
+        let mut find = FindStringAdditions {
+            aliases: &aliases,
+            found: vec![],
+        };
+
+        let name = match block {
+            Block::Function(function) => {
+                find.visit_function(function);
+                function.name.to_string()
+            }
+            Block::Test(test) => {
+                find.visit_test(test);
+                format!("test {}", test.name)
+            }
+            _ => continue,
+        };
+
+        errors.extend(find.found.iter().map(|info| {
+            error_at(
+                format!("{} adds strings with +, but + only adds numbers", name),
+                info,
+            )
+        }));
+    }
+
+    errors
+}
+
+const PRIMITIVE_TYPES: [&str; 6] = ["i32", "i64", "f32", "f64", "string", "void"];
+
+fn aliases(program: &Program) -> Vec<TypeAlias> {
+    program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::TypeAlias(alias) => Some(alias.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn check_aliases(aliases: &[TypeAlias]) -> Vec<CompileError> {
+    let mut errors: Vec<CompileError> = vec![];
+
+    for (index, alias) in aliases.iter().enumerate() {
+        if PRIMITIVE_TYPES.contains(&alias.name.as_str()) {
+            errors.push(CompileError::new(format!(
+                "Type {} can't be redefined",
+                alias.name
+            )));
+        } else if aliases[..index]
+            .iter()
+            .any(|existing| existing.name == alias.name)
+        {
+            errors.push(CompileError::new(format!(
+                "Type {} is declared twice",
+                alias.name
+            )));
+        } else if !PRIMITIVE_TYPES.contains(&resolve_type(aliases, &alias.name).as_str()) {
+            let resolved = resolve_type(aliases, &alias.name);
+
+            errors.push(if aliases.iter().any(|other| other.name == resolved) {
+                CompileError::new(format!("Type {} refers back to itself", alias.name))
+            } else {
+                CompileError::new(format!(
+                    "Type {} refers to unknown type {}",
+                    alias.name, resolved
+                ))
+            });
+        }
+    }
+
+    errors
+}
+
+fn check_memories(program: &Program) -> Vec<CompileError> {
+    let mut errors: Vec<CompileError> = vec![];
+
+    let names: Vec<Option<&String>> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::ImportMemory(import) => Some(import.name.as_ref()),
+            Block::Memory(memory) => Some(memory.name.as_ref()),
+            _ => None,
+        })
+        .collect();
+
+    if names.len() > 1 && names.contains(&None) {
+        errors.push(CompileError::new(String::from(
+            "Memories have to be named when there's more than one",
+        )));
+    }
+
+    for (index, name) in names.iter().enumerate() {
+        if let Some(name) = name.filter(|name| names[..index].contains(&Some(name))) {
+            errors.push(CompileError::new(format!(
+                "Memory {} is declared twice",
+                name
+            )));
+        }
+    }
+
+    for block in &program.blocks {
+        let Block::Function(function) = block else {
+            continue;
+        };
+
+        match function.memory_name() {
+            None if function.has_attribute("memory") => errors.push(CompileError::new(format!(
+                "@memory on {} needs the name of a memory",
+                function.name
+            ))),
+            Some(memory)
+                if !names
+                    .iter()
+                    .any(|name| name.is_some_and(|name| name == memory)) =>
+            {
+                errors.push(CompileError::new(format!(
+                    "{} uses memory {}, which isn't declared",
+                    function.name, memory
+                )))
+            }
+            _ => (),
+        }
+    }
+
+    errors
+}
+
+fn check_data(program: &Program) -> Vec<CompileError> {
+    let names: Vec<&String> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Data(data) => Some(&data.name),
+            _ => None,
+        })
+        .collect();
+
+    names
+        .iter()
+        .enumerate()
+        .filter(|(index, name)| names[..*index].contains(name))
+        .map(|(_, name)| CompileError::new(format!("Data {} is declared twice", name)))
+        .collect()
+}
+
+fn check_generics(program: &Program) -> Vec<CompileError> {
+    let generics: Vec<&Function> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function) if !function.type_params.is_empty() => Some(function),
+            _ => None,
+        })
+        .collect();
+
+    let mut errors: Vec<CompileError> = vec![];
+
+    for generic in &generics {
+        for (index, type_param) in generic.type_params.iter().enumerate() {
+            if generic.type_params[..index].contains(type_param) {
+                errors.push(CompileError::new(format!(
+                    "Type parameter {} of {} is declared twice",
+                    type_param, generic.name
+                )));
+            } else if !generic
+                .params
+                .iter()
+                .any(|param| param.type_name == *type_param)
+            {
+                // calls only know a type parameter from the arguments given
+                errors.push(CompileError::new(format!(
+                    "Type parameter {} of {} has to be the type of one of its params",
+                    type_param, generic.name
+                )));
+            }
+        }
+
+        let exported = generic.export_name().is_some()
+            || program.blocks.iter().any(
+                |block| matches!(block, Block::Export(export) if export.function_name == generic.name),
+            );
+        if exported {
+            errors.push(CompileError::new(format!(
+                "{} is generic, so can't be exported",
+                generic.name
+            )));
+        }
+    }
+
+    errors
+}
+
+/// How many single character edits it takes to turn one name into another
+fn edit_distance(from: &str, to: &str) -> usize {
+    let to: Vec<char> = to.chars().collect();
+    let mut previous: Vec<usize> = (0..=to.len()).collect();
+
+    for (row, from_char) in from.chars().enumerate() {
+        let mut current = vec![row + 1];
+
+        for (column, to_char) in to.iter().enumerate() {
+            let substitution = previous[column] + usize::from(from_char != *to_char);
+            current.push(
+                substitution
+                    .min(previous[column + 1] + 1)
+                    .min(current[column] + 1),
+            );
+        }
+
+        previous = current;
+    }
+
+    previous[to.len()]
+}
+
+/// The name most like a misspelt one, when any is close enough to be a typo
+fn closest_name<'a>(name: &str, names: &[&'a str]) -> Option<&'a str> {
+    names
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), *candidate))
+        .filter(|(distance, candidate)| *distance <= 2 && *distance < candidate.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn check_exports(program: &Program) -> Vec<CompileError> {
+    let functions: Vec<&str> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function) => Some(function.name.as_str()),
+            Block::ImportFunction(import) => Some(import.name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Export(export) if !functions.contains(&export.function_name.as_str()) => {
+                let suggestion = match closest_name(&export.function_name, &functions) {
+                    Some(name) => format!(", did you mean {}?", name),
+                    None => String::new(),
+                };
+
+                Some(error_at(
+                    format!(
+                        "Export {} is of {}, which isn't a function{}",
+                        export.external_name, export.function_name, suggestion
+                    ),
+                    &export.info,
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn check_constants(program: &Program) -> Vec<CompileError> {
+    let names: Vec<&String> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Constant(constant) => Some(&constant.name),
+            _ => None,
+        })
+        .collect();
+
+    let mut errors: Vec<CompileError> = names
+        .iter()
+        .enumerate()
+        .filter(|(index, name)| names[..*index].contains(name))
+        .map(|(_, name)| CompileError::new(format!("Constant {} is declared twice", name)))
+        .collect();
+
+    match evaluate_constants(&program.blocks) {
+        Err(error) => errors.push(error),
+        Ok(constants) => errors.extend(program.blocks.iter().filter_map(|block| match block {
+            Block::ImportMemory(ImportMemory { size, .. }) | Block::Memory(Memory { size, .. }) => {
+                size.pages(&constants).err()
+            }
+            _ => None,
+        })),
+    }
+
+    errors
+}
+
+/// Every function of an interface has to be imported or written in gwe, with
+/// the params and return type the interface gives it
+fn check_interfaces(program: &Program) -> Vec<CompileError> {
+    let aliases = aliases(program);
+    let resolved = |params: &[Param], return_type: &str| {
+        function_type(
+            &params
+                .iter()
+                .map(|param| resolve_type(&aliases, &param.type_name))
+                .collect::<Vec<String>>(),
+            &resolve_type(&aliases, return_type),
+        )
+    };
+    let interfaces: Vec<&Interface> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Interface(interface) => Some(interface),
+            _ => None,
+        })
+        .collect();
+    let mut errors: Vec<CompileError> = vec![];
+
+    for (index, interface) in interfaces.iter().enumerate() {
+        if interfaces[..index]
+            .iter()
+            .any(|existing| existing.name == interface.name)
+        {
+            errors.push(CompileError::new(format!(
+                "Interface {} is declared twice",
+                interface.name
+            )));
+            continue;
+        }
+
+        for function in &interface.functions {
+            let expected = resolved(&function.params, &function.return_type);
+            // imports don't return anything
+            let found = program.blocks.iter().find_map(|block| match block {
+                Block::Function(found) if found.name == function.name => {
+                    Some(resolved(&found.params, &found.return_type))
+                }
+                Block::ImportFunction(found) if found.name == function.name => {
+                    Some(resolved(&found.params, "void"))
+                }
+                _ => None,
+            });
+
+            match found {
+                None => errors.push(CompileError::new(format!(
+                    "{} needs a function {}, either imported or written in gwe",
+                    interface.name, function.name
+                ))),
+                Some(found) if found != expected => errors.push(CompileError::new(format!(
+                    "{} needs {} to be {}, but it's {}",
+                    interface.name,
+                    function.name,
+                    function.function_type(),
+                    found
+                ))),
+                Some(_) => (),
+            }
+        }
+    }
+
+    errors
+}
+
+pub fn check(program: &Program) -> Result<(), Vec<CompileError>> {
+    let mut errors = check_aliases(&aliases(program));
+    errors.extend(check_memories(program));
+    errors.extend(check_data(program));
+    errors.extend(check_constants(program));
+    errors.extend(check_generics(program));
+    errors.extend(check_exports(program));
+    errors.extend(check_interfaces(program));
+    errors.extend(check_string_additions(program));
+
+    errors.extend(program.blocks.iter().filter_map(|block| match block {
+        Block::Function(Function { name, .. })
+        | Block::ImportFunction(ImportFunction { name, .. })
+            if find_builtin(name).is_some() =>
+        {
+            Some(CompileError::new(format!(
+                "{} is a builtin and can't be redefined",
+                name
+            )))
+        }
+        Block::Function(function) => check_function(function).err(),
+        Block::Test(test) => {
+            check_function(&test.as_function(&format!("test {}", test.name))).err()
+        }
+        _ => None,
+    }));
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn resolve_params(aliases: &[TypeAlias], params: Vec<Param>) -> Vec<Param> {
+    params
+        .into_iter()
+        .map(|param| Param {
+            type_name: resolve_type(aliases, &param.type_name),
+            name: param.name,
+            info: param.info,
+        })
+        .collect()
+}
+
+fn resolve_expression(aliases: &[TypeAlias], expression: Expression) -> Expression {
+    let resolve_all = |expressions: Vec<Expression>| -> Vec<Expression> {
+        expressions
+            .into_iter()
+            .map(|expression| resolve_expression(aliases, expression))
+            .collect()
+    };
+    let resolve_boxed =
+        |expression: Box<Expression>| Box::new(resolve_expression(aliases, *expression));
+
+    match expression {
+        Expression::Variable { body, type_name } => Expression::Variable {
+            body,
+            type_name: resolve_type(aliases, &type_name),
+        },
+        Expression::LocalAssign {
+            name,
+            type_name,
+            expression,
+            info,
+        } => Expression::LocalAssign {
+            name,
+            type_name: resolve_type(aliases, &type_name),
+            expression: resolve_boxed(expression),
+            info,
+        },
+        Expression::GlobalAssign {
+            name,
+            type_name,
+            expression,
+        } => Expression::GlobalAssign {
+            name,
+            type_name: resolve_type(aliases, &type_name),
+            expression: resolve_boxed(expression),
+        },
+        Expression::Return { expression, info } => Expression::Return {
+            expression: resolve_boxed(expression),
+            info,
+        },
+        Expression::Addition {
+            left,
+            right,
+            type_name,
+            info,
+        } => Expression::Addition {
+            left: resolve_boxed(left),
+            right: resolve_boxed(right),
+            type_name: resolve_type(aliases, &type_name),
+            info,
+        },
+        Expression::Conditional {
+            predicate,
+            success,
+            fail,
+            type_name,
+        } => Expression::Conditional {
+            predicate: resolve_boxed(predicate),
+            success: resolve_boxed(success),
+            fail: resolve_boxed(fail),
+            type_name: resolve_type(aliases, &type_name),
+        },
+        Expression::FunctionCall { name, args, info } => Expression::FunctionCall {
+            name,
+            args: resolve_all(args),
+            info,
+        },
+        Expression::TailCall { name, args } => Expression::TailCall {
+            name,
+            args: resolve_all(args),
+        },
+        Expression::IfStatement {
+            predicate,
+            success,
+            fail,
+        } => Expression::IfStatement {
+            predicate: resolve_boxed(predicate),
+            success: resolve_all(success),
+            fail: resolve_all(fail),
+        },
+        Expression::ForStatement {
+            initial_value,
+            incrementor,
+            break_condition,
+            direction,
+            body,
+        } => Expression::ForStatement {
+            initial_value: resolve_boxed(initial_value),
+            incrementor: resolve_boxed(incrementor),
+            break_condition: resolve_boxed(break_condition),
+            direction,
+            body: resolve_all(body),
+        },
+        Expression::ForEach {
+            name,
+            type_name,
+            array,
+            body,
+            line,
+        } => Expression::ForEach {
+            name,
+            type_name: resolve_type(aliases, &type_name),
+            array,
+            body: resolve_all(body),
+            line,
+        },
+        Expression::TryStatement { body, catch } => Expression::TryStatement {
+            body: resolve_all(body),
+            catch: resolve_all(catch),
+        },
+        Expression::MatchStatement {
+            subject,
+            type_name,
+            arms,
+            default,
+            info,
+        } => Expression::MatchStatement {
+            subject: resolve_boxed(subject),
+            type_name: resolve_type(aliases, &type_name),
+            arms: arms
+                .into_iter()
+                .map(|(pattern, body)| (pattern, resolve_all(body)))
+                .collect(),
+            default: default.map(resolve_all),
+            info,
+        },
+        Expression::Closure { function } => Expression::Closure {
+            function: Box::new(Function {
+                params: resolve_params(aliases, function.params),
+                return_type: resolve_type(aliases, &function.return_type),
+                expressions: resolve_all(function.expressions),
+                ..*function
+            }),
+        },
+        Expression::Assert {
+            left,
+            right,
+            type_name,
+            line,
+        } => Expression::Assert {
+            left: resolve_boxed(left),
+            right: right.map(resolve_boxed),
+            type_name: resolve_type(aliases, &type_name),
+            line,
+        },
+        expression => expression,
+    }
+}
+
+/// Replaces every type alias with the primitive type it stands for, dropping
+/// the alias blocks, so that codegen only sees primitive types
+pub fn resolve_aliases(program: Program) -> Program {
+    let aliases = aliases(&program);
+
+    if aliases.is_empty() {
+        return program;
+    }
+
+    let blocks = program
+        .blocks
+        .into_iter()
+        .filter_map(|block| match block {
+            Block::TypeAlias(_) => None,
+            Block::Function(function) => Some(Block::Function(Function {
+                name: function.name,
+                params: resolve_params(&aliases, function.params),
+                return_type: resolve_type(&aliases, &function.return_type),
+                expressions: function
+                    .expressions
+                    .into_iter()
+                    .map(|expression| resolve_expression(&aliases, expression))
+                    .collect(),
+                doc: function.doc,
+                attributes: function.attributes,
+                type_params: function.type_params,
+            })),
+            Block::ImportFunction(import) => Some(Block::ImportFunction(ImportFunction {
+                name: import.name,
+                params: resolve_params(&aliases, import.params),
+                external_name: import.external_name,
+                doc: import.doc,
+                grouped: import.grouped,
+            })),
+            Block::Test(test) => Some(Block::Test(Test {
+                name: test.name,
+                expressions: test
+                    .expressions
+                    .into_iter()
+                    .map(|expression| resolve_expression(&aliases, expression))
+                    .collect(),
+            })),
+            block => Some(block),
+        })
+        .collect();
+
+    Program { blocks }
+}
+
+/// The type a call's argument has, once its callee has been instantiated
+fn argument_type(return_types: &[(String, String)], argument: &Expression) -> Option<String> {
+    match argument {
+        Expression::FunctionCall { name, .. } => return_types
+            .iter()
+            .find(|(found, _)| found == name)
+            .map(|(_, return_type)| return_type.to_string()),
+        Expression::Addition { type_name, .. } if type_name.is_empty() => Some(String::from("f32")),
+        argument => argument.type_name(),
+    }
+}
+
+/// The copies of generic functions made so far
+struct Instantiation<'a> {
+    generics: &'a [Function],
+    return_types: Vec<(String, String)>,
+    /// Each copy, along with the name of the generic function it was made from
+    instances: Vec<(String, Function)>,
+}
+
+impl Instantiation<'_> {
+    fn instantiate_all(&mut self, expressions: &mut [Expression]) -> Result<(), CompileError> {
+        for expression in expressions {
+            self.instantiate(expression)?;
+        }
+
+        Ok(())
+    }
+
+    /// Points calls to generic functions at a copy for the types they're
+    /// called with, making the copy the first time those types are seen
+    fn instantiate(&mut self, expression: &mut Expression) -> Result<(), CompileError> {
+        match expression {
+            Expression::FunctionCall { name, args, .. } | Expression::TailCall { name, args } => {
+                self.instantiate_all(args)?;
+
+                if let Some(generic) = self.generics.iter().find(|generic| generic.name == *name) {
+                    *name = self.instance_name(generic, args)?;
+                }
+                Ok(())
+            }
+            Expression::Return { expression, .. }
+            | Expression::LocalAssign { expression, .. }
+            | Expression::GlobalAssign { expression, .. } => self.instantiate(expression),
+            Expression::Addition { left, right, .. } => {
+                self.instantiate(left)?;
+                self.instantiate(right)
+            }
+            Expression::Conditional {
+                predicate,
+                success,
+                fail,
+                ..
+            } => {
+                self.instantiate(predicate)?;
+                self.instantiate(success)?;
+                self.instantiate(fail)
+            }
+            Expression::IfStatement {
+                predicate,
+                success,
+                fail,
+            } => {
+                self.instantiate(predicate)?;
+                self.instantiate_all(success)?;
+                self.instantiate_all(fail)
+            }
+            Expression::ForStatement {
+                initial_value,
+                incrementor,
+                break_condition,
+                body,
+                ..
+            } => {
+                self.instantiate(initial_value)?;
+                self.instantiate(incrementor)?;
+                self.instantiate(break_condition)?;
+                self.instantiate_all(body)
+            }
+            Expression::ForEach { body, .. } => self.instantiate_all(body),
+            Expression::TryStatement { body, catch } => {
+                self.instantiate_all(body)?;
+                self.instantiate_all(catch)
+            }
+            Expression::MatchStatement {
+                subject,
+                arms,
+                default,
+                ..
+            } => {
+                self.instantiate(subject)?;
+                for (_, body) in arms.iter_mut() {
+                    self.instantiate_all(body)?;
+                }
+                match default {
+                    Some(default) => self.instantiate_all(default),
+                    None => Ok(()),
+                }
+            }
+            Expression::Closure { function } => self.instantiate_all(&mut function.expressions),
+            Expression::Assert { left, right, .. } => {
+                self.instantiate(left)?;
+                match right {
+                    Some(right) => self.instantiate(right),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn instance_name(
+        &mut self,
+        generic: &Function,
+        args: &[Expression],
+    ) -> Result<String, CompileError> {
+        let arg_types: Vec<Option<String>> = args
+            .iter()
+            .map(|arg| argument_type(&self.return_types, arg))
+            .collect();
+        let bound = generic.bind_type_params(&arg_types)?;
+        let types = generic
+            .type_params
+            .iter()
+            .map(
+                |type_param| match bound.iter().find(|alias| alias.name == *type_param) {
+                    Some(alias) => Ok(alias.type_name.to_string()),
+                    None => Err(CompileError::new(format!(
+                        "Can't tell what {} is in a call to {}",
+                        type_param, generic.name
+                    ))),
+                },
+            )
+            .collect::<Result<Vec<String>, CompileError>>()?;
+
+        let instance_name = format!("{}__{}", generic.name, types.join("__"));
+
+        if !self
+            .instances
+            .iter()
+            .any(|(_, instance)| instance.name == instance_name)
+        {
+            let instance = Function {
+                name: instance_name.to_string(),
+                params: resolve_params(&bound, generic.params.clone()),
+                return_type: resolve_type(&bound, &generic.return_type),
+                expressions: generic
+                    .expressions
+                    .iter()
+                    .map(|expression| resolve_expression(&bound, expression.clone()))
+                    .collect(),
+                type_params: vec![],
+                ..generic.clone()
+            };
+
+            self.return_types
+                .push((instance_name.to_string(), instance.return_type.to_string()));
+            self.instances.push((generic.name.to_string(), instance));
+        }
+
+        Ok(instance_name)
+    }
+}
+
+/// Replaces each generic function with a copy for every set of types it's
+/// called with, named like `identity__i32`, so that codegen only sees
+/// concrete types
+pub fn monomorphize(program: Program) -> Result<Program, CompileError> {
+    let generics: Vec<Function> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function) if !function.type_params.is_empty() => Some(function.clone()),
+            _ => None,
+        })
+        .collect();
+
+    if generics.is_empty() {
+        return Ok(program);
+    }
+
+    let return_types: Vec<(String, String)> = program
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Function(function) if function.type_params.is_empty() => {
+                Some((function.name.to_string(), function.return_type.to_string()))
+            }
+            _ => None,
+        })
+        .chain(
+            BUILTINS
+                .iter()
+                .map(|builtin| (builtin.name.to_string(), builtin.return_type.to_string())),
+        )
+        .collect();
+    let mut instantiation = Instantiation {
+        generics: &generics,
+        return_types,
+        instances: vec![],
+    };
+
+    let mut blocks = program.blocks;
+    for block in blocks.iter_mut() {
+        match block {
+            Block::Function(function) if function.type_params.is_empty() => {
+                instantiation.instantiate_all(&mut function.expressions)?
+            }
+            Block::Test(test) => instantiation.instantiate_all(&mut test.expressions)?,
+            _ => (),
+        }
+    }
+
+    // copies can call other generic functions, making more copies
+    let mut index = 0;
+    while index < instantiation.instances.len() {
+        let mut expressions = std::mem::take(&mut instantiation.instances[index].1.expressions);
+        instantiation.instantiate_all(&mut expressions)?;
+        instantiation.instances[index].1.expressions = expressions;
+        index += 1;
+    }
+    let instances = instantiation.instances;
+
+    // the copies go where the generic function was
+    let mut blocks: Vec<Block> = blocks
+        .into_iter()
+        .flat_map(|block| match block {
+            Block::Function(function) if !function.type_params.is_empty() => instances
+                .iter()
+                .filter(|(generic, _)| *generic == function.name)
+                .map(|(_, instance)| Block::Function(instance.clone()))
+                .collect(),
+            block => vec![block],
+        })
+        .collect();
+
+    // number literals in the copies take the types that were filled in
+    match infer_types(&mut blocks).into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(Program { blocks }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::join_errors;
+    use crate::parser::parse;
+
+    fn check_source(source: &str) -> Result<(), String> {
+        match parse(String::from(source)) {
+            Ok(program) => check(&program).map_err(|errors| join_errors(&errors)),
+            Err(errors) => panic!("{}", join_errors(&errors)),
+        }
+    }
+
+    #[test]
+    fn examples_pass() {
+        for example in [
+            include_str!("../examples/hello_world_console_log_for_loop.gwe"),
+            include_str!("../examples/if_statement.gwe"),
+        ] {
+            assert_eq!(check_source(example), Ok(()));
+        }
+    }
+
+    #[test]
+    fn builtins_cant_be_redefined() {
+        assert_eq!(
+            check_source(
+                "import fn memory_grow(pages: i32) js.grow
+
+fn memory_copy(): void {
+}"
+            ),
+            Err(String::from(
+                "memory_grow is a builtin and can't be redefined\nmemory_copy is a builtin and can't be redefined"
+            ))
+        )
+    }
+
+    #[test]
+    fn memories_need_names_to_pick_between() {
+        assert_eq!(
+            check_source(
+                "import memory 1 js.mem
+memory scratch 1
+memory scratch 2"
+            ),
+            Err(String::from(
+                "Memories have to be named when there's more than one\nMemory scratch is declared twice"
+            ))
+        );
+        assert_eq!(
+            check_source(
+                "memory heap 1
+
+@memory(\"scratch\")
+fn clear(): void {
+}"
+            ),
+            Err(String::from(
+                "clear uses memory scratch, which isn't declared"
+            ))
+        )
+    }
+
+    #[test]
+    fn data_is_declared_once() {
+        assert_eq!(
+            check_source(
+                "data \"logo.bin\" as logo
+data \"logo@2x.bin\" as logo"
+            ),
+            Err(String::from("Data logo is declared twice"))
+        )
+    }
+
+    #[test]
+    fn exports_are_of_functions() {
+        assert_eq!(
+            check_source(
+                "fn main(): void {
+}
+export main mainn
+export other missing"
+            ),
+            Err(String::from(
+                "Export main is of mainn, which isn't a function, did you mean main? at line 3, index 12
+Export other is of missing, which isn't a function at line 4, index 13"
+            ))
+        );
+        assert_eq!(
+            check_source(
+                "import fn log(n: i32) console.log
+export log log"
+            ),
+            Ok(())
+        );
+        assert_eq!(edit_distance("mian", "main"), 2);
+        assert_eq!(edit_distance("", "main"), 4);
+    }
+
+    #[test]
+    fn interfaces_are_satisfied_by_imports_or_functions() {
+        assert_eq!(
+            check_source(
+                "interface Logger {
+    fn log(n: i32);
+    fn level(): i32;
+}
+
+import fn log(n: i32) console.log
+
+type Level = i32;
+
+fn level(): Level {
+    return 2;
+}"
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check_source(
+                "interface Logger {
+    fn log(n: i32);
+    fn level(): i32;
+    fn flush();
+}
+
+import fn log(n: f32) console.log
+
+fn level(): void {
+}"
+            ),
+            Err(String::from(
+                "Logger needs log to be fn(i32): void, but it's fn(f32): void
+Logger needs level to be fn(): i32, but it's fn(): void
+Logger needs a function flush, either imported or written in gwe"
+            ))
+        );
+    }
+
+    #[test]
+    fn constants_are_declared_once() {
+        assert_eq!(
+            check_source(
+                "const SIZE: i32 = 1;
+const SIZE: i32 = 2;"
+            ),
+            Err(String::from("Constant SIZE is declared twice"))
+        )
+    }
+
+    #[test]
+    fn memory_sizes_need_a_constant() {
+        assert_eq!(
+            check_source("memory PAGES"),
+            Err(String::from("Unknown constant PAGES used as a memory size"))
+        )
+    }
+
+    #[test]
+    fn type_params_are_the_types_of_params() {
+        assert_eq!(
+            check_source(
+                "fn zero<T>(x: i32): T {
+    return x;
+}"
+            ),
+            Err(String::from(
+                "Type parameter T of zero has to be the type of one of its params"
+            ))
+        )
+    }
+
+    #[test]
+    fn generic_functions_are_not_exported() {
+        assert_eq!(
+            check_source(
+                "fn identity<T>(x: T): T {
+    return x;
+}
+
+export identity identity"
+            ),
+            Err(String::from("identity is generic, so can't be exported"))
+        )
+    }
+
+    #[test]
+    fn closures_are_locals_with_their_own_scope() {
+        assert_eq!(
+            check_source(
+                "fn main(): void {
+    local get: i32 = 1;
+    fn get(): i32 {
+        return 2;
+    }
+}"
+            ),
+            Err(String::from("Local get is already declared in main"))
+        );
+        assert_eq!(
+            check_source(
+                "fn main(): void {
+    fn get(x: i32): i32 {
+        local x: i32 = 2;
+        return x;
+    }
+}"
+            ),
+            Err(String::from(
+                "Local x shadows the param x in get at line 3, index 14"
+            ))
+        );
+    }
+
+    #[test]
+    fn matches_cover_every_number_once() {
+        assert_eq!(
+            check_source(
+                "fn main(n: i32): void {
+    match n {
+        0 => log(1),
+        1 => log(2)
+    }
+}"
+            ),
+            Err(String::from(
+                "A match in main needs a _ arm for the numbers it doesn't list at line 2, index 4"
+            ))
+        );
+        assert_eq!(
+            check_source(
+                "fn main(n: i32): void {
+    match n {
+        0 => log(1),
+        0 => log(2),
+        _ => log(3)
+    }
+}"
+            ),
+            Err(String::from(
+                "A match in main has the pattern 0 twice at line 2, index 4"
+            ))
+        );
+        assert_eq!(
+            check_source(
+                "fn main(n: f32): void {
+    match n {
+        _ => log(1)
+    }
+}"
+            ),
+            Err(String::from(
+                "A match in main is on f32, but only i32 and i64 can be matched at line 2, index 4"
+            ))
+        );
+    }
+
+    #[test]
+    fn duplicate_local_errors() {
+        assert_eq!(
+            check_source(
+                "fn main(): void {
+    local x: i32 = 1;
+    local x: i32 = 2;
+}"
+            ),
+            Err(String::from(
+                "Local x is already declared in main at line 3, index 10"
+            ))
+        )
+    }
+
+    #[test]
+    fn local_shadowing_a_param_errors() {
+        assert_eq!(
+            check_source(
+                "fn main(x: i32): void {
+    local x: i32 = 1;
+}"
+            ),
+            Err(String::from(
+                "Local x shadows the param x in main at line 2, index 10"
+            ))
+        )
+    }
+
+    #[test]
+    fn duplicate_param_errors() {
+        assert_eq!(
+            check_source("fn main(x: i32, x: i32): void {}"),
+            Err(String::from(
+                "Param x is declared twice in main at line 1, index 16"
+            ))
+        )
+    }
+
+    #[test]
+    fn local_shadowing_an_outer_local_errors() {
+        assert_eq!(
+            check_source(
+                "fn main(): void {
+    local x: i32 = 1;
+    if (true) {
+        local x: i32 = 2;
+    } else {
+    };
+}"
+            ),
+            Err(String::from(
+                "Local x shadows a local from an outer block in main at line 4, index 14"
+            ))
+        )
+    }
+
+    #[test]
+    fn locals_in_separate_blocks_pass() {
+        assert_eq!(
+            check_source(
+                "fn main(): void {
+    if (true) {
+        local x: i32 = 1;
+    } else {
+        local x: i32 = 2;
+    };
+}"
+            ),
+            Ok(())
+        )
+    }
+
+    #[test]
+    fn locals_in_separate_blocks_need_the_same_type() {
+        assert_eq!(
+            check_source(
+                "fn main(): void {
+    if (true) {
+        local x: i32 = 1;
+    } else {
+        local x: f32 = 2;
+    };
+}"
+            ),
+            Err(String::from(
+                "Local x is declared as both i32 and f32 in main at line 5, index 14"
+            ))
+        )
+    }
+
+    #[test]
+    fn each_function_is_checked() {
+        assert_eq!(
+            check_source(
+                "fn first(x: i32): void {
+    local x: i32 = 1;
+}
+
+fn second(y: i32): void {
+    local y: i32 = 1;
+}"
+            ),
+            Err(String::from(
+                "Local x shadows the param x in first at line 2, index 10\nLocal y shadows the param y in second at line 6, index 10"
+            ))
+        )
+    }
+
+    #[test]
+    fn strings_cant_be_added() {
+        assert_eq!(
+            check_source(include_str!("../examples/addition.gwe")),
+            Err(String::from(
+                "say_hello adds strings with +, but + only adds numbers at line 2, index 37"
+            ))
+        )
+    }
+
+    #[test]
+    fn type_aliases_must_resolve_to_a_primitive() {
+        assert_eq!(
+            check_source(
+                "type Seconds = f32;
+type Seconds = i32;
+type Minutes = Hours;
+type Ping = Pong;
+type Pong = Ping;
+type i32 = f32;"
+            ),
+            Err(String::from(
+                "Type Seconds is declared twice\nType Minutes refers to unknown type Hours\nType Ping refers back to itself\nType Pong refers back to itself\nType i32 can't be redefined"
+            ))
+        )
+    }
+
+    #[test]
+    fn type_aliases_are_resolved() {
+        let program = parse(String::from(
+            "type Seconds = f32;
+type Delay = Seconds;
+
+fn wait(delay: Delay): Seconds {
+    local total: Seconds = delay;
+    return total;
+}",
+        ))
+        .unwrap();
+
+        assert_eq!(check(&program), Ok(()));
+        assert_eq!(
+            resolve_aliases(program).blocks,
+            vec![Block::Function(Function {
+                name: String::from("wait"),
+                params: vec![Param {
+                    name: String::from("delay"),
+                    type_name: String::from("f32"),
+                    info: Some(TokenInfo { line: 3, index: 8 }),
+                }],
+                return_type: String::from("f32"),
+                doc: None,
+                attributes: vec![],
+                type_params: vec![],
+                expressions: vec![
+                    Expression::LocalAssign {
+                        name: String::from("total"),
+                        type_name: String::from("f32"),
+                        expression: Box::new(Expression::Variable {
+                            body: String::from("delay"),
+                            type_name: String::from("f32")
+                        }),
+                        info: Some(TokenInfo { line: 4, index: 10 }),
+                    },
+                    Expression::Return {
+                        expression: Box::new(Expression::Variable {
+                            body: String::from("total"),
+                            type_name: String::from("f32")
+                        }),
+                        info: Some(TokenInfo { line: 5, index: 4 }),
+                    }
+                ]
+            })]
+        )
+    }
+}
@@ -0,0 +1,210 @@
+//! Walking a program without changing it, for lints and analyzers. Each
+//! `visit_` method defaults to its `walk_` function, which visits everything
+//! inside, so a visitor only overrides what it looks at and calls the `walk_`
+//! function itself to keep going deeper
+
+use crate::{
+    blocks::{Block, Function, Test},
+    expressions::Expression,
+    parser::Program,
+};
+
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program)
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block)
+    }
+
+    /// Functions, along with closures inside of them
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function)
+    }
+
+    fn visit_test(&mut self, test: &Test) {
+        walk_test(self, test)
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression)
+    }
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for block in &program.blocks {
+        visitor.visit_block(block);
+    }
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, block: &Block) {
+    match block {
+        Block::Function(function) => visitor.visit_function(function),
+        Block::Test(test) => visitor.visit_test(test),
+        _ => (),
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, function: &Function) {
+    walk_body(visitor, &function.expressions)
+}
+
+pub fn walk_test<V: Visitor + ?Sized>(visitor: &mut V, test: &Test) {
+    walk_body(visitor, &test.expressions)
+}
+
+fn walk_body<V: Visitor + ?Sized>(visitor: &mut V, expressions: &[Expression]) {
+    for expression in expressions {
+        visitor.visit_expression(expression);
+    }
+}
+
+/// Visits the expressions directly inside, in the order they're written
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match expression {
+        Expression::Return { expression, .. }
+        | Expression::LocalAssign { expression, .. }
+        | Expression::GlobalAssign { expression, .. } => visitor.visit_expression(expression),
+        Expression::Addition { left, right, .. } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        Expression::FunctionCall { args, .. }
+        | Expression::TailCall { args, .. }
+        | Expression::ClosureCall { args, .. } => walk_body(visitor, args),
+        Expression::Closure { function } => visitor.visit_function(function),
+        Expression::EnvironmentStore { value, .. } => visitor.visit_expression(value),
+        Expression::IfStatement {
+            predicate,
+            success,
+            fail,
+        } => {
+            visitor.visit_expression(predicate);
+            walk_body(visitor, success);
+            walk_body(visitor, fail);
+        }
+        Expression::Conditional {
+            predicate,
+            success,
+            fail,
+            ..
+        } => {
+            visitor.visit_expression(predicate);
+            visitor.visit_expression(success);
+            visitor.visit_expression(fail);
+        }
+        Expression::MatchStatement {
+            subject,
+            arms,
+            default,
+            ..
+        } => {
+            visitor.visit_expression(subject);
+            for (_, body) in arms {
+                walk_body(visitor, body);
+            }
+            if let Some(default) = default {
+                walk_body(visitor, default);
+            }
+        }
+        Expression::ForStatement {
+            initial_value,
+            incrementor,
+            break_condition,
+            body,
+            ..
+        } => {
+            visitor.visit_expression(initial_value);
+            visitor.visit_expression(break_condition);
+            visitor.visit_expression(incrementor);
+            walk_body(visitor, body);
+        }
+        Expression::Assert { left, right, .. } => {
+            visitor.visit_expression(left);
+            if let Some(right) = right {
+                visitor.visit_expression(right);
+            }
+        }
+        Expression::Panic { message } => visitor.visit_expression(message),
+        Expression::Array { values, .. } => walk_body(visitor, values),
+        Expression::ForEach { body, .. } => walk_body(visitor, body),
+        Expression::Index { index, .. } => visitor.visit_expression(index),
+        Expression::TryStatement { body, catch } => {
+            walk_body(visitor, body);
+            walk_body(visitor, catch);
+        }
+        Expression::Number { .. }
+        | Expression::Variable { .. }
+        | Expression::String { .. }
+        | Expression::MemoryReference { .. }
+        | Expression::DataField { .. }
+        | Expression::Constant { .. }
+        | Expression::EnvironmentLoad { .. }
+        | Expression::Boolean { .. }
+        | Expression::Character { .. }
+        | Expression::Trivia { .. } => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    /// Names of every function called, closures and tests included
+    #[derive(Default)]
+    struct Calls(Vec<String>);
+
+    impl Visitor for Calls {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::FunctionCall { name, .. } = expression {
+                self.0.push(name.to_string());
+            }
+            walk_expression(self, expression)
+        }
+    }
+
+    #[test]
+    fn every_expression_is_visited_in_order() {
+        let program = parse(String::from(
+            "import fn log(n: i32) console.log
+
+fn main(n: i32): void {
+    if (n) {
+        log(one());
+    } else {
+        match n {
+            2 => {
+                log(2);
+            },
+            _ => {
+                try {
+                    log(3);
+                } catch {
+                    log(4);
+                };
+            }
+        };
+    };
+    fn inner(): void {
+        log(5);
+    };
+}
+
+fn one(): i32 {
+    return 1;
+}
+
+test \"logs\" {
+    log(6);
+}",
+        ))
+        .unwrap();
+
+        let mut calls = Calls::default();
+        calls.visit_program(&program);
+
+        assert_eq!(calls.0, ["log", "one", "log", "log", "log", "log", "log"]);
+    }
+}
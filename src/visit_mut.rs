@@ -0,0 +1,192 @@
+//! Walking a program while changing it in place, for transforms. Works the
+//! same as `visit::Visitor`, with each `visit_` method defaulting to its
+//! `walk_` function
+
+use crate::{
+    blocks::{Block, Function, Test},
+    expressions::Expression,
+    parser::Program,
+};
+
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program)
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block)
+    }
+
+    /// Functions, along with closures inside of them
+    fn visit_function_mut(&mut self, function: &mut Function) {
+        walk_function_mut(self, function)
+    }
+
+    fn visit_test_mut(&mut self, test: &mut Test) {
+        walk_test_mut(self, test)
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression)
+    }
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for block in program.blocks.iter_mut() {
+        visitor.visit_block_mut(block);
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    match block {
+        Block::Function(function) => visitor.visit_function_mut(function),
+        Block::Test(test) => visitor.visit_test_mut(test),
+        _ => (),
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(visitor: &mut V, function: &mut Function) {
+    walk_body_mut(visitor, &mut function.expressions)
+}
+
+pub fn walk_test_mut<V: VisitorMut + ?Sized>(visitor: &mut V, test: &mut Test) {
+    walk_body_mut(visitor, &mut test.expressions)
+}
+
+fn walk_body_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expressions: &mut [Expression]) {
+    for expression in expressions {
+        visitor.visit_expression_mut(expression);
+    }
+}
+
+/// Visits the expressions directly inside, in the order they're written
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match expression {
+        Expression::Return { expression, .. }
+        | Expression::LocalAssign { expression, .. }
+        | Expression::GlobalAssign { expression, .. } => visitor.visit_expression_mut(expression),
+        Expression::Addition { left, right, .. } => {
+            visitor.visit_expression_mut(left);
+            visitor.visit_expression_mut(right);
+        }
+        Expression::FunctionCall { args, .. }
+        | Expression::TailCall { args, .. }
+        | Expression::ClosureCall { args, .. } => walk_body_mut(visitor, args),
+        Expression::Closure { function } => visitor.visit_function_mut(function),
+        Expression::EnvironmentStore { value, .. } => visitor.visit_expression_mut(value),
+        Expression::IfStatement {
+            predicate,
+            success,
+            fail,
+        } => {
+            visitor.visit_expression_mut(predicate);
+            walk_body_mut(visitor, success);
+            walk_body_mut(visitor, fail);
+        }
+        Expression::Conditional {
+            predicate,
+            success,
+            fail,
+            ..
+        } => {
+            visitor.visit_expression_mut(predicate);
+            visitor.visit_expression_mut(success);
+            visitor.visit_expression_mut(fail);
+        }
+        Expression::MatchStatement {
+            subject,
+            arms,
+            default,
+            ..
+        } => {
+            visitor.visit_expression_mut(subject);
+            for (_, body) in arms {
+                walk_body_mut(visitor, body);
+            }
+            if let Some(default) = default {
+                walk_body_mut(visitor, default);
+            }
+        }
+        Expression::ForStatement {
+            initial_value,
+            incrementor,
+            break_condition,
+            body,
+            ..
+        } => {
+            visitor.visit_expression_mut(initial_value);
+            visitor.visit_expression_mut(break_condition);
+            visitor.visit_expression_mut(incrementor);
+            walk_body_mut(visitor, body);
+        }
+        Expression::Assert { left, right, .. } => {
+            visitor.visit_expression_mut(left);
+            if let Some(right) = right {
+                visitor.visit_expression_mut(right);
+            }
+        }
+        Expression::Panic { message } => visitor.visit_expression_mut(message),
+        Expression::Array { values, .. } => walk_body_mut(visitor, values),
+        Expression::ForEach { body, .. } => walk_body_mut(visitor, body),
+        Expression::Index { index, .. } => visitor.visit_expression_mut(index),
+        Expression::TryStatement { body, catch } => {
+            walk_body_mut(visitor, body);
+            walk_body_mut(visitor, catch);
+        }
+        Expression::Number { .. }
+        | Expression::Variable { .. }
+        | Expression::String { .. }
+        | Expression::MemoryReference { .. }
+        | Expression::DataField { .. }
+        | Expression::Constant { .. }
+        | Expression::EnvironmentLoad { .. }
+        | Expression::Boolean { .. }
+        | Expression::Character { .. }
+        | Expression::Trivia { .. } => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generators::gwe::{generate, FormatOptions},
+        parser::parse,
+    };
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            if let Expression::FunctionCall { name, .. } = expression {
+                *name = format!("{}_renamed", name);
+            }
+            walk_expression_mut(self, expression)
+        }
+    }
+
+    #[test]
+    fn nested_expressions_are_changed() {
+        let mut program = parse(String::from(
+            "fn main(n: i32): i32 {
+    for (local i: i32 = 0, 3, 1) {
+        log(n ? one() : 2);
+    };
+    return one();
+}",
+        ))
+        .unwrap();
+
+        Rename.visit_program_mut(&mut program);
+
+        assert_eq!(
+            generate(program, &FormatOptions::default()),
+            "fn main(n: i32): i32 {
+    for (local i: i32 = 0, 3, 1) {
+        log_renamed(n ? one_renamed() : 2);
+    };
+    return one_renamed();
+}"
+        );
+    }
+}
@@ -0,0 +1,1281 @@
+//! Compiles gwe programs to WAT, instantiates them with wasmtime and asserts
+//! on what they return or pass to their imports.
+
+// the examples log 3.14 rather than PI
+#![allow(clippy::approx_constant)]
+
+use std::fs;
+
+use gwe::{
+    errors::join_errors,
+    generators::web_assembly,
+    options::{Optimize, Passes},
+    parser::parse,
+};
+use wasmtime::{Caller, Engine, Instance, Linker, Memory, MemoryType, Module, Store};
+
+fn compile(source: String) -> String {
+    match parse(source) {
+        Ok(program) => web_assembly::generate(program).unwrap(),
+        Err(errors) => panic!("Error parsing: {}", join_errors(&errors)),
+    }
+}
+
+fn example(name: &str) -> String {
+    compile(fs::read_to_string(format!("examples/{}.gwe", name)).unwrap())
+}
+
+/// Host state shared with the imports, recording every value passed to `console.log`
+struct Host<T: 'static> {
+    engine: Engine,
+    linker: Linker<Vec<T>>,
+    store: Store<Vec<T>>,
+}
+
+impl<T: 'static> Host<T> {
+    fn new() -> Self {
+        let engine = Engine::default();
+        let linker = Linker::new(&engine);
+        let store = Store::new(&engine, vec![]);
+
+        Host {
+            engine,
+            linker,
+            store,
+        }
+    }
+
+    fn instantiate(&mut self, wat: &str) -> Instance {
+        let module = match Module::new(&self.engine, wat) {
+            Ok(module) => module,
+            Err(error) => panic!("Invalid module: {:?}\n{}", error, wat),
+        };
+
+        self.linker.instantiate(&mut self.store, &module).unwrap()
+    }
+
+    fn logs(&self) -> &Vec<T> {
+        self.store.data()
+    }
+}
+
+#[test]
+fn hello_world_returns_one() {
+    let mut host: Host<f32> = Host::new();
+    let instance = host.instantiate(&example("hello_world"));
+
+    let hello_world = instance
+        .get_typed_func::<(), f32>(&mut host.store, "helloWorld")
+        .unwrap();
+
+    assert_eq!(hello_world.call(&mut host.store, ()).unwrap(), 1.0);
+}
+
+#[test]
+fn console_log_receives_number() {
+    let mut host: Host<f32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<f32>>, number: f32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let instance = host.instantiate(&example("hello_world_console_log"));
+
+    let main = instance
+        .get_typed_func::<(), ()>(&mut host.store, "main")
+        .unwrap();
+    main.call(&mut host.store, ()).unwrap();
+
+    assert_eq!(host.logs(), &vec![3.14]);
+}
+
+#[test]
+fn for_loop_counts_to_ten() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<i32>>, number: i32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let instance = host.instantiate(&example("hello_world_console_log_for_loop"));
+
+    let main = instance
+        .get_typed_func::<(), ()>(&mut host.store, "main")
+        .unwrap();
+    main.call(&mut host.store, ()).unwrap();
+
+    assert_eq!(host.logs(), &(0..10).collect::<Vec<i32>>());
+}
+
+#[test]
+fn string_is_written_to_memory() {
+    let mut host: Host<(i32, i32)> = Host::new();
+    let memory = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker
+        .define(&host.store, "js", "mem", memory)
+        .unwrap();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<(i32, i32)>>, offset: i32, length: i32| {
+                caller.data_mut().push((offset, length))
+            },
+        )
+        .unwrap();
+    let instance = host.instantiate(&example("hello_world_string"));
+
+    let main = instance
+        .get_typed_func::<(), ()>(&mut host.store, "main")
+        .unwrap();
+    main.call(&mut host.store, ()).unwrap();
+
+    let (offset, length) = host.logs()[0];
+    let bytes = &memory.data(&host.store)[offset as usize..(offset + length) as usize];
+
+    assert_eq!(std::str::from_utf8(bytes), Ok("Hello world"));
+}
+
+#[test]
+fn panic_passes_its_message_to_the_handler() {
+    let mut host: Host<(i32, i32)> = Host::new();
+    let memory = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker
+        .define(&host.store, "js", "mem", memory)
+        .unwrap();
+    host.linker
+        .func_wrap(
+            "gwe",
+            "panic",
+            |mut caller: Caller<'_, Vec<(i32, i32)>>, offset: i32, length: i32| {
+                caller.data_mut().push((offset, length))
+            },
+        )
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import fn on_panic(offset: i32, length: i32) gwe.panic
+import memory 1 js.mem
+
+fn main(): void {
+    panic(\"gave up\");
+}
+
+export main main",
+    )));
+
+    let main = instance
+        .get_typed_func::<(), ()>(&mut host.store, "main")
+        .unwrap();
+    assert!(main.call(&mut host.store, ()).is_err());
+
+    let (offset, length) = host.logs()[0];
+    let bytes = &memory.data(&host.store)[offset as usize..(offset + length) as usize];
+
+    assert_eq!(std::str::from_utf8(bytes), Ok("gave up"));
+}
+
+#[test]
+fn debug_checks_report_where_they_failed() {
+    let mut host: Host<(i32, i32)> = Host::new();
+    let memory = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker
+        .define(&host.store, "js", "mem", memory)
+        .unwrap();
+    host.linker
+        .func_wrap(
+            "gwe",
+            "panic",
+            |mut caller: Caller<'_, Vec<(i32, i32)>>, offset: i32, length: i32| {
+                caller.data_mut().push((offset, length))
+            },
+        )
+        .unwrap();
+    let program = parse(String::from(
+        "import fn on_panic(offset: i32, length: i32) gwe.panic
+import memory 1 js.mem
+
+@export
+fn fill(length: i32): void {
+    memory_fill(1024, 1, length);
+}
+
+@export
+fn count(): void {
+    for (local i: i32 = 2147483600, 2147483647, 100) {
+    };
+}",
+    ))
+    .unwrap();
+    let options = web_assembly::GenerateOptions {
+        passes: Passes {
+            debug: true,
+            ..Default::default()
+        },
+        ..web_assembly::GenerateOptions::default()
+    };
+    let instance = host.instantiate(&web_assembly::generate_with(program, &options).unwrap());
+
+    let fill = instance
+        .get_typed_func::<i32, ()>(&mut host.store, "fill")
+        .unwrap();
+    let count = instance
+        .get_typed_func::<(), ()>(&mut host.store, "count")
+        .unwrap();
+
+    fill.call(&mut host.store, 10).unwrap();
+    assert!(host.logs().is_empty());
+    assert!(fill.call(&mut host.store, 70000).is_err());
+    assert!(count.call(&mut host.store, ()).is_err());
+
+    let messages: Vec<&str> = host
+        .logs()
+        .iter()
+        .map(|(offset, length)| {
+            std::str::from_utf8(
+                &memory.data(&host.store)[*offset as usize..(offset + length) as usize],
+            )
+            .unwrap()
+        })
+        .collect();
+
+    assert_eq!(
+        messages,
+        vec![
+            "memory_fill out of bounds in fill",
+            "i32 for loop overflowed in count"
+        ]
+    );
+}
+
+#[test]
+fn for_each_visits_every_element() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<i32>>, number: i32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let memory = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker
+        .define(&host.store, "js", "mem", memory)
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import fn log(n: i32) console.log
+import memory 1 js.mem
+
+fn main(): void {
+    local primes: [i32] = [2, 3, 5, 7];
+    local letters: [i32] = ['a', 'b'];
+    for (p in primes) {
+        log(p);
+    };
+    for (letter in letters) {
+        for (p in primes) {
+            log(letter);
+        };
+    };
+}
+
+export main main",
+    )));
+
+    let main = instance
+        .get_typed_func::<(), ()>(&mut host.store, "main")
+        .unwrap();
+
+    main.call(&mut host.store, ()).unwrap();
+    assert_eq!(
+        host.logs(),
+        &vec![2, 3, 5, 7, 97, 97, 97, 97, 98, 98, 98, 98]
+    );
+}
+
+#[test]
+fn debug_checks_catch_indexes_out_of_bounds() {
+    let mut host: Host<(i32, i32)> = Host::new();
+    let memory = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker
+        .define(&host.store, "js", "mem", memory)
+        .unwrap();
+    host.linker
+        .func_wrap(
+            "gwe",
+            "panic",
+            |mut caller: Caller<'_, Vec<(i32, i32)>>, offset: i32, length: i32| {
+                caller.data_mut().push((offset, length))
+            },
+        )
+        .unwrap();
+    let program = parse(String::from(
+        "import fn on_panic(offset: i32, length: i32) gwe.panic
+import memory 1 js.mem
+
+@export
+fn pick(i: i32): i32 {
+    local primes: [i32] = [2, 3, 5, 7];
+    return primes[i];
+}",
+    ))
+    .unwrap();
+    let options = web_assembly::GenerateOptions {
+        passes: Passes {
+            debug: true,
+            ..Default::default()
+        },
+        ..web_assembly::GenerateOptions::default()
+    };
+    let instance = host.instantiate(&web_assembly::generate_with(program, &options).unwrap());
+
+    let pick = instance
+        .get_typed_func::<i32, i32>(&mut host.store, "pick")
+        .unwrap();
+
+    assert_eq!(pick.call(&mut host.store, 3).unwrap(), 7);
+    assert!(host.logs().is_empty());
+    assert!(pick.call(&mut host.store, 4).is_err());
+    assert!(pick.call(&mut host.store, -1).is_err());
+
+    let (offset, length) = host.logs()[0];
+    let bytes = &memory.data(&host.store)[offset as usize..(offset + length) as usize];
+
+    assert_eq!(host.logs().len(), 2);
+    assert_eq!(
+        std::str::from_utf8(bytes),
+        Ok("primes index out of bounds on line 7 in pick")
+    );
+}
+
+#[test]
+fn memory_builtins_fill_copy_and_grow() {
+    let mut host: Host<()> = Host::new();
+    let memory = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker
+        .define(&host.store, "js", "mem", memory)
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import memory 1 js.mem
+
+fn main(): i32 {
+    memory_fill(0, 7, 4);
+    memory_copy(4, 0, 2);
+    return memory_grow(2);
+}
+
+export main main",
+    )));
+
+    let main = instance
+        .get_typed_func::<(), i32>(&mut host.store, "main")
+        .unwrap();
+
+    // grow returns the size before growing
+    assert_eq!(main.call(&mut host.store, ()).unwrap(), 1);
+    assert_eq!(&memory.data(&host.store)[0..7], &[7, 7, 7, 7, 7, 7, 0]);
+    assert_eq!(memory.size(&host.store), 3);
+}
+
+#[test]
+fn functions_write_to_the_memory_they_name() {
+    let mut host: Host<()> = Host::new();
+    let heap = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    let scratch = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker.define(&host.store, "js", "heap", heap).unwrap();
+    host.linker
+        .define(&host.store, "js", "scratch", scratch)
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import memory heap 1 js.heap
+import memory scratch 1 js.scratch
+
+fn fill_heap(): void {
+    memory_fill(0, 1, 2);
+}
+
+@memory(\"scratch\")
+fn fill_scratch(): void {
+    memory_fill(0, 2, 4);
+}
+
+export fill_heap fill_heap
+export fill_scratch fill_scratch",
+    )));
+
+    for name in ["fill_heap", "fill_scratch"] {
+        instance
+            .get_typed_func::<(), ()>(&mut host.store, name)
+            .unwrap()
+            .call(&mut host.store, ())
+            .unwrap();
+    }
+
+    assert_eq!(&heap.data(&host.store)[0..4], &[1, 1, 0, 0]);
+    assert_eq!(&scratch.data(&host.store)[0..4], &[2, 2, 2, 2]);
+}
+
+#[test]
+fn strings_pass_both_ways_through_the_string_buffer() {
+    let mut host: Host<String> = Host::new();
+    let memory = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker
+        .define(&host.store, "js", "mem", memory)
+        .unwrap();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            move |mut caller: Caller<'_, Vec<String>>, offset: i32, length: i32| {
+                let bytes =
+                    memory.data(&caller)[offset as usize..(offset + length) as usize].to_vec();
+                caller.data_mut().push(String::from_utf8(bytes).unwrap());
+            },
+        )
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import fn log(message: string) console.log
+import memory 1 js.mem
+
+fn echo(name: string): string {
+    log(name);
+    return name;
+}
+
+@export
+fn hello(): string {
+    return \"Hello\";
+}
+
+@export
+fn greet(): string {
+    return \"héllo \\ 世界\";
+}
+
+export echo echo",
+    )));
+
+    let buffer = instance
+        .get_global(&mut host.store, "__string_buffer")
+        .unwrap()
+        .get(&mut host.store)
+        .unwrap_i32() as usize;
+    let read = |host: &Host<String>, length: i32| {
+        String::from_utf8(memory.data(&host.store)[buffer..buffer + length as usize].to_vec())
+            .unwrap()
+    };
+
+    let name = "wörld".as_bytes();
+    memory.data_mut(&mut host.store)[buffer..buffer + name.len()].copy_from_slice(name);
+    let echo = instance
+        .get_typed_func::<(i32, i32), i32>(&mut host.store, "echo")
+        .unwrap();
+    let length = echo
+        .call(&mut host.store, (buffer as i32, name.len() as i32))
+        .unwrap();
+
+    assert_eq!(read(&host, length), "wörld");
+    assert_eq!(host.logs(), &vec![String::from("wörld")]);
+
+    let hello = instance
+        .get_typed_func::<(), i32>(&mut host.store, "hello")
+        .unwrap();
+    let length = hello.call(&mut host.store, ()).unwrap();
+
+    assert_eq!(read(&host, length), "Hello");
+
+    let greet = instance
+        .get_typed_func::<(), i32>(&mut host.store, "greet")
+        .unwrap();
+    let length = greet.call(&mut host.store, ()).unwrap();
+
+    assert_eq!(read(&host, length), "héllo \\ 世界");
+}
+
+#[test]
+fn alloc_bumps_grows_and_resets() {
+    let mut host: Host<()> = Host::new();
+    let memory = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker
+        .define(&host.store, "js", "mem", memory)
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import memory 1 js.mem
+
+fn allocate(size: i32): i32 {
+    return alloc(size);
+}
+
+fn free(): void {
+    reset();
+}
+
+export allocate allocate
+export free free",
+    )));
+
+    let allocate = instance
+        .get_typed_func::<i32, i32>(&mut host.store, "allocate")
+        .unwrap();
+    let free = instance
+        .get_typed_func::<(), ()>(&mut host.store, "free")
+        .unwrap();
+
+    assert_eq!(allocate.call(&mut host.store, 5).unwrap(), 0);
+    assert_eq!(allocate.call(&mut host.store, 1).unwrap(), 8);
+    assert_eq!(allocate.call(&mut host.store, 70000).unwrap(), 16);
+    assert_eq!(memory.size(&host.store), 2);
+
+    free.call(&mut host.store, ()).unwrap();
+    assert_eq!(allocate.call(&mut host.store, 1).unwrap(), 0);
+}
+
+#[test]
+fn error_codes_from_imports_run_the_catch_block() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "host",
+            "fetch",
+            // odd ids fail
+            |_: Caller<'_, Vec<i32>>, id: i32| -> i32 { id % 2 },
+        )
+        .unwrap();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<i32>>, number: i32| -> i32 {
+                caller.data_mut().push(number);
+                0
+            },
+        )
+        .unwrap();
+
+    let program = parse(String::from(
+        "import fn fetch(id: i32) host.fetch
+import fn log(number: i32) console.log
+
+fn main(): void {
+    try {
+        fetch(2);
+        log(1);
+        fetch(3);
+        log(2);
+    } catch {
+        log(3);
+    }
+    log(4);
+}
+
+export main main",
+    ))
+    .unwrap();
+    let options = web_assembly::GenerateOptions {
+        exceptions: web_assembly::Exceptions::ErrorCodes,
+        ..web_assembly::GenerateOptions::default()
+    };
+    let instance = host.instantiate(&web_assembly::generate_with(program, &options).unwrap());
+
+    let main = instance
+        .get_typed_func::<(), ()>(&mut host.store, "main")
+        .unwrap();
+    main.call(&mut host.store, ()).unwrap();
+
+    assert_eq!(host.logs(), &vec![1, 3, 4]);
+}
+
+#[test]
+fn optimized_code_runs_the_same() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<i32>>, number: i32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let program = parse(String::from(
+        "import fn log(n: i32) console.log
+
+@export
+fn main(n: i32): i32 {
+    for (local x: i32 = 0, 3, 1) {
+        log(x);
+    };
+    local kept: i32 = n;
+    return kept;
+}",
+    ))
+    .unwrap();
+    let options = web_assembly::GenerateOptions {
+        passes: Passes::optimize(Optimize::Level(1)),
+        ..web_assembly::GenerateOptions::default()
+    };
+    let wat = web_assembly::generate_with(program, &options).unwrap();
+    assert!(wat.contains("(local.tee $kept"));
+    let instance = host.instantiate(&wat);
+
+    let main = instance
+        .get_typed_func::<i32, i32>(&mut host.store, "main")
+        .unwrap();
+
+    assert_eq!(main.call(&mut host.store, 4).unwrap(), 4);
+    assert_eq!(host.logs(), &vec![0, 1, 2]);
+}
+
+#[test]
+fn inlined_functions_run_the_same() {
+    let mut host: Host<f32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<f32>>, number: f32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let program = parse(String::from(
+        "import fn log(n: f32) console.log
+
+fn double(x: f32): f32 {
+    local twice: f32 = x + x;
+    return twice;
+}
+
+fn report(x: f32): void {
+    log(x);
+}
+
+@export
+fn main(n: f32): f32 {
+    for (local i: i32 = 0, 2, 1) {
+        local doubled: f32 = double(n);
+        report(doubled);
+    };
+    return double(n + 1.0);
+}",
+    ))
+    .unwrap();
+    let options = web_assembly::GenerateOptions {
+        passes: Passes::optimize(Optimize::Level(2)),
+        ..web_assembly::GenerateOptions::default()
+    };
+    let wat = web_assembly::generate_with(program, &options).unwrap();
+    assert!(!wat.contains("(call $double)"));
+    assert!(!wat.contains("(call $report)"));
+    let instance = host.instantiate(&wat);
+
+    let main = instance
+        .get_typed_func::<f32, f32>(&mut host.store, "main")
+        .unwrap();
+
+    assert_eq!(main.call(&mut host.store, 1.5).unwrap(), 5.0);
+    assert_eq!(host.logs(), &vec![3.0, 3.0]);
+}
+
+#[test]
+fn generic_functions_run_for_each_type() {
+    let mut host: Host<()> = Host::new();
+    let instance = host.instantiate(&compile(String::from(
+        "fn pick<T, U>(kept: T, dropped: U): T {
+    return kept;
+}
+
+@export
+fn narrow(n: i32, big: i64): i32 {
+    return pick(n, big);
+}
+
+@export
+fn wide(n: i32, big: i64): i64 {
+    return pick(big, n);
+}",
+    )));
+
+    let narrow = instance
+        .get_typed_func::<(i32, i64), i32>(&mut host.store, "narrow")
+        .unwrap();
+    let wide = instance
+        .get_typed_func::<(i32, i64), i64>(&mut host.store, "wide")
+        .unwrap();
+
+    assert_eq!(narrow.call(&mut host.store, (3, 1 << 40)).unwrap(), 3);
+    assert_eq!(wide.call(&mut host.store, (3, 1 << 40)).unwrap(), 1 << 40);
+}
+
+#[test]
+fn closures_use_what_they_captured() {
+    let mut host: Host<()> = Host::new();
+    let memory = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker
+        .define(&host.store, "js", "mem", memory)
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import memory 1 js.mem
+
+@export
+fn main(n: f32, step: f32): f32 {
+    fn add(x: f32): f32 {
+        return x + step;
+    }
+    fn twice(x: f32): f32 {
+        local once: f32 = add(x);
+        return add(once);
+    }
+    return twice(n);
+}",
+    )));
+
+    let main = instance
+        .get_typed_func::<(f32, f32), f32>(&mut host.store, "main")
+        .unwrap();
+
+    assert_eq!(main.call(&mut host.store, (1.0, 2.5)).unwrap(), 6.0);
+}
+
+#[test]
+fn functions_are_passed_to_params_of_function_type() {
+    let mut host: Host<()> = Host::new();
+    let memory = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker
+        .define(&host.store, "js", "mem", memory)
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import memory 1 js.mem
+
+fn apply(f: fn(f32): f32, x: f32): f32 {
+    return f(x);
+}
+
+fn twice(f: fn(f32): f32, x: f32): f32 {
+    fn again(y: f32): f32 {
+        return f(f(y));
+    }
+    return apply(again, x);
+}
+
+@export
+fn main(n: f32, step: f32): f32 {
+    fn add(x: f32): f32 {
+        return x + step;
+    }
+    return twice(add, n);
+}",
+    )));
+
+    let main = instance
+        .get_typed_func::<(f32, f32), f32>(&mut host.store, "main")
+        .unwrap();
+
+    assert_eq!(main.call(&mut host.store, (1.0, 2.5)).unwrap(), 6.0);
+}
+
+#[test]
+fn tail_calls_recurse_without_growing_the_stack() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "host",
+            "tick",
+            // deep enough to exhaust the stack if every call kept its frame
+            |mut caller: Caller<'_, Vec<i32>>| -> wasmtime::Result<()> {
+                caller.data_mut().push(1);
+                if caller.data().len() == 1_000_000 {
+                    Err(wasmtime::Error::msg("done"))
+                } else {
+                    Ok(())
+                }
+            },
+        )
+        .unwrap();
+
+    let program = parse(String::from(
+        "import fn tick() host.tick
+
+fn spin(): void {
+    tick();
+    tailcall spin();
+}
+
+export spin spin",
+    ))
+    .unwrap();
+    let options = web_assembly::GenerateOptions {
+        tail_calls: true,
+        ..web_assembly::GenerateOptions::default()
+    };
+    let instance = host.instantiate(&web_assembly::generate_with(program, &options).unwrap());
+
+    let spin = instance
+        .get_typed_func::<(), ()>(&mut host.store, "spin")
+        .unwrap();
+    let error = spin.call(&mut host.store, ()).unwrap_err();
+
+    assert_eq!(error.root_cause().to_string(), "done");
+    assert_eq!(host.logs().len(), 1_000_000);
+}
+
+#[test]
+fn instrumented_calls_are_counted_and_traced() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "gwe",
+            "trace",
+            |mut caller: Caller<'_, Vec<i32>>, id: i32| caller.data_mut().push(id),
+        )
+        .unwrap();
+    let program = parse(String::from(
+        "fn step(): void {
+}
+
+@export
+fn main(): void {
+    for (local i: i32 = 0, 3, 1) {
+        step();
+    };
+}",
+    ))
+    .unwrap();
+    let options = web_assembly::GenerateOptions {
+        instrument: Some(web_assembly::Instrument::Trace),
+        ..web_assembly::GenerateOptions::default()
+    };
+    let instance = host.instantiate(&web_assembly::generate_with(program, &options).unwrap());
+
+    let main = instance
+        .get_typed_func::<(), ()>(&mut host.store, "main")
+        .unwrap();
+    main.call(&mut host.store, ()).unwrap();
+    main.call(&mut host.store, ()).unwrap();
+
+    let calls = instance.get_memory(&mut host.store, "__calls").unwrap();
+    let counters: Vec<i32> = calls.data(&host.store)[..8]
+        .chunks(4)
+        .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+
+    assert_eq!(counters, vec![6, 2]);
+    assert_eq!(&calls.data(&host.store)[8..18], b"step\nmain\0");
+    assert_eq!(host.logs(), &vec![1, 0, 0, 0, 1, 0, 0, 0]);
+}
+
+#[test]
+fn if_statement_takes_the_right_branch() {
+    let mut host: Host<f32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<f32>>, number: f32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let instance = host.instantiate(&example("if_statement"));
+
+    let main = instance
+        .get_typed_func::<i32, ()>(&mut host.store, "main")
+        .unwrap();
+    main.call(&mut host.store, 1).unwrap();
+    main.call(&mut host.store, 0).unwrap();
+
+    assert_eq!(host.logs(), &vec![3.14, 42.0]);
+}
+
+#[test]
+fn match_runs_the_arm_for_the_number() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<i32>>, number: i32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import fn log(x: i32) console.log
+
+@export
+fn main(n: i32, big: i64): void {
+    match n {
+        0 => log(10),
+        1 => log(11),
+        3 => log(13),
+        _ => {
+            match big {
+                5 => log(5),
+                _ => log(99)
+            }
+        }
+    }
+}",
+    )));
+
+    let main = instance
+        .get_typed_func::<(i32, i64), ()>(&mut host.store, "main")
+        .unwrap();
+    for (n, big) in [(0, 0), (3, 0), (2, 5), (-4, 1)] {
+        main.call(&mut host.store, (n, big)).unwrap();
+    }
+
+    assert_eq!(host.logs(), &vec![10, 13, 5, 99]);
+}
+
+#[test]
+fn conditionals_pick_a_side() {
+    let mut host: Host<()> = Host::new();
+    let instance = host.instantiate(&compile(String::from(
+        "fn double(x: f32): f32 {
+    return x + x;
+}
+
+@export
+fn main(c: i32, d: i32, x: f32): f32 {
+    return c ? double(x) : d ? x + 1.0 : 0.0;
+}",
+    )));
+
+    let main = instance
+        .get_typed_func::<(i32, i32, f32), f32>(&mut host.store, "main")
+        .unwrap();
+
+    assert_eq!(main.call(&mut host.store, (1, 0, 3.0)).unwrap(), 6.0);
+    assert_eq!(main.call(&mut host.store, (0, 1, 3.0)).unwrap(), 4.0);
+    assert_eq!(main.call(&mut host.store, (0, 0, 3.0)).unwrap(), 0.0);
+}
+
+#[test]
+fn booleans_are_not_inverted() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<i32>>, number: i32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import fn log(n: i32) console.log
+
+fn main(): void {
+    if (true) { log(true); } else { log(false); };
+    if (false) { log(true); } else { log(false); };
+}
+
+export main main",
+    )));
+
+    let main = instance
+        .get_typed_func::<(), ()>(&mut host.store, "main")
+        .unwrap();
+    main.call(&mut host.store, ()).unwrap();
+
+    assert_eq!(host.logs(), &vec![1, 0]);
+}
+
+#[test]
+fn globals_shared_between_functions_are_valid() {
+    let mut host: Host<f32> = Host::new();
+    let instance = host.instantiate(&compile(String::from(
+        "fn start(): void {
+    global count: f32 = 1;
+}
+
+fn restart(): void {
+    global count: f32 = 2;
+}
+
+export start start
+export restart restart",
+    )));
+
+    for name in ["start", "restart"] {
+        let function = instance
+            .get_typed_func::<(), ()>(&mut host.store, name)
+            .unwrap();
+        function.call(&mut host.store, ()).unwrap();
+    }
+}
+
+#[test]
+fn for_loops_count_down_and_step_floats() {
+    let mut host: Host<f32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<f32>>, number: f32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import fn log(n: f32) console.log
+
+@export
+fn main(): void {
+    for (local x: f32 = 0.0, 1.0, 0.25) {
+        log(x);
+    };
+    for (local y: f32 = 3.0, 0.0, 1.5, down) {
+        log(y);
+    };
+}",
+    )));
+
+    let main = instance
+        .get_typed_func::<(), ()>(&mut host.store, "main")
+        .unwrap();
+    main.call(&mut host.store, ()).unwrap();
+
+    assert_eq!(host.logs(), &vec![0.0, 0.25, 0.5, 0.75, 3.0, 1.5]);
+}
+
+#[test]
+fn nested_ifs_inside_a_for_loop() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<i32>>, number: i32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import fn log(n: i32) console.log
+
+fn main(n: i32): void {
+    for (local x: i32 = 0, 3, 1) {
+        if (n) {
+            if (x) { log(x); } else { log(n); };
+        } else {
+            log(x);
+        };
+    };
+}
+
+export main main",
+    )));
+
+    let main = instance
+        .get_typed_func::<i32, ()>(&mut host.store, "main")
+        .unwrap();
+    main.call(&mut host.store, 5).unwrap();
+
+    assert_eq!(host.logs(), &vec![5, 1, 2]);
+}
+
+#[test]
+fn standard_library_modules_can_be_used() {
+    let mut host: Host<i32> = Host::new();
+    let instance = host.instantiate(&compile(String::from(
+        "use std.math;
+use std.memory;
+
+memory 1
+
+fn main(x: f32): f32 {
+    memory_zero(0, 8);
+    local doubled: f32 = double(x);
+    local total: f32 = sum3(doubled, x, 1.0);
+    return total;
+}
+
+fn chosen(condition: i32): f32 {
+    local chosen: f32 = pick(condition, 1.0, 2.0);
+    return chosen;
+}
+
+export main main
+export chosen chosen",
+    )));
+
+    let main = instance
+        .get_typed_func::<f32, f32>(&mut host.store, "main")
+        .unwrap();
+    let chosen = instance
+        .get_typed_func::<i32, f32>(&mut host.store, "chosen")
+        .unwrap();
+
+    assert_eq!(main.call(&mut host.store, 2.0).unwrap(), 7.0);
+    assert_eq!(chosen.call(&mut host.store, 1).unwrap(), 1.0);
+    assert_eq!(chosen.call(&mut host.store, 0).unwrap(), 2.0);
+}
+
+#[test]
+fn characters_are_their_code_points() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<i32>>, number: i32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import fn log(n: i32) console.log
+
+fn main(): i32 {
+    local letter: i32 = 'a';
+    log(letter);
+    log('\\n');
+    log('\\u{1F600}');
+    return 'é';
+}
+
+export main main",
+    )));
+
+    let main = instance
+        .get_typed_func::<(), i32>(&mut host.store, "main")
+        .unwrap();
+
+    assert_eq!(main.call(&mut host.store, ()).unwrap(), 233);
+    assert_eq!(host.logs(), &vec![97, 10, 128512]);
+}
+
+#[test]
+fn returns_leave_the_function_early() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<i32>>, number: i32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import fn log(n: i32) console.log
+
+fn pick(n: i32): i32 {
+    if (n) {
+        return 10;
+    } else {
+        log(n);
+    };
+    return 20;
+}
+
+fn first(n: i32): i32 {
+    for (local x: i32 = 0, 10, 1) {
+        log(x);
+        return n;
+    };
+    return 0;
+}
+
+fn sign(n: i32): i32 {
+    if (n) {
+        return 1;
+    } else {
+        return 0;
+    };
+}
+
+export pick pick
+export first first
+export sign sign",
+    )));
+
+    let pick = instance
+        .get_typed_func::<i32, i32>(&mut host.store, "pick")
+        .unwrap();
+    let first = instance
+        .get_typed_func::<i32, i32>(&mut host.store, "first")
+        .unwrap();
+    let sign = instance
+        .get_typed_func::<i32, i32>(&mut host.store, "sign")
+        .unwrap();
+
+    assert_eq!(pick.call(&mut host.store, 1).unwrap(), 10);
+    assert_eq!(pick.call(&mut host.store, 0).unwrap(), 20);
+    assert_eq!(first.call(&mut host.store, 5).unwrap(), 5);
+    assert_eq!(sign.call(&mut host.store, 3).unwrap(), 1);
+    assert_eq!(sign.call(&mut host.store, 0).unwrap(), 0);
+    assert_eq!(host.logs(), &vec![0, 0]);
+}
+
+#[test]
+fn arrays_are_read_from_memory() {
+    let mut host: Host<i32> = Host::new();
+    host.linker
+        .func_wrap(
+            "console",
+            "log",
+            |mut caller: Caller<'_, Vec<i32>>, number: i32| caller.data_mut().push(number),
+        )
+        .unwrap();
+    let memory = Memory::new(&mut host.store, MemoryType::new(1, None)).unwrap();
+    host.linker
+        .define(&host.store, "js", "mem", memory)
+        .unwrap();
+    let instance = host.instantiate(&compile(String::from(
+        "import fn log(n: i32) console.log
+import memory 1 js.mem
+
+fn main(): f64 {
+    local fibonacci: [i32] = [1, 1, 2, 3, 5, 8];
+    local halves: [f64] = [0.5, 1.5, 2.5];
+    for (local i: i32 = 0, 6, 1) {
+        log(fibonacci[i]);
+    };
+    log(fibonacci.length);
+    return halves[2];
+}
+
+export main main",
+    )));
+
+    let main = instance
+        .get_typed_func::<(), f64>(&mut host.store, "main")
+        .unwrap();
+
+    assert_eq!(main.call(&mut host.store, ()).unwrap(), 2.5);
+    assert_eq!(host.logs(), &vec![1, 1, 2, 3, 5, 8, 6]);
+}
+
+#[test]
+fn additions_take_the_type_of_their_operands() {
+    let mut host: Host<i64> = Host::new();
+    let instance = host.instantiate(&compile(String::from(
+        "fn sum(): i32 {
+    local x: i32 = 1 + 2;
+    return x;
+}
+
+fn next(n: i32): i32 {
+    return n + 1;
+}
+
+fn wide(n: i64): i64 {
+    return n + n + 2;
+}
+
+fn half(n: f64): f64 {
+    return n + 0.5;
+}
+
+export sum sum
+export next next
+export wide wide
+export half half",
+    )));
+
+    let sum = instance
+        .get_typed_func::<(), i32>(&mut host.store, "sum")
+        .unwrap();
+    let next = instance
+        .get_typed_func::<i32, i32>(&mut host.store, "next")
+        .unwrap();
+    let wide = instance
+        .get_typed_func::<i64, i64>(&mut host.store, "wide")
+        .unwrap();
+    let half = instance
+        .get_typed_func::<f64, f64>(&mut host.store, "half")
+        .unwrap();
+
+    assert_eq!(sum.call(&mut host.store, ()).unwrap(), 3);
+    assert_eq!(next.call(&mut host.store, 41).unwrap(), 42);
+    assert_eq!(wide.call(&mut host.store, 1 << 40).unwrap(), (1 << 41) + 2);
+    assert_eq!(half.call(&mut host.store, 1.0).unwrap(), 1.5);
+}
@@ -0,0 +1,130 @@
+//! Feeds arbitrary and grammar-guided input through the tokenizer, parser
+//! and generators, which must report errors rather than panic.
+
+use gwe::{
+    generators::{
+        gwe::{generate, FormatOptions},
+        web_assembly,
+    },
+    parser::parse,
+    tokenizer::tokenize,
+};
+use proptest::prelude::*;
+
+const FRAGMENTS: &[&str] = &[
+    "fn",
+    "import",
+    "export",
+    "memory",
+    "local",
+    "global",
+    "return",
+    "if",
+    "else",
+    "for",
+    "true",
+    "false",
+    "(",
+    ")",
+    "{",
+    "}",
+    ":",
+    ",",
+    ";",
+    "=",
+    "+",
+    ".",
+    "\"",
+    "\"hello\"",
+    "main",
+    "log",
+    "x",
+    "i32",
+    "f32",
+    "void",
+    "string",
+    "0",
+    "1",
+    "3.14",
+    "1.2.3",
+];
+
+const SEPARATORS: &[&str] = &[" ", "\n", ""];
+
+/// Programs built from gwe's own tokens, so that inputs get past the tokenizer
+/// and exercise the block and expression parsers
+fn grammar_input() -> impl Strategy<Value = String> {
+    prop::collection::vec(
+        (
+            prop::sample::select(FRAGMENTS),
+            prop::sample::select(SEPARATORS),
+        ),
+        0..64,
+    )
+    .prop_map(|parts| {
+        parts
+            .into_iter()
+            .map(|(fragment, separator)| format!("{}{}", fragment, separator))
+            .collect()
+    })
+}
+
+const EXAMPLES: &[&str] = &[
+    include_str!("../examples/addition.gwe"),
+    include_str!("../examples/hello_world.gwe"),
+    include_str!("../examples/hello_world_console_log.gwe"),
+    include_str!("../examples/hello_world_console_log_for_loop.gwe"),
+    include_str!("../examples/hello_world_string.gwe"),
+    include_str!("../examples/if_statement.gwe"),
+];
+
+/// Valid programs with a range of characters removed and the rest cut short,
+/// which produces almost-right input like missing brackets and half statements
+fn mutated_example() -> impl Strategy<Value = String> {
+    (
+        prop::sample::select(EXAMPLES),
+        any::<prop::sample::Index>(),
+        0..16usize,
+        any::<prop::sample::Index>(),
+    )
+        .prop_map(|(example, start, removed, end)| {
+            let chars: Vec<char> = example.chars().collect();
+            let start = start.index(chars.len());
+            let end = end.index(chars.len() + 1);
+
+            chars[..start]
+                .iter()
+                .chain(chars.iter().skip(start + removed))
+                .take(end)
+                .collect()
+        })
+}
+
+fn compile(input: String) {
+    if let Ok(program) = parse(input) {
+        generate(program.clone(), &FormatOptions::default());
+        let _ = web_assembly::generate(program);
+    }
+}
+
+proptest! {
+    #[test]
+    fn tokenize_never_panics(input in any::<String>()) {
+        tokenize(input);
+    }
+
+    #[test]
+    fn parse_never_panics(input in any::<String>()) {
+        compile(input);
+    }
+
+    #[test]
+    fn grammar_guided_parse_never_panics(input in grammar_input()) {
+        compile(input);
+    }
+
+    #[test]
+    fn mutated_examples_never_panic(input in mutated_example()) {
+        compile(input);
+    }
+}
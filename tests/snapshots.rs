@@ -0,0 +1,138 @@
+//! Compiles every `examples/*.gwe` to WAT, validates it, and compares it
+//! against the checked-in snapshot in `tests/snapshots/`. Examples the
+//! compiler rejects are compared against the error they give instead. The
+//! pretty and minified layouts of each are checked along the way, as is a
+//! copy of each example with CRLF line endings.
+//!
+//! Run `cargo test --test snapshots -- --bless` to update the snapshots
+//! after an intentional change to the generated code.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use gwe::{
+    errors::join_errors,
+    generators::{layout, web_assembly},
+    parser::parse,
+    typechecker,
+};
+
+const EXAMPLES_DIR: &str = "examples";
+const SNAPSHOTS_DIR: &str = "tests/snapshots";
+
+fn examples() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(EXAMPLES_DIR)
+        .expect("Failed to read examples directory")
+        .map(|entry| entry.expect("Failed to read example").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "gwe"))
+        .collect();
+
+    paths.sort();
+    paths
+}
+
+/// `.wat` for the generated code, or `.err` for examples of what the
+/// compiler rejects
+fn snapshot_path(example: &Path, extension: &str) -> PathBuf {
+    let mut path = Path::new(SNAPSHOTS_DIR).join(example.file_name().unwrap());
+    path.set_extension(extension);
+    path
+}
+
+fn read(example: &Path) -> Result<String, String> {
+    fs::read_to_string(example).map_err(|error| format!("Unable to read file due to {}", error))
+}
+
+fn compile(body: String) -> Result<String, String> {
+    match parse(body) {
+        Ok(program) => match typechecker::check(&program) {
+            Ok(()) => match web_assembly::generate_with(
+                program,
+                &web_assembly::GenerateOptions {
+                    validate: true,
+                    ..web_assembly::GenerateOptions::default()
+                },
+            ) {
+                Ok(output) => Ok(output),
+                Err(error) => Err(format!("Error generating: {}", error)),
+            },
+            Err(errors) => Err(format!("Error checking: {}", join_errors(&errors))),
+        },
+        Err(errors) => Err(format!("Error parsing: {}", join_errors(&errors))),
+    }
+}
+
+fn check(example: &Path, bless: bool) -> Result<(), String> {
+    let body = read(example)?;
+    let actual = match compile(body.clone()) {
+        Ok(actual) => actual,
+        Err(error) => return compare(&snapshot_path(example, "err"), &error, bless),
+    };
+
+    // the same example saved with Windows line endings
+    if compile(body.replace('\n', "\r\n"))? != actual {
+        return Err(String::from("CRLF line endings change the generated wat"));
+    }
+
+    // the other layouts only move whitespace around
+    if layout::pretty(&actual) != actual {
+        return Err(String::from("Pretty wat does not match the generated wat"));
+    }
+    if let Err(error) = web_assembly::validate(&layout::minify(&actual)) {
+        return Err(format!("Minified wat is invalid: {}", error));
+    }
+
+    compare(&snapshot_path(example, "wat"), &actual, bless)
+}
+
+fn compare(snapshot: &Path, actual: &str, bless: bool) -> Result<(), String> {
+    if bless {
+        return match fs::write(snapshot, actual) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(format!("Unable to write snapshot due to {}", error)),
+        };
+    }
+
+    match fs::read_to_string(snapshot) {
+        Ok(expected) if expected == actual => Ok(()),
+        Ok(expected) => Err(format!(
+            "Snapshot {} does not match\nExpected:\n{}\n\n========\nGenerated:\n{}",
+            snapshot.to_string_lossy(),
+            expected,
+            actual
+        )),
+        Err(_) => Err(format!(
+            "Missing snapshot {}, run with --bless to create it",
+            snapshot.to_string_lossy()
+        )),
+    }
+}
+
+fn main() {
+    let bless = env::args().any(|arg| arg == "--bless");
+
+    if bless {
+        fs::create_dir_all(SNAPSHOTS_DIR).expect("Failed to create snapshots directory");
+    }
+
+    let mut failures = 0;
+
+    for example in examples() {
+        match check(&example, bless) {
+            Ok(_) => println!("snapshot {} ... ok", example.to_string_lossy()),
+            Err(error) => {
+                failures += 1;
+                println!("snapshot {} ... FAILED", example.to_string_lossy());
+                println!("{}\n", error);
+            }
+        }
+    }
+
+    if failures > 0 {
+        println!("{} snapshot(s) failed", failures);
+        exit(1);
+    }
+}
@@ -0,0 +1,6 @@
+(module
+  (func $hello_world (result f32)
+    (f32.const 1)
+  )
+  (export "helloWorld" (func $hello_world))
+)
\ No newline at end of file
@@ -0,0 +1,8 @@
+(module
+  (import "console" "log" (func $log (param f32)))
+  (func $main
+    (f32.const 3.14)
+    (call $log)
+  )
+  (export "main" (func $main))
+)
\ No newline at end of file
@@ -0,0 +1,20 @@
+(module
+  (import "console" "log" (func $log (param i32)))
+  (func $main
+    (local $x i32)
+    (local.set $x (i32.const 0))
+    (loop $loop
+      (local.get $x)
+      (call $log)
+      (local.get $x)
+      (i32.const 1)
+      (i32.add)
+      (local.set $x)
+      (local.get $x)
+      (i32.const 10)
+      (i32.lt_s)
+      (br_if $loop)
+    )
+  )
+  (export "main" (func $main))
+)
\ No newline at end of file
@@ -0,0 +1,11 @@
+(module
+  (import "console" "log" (func $log (param i32 i32)))
+  (import "js" "mem" (memory 1))
+  (data (i32.const 0) "Hello world")
+  (func $main
+    (i32.const 0)
+    (i32.const 11)
+    (call $log)
+  )
+  (export "main" (func $main))
+)
\ No newline at end of file
@@ -0,0 +1,17 @@
+(module
+  (import "console" "log" (func $log (param f32)))
+  (func $main (param $n i32)
+    (if
+      (local.get $n)
+      (then
+        (f32.const 3.14)
+        (call $log)
+      )
+      (else
+        (f32.const 42)
+        (call $log)
+      )
+    )
+  )
+  (export "main" (func $main))
+)
\ No newline at end of file